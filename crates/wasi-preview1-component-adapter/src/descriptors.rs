@@ -29,6 +29,11 @@ impl Drop for Descriptor {
                 }
                 match &stream.type_ {
                     StreamType::File(file) => filesystem::drop_descriptor(file.fd),
+                    #[cfg(feature = "sockets")]
+                    StreamType::Socket(socket) => tcp::drop_tcp_socket(*socket),
+                    // Without listeners there is nothing to accept sockets
+                    // from.
+                    #[cfg(not(feature = "sockets"))]
                     StreamType::Socket(_) => unreachable!(),
                     StreamType::Stdio => {}
                 }
@@ -104,7 +109,6 @@ impl Streams {
     }
 }
 
-#[allow(dead_code)] // until Socket is implemented
 pub enum StreamType {
     /// Stream is used for implementing stdio.
     Stdio,
@@ -129,6 +133,10 @@ pub struct Descriptors {
     /// Preopened directories. Initialized lazily. Access with `State::get_preopens`
     /// to take care of initialization.
     preopens: Cell<Option<&'static [Preopen]>>,
+
+    /// The descriptor of the first preopened directory. Preopened listeners
+    /// come between stdio and the directories.
+    preopens_start: Cell<Fd>,
 }
 
 impl Descriptors {
@@ -138,6 +146,7 @@ impl Descriptors {
             table_len: Cell::new(0),
             closed: None,
             preopens: Cell::new(None),
+            preopens_start: Cell::new(3),
         };
 
         let stdio = crate::bindings::preopens::get_stdio();
@@ -162,6 +171,25 @@ impl Descriptors {
         }))
         .trapping_unwrap();
 
+        // Listeners have no streams until they accept a connection.
+        #[cfg(feature = "sockets")]
+        {
+            let listeners = import_alloc.with_arena(arena, || {
+                crate::bindings::preopened_listeners::get_listeners()
+            });
+            for socket in listeners.iter() {
+                d.push(Descriptor::Streams(Streams {
+                    input: Cell::new(None),
+                    output: Cell::new(None),
+                    type_: StreamType::Socket(*socket),
+                }))
+                .trapping_unwrap();
+            }
+            // The list lives in the arena, so it must not be freed.
+            core::mem::forget(listeners);
+            d.preopens_start.set(d.table_len.get().into());
+        }
+
         #[link(wasm_import_module = "preopens")]
         extern "C" {
             #[link_name = "get-directories"]
@@ -181,7 +209,8 @@ impl Descriptors {
         };
         for preopen in preopens {
             // Expectation is that the descriptor index is initialized with
-            // stdio (0,1,2) and no others, so that preopens are 3..
+            // stdio (0,1,2) and the listeners and no others, so that
+            // preopens are `preopens_start..`
             d.push(Descriptor::Streams(Streams {
                 input: Cell::new(None),
                 output: Cell::new(None),
@@ -266,8 +295,9 @@ impl Descriptors {
 
     pub fn get_preopen(&self, fd: Fd) -> Option<&Preopen> {
         let preopens = self.preopens.get().trapping_unwrap();
-        // Subtract 3 for the stdio indices to compute the preopen index.
-        let index = fd.checked_sub(3)? as usize;
+        // Subtract the stdio and listener indices to compute the preopen
+        // index.
+        let index = fd.checked_sub(self.preopens_start.get())? as usize;
         preopens.get(index)
     }
 
@@ -291,21 +321,12 @@ impl Descriptors {
         Ok(())
     }
 
-    // Expand the table by pushing a closed descriptor to the end. Used for renumbering.
-    fn push_closed(&mut self) -> Result<(), Errno> {
-        let old_closed = self.closed;
-        let new_closed = self.push(Descriptor::Closed(old_closed))?;
-        self.closed = Some(new_closed);
-        Ok(())
-    }
-
     // Implementation of fd_renumber
     pub fn renumber(&mut self, from_fd: Fd, to_fd: Fd) -> Result<(), Errno> {
-        // First, ensure from_fd is in bounds:
+        // First, ensure from_fd is in bounds, and to_fd is open:
         drop(self.get(from_fd)?);
-        // Expand table until to_fd is in bounds as well:
-        while self.table_len.get() as u32 <= to_fd as u32 {
-            self.push_closed()?;
+        if let Descriptor::Closed(_) = self.get(to_fd)? {
+            return Err(wasi::ERRNO_BADF);
         }
         // Then, close from_fd and put its contents into to_fd:
         let desc = self.close_(from_fd)?;
@@ -343,7 +364,6 @@ impl Descriptors {
         }
     }
 
-    #[allow(dead_code)] // until Socket is implemented
     pub fn get_socket(&self, fd: Fd) -> Result<crate::bindings::tcp::TcpSocket, Errno> {
         match self.get(fd)? {
             Descriptor::Streams(Streams {
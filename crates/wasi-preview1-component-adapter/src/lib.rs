@@ -1,7 +1,7 @@
 #![allow(unused_variables)] // TODO: remove this when more things are implemented
 
 use crate::bindings::{
-    exit, filesystem, monotonic_clock, network, poll, random, streams, wall_clock,
+    exit, filesystem, monotonic_clock, network, poll, random, streams, tcp, wall_clock,
 };
 use core::cell::{Cell, RefCell, RefMut, UnsafeCell};
 use core::cmp::min;
@@ -504,9 +504,40 @@ pub unsafe extern "C" fn fd_fdstat_get(fd: Fd, stat: *mut Fdstat) -> Errno {
         Descriptor::Streams(Streams {
             input,
             output,
-            type_: StreamType::Socket(_),
-        })
-        | Descriptor::Streams(Streams {
+            type_: StreamType::Socket(socket),
+        }) => {
+            let fs_filetype = FILETYPE_SOCKET_STREAM;
+            #[cfg(feature = "sockets")]
+            let fs_flags = if tcp::non_blocking(*socket)? {
+                FDFLAGS_NONBLOCK
+            } else {
+                0
+            };
+            #[cfg(not(feature = "sockets"))]
+            let fs_flags = 0;
+            let mut fs_rights_base = RIGHTS_FD_FDSTAT_SET_FLAGS | RIGHTS_POLL_FD_READWRITE;
+            // A socket without streams is a listener.
+            if input.get().is_none() && output.get().is_none() {
+                fs_rights_base |= RIGHTS_SOCK_ACCEPT;
+            } else {
+                fs_rights_base |= RIGHTS_SOCK_SHUTDOWN;
+            }
+            if input.get().is_some() {
+                fs_rights_base |= RIGHTS_FD_READ;
+            }
+            if output.get().is_some() {
+                fs_rights_base |= RIGHTS_FD_WRITE;
+            }
+            let fs_rights_inheriting = fs_rights_base;
+            stat.write(Fdstat {
+                fs_filetype,
+                fs_flags,
+                fs_rights_base,
+                fs_rights_inheriting,
+            });
+            Ok(())
+        }
+        Descriptor::Streams(Streams {
             input,
             output,
             type_: StreamType::Stdio,
@@ -549,6 +580,18 @@ pub unsafe extern "C" fn fd_fdstat_set_flags(fd: Fd, flags: Fdflags) -> Errno {
                 type_: StreamType::File(file),
                 ..
             }) if !file.is_dir() => file,
+            // Sockets can't be put in append mode.
+            Descriptor::Streams(Streams {
+                type_: StreamType::Socket(socket),
+                ..
+            }) => {
+                if flags & FDFLAGS_APPEND == FDFLAGS_APPEND {
+                    return Err(wasi::ERRNO_INVAL);
+                }
+                #[cfg(feature = "sockets")]
+                tcp::set_non_blocking(*socket, flags & FDFLAGS_NONBLOCK == FDFLAGS_NONBLOCK)?;
+                return Ok(());
+            }
             _ => Err(wasi::ERRNO_BADF)?,
         };
         file.append = flags & FDFLAGS_APPEND == FDFLAGS_APPEND;
@@ -609,6 +652,23 @@ pub unsafe extern "C" fn fd_filestat_get(fd: Fd, buf: *mut Filestat) -> Errno {
                 };
                 Ok(())
             }
+            // Sockets are likewise all zero fields, except for the filetype
+            Descriptor::Streams(Streams {
+                type_: StreamType::Socket(_),
+                ..
+            }) => {
+                *buf = Filestat {
+                    dev: 0,
+                    ino: 0,
+                    filetype: FILETYPE_SOCKET_STREAM,
+                    nlink: 0,
+                    size: 0,
+                    atim: 0,
+                    mtim: 0,
+                    ctim: 0,
+                };
+                Ok(())
+            }
             _ => Err(wasi::ERRNO_BADF),
         }
     })
@@ -813,10 +873,11 @@ pub unsafe extern "C" fn fd_read(
     State::with(|state| {
         match state.descriptors().get(fd)? {
             Descriptor::Streams(streams) => {
-                let blocking = if let StreamType::File(file) = &streams.type_ {
-                    file.blocking
-                } else {
-                    false
+                let blocking = match &streams.type_ {
+                    StreamType::File(file) => file.blocking,
+                    #[cfg(feature = "sockets")]
+                    StreamType::Socket(socket) => !tcp::non_blocking(*socket)?,
+                    _ => false,
                 };
 
                 let read_len = u64::try_from(len).trapping_unwrap();
@@ -844,7 +905,11 @@ pub unsafe extern "C" fn fd_read(
                 let len = data.len();
                 forget(data);
                 if !end && len == 0 {
-                    Err(ERRNO_INTR)
+                    // A non-blocking socket with nothing to read would block.
+                    match streams.type_ {
+                        StreamType::Socket(_) => Err(ERRNO_AGAIN),
+                        _ => Err(ERRNO_INTR),
+                    }
                 } else {
                     *nread = len;
                     Ok(())
@@ -894,7 +959,7 @@ pub unsafe extern "C" fn fd_readdir(
             };
 
         // Compute the inode of `.` so that the iterator can produce an entry
-        // for it.
+        // for it. The iterator only looks up `..` when it produces its entry.
         let ds = state.descriptors();
         let dir = ds.get_dir(fd)?;
         let stat = filesystem::stat(dir.fd)?;
@@ -912,6 +977,7 @@ pub unsafe extern "C" fn fd_readdir(
                     state,
                     cookie,
                     use_cache: true,
+                    dir: dir.fd,
                     dot_inode,
                 }
             }
@@ -927,12 +993,16 @@ pub unsafe extern "C" fn fd_readdir(
                     cookie: wasi::DIRCOOKIE_START,
                     use_cache: false,
                     stream: DirectoryEntryStream(filesystem::read_directory(dir.fd)?),
+                    dir: dir.fd,
                     dot_inode,
                 };
 
                 // Skip to the entry that is requested by the `cookie`
-                // parameter.
-                for _ in wasi::DIRCOOKIE_START..cookie {
+                // parameter. `.` and `..` are skipped without producing
+                // them, so that `..` isn't looked up for nothing.
+                let dots = cookie.min(2);
+                iter.cookie = dots;
+                for _ in dots..cookie {
                     match iter.next() {
                         Some(Ok(_)) => {}
                         Some(Err(e)) => return Err(e),
@@ -1006,6 +1076,8 @@ pub unsafe extern "C" fn fd_readdir(
         use_cache: bool,
         cookie: Dircookie,
         stream: DirectoryEntryStream,
+        /// The directory being read, to look up `..` in.
+        dir: filesystem::Descriptor,
         dot_inode: wasi::Inode,
     }
 
@@ -1032,9 +1104,19 @@ pub unsafe extern "C" fn fd_readdir(
                     return Some(Ok((dirent, &self.state.dotdot[..1])));
                 }
                 1 => {
+                    // The parent of a preopen is outside of it, so it has no
+                    // inode.
+                    let dotdot_inode = match filesystem::stat_at(
+                        self.dir,
+                        filesystem::PathFlags::empty(),
+                        b"..",
+                    ) {
+                        Ok(stat) => stat.inode,
+                        Err(_) => 0,
+                    };
                     let dirent = wasi::Dirent {
                         d_next: self.cookie,
-                        d_ino: 0,
+                        d_ino: dotdot_inode,
                         d_type: wasi::FILETYPE_DIRECTORY,
                         d_namlen: 2,
                     };
@@ -1738,28 +1820,7 @@ pub unsafe extern "C" fn poll_oneoff(
                                     flags = 0;
                                 }
                             },
-                            StreamType::Socket(connection) => {
-                                unreachable!() // TODO
-                                               /*
-                                               match tcp::bytes_readable(*connection) {
-                                                   Ok(result) => {
-                                                       error = ERRNO_SUCCESS;
-                                                       nbytes = result.0;
-                                                       flags = if result.1 {
-                                                           EVENTRWFLAGS_FD_READWRITE_HANGUP
-                                                       } else {
-                                                           0
-                                                       };
-                                                   }
-                                                   Err(e) => {
-                                                       error = e.into();
-                                                       nbytes = 0;
-                                                       flags = 0;
-                                                   }
-                                               }
-                                               */
-                            }
-                            StreamType::Stdio => {
+                            StreamType::Socket(_) | StreamType::Stdio => {
                                 error = ERRNO_SUCCESS;
                                 nbytes = 1;
                                 flags = 0;
@@ -1776,32 +1837,11 @@ pub unsafe extern "C" fn poll_oneoff(
                         .trapping_unwrap();
                     match desc {
                         Descriptor::Streams(streams) => match streams.type_ {
-                            StreamType::File(_) | StreamType::Stdio => {
+                            StreamType::File(_) | StreamType::Socket(_) | StreamType::Stdio => {
                                 error = ERRNO_SUCCESS;
                                 nbytes = 1;
                                 flags = 0;
                             }
-                            StreamType::Socket(connection) => {
-                                unreachable!() // TODO
-                                               /*
-                                               match tcp::bytes_writable(connection) {
-                                                   Ok(result) => {
-                                                       error = ERRNO_SUCCESS;
-                                                       nbytes = result.0;
-                                                       flags = if result.1 {
-                                                           EVENTRWFLAGS_FD_READWRITE_HANGUP
-                                                       } else {
-                                                           0
-                                                       };
-                                                   }
-                                                   Err(e) => {
-                                                       error = e.into();
-                                                       nbytes = 0;
-                                                       flags = 0;
-                                                   }
-                                               }
-                                               */
-                            }
                         },
                         _ => unreachable!(),
                     }
@@ -1886,7 +1926,26 @@ pub unsafe extern "C" fn random_get(buf: *mut u8, buf_len: Size) -> Errno {
 /// Note: This is similar to `accept` in POSIX.
 #[no_mangle]
 pub unsafe extern "C" fn sock_accept(fd: Fd, flags: Fdflags, connection: *mut Fd) -> Errno {
-    unreachable!()
+    // Only the NONBLOCK flag is meaningful for a new connection.
+    if flags & !FDFLAGS_NONBLOCK != 0 {
+        return wasi::ERRNO_INVAL;
+    }
+
+    State::with_mut(|state| {
+        let mut ds = state.descriptors_mut();
+        let socket = ds.get_socket(fd)?;
+        let (socket, input, output) = tcp::accept(socket)?;
+        let desc = Descriptor::Streams(Streams {
+            input: Cell::new(Some(input)),
+            output: Cell::new(Some(output)),
+            type_: StreamType::Socket(socket),
+        });
+        if flags & FDFLAGS_NONBLOCK == FDFLAGS_NONBLOCK {
+            tcp::set_non_blocking(socket, true)?;
+        }
+        *connection = ds.open(desc)?;
+        Ok(())
+    })
 }
 
 /// Receive a message from a socket.
@@ -1901,7 +1960,23 @@ pub unsafe extern "C" fn sock_recv(
     ro_datalen: *mut Size,
     ro_flags: *mut Roflags,
 ) -> Errno {
-    unreachable!()
+    // Peeking and waiting for a full buffer aren't supported.
+    if ri_flags != 0 {
+        return ERRNO_NOTSUP;
+    }
+
+    let result = State::with(|state| {
+        let ds = state.descriptors();
+        ds.get_socket(fd)?;
+        ds.get_read_stream(fd).map_err(|_| ERRNO_NOTCONN)?;
+        Ok(())
+    });
+    if result != ERRNO_SUCCESS {
+        return result;
+    }
+
+    *ro_flags = 0;
+    fd_read(fd, ri_data_ptr, ri_data_len, ro_datalen)
 }
 
 /// Send a message on a socket.
@@ -1915,14 +1990,40 @@ pub unsafe extern "C" fn sock_send(
     si_flags: Siflags,
     so_datalen: *mut Size,
 ) -> Errno {
-    unreachable!()
+    let result = State::with(|state| {
+        let ds = state.descriptors();
+        ds.get_socket(fd)?;
+        ds.get_write_stream(fd).map_err(|_| ERRNO_NOTCONN)?;
+        Ok(())
+    });
+    if result != ERRNO_SUCCESS {
+        return result;
+    }
+
+    fd_write(fd, si_data_ptr, si_data_len, so_datalen)
 }
 
 /// Shut down socket send and receive channels.
 /// Note: This is similar to `shutdown` in POSIX.
 #[no_mangle]
 pub unsafe extern "C" fn sock_shutdown(fd: Fd, how: Sdflags) -> Errno {
-    unreachable!()
+    let shutdown_type = if how == SDFLAGS_RD | SDFLAGS_WR {
+        tcp::ShutdownType::Both
+    } else if how == SDFLAGS_RD {
+        tcp::ShutdownType::Receive
+    } else if how == SDFLAGS_WR {
+        tcp::ShutdownType::Send
+    } else {
+        return ERRNO_INVAL;
+    };
+
+    State::with(|state| {
+        let ds = state.descriptors();
+        let socket = ds.get_socket(fd)?;
+        ds.get_read_stream(fd).map_err(|_| ERRNO_NOTCONN)?;
+        tcp::shutdown(socket, shutdown_type)?;
+        Ok(())
+    })
 }
 
 fn datetime_to_timestamp(datetime: filesystem::Datetime) -> Timestamp {
@@ -2032,7 +2133,7 @@ impl From<filesystem::DescriptorType> for wasi::Filetype {
             filesystem::DescriptorType::Fifo => FILETYPE_UNKNOWN,
             // TODO: Add a way to disginguish between FILETYPE_SOCKET_STREAM and
             // FILETYPE_SOCKET_DGRAM.
-            filesystem::DescriptorType::Socket => unreachable!(),
+            filesystem::DescriptorType::Socket => FILETYPE_SOCKET_STREAM,
             filesystem::DescriptorType::SymbolicLink => FILETYPE_SYMBOLIC_LINK,
             filesystem::DescriptorType::Unknown => FILETYPE_UNKNOWN,
         }
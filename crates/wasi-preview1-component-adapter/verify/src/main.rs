@@ -20,6 +20,7 @@ const ALLOWED_IMPORT_MODULES: &[&str] = &[
     "environment",
     "preopens",
     "exit",
+    "preopened-listeners",
     "canonical_abi",
     "__main_module__",
 ];
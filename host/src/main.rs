@@ -1,6 +1,12 @@
 use anyhow::{Context, Result};
-use wasi_common::preview1::{self, WasiPreview1Adapter, WasiPreview1View};
-use wasi_common::{wasi, Table, WasiCtx, WasiCtxBuilder, WasiView};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use wasi_common::filesystem::archive::Archive;
+use wasi_common::http::{self, TableHttpExt};
+use wasi_common::preview1::{self, WasiPreview1Adapter, WasiPreview1Sockets, WasiPreview1View};
+use wasi_common::{wasi, PreopenPerms, Table, WasiCtx, WasiCtxBuilder, WasiView};
 use wasmtime::{
     component::{Component, Linker},
     Config, Engine, Store,
@@ -24,27 +30,46 @@ struct Args {
     #[arg(long, default_value_t = String::from("command"))]
     world: String,
 
+    /// Map a host directory into the guest. The host path may also be a tar
+    /// or zip archive, which can only be mapped read-only.
     #[arg(
         long = "mapdir",
         number_of_values = 1,
-        value_name = "GUEST_DIR::HOST_DIR",
+        value_name = "GUEST_DIR::HOST_DIR[:PERMS]",
         value_parser = parse_map_dir
     )]
-    map_dirs: Vec<(String, String)>,
+    map_dirs: Vec<(String, String, Option<PreopenPerms>)>,
+
+    /// Address to serve HTTP requests on, for the proxy world.
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<SocketAddr>,
+
+    /// Address to listen for TCP connections on. Preview 1 modules get the
+    /// listener as a preopened socket.
+    #[arg(long = "tcplisten", number_of_values = 1, value_name = "ADDR")]
+    tcp_listen: Vec<SocketAddr>,
 }
 
-fn parse_map_dir(s: &str) -> Result<(String, String)> {
+fn parse_map_dir(s: &str) -> Result<(String, String, Option<PreopenPerms>)> {
     let parts: Vec<&str> = s.split("::").collect();
     if parts.len() != 2 {
         anyhow::bail!(
             "failed parsing map dir: must contain exactly one double colon `::`, got {s:?}"
         )
     }
-    Ok((parts[0].to_string(), parts[1].to_string()))
+    // The perms are an optional suffix such as `:ro`. Host paths may contain
+    // colons too, so only a recognized suffix is taken as the perms.
+    let (host, perms) = match parts[1].rsplit_once(':') {
+        Some((host, perms)) => match perms.parse() {
+            Ok(perms) => (host, Some(perms)),
+            Err(_) => (parts[1], None),
+        },
+        None => (parts[1], None),
+    };
+    Ok((parts[0].to_string(), host.to_string(), perms))
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
     tracing_subscriber::registry()
@@ -53,6 +78,24 @@ async fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
+
+    // A proxy guest may block its thread while handling a request, so
+    // connections are handled on a multi-thread runtime, where one blocked
+    // guest doesn't hold up the others.
+    let runtime = if args.world == "proxy" {
+        tokio::runtime::Builder::new_multi_thread()
+    } else {
+        tokio::runtime::Builder::new_current_thread()
+    }
+    .enable_all()
+    .build()?;
+    runtime.block_on(run(args))
+}
+
+async fn run(args: Args) -> Result<()> {
+    if args.world == "proxy" && (!args.map_dirs.is_empty() || !args.args.is_empty()) {
+        anyhow::bail!("the proxy world doesn't take `--mapdir` or command-line arguments");
+    }
     let input =
         std::fs::read(&args.component).with_context(|| format!("reading '{}'", args.component))?;
 
@@ -61,22 +104,32 @@ async fn main() -> Result<()> {
 
     let mut builder = WasiCtxBuilder::new().inherit_stdio().set_args(&argv);
 
-    for (guest, host) in args.map_dirs {
+    for (guest, host, perms) in args.map_dirs {
+        if std::fs::metadata(&host)
+            .context(format!("opening {host:?}"))?
+            .is_file()
+        {
+            if perms.is_some() && perms != Some(PreopenPerms::ReadOnly) {
+                anyhow::bail!("archive {host:?} can only be mapped read-only");
+            }
+            let archive = Archive::open(&host)?;
+            builder = builder.push_preopened_archive(archive, &guest);
+            continue;
+        }
         let dir = cap_std::fs::Dir::open_ambient_dir(&host, cap_std::ambient_authority())
             .context(format!("opening directory {host:?}"))?;
-        builder = builder.push_preopened_dir(
-            dir,
-            wasi_common::DirPerms::all(),
-            wasi_common::FilePerms::all(),
-            &guest,
-        );
+        let perms = perms.unwrap_or(PreopenPerms::ReadWrite);
+        builder = builder.push_preopened_dir_with(dir, perms, &guest);
     }
 
     let mut table = Table::new();
     let wasi = builder.build(&mut table)?;
 
     if input.get(0..8) != Some(&[0x00, 0x61, 0x73, 0x6d, 0x0a, 0x00, 0x01, 0x00]) {
-        return module_main(input, table, wasi).await;
+        return module_main(input, table, wasi, &args.tcp_listen).await;
+    }
+    if !args.tcp_listen.is_empty() {
+        anyhow::bail!("`--tcplisten` is only supported for preview 1 modules");
     }
 
     let mut config = Config::new();
@@ -148,48 +201,229 @@ async fn main() -> Result<()> {
 
         Ok(())
     } else if args.world == "proxy" {
-        struct ProxyCtx {
-            table: Table,
-            wasi: WasiCtx,
+        let addr = args
+            .listen
+            .context("the proxy world requires an address to `--listen` on")?;
+        proxy_main(engine, component, addr).await
+    } else {
+        anyhow::bail!("no such world {}", args.world)
+    }
+}
+
+/// How long a client may take to send the head of its request.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a single read of the request body, or write of the response, may
+/// wait on the client.
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many bytes of a request or response body the guest may write ahead of
+/// the other end.
+const BODY_BUFFER_LIMIT: u64 = 64 * 1024;
+
+async fn proxy_main(engine: Engine, component: Component, addr: SocketAddr) -> Result<()> {
+    struct ProxyCtx {
+        table: Table,
+        wasi: WasiCtx,
+    }
+    impl WasiView for ProxyCtx {
+        fn table(&self) -> &Table {
+            &self.table
         }
-        impl WasiView for ProxyCtx {
-            fn table(&self) -> &Table {
-                &self.table
-            }
-            fn table_mut(&mut self) -> &mut Table {
-                &mut self.table
+        fn table_mut(&mut self) -> &mut Table {
+            &mut self.table
+        }
+        fn ctx(&self) -> &WasiCtx {
+            &self.wasi
+        }
+        fn ctx_mut(&mut self) -> &mut WasiCtx {
+            &mut self.wasi
+        }
+    }
+
+    /// Run a write to the connection on a blocking thread.
+    async fn write_with(
+        stream: &Arc<TcpStream>,
+        write: impl FnOnce(&mut &TcpStream) -> Result<(), wasi::types::Error> + Send + 'static,
+    ) -> Result<()> {
+        let stream = Arc::clone(stream);
+        tokio::task::spawn_blocking(move || write(&mut &*stream))
+            .await?
+            .map_err(|e| anyhow::anyhow!("writing response: {e:?}"))
+    }
+
+    /// Send the response as soon as the guest sets it, and then its body as
+    /// the guest writes it. `guest_done` resolves, with whether the guest
+    /// succeeded, once the guest returns.
+    async fn send_response(
+        stream: &Arc<TcpStream>,
+        response: oneshot::Receiver<Option<http::OutgoingResponse>>,
+        mut guest_done: oneshot::Receiver<bool>,
+    ) -> Result<()> {
+        // A response set just before the guest returns is still sent.
+        let response = tokio::select! {
+            biased;
+            response = response => response.ok().flatten(),
+            _ = &mut guest_done => None,
+        };
+        let response = match response {
+            Some(response) => response,
+            None => {
+                write_with(stream, |s| http::server::write_error_response(s, 500)).await?;
+                anyhow::bail!("proxy did not set a response");
             }
-            fn ctx(&self) -> &WasiCtx {
-                &self.wasi
+        };
+
+        let body = response.body.clone();
+        write_with(stream, move |s| {
+            http::server::write_response_head(s, &response)
+        })
+        .await?;
+
+        let mut guest_result = None;
+        loop {
+            if guest_result.is_none() {
+                tokio::select! {
+                    _ = body.wait_for_contents() => {}
+                    ok = &mut guest_done => guest_result = Some(ok.unwrap_or(false)),
+                }
             }
-            fn ctx_mut(&mut self) -> &mut WasiCtx {
-                &mut self.wasi
+            let finished = body.is_finished();
+            let contents = body.take_contents();
+            write_with(stream, move |s| http::server::write_chunk(s, &contents)).await?;
+
+            // A guest that returns without finishing the body has written
+            // all of it, unless it failed.
+            if !finished {
+                match guest_result {
+                    None => continue,
+                    Some(false) => anyhow::bail!("proxy failed while writing the response body"),
+                    Some(true) => {}
+                }
             }
+            let trailers = body.trailers();
+            return write_with(stream, move |s| {
+                http::server::write_last_chunk(s, trailers.as_ref())
+            })
+            .await;
         }
+    }
 
-        let mut linker = Linker::new(&engine);
-        wasi::proxy::add_to_linker(&mut linker)?;
-
-        let mut store = Store::new(&engine, ProxyCtx { table, wasi });
-
-        let (wasi, _instance) =
-            wasi::proxy::Proxy::instantiate_async(&mut store, &component, &linker).await?;
-
-        // TODO: do something
-        let _ = wasi;
-        let result: Result<(), ()> = Ok(());
-
-        if result.is_err() {
-            anyhow::bail!("proxy returned with failing exit status");
+    async fn handle(
+        engine: &Engine,
+        component: &Component,
+        linker: &Linker<ProxyCtx>,
+        stream: tokio::net::TcpStream,
+    ) -> Result<()> {
+        // The request body is read on blocking threads as the guest consumes
+        // it, so the connection goes back to blocking mode, with timeouts on
+        // each read and write.
+        let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let stream = Arc::new(stream);
+
+        let reader = Arc::clone(&stream);
+        let read = tokio::task::spawn_blocking(move || http::server::read_request(&reader));
+        let request = match tokio::time::timeout(HEADER_TIMEOUT, read).await {
+            Ok(request) => request?,
+            Err(_) => {
+                // Wake the blocked read, which then fails.
+                let _ = stream.shutdown(Shutdown::Read);
+                let _ = write_with(&stream, |s| http::server::write_error_response(s, 408)).await;
+                anyhow::bail!("timed out reading request");
+            }
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                let _ = write_with(&stream, |s| http::server::write_error_response(s, 400)).await;
+                anyhow::bail!("reading request: {e:?}");
+            }
+        };
+
+        // Each request gets a fresh store. The proxy world doesn't import
+        // arguments, the environment, or preopens, so only stdio and the
+        // body buffer limits are set up.
+        let mut table = Table::new();
+        let wasi = WasiCtxBuilder::new()
+            .inherit_stdio()
+            .set_http_request_buffer_limit(BODY_BUFFER_LIMIT)
+            .set_http_response_buffer_limit(BODY_BUFFER_LIMIT)
+            .build(&mut table)?;
+        let mut store = Store::new(engine, ProxyCtx { table, wasi });
+
+        let request = store
+            .data_mut()
+            .table_mut()
+            .push_incoming_request(request)?;
+        let (outparam, response) = http::push_streaming_response_outparam(store.data_mut())?;
+
+        let (proxy, _instance) =
+            wasi::proxy::Proxy::instantiate_async(&mut store, component, linker).await?;
+
+        // The response is sent while the guest is still running, so that the
+        // guest can stream its body.
+        let (done, guest_done) = oneshot::channel();
+        let guest = async {
+            let result = proxy
+                .http()
+                .call_handle(&mut store, request, outparam)
+                .await;
+            let _ = done.send(result.is_ok());
+            result
+        };
+        let (result, sent) = tokio::join!(guest, send_response(&stream, response, guest_done));
+        result?;
+
+        if let Some(Err(e)) = store
+            .data()
+            .table()
+            .get_response_outparam(outparam)?
+            .response()
+        {
+            anyhow::bail!("proxy returned an error: {e:?}");
         }
+        sent
+    }
 
-        Ok(())
-    } else {
-        anyhow::bail!("no such world {}", args.world)
+    let mut linker = Linker::new(&engine);
+    wasi::proxy::add_to_linker(&mut linker)?;
+    let linker = Arc::new(linker);
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("listening on {addr}"))?;
+    tracing::info!("listening on http://{}", listener.local_addr()?);
+
+    // Each connection is handled on its own task, so a slow client doesn't
+    // hold up the others.
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::error!("error accepting connection: {e:?}");
+                continue;
+            }
+        };
+        let engine = engine.clone();
+        let component = component.clone();
+        let linker = Arc::clone(&linker);
+        tokio::spawn(async move {
+            if let Err(e) = handle(&engine, &component, &linker, stream).await {
+                tracing::error!("error handling request: {e:?}");
+            }
+        });
     }
 }
 
-async fn module_main(module_bytes: Vec<u8>, table: Table, wasi: WasiCtx) -> Result<()> {
+async fn module_main(
+    module_bytes: Vec<u8>,
+    table: Table,
+    wasi: WasiCtx,
+    tcp_listen: &[SocketAddr],
+) -> Result<()> {
     struct Preview1CommandCtx {
         table: Table,
         wasi: WasiCtx,
@@ -231,11 +465,39 @@ async fn module_main(module_bytes: Vec<u8>, table: Table, wasi: WasiCtx) -> Resu
         fn adapter_mut(&mut self) -> &mut WasiPreview1Adapter {
             &mut self.adapter
         }
+        fn sockets(&mut self) -> Option<&mut dyn WasiPreview1Sockets> {
+            Some(self)
+        }
+    }
+    #[async_trait::async_trait]
+    impl WasiPreview1Sockets for Preview1CommandCtx {
+        async fn preopened_sockets(&mut self) -> Result<Vec<u32>> {
+            wasmtime_wasi_sockets::preview1::preopened_sockets(self)
+        }
+        async fn accept(
+            &mut self,
+            socket: u32,
+            nonblocking: bool,
+        ) -> Result<(u32, wasi::streams::InputStream, wasi::streams::OutputStream)> {
+            wasmtime_wasi_sockets::preview1::accept(self, socket, nonblocking).await
+        }
+        async fn set_nonblocking(&mut self, socket: u32, nonblocking: bool) -> Result<()> {
+            wasmtime_wasi_sockets::preview1::set_nonblocking(self, socket, nonblocking)
+        }
+        async fn shutdown(&mut self, socket: u32, how: std::net::Shutdown) -> Result<()> {
+            wasmtime_wasi_sockets::preview1::shutdown(&*self, socket, how).await
+        }
+        async fn drop_socket(&mut self, socket: u32) -> Result<()> {
+            wasmtime_wasi_sockets::preview1::drop_socket(self, socket)
+        }
     }
 
-    let sockets = WasiSocketsCtxBuilder::new()
-        .inherit_network(cap_std::ambient_authority())
-        .build();
+    let mut sockets = WasiSocketsCtxBuilder::new().inherit_network(cap_std::ambient_authority());
+    for addr in tcp_listen {
+        let listener = TcpListener::bind(addr).with_context(|| format!("listening on {addr}"))?;
+        sockets = sockets.preopened_listener(cap_std::net::TcpListener::from_std(listener));
+    }
+    let sockets = sockets.build();
     let adapter = WasiPreview1Adapter::new();
     let ctx = Preview1CommandCtx {
         table,
@@ -263,3 +525,42 @@ async fn module_main(module_bytes: Vec<u8>, table: Table, wasi: WasiCtx) -> Resu
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn map_dir_perms() {
+        let (guest, host, perms) = parse_map_dir("/data::/srv/data:ro").unwrap();
+        assert_eq!((guest.as_str(), host.as_str()), ("/data", "/srv/data"));
+        assert_eq!(perms, Some(PreopenPerms::ReadOnly));
+
+        let (_, host, perms) = parse_map_dir("/logs::logs:wo").unwrap();
+        assert_eq!(host, "logs");
+        assert_eq!(perms, Some(PreopenPerms::WriteOnly));
+
+        let (_, host, perms) = parse_map_dir("/data::/srv/data").unwrap();
+        assert_eq!(host, "/srv/data");
+        assert_eq!(perms, None);
+
+        assert!(parse_map_dir("/data:/srv/data").is_err());
+        assert!(parse_map_dir("/a::/b::/c").is_err());
+    }
+
+    #[test]
+    fn map_dir_host_paths_with_colons() {
+        // Only a recognized suffix is taken as the perms.
+        let (_, host, perms) = parse_map_dir("/data::C:\\data").unwrap();
+        assert_eq!(host, "C:\\data");
+        assert_eq!(perms, None);
+
+        let (_, host, perms) = parse_map_dir("/data::/srv/a:b").unwrap();
+        assert_eq!(host, "/srv/a:b");
+        assert_eq!(perms, None);
+
+        let (_, host, perms) = parse_map_dir("/data::/srv/a:b:ro").unwrap();
+        assert_eq!(host, "/srv/a:b");
+        assert_eq!(perms, Some(PreopenPerms::ReadOnly));
+    }
+}
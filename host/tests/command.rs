@@ -3,14 +3,18 @@ use cap_rand::RngCore;
 use cap_std::{ambient_authority, fs::Dir, time::Duration};
 use std::{
     io::{Cursor, Write},
-    sync::Mutex,
+    sync::{Arc, Mutex},
 };
 use wasi_common::{
     clocks::{WasiMonotonicClock, WasiWallClock},
+    filesystem::archive::Archive,
+    filesystem::mem::MemFs,
+    filesystem::overlay::{Change, Overlay},
+    filesystem::{FsEvent, FsOp},
     pipe::ReadPipe,
     wasi::command::add_to_linker,
     wasi::command::Command,
-    DirPerms, FilePerms, Table, WasiCtx, WasiCtxBuilder, WasiView,
+    DirPerms, FilePerms, FsLimits, Table, WasiCtx, WasiCtxBuilder, WasiView,
 };
 use wasmtime::{
     component::{Component, Linker},
@@ -269,6 +273,77 @@ async fn file_read() -> Result<()> {
         .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))
 }
 
+#[test_log::test(tokio::test)]
+async fn file_read_audited() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    std::fs::File::create(dir.path().join("bar.txt"))?.write_all(b"And stood awhile in thought")?;
+
+    let open_dir = Dir::open_ambient_dir(dir.path(), ambient_authority())?;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&events);
+    let mut table = Table::new();
+    let wasi = WasiCtxBuilder::new()
+        .push_preopened_dir(open_dir, DirPerms::all(), FilePerms::all(), "/")
+        .set_fs_auditor(move |event: &FsEvent| recorded.lock().unwrap().push(event.clone()))
+        .build(&mut table)?;
+
+    let (mut store, command) =
+        instantiate(get_component("file_read"), CommandCtx { table, wasi }).await?;
+    command
+        .call_run(&mut store)
+        .await?
+        .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))?;
+
+    let events = events.lock().unwrap();
+    let open = events
+        .iter()
+        .find(|e| e.op == FsOp::OpenAt)
+        .expect("bar.txt was opened");
+    assert_eq!(open.path.to_string(), "/bar.txt");
+    assert_eq!(open.result, Ok(()));
+
+    // Everything read from the file was reported, including the second read
+    // after seeking.
+    let read: u64 = events
+        .iter()
+        .filter(|e| e.op == FsOp::Read && e.path.path == "bar.txt")
+        .filter_map(|e| e.bytes)
+        .sum();
+    assert!(read >= 27 + 16);
+
+    // Closing the file was reported too.
+    assert!(events
+        .iter()
+        .any(|e| e.op == FsOp::DropDescriptor && e.path.path == "bar.txt"));
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn file_read_archive() -> Result<()> {
+    let contents = b"And stood awhile in thought";
+    let mut header = tar::Header::new_ustar();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    let mut tar = tar::Builder::new(Vec::new());
+    tar.append_data(&mut header, "bar.txt", &contents[..])?;
+    let archive = Archive::from_bytes(tar.into_inner()?)?;
+
+    let mut table = Table::new();
+    let wasi = WasiCtxBuilder::new()
+        .push_preopened_archive(archive, "/")
+        .build(&mut table)?;
+
+    let (mut store, command) =
+        instantiate(get_component("file_read"), CommandCtx { table, wasi }).await?;
+
+    command
+        .call_run(&mut store)
+        .await?
+        .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))
+}
+
 #[test_log::test(tokio::test)]
 async fn file_append() -> Result<()> {
     let dir = tempfile::tempdir()?;
@@ -301,6 +376,100 @@ async fn file_append() -> Result<()> {
     Ok(())
 }
 
+#[test_log::test(tokio::test)]
+async fn file_append_over_quota() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    std::fs::File::create(dir.path().join("bar.txt"))?
+        .write_all(b"'Twas brillig, and the slithy toves.\n")?;
+
+    let open_dir = Dir::open_ambient_dir(dir.path(), ambient_authority())?;
+
+    let mut table = Table::new();
+    let wasi = WasiCtxBuilder::new()
+        .push_preopened_dir(open_dir, DirPerms::all(), FilePerms::all(), "/")
+        .set_fs_limits(FsLimits {
+            max_bytes_written: Some(10),
+            ..FsLimits::default()
+        })
+        .build(&mut table)?;
+
+    let (mut store, command) =
+        instantiate(get_component("file_append"), CommandCtx { table, wasi }).await?;
+    let result = command.call_run(&mut store).await;
+    assert!(!matches!(result, Ok(Ok(()))));
+
+    // Nothing was written to the host file.
+    let contents = std::fs::read(dir.path().join("bar.txt"))?;
+    assert_eq!(contents, b"'Twas brillig, and the slithy toves.\n");
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn file_append_in_memory() -> Result<()> {
+    let fs = MemFs::new();
+    fs.write("bar.txt", "'Twas brillig, and the slithy toves.\n")?;
+
+    let mut table = Table::new();
+    let wasi = WasiCtxBuilder::new()
+        .push_preopened_dir(fs.root(), DirPerms::all(), FilePerms::all(), "/")
+        .build(&mut table)?;
+
+    let (mut store, command) =
+        instantiate(get_component("file_append"), CommandCtx { table, wasi }).await?;
+    command
+        .call_run(&mut store)
+        .await?
+        .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))?;
+
+    assert_eq!(
+        std::str::from_utf8(&fs.read("bar.txt")?).unwrap(),
+        "'Twas brillig, and the slithy toves.\n\
+               Did gyre and gimble in the wabe;\n\
+               All mimsy were the borogoves,\n\
+               And the mome raths outgrabe.\n"
+    );
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn file_append_overlay() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    std::fs::File::create(dir.path().join("bar.txt"))?
+        .write_all(b"'Twas brillig, and the slithy toves.\n")?;
+
+    let lower = Dir::open_ambient_dir(dir.path(), ambient_authority())?;
+    let upper = MemFs::new();
+    let overlay = Overlay::new(lower, upper.root());
+
+    let mut table = Table::new();
+    let wasi = WasiCtxBuilder::new()
+        .push_preopened_dir(overlay.clone(), DirPerms::all(), FilePerms::all(), "/")
+        .build(&mut table)?;
+
+    let (mut store, command) =
+        instantiate(get_component("file_append"), CommandCtx { table, wasi }).await?;
+    command
+        .call_run(&mut store)
+        .await?
+        .map_err(|()| anyhow::anyhow!("command returned with failing exit status"))?;
+
+    assert_eq!(
+        std::fs::read(dir.path().join("bar.txt"))?,
+        b"'Twas brillig, and the slithy toves.\n"
+    );
+    assert_eq!(
+        std::str::from_utf8(&upper.read("bar.txt")?).unwrap(),
+        "'Twas brillig, and the slithy toves.\n\
+               Did gyre and gimble in the wabe;\n\
+               All mimsy were the borogoves,\n\
+               And the mome raths outgrabe.\n"
+    );
+    assert_eq!(overlay.changes()?, [Change::Modified("bar.txt".to_owned())]);
+    Ok(())
+}
+
 #[test_log::test(tokio::test)]
 async fn file_dir_sync() -> Result<()> {
     let dir = tempfile::tempdir()?;
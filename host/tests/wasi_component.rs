@@ -6,6 +6,8 @@ use wasmtime::{
     component::{Component, Linker},
     Config, Engine, Store,
 };
+use wasmtime_wasi_sockets::{WasiSocketsCtx, WasiSocketsView};
+use wasmtime_wasi_sockets_sync::WasiSocketsCtxBuilder;
 
 lazy_static::lazy_static! {
     static ref ENGINE: Engine = {
@@ -24,6 +26,7 @@ test_programs::wasi_tests_components!();
 struct CommandCtx {
     table: Table,
     wasi: WasiCtx,
+    sockets: WasiSocketsCtx,
 }
 
 impl WasiView for CommandCtx {
@@ -41,12 +44,31 @@ impl WasiView for CommandCtx {
     }
 }
 
+impl WasiSocketsView for CommandCtx {
+    fn table(&self) -> &Table {
+        &self.table
+    }
+    fn table_mut(&mut self) -> &mut Table {
+        &mut self.table
+    }
+    fn ctx(&self) -> &WasiSocketsCtx {
+        &self.sockets
+    }
+    fn ctx_mut(&mut self) -> &mut WasiSocketsCtx {
+        &mut self.sockets
+    }
+}
+
 async fn instantiate(
     component: Component,
     ctx: CommandCtx,
+    link_sockets: bool,
 ) -> Result<(Store<CommandCtx>, Command)> {
     let mut linker = Linker::new(&ENGINE);
     add_to_linker(&mut linker)?;
+    if link_sockets {
+        wasmtime_wasi_sockets::add_to_linker(&mut linker)?;
+    }
 
     let mut store = Store::new(&ENGINE, ctx);
 
@@ -54,6 +76,14 @@ async fn instantiate(
     Ok((store, command))
 }
 async fn run_with_temp_dir(component: &str) {
+    run(component, None).await
+}
+
+async fn run_with_sockets(component: &str, sockets: WasiSocketsCtxBuilder) {
+    run(component, Some(sockets)).await
+}
+
+async fn run(component: &str, sockets: Option<WasiSocketsCtxBuilder>) {
     let mut builder = WasiCtxBuilder::new()
         .push_env("NO_FDFLAGS_SYNC_SUPPORT", "1")
         .push_env("TEST", "1");
@@ -87,9 +117,19 @@ async fn run_with_temp_dir(component: &str) {
         .build(&mut table)
         .expect("build wasi ctx");
 
-    let (mut store, command) = instantiate(get_component(component), CommandCtx { table, wasi })
-        .await
-        .expect("instantiate");
+    let link_sockets = sockets.is_some();
+    let sockets = sockets.unwrap_or_else(WasiSocketsCtxBuilder::new).build();
+    let (mut store, command) = instantiate(
+        get_component(component),
+        CommandCtx {
+            table,
+            wasi,
+            sockets,
+        },
+        link_sockets,
+    )
+    .await
+    .expect("instantiate");
 
     command
         .call_run(&mut store)
@@ -258,7 +298,6 @@ async fn poll_oneoff_files() {
 }
 
 #[test_log::test(tokio::test)]
-#[should_panic]
 async fn poll_oneoff_stdio() {
     run_with_temp_dir("poll_oneoff_stdio").await
 }
@@ -291,6 +330,27 @@ async fn sched_yield() {
     run_with_temp_dir("sched_yield").await
 }
 
+#[test_log::test(tokio::test)]
+async fn sock_echo() {
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = std::thread::spawn(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(b"hello, adapter").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut echoed = Vec::new();
+        stream.read_to_end(&mut echoed).unwrap();
+        echoed
+    });
+
+    let sockets = WasiSocketsCtxBuilder::new()
+        .preopened_listener(cap_std::net::TcpListener::from_std(listener));
+    run_with_sockets("sock_echo", sockets).await;
+    assert_eq!(client.join().unwrap(), b"hello, adapter");
+}
+
 #[test_log::test(tokio::test)]
 async fn stdio() {
     run_with_temp_dir("stdio").await
@@ -38,6 +38,12 @@ fn main() {
     let reactor_adapter = build_adapter("reactor", &[]);
     let command_adapter =
         build_adapter("command", &["--no-default-features", "--features=command"]);
+    // Only programs that use preopened listeners need an adapter that
+    // imports them.
+    let sockets_adapter = build_adapter(
+        "command-sockets",
+        &["--no-default-features", "--features=command,sockets"],
+    );
 
     println!("cargo:rerun-if-changed=./wasi-tests");
     println!("cargo:rerun-if-changed=./command-tests");
@@ -97,7 +103,13 @@ fn main() {
 
     let wasi_tests_components = targets_in_package(&meta, "wasi-tests", "bin")
         .into_iter()
-        .map(|stem| compile_component(stem, &out_dir, &command_adapter))
+        .map(|stem| {
+            let adapter = match stem.as_str() {
+                "sock_echo" => &sockets_adapter,
+                _ => &command_adapter,
+            };
+            compile_component(stem, &out_dir, adapter)
+        })
         .collect::<Vec<_>>();
 
     let reactor_tests = targets_in_package(&meta, "reactor-tests", "cdylib")
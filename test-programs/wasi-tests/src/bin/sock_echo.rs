@@ -0,0 +1,45 @@
+// Expects a preopened listener at fd 3, as wasmtime's `--tcplisten` provides.
+const LISTENER_FD: wasi::Fd = 3;
+
+unsafe fn test_sock_echo(listener: wasi::Fd) {
+    let stat = wasi::fd_fdstat_get(listener).expect("fd_fdstat_get on the listener");
+    assert_eq!(stat.fs_filetype, wasi::FILETYPE_SOCKET_STREAM);
+    assert_eq!(stat.fs_flags & wasi::FDFLAGS_NONBLOCK, 0);
+    assert_eq!(
+        stat.fs_rights_base & wasi::RIGHTS_SOCK_ACCEPT,
+        wasi::RIGHTS_SOCK_ACCEPT
+    );
+
+    let connection = wasi::sock_accept(listener, 0).expect("sock_accept");
+    let stat = wasi::fd_fdstat_get(connection).expect("fd_fdstat_get on the connection");
+    assert_eq!(stat.fs_filetype, wasi::FILETYPE_SOCKET_STREAM);
+
+    // Echo everything back until the peer shuts down its side.
+    let mut buf = [0; 64];
+    loop {
+        let iovec = wasi::Iovec {
+            buf: buf.as_mut_ptr(),
+            buf_len: buf.len(),
+        };
+        let (n, _) = wasi::sock_recv(connection, &[iovec], 0).expect("sock_recv");
+        if n == 0 {
+            break;
+        }
+        let mut sent = 0;
+        while sent < n {
+            let ciovec = wasi::Ciovec {
+                buf: buf[sent..].as_ptr(),
+                buf_len: n - sent,
+            };
+            sent += wasi::sock_send(connection, &[ciovec], 0).expect("sock_send");
+        }
+    }
+
+    wasi::sock_shutdown(connection, wasi::SDFLAGS_WR).expect("sock_shutdown");
+    wasi::fd_close(connection).expect("closing the connection");
+}
+
+fn main() {
+    // Run the tests.
+    unsafe { test_sock_echo(LISTENER_FD) }
+}
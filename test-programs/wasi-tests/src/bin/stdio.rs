@@ -1,9 +1,14 @@
-use wasi_tests::{STDERR_FD, STDIN_FD, STDOUT_FD};
+use wasi_tests::{assert_errno, STDERR_FD, STDIN_FD, STDOUT_FD};
 
 unsafe fn test_stdio() {
     for fd in &[STDIN_FD, STDOUT_FD, STDERR_FD] {
         wasi::fd_fdstat_get(*fd).expect("fd_fdstat_get on stdio");
-        wasi::fd_renumber(*fd, *fd + 100).expect("renumbering stdio");
+        // Only an open descriptor can be renumbered over.
+        assert_errno!(
+            wasi::fd_renumber(*fd, *fd + 100).expect_err("renumbering onto a closed descriptor"),
+            wasi::ERRNO_BADF
+        );
+        wasi::fd_fdstat_get(*fd).expect("fd_fdstat_get on stdio after a failed renumber");
     }
 }
 
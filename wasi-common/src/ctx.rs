@@ -1,9 +1,15 @@
 use crate::clocks::WasiClocks;
-use crate::filesystem::{Dir, TableFsExt};
+#[cfg(feature = "archive")]
+use crate::filesystem::archive::Archive;
+use crate::filesystem::{
+    Dir, FsAuditor, FsEvent, FsLimits, FsPath, FsQuota, StreamAudit, TableFsExt, WasiDir,
+};
+use crate::http::{WasiHttpClient, WasiHttpCtx, WasiHttpPolicy};
 use crate::sched::WasiSched;
 use crate::stream::{InputStream, OutputStream, TableStreamExt};
-use crate::{DirPerms, FilePerms, Table};
+use crate::{DirPerms, FilePerms, PreopenPerms, Table};
 use cap_rand::RngCore;
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct WasiCtxBuilder {
@@ -13,11 +19,18 @@ pub struct WasiCtxBuilder {
     env: Vec<(String, String)>,
     args: Vec<String>,
     preopens: Vec<(Dir, String)>,
+    fs_limits: FsLimits,
+    fs_auditor: Option<Arc<dyn FsAuditor>>,
 
     random: Option<Box<dyn RngCore + Send + Sync>>,
     clocks: Option<WasiClocks>,
 
     sched: Option<Box<dyn WasiSched>>,
+
+    http_policy: Option<Box<dyn WasiHttpPolicy>>,
+    http_client: Option<Arc<dyn WasiHttpClient>>,
+    http_request_buffer_limit: Option<u64>,
+    http_response_buffer_limit: Option<u64>,
 }
 
 impl WasiCtxBuilder {
@@ -76,16 +89,48 @@ impl WasiCtxBuilder {
         self
     }
 
-    // TODO: optionally read-only
+    /// Preopen `dir`, which may be a `cap_std::fs::Dir` or any other
+    /// [`WasiDir`], such as the root of a [`MemFs`](crate::filesystem::mem::MemFs).
     pub fn push_preopened_dir(
         mut self,
-        dir: cap_std::fs::Dir,
+        dir: impl WasiDir + 'static,
         perms: DirPerms,
         file_perms: FilePerms,
         path: impl AsRef<str>,
     ) -> Self {
-        self.preopens
-            .push((Dir::new(dir, perms, file_perms), path.as_ref().to_owned()));
+        self.preopens.push((
+            Dir::new(Box::new(dir), perms, file_perms),
+            path.as_ref().to_owned(),
+        ));
+        self
+    }
+
+    /// Preopen `dir` with the perms of one of the [`PreopenPerms`] presets.
+    pub fn push_preopened_dir_with(
+        self,
+        dir: impl WasiDir + 'static,
+        perms: PreopenPerms,
+        path: impl AsRef<str>,
+    ) -> Self {
+        self.push_preopened_dir(dir, perms.dir_perms(), perms.file_perms(), path)
+    }
+
+    /// Preopen the contents of a tar or zip [`Archive`] as a read-only
+    /// directory.
+    #[cfg(feature = "archive")]
+    pub fn push_preopened_archive(self, archive: Archive, path: impl AsRef<str>) -> Self {
+        self.push_preopened_dir_with(archive.root(), PreopenPerms::ReadOnly, path)
+    }
+
+    /// Limit how much the guest can write through its preopens.
+    pub fn set_fs_limits(mut self, limits: FsLimits) -> Self {
+        self.fs_limits = limits;
+        self
+    }
+
+    /// Report every filesystem operation of the guest to `auditor`.
+    pub fn set_fs_auditor(mut self, auditor: impl FsAuditor + 'static) -> Self {
+        self.fs_auditor = Some(Arc::new(auditor));
         self
     }
 
@@ -103,6 +148,30 @@ impl WasiCtxBuilder {
         self
     }
 
+    pub fn set_http_policy(mut self, policy: impl WasiHttpPolicy + 'static) -> Self {
+        self.http_policy = Some(Box::new(policy));
+        self
+    }
+
+    pub fn set_http_client(mut self, client: impl WasiHttpClient + 'static) -> Self {
+        self.http_client = Some(Arc::new(client));
+        self
+    }
+
+    /// Limit how many bytes of an outgoing request body the guest can write
+    /// before the host has taken them.
+    pub fn set_http_request_buffer_limit(mut self, limit: u64) -> Self {
+        self.http_request_buffer_limit = Some(limit);
+        self
+    }
+
+    /// Limit how many bytes of an outgoing response body the guest can write
+    /// before the host has taken them.
+    pub fn set_http_response_buffer_limit(mut self, limit: u64) -> Self {
+        self.http_response_buffer_limit = Some(limit);
+        self
+    }
+
     pub fn build(self, table: &mut Table) -> Result<WasiCtx, anyhow::Error> {
         use anyhow::Context;
 
@@ -117,7 +186,8 @@ impl WasiCtxBuilder {
             .context("stderr")?;
 
         let mut preopens = Vec::new();
-        for (dir, path) in self.preopens {
+        for (mut dir, path) in self.preopens {
+            dir.location = FsPath::preopen(&path);
             let dirfd = table
                 .push_dir(dir)
                 .with_context(|| format!("preopen {path:?}"))?;
@@ -128,9 +198,18 @@ impl WasiCtxBuilder {
             random: self.random.context("required member random")?,
             clocks: self.clocks.context("required member clocks")?,
             sched: self.sched.context("required member sched")?,
+            http: WasiHttpCtx {
+                policy: self.http_policy,
+                client: self.http_client,
+                request_buffer_limit: self.http_request_buffer_limit,
+                response_buffer_limit: self.http_response_buffer_limit,
+                ..WasiHttpCtx::new()
+            },
             env: self.env,
             args: self.args,
             preopens,
+            fs_quota: Arc::new(FsQuota::new(self.fs_limits)),
+            fs_auditor: self.fs_auditor,
             stdin,
             stdout,
             stderr,
@@ -149,9 +228,12 @@ pub struct WasiCtx {
     pub random: Box<dyn RngCore + Send + Sync>,
     pub clocks: WasiClocks,
     pub sched: Box<dyn WasiSched>,
+    pub http: WasiHttpCtx,
     pub env: Vec<(String, String)>,
     pub args: Vec<String>,
     pub preopens: Vec<(u32, String)>,
+    pub fs_quota: Arc<FsQuota>,
+    pub fs_auditor: Option<Arc<dyn FsAuditor>>,
     pub stdin: u32,
     pub stdout: u32,
     pub stderr: u32,
@@ -162,4 +244,19 @@ impl WasiCtx {
     pub fn builder() -> WasiCtxBuilder {
         WasiCtxBuilder::default()
     }
+
+    /// Report the event built by `event` to the auditor, if there is one.
+    pub(crate) fn audit(&self, event: impl FnOnce() -> FsEvent) {
+        if let Some(auditor) = &self.fs_auditor {
+            auditor.record(&event());
+        }
+    }
+
+    /// Auditing for a stream over the file at `path`.
+    pub(crate) fn stream_audit(&self, path: &FsPath) -> Option<StreamAudit> {
+        self.fs_auditor.as_ref().map(|auditor| StreamAudit {
+            auditor: Arc::clone(auditor),
+            path: path.clone(),
+        })
+    }
 }
@@ -1,7 +1,233 @@
+use crate::wasi::filesystem::{
+    Advice, DescriptorFlags, DescriptorStat, DirectoryEntry, Error, ErrorCode, Modes, NewTimestamp,
+    OpenFlags,
+};
 use crate::{InputStream, OutputStream, Table, TableError};
 use std::any::Any;
 use std::sync::Arc;
 
+#[cfg(feature = "archive")]
+pub mod archive;
+mod audit;
+mod host;
+pub mod mem;
+pub mod overlay;
+mod quota;
+
+pub(crate) use audit::StreamAudit;
+pub use audit::{FsAuditor, FsEvent, FsOp, FsPath};
+pub use host::HostFile;
+pub use quota::{FsLimits, FsQuota};
+
+/// The result of a filesystem operation.
+pub type FsResult<T> = Result<T, Error>;
+
+/// An iterator over the entries of a directory, as returned by
+/// [`WasiDir::read_dir`].
+pub type ReaddirIter = Box<dyn Iterator<Item = FsResult<DirectoryEntry>> + Send>;
+
+/// An open file.
+///
+/// This is implemented for `cap_std::fs::File`, and for the files of other
+/// backends such as [`mem::MemFs`]. The `DirPerms` and `FilePerms` of the
+/// descriptor are checked before any of these methods are called.
+///
+/// These methods may block. Reads, writes, and syncs are run on tokio's
+/// blocking thread pool, so they don't hold up the executor.
+pub trait WasiFile: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    /// If this file is backed by a host file descriptor, return it so that
+    /// streams over the file can be polled with a host poll.
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd> {
+        None
+    }
+
+    /// If this file is backed by a host file descriptor, return it so that
+    /// streams over the file can be polled with a host poll.
+    #[cfg(windows)]
+    fn pollable(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
+        None
+    }
+
+    /// If this file is a host file, return its descriptor so that the kernel
+    /// can copy bytes out of it directly, as with `sendfile`.
+    #[cfg(unix)]
+    fn host_fd(&self) -> Option<rustix::fd::BorrowedFd> {
+        None
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat>;
+
+    /// The sync flags the file was opened with. `READ` and `WRITE` come from
+    /// the descriptor's perms, not from here.
+    fn sync_flags(&self) -> FsResult<DescriptorFlags> {
+        Ok(DescriptorFlags::empty())
+    }
+
+    fn advise(&self, _offset: u64, _len: u64, _advice: Advice) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> FsResult<()>;
+
+    fn sync(&self) -> FsResult<()>;
+
+    fn set_len(&self, size: u64) -> FsResult<()>;
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()>;
+
+    /// Read bytes at `offset`. On success, returns a pair holding the number
+    /// of bytes read and a flag indicating whether the end of the file was
+    /// reached.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> FsResult<(u64, bool)>;
+
+    /// Vectored-I/O form of `read_at`.
+    fn read_vectored_at(
+        &self,
+        bufs: &mut [std::io::IoSliceMut<'_>],
+        offset: u64,
+    ) -> FsResult<(u64, bool)> {
+        match bufs.iter_mut().find(|b| !b.is_empty()) {
+            Some(buf) => self.read_at(buf, offset),
+            None => self.read_at(&mut [], offset),
+        }
+    }
+
+    /// Test whether vectored I/O reads are known to be optimized in the
+    /// underlying implementation.
+    fn is_read_vectored_at(&self) -> bool {
+        false
+    }
+
+    /// Write bytes at `offset`. On success, returns the number of bytes
+    /// written.
+    fn write_at(&self, buf: &[u8], offset: u64) -> FsResult<u64>;
+
+    /// Vectored-I/O form of `write_at`.
+    fn write_vectored_at(&self, bufs: &[std::io::IoSlice<'_>], offset: u64) -> FsResult<u64> {
+        match bufs.iter().find(|b| !b.is_empty()) {
+            Some(buf) => self.write_at(buf, offset),
+            None => Ok(0),
+        }
+    }
+
+    /// Test whether vectored I/O writes are known to be optimized in the
+    /// underlying implementation.
+    fn is_write_vectored_at(&self) -> bool {
+        false
+    }
+
+    /// Write bytes at the end of the file. On success, returns the number of
+    /// bytes written.
+    fn append(&self, buf: &[u8]) -> FsResult<u64>;
+
+    /// Vectored-I/O form of `append`.
+    fn append_vectored(&self, bufs: &[std::io::IoSlice<'_>]) -> FsResult<u64> {
+        match bufs.iter().find(|b| !b.is_empty()) {
+            Some(buf) => self.append(buf),
+            None => Ok(0),
+        }
+    }
+
+    /// Apply `op` to the advisory lock on the file. `Shared` and `Exclusive`
+    /// wait for other holders, and are called on the blocking thread pool.
+    fn lock(&self, _op: LockOp) -> FsResult<()> {
+        Err(ErrorCode::Unsupported.into())
+    }
+}
+
+/// What [`WasiDir::open_at`] opened.
+pub enum Opened {
+    File(Box<dyn WasiFile>),
+    Dir(Box<dyn WasiDir>),
+}
+
+/// An open directory, which can be used as a preopen.
+///
+/// This is implemented for `cap_std::fs::Dir`, which accesses the host
+/// filesystem, and for [`mem::MemDir`]. Paths are relative to the directory
+/// and must not escape it. As with [`WasiFile`], permission checks happen
+/// before these methods are called.
+pub trait WasiDir: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    fn stat(&self) -> FsResult<DescriptorStat>;
+
+    /// The sync flags the directory was opened with.
+    fn sync_flags(&self) -> FsResult<DescriptorFlags> {
+        Ok(DescriptorFlags::empty())
+    }
+
+    fn sync_data(&self) -> FsResult<()>;
+
+    fn sync(&self) -> FsResult<()>;
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()>;
+
+    /// Open the file or directory at `path`. The `DIRECTORY` open flag is
+    /// never combined with `CREATE`, `EXCLUSIVE`, or `TRUNCATE`, and `flags`
+    /// never contains sync flags.
+    fn open_at(
+        &self,
+        path: &str,
+        follow: bool,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Opened>;
+
+    fn create_dir_at(&self, path: &str) -> FsResult<()>;
+
+    fn read_dir(&self) -> FsResult<ReaddirIter>;
+
+    fn stat_at(&self, path: &str, follow: bool) -> FsResult<DescriptorStat>;
+
+    fn set_times_at(
+        &self,
+        path: &str,
+        follow: bool,
+        atim: NewTimestamp,
+        mtim: NewTimestamp,
+    ) -> FsResult<()>;
+
+    /// Create a hard link at `new_path` in `new_dir`. Implementations return
+    /// `cross-device` if `new_dir` is from another backend.
+    fn link_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()>;
+
+    fn readlink_at(&self, path: &str) -> FsResult<String>;
+
+    fn remove_dir_at(&self, path: &str) -> FsResult<()>;
+
+    /// Rename `old_path` to `new_path` in `new_dir`. Implementations return
+    /// `cross-device` if `new_dir` is from another backend.
+    fn rename_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()>;
+
+    fn symlink_at(&self, src_path: &str, dest_path: &str) -> FsResult<()>;
+
+    fn unlink_file_at(&self, path: &str) -> FsResult<()>;
+
+    /// Set the permissions of the file, or directory if `is_dir`, at `path`.
+    /// The caller has already checked that `path` is of the right type.
+    fn set_permissions_at(
+        &self,
+        path: &str,
+        follow: bool,
+        modes: Modes,
+        is_dir: bool,
+    ) -> FsResult<()>;
+}
+
+/// An advisory lock operation, with the semantics of `flock`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockOp {
+    Shared,
+    Exclusive,
+    TryShared,
+    TryExclusive,
+    Unlock,
+}
+
 bitflags::bitflags! {
     pub struct FilePerms: usize {
         const READ = 0b1;
@@ -10,15 +236,18 @@ bitflags::bitflags! {
 }
 
 pub(crate) struct File {
-    pub file: Arc<cap_std::fs::File>,
+    pub file: Arc<dyn WasiFile>,
     pub perms: FilePerms,
+    /// Where the guest opened the file, for auditing.
+    pub location: FsPath,
 }
 
 impl File {
-    pub fn new(file: cap_std::fs::File, perms: FilePerms) -> Self {
+    pub fn new(file: Box<dyn WasiFile>, perms: FilePerms) -> Self {
         Self {
-            file: Arc::new(file),
+            file: Arc::from(file),
             perms,
+            location: FsPath::default(),
         }
     }
 }
@@ -69,29 +298,167 @@ bitflags::bitflags! {
     }
 }
 
+/// Common combinations of [`DirPerms`] and [`FilePerms`] for preopened
+/// directories.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreopenPerms {
+    /// Everything is permitted.
+    #[default]
+    ReadWrite,
+    /// Files and directories can be read, but nothing can be changed.
+    ReadOnly,
+    /// Files can be created and written, but not read back. Directories can
+    /// still be listed and changed. Writes may go anywhere in a file, so this
+    /// doesn't make files append-only.
+    WriteOnly,
+    /// Existing files can be read and written, but nothing can be created,
+    /// removed, or renamed.
+    NoCreate,
+}
+
+impl PreopenPerms {
+    pub fn dir_perms(self) -> DirPerms {
+        match self {
+            PreopenPerms::ReadWrite | PreopenPerms::WriteOnly => DirPerms::all(),
+            PreopenPerms::ReadOnly | PreopenPerms::NoCreate => DirPerms::READ,
+        }
+    }
+
+    pub fn file_perms(self) -> FilePerms {
+        match self {
+            PreopenPerms::ReadWrite | PreopenPerms::NoCreate => FilePerms::all(),
+            PreopenPerms::ReadOnly => FilePerms::READ,
+            PreopenPerms::WriteOnly => FilePerms::WRITE,
+        }
+    }
+}
+
+impl std::str::FromStr for PreopenPerms {
+    type Err = anyhow::Error;
+
+    /// Parse `rw`, `ro`, `wo`, or `nocreate`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rw" => Ok(PreopenPerms::ReadWrite),
+            "ro" => Ok(PreopenPerms::ReadOnly),
+            "wo" => Ok(PreopenPerms::WriteOnly),
+            "nocreate" => Ok(PreopenPerms::NoCreate),
+            _ => anyhow::bail!("unknown preopen permissions {s:?}"),
+        }
+    }
+}
+
 pub(crate) struct Dir {
-    pub dir: cap_std::fs::Dir,
+    pub dir: Arc<dyn WasiDir>,
     pub perms: DirPerms,
     pub file_perms: FilePerms,
+    /// Where the guest opened the directory, for auditing.
+    pub location: FsPath,
 }
 
 impl Dir {
-    pub fn new(dir: cap_std::fs::Dir, perms: DirPerms, file_perms: FilePerms) -> Self {
+    pub fn new(dir: Box<dyn WasiDir>, perms: DirPerms, file_perms: FilePerms) -> Self {
         Dir {
-            dir,
+            dir: Arc::from(dir),
             perms,
             file_perms,
+            location: FsPath::default(),
         }
     }
 }
 
+/// The error for stream operations that the file's perms don't allow.
+fn not_permitted() -> std::io::Error {
+    std::io::ErrorKind::PermissionDenied.into()
+}
+
+/// Run `f`, which does blocking I/O, on tokio's blocking thread pool, so
+/// that a slow disk or a socket waiting for a peer doesn't stall the other
+/// tasks of the executor. Outside of a tokio runtime, `f` runs on the
+/// current thread.
+pub async fn spawn_blocking<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => match handle.spawn_blocking(f).await {
+            Ok(t) => t,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => panic!("blocking operation failed: {e}"),
+        },
+        Err(_) => f(),
+    }
+}
+
+/// Gather `bufs` into one buffer, so that a vectored write can be moved to
+/// another thread.
+fn gather(bufs: &[std::io::IoSlice<'_>]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(bufs.iter().map(|b| b.len()).sum());
+    for b in bufs {
+        buf.extend_from_slice(b);
+    }
+    buf
+}
+
+/// Copy `data` into `bufs`, in order.
+fn scatter(data: &[u8], bufs: &mut [std::io::IoSliceMut<'_>]) {
+    let mut data = data;
+    for buf in bufs {
+        let n = buf.len().min(data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        data = &data[n..];
+    }
+}
+
 pub(crate) struct FileInputStream {
-    file: Arc<cap_std::fs::File>,
+    file: Arc<dyn WasiFile>,
+    perms: FilePerms,
+    audit: Option<StreamAudit>,
     position: u64,
 }
 impl FileInputStream {
-    pub fn new(file: Arc<cap_std::fs::File>, position: u64) -> Self {
-        Self { file, position }
+    pub fn new(
+        file: Arc<dyn WasiFile>,
+        perms: FilePerms,
+        audit: Option<StreamAudit>,
+        position: u64,
+    ) -> Self {
+        Self {
+            file,
+            perms,
+            audit,
+            position,
+        }
+    }
+
+    fn check_perms(&self) -> std::io::Result<()> {
+        if self.perms.contains(FilePerms::READ) {
+            Ok(())
+        } else {
+            Err(not_permitted())
+        }
+    }
+
+    /// Read up to `len` bytes from the current position, on the blocking
+    /// thread pool.
+    async fn read_blocking(&mut self, len: usize) -> anyhow::Result<(Vec<u8>, bool)> {
+        self.check_perms()?;
+        let file = Arc::clone(&self.file);
+        let position = self.position;
+        let result = spawn_blocking(move || -> FsResult<_> {
+            let mut buf = vec![0; len];
+            let (n, end) = file.read_at(&mut buf, position)?;
+            buf.truncate(n as usize);
+            Ok((buf, end))
+        })
+        .await;
+        if let Some(audit) = &self.audit {
+            audit.record(FsOp::Read, &result, |(buf, _)| buf.len() as u64);
+        }
+        let (buf, end) = result?;
+        self.position = self.position.wrapping_add(buf.len() as u64);
+        Ok((buf, end))
     }
 }
 
@@ -102,59 +469,118 @@ impl InputStream for FileInputStream {
     }
     #[cfg(unix)]
     fn pollable_read(&self) -> Option<rustix::fd::BorrowedFd> {
-        use cap_std::io_lifetimes::AsFd;
-        Some(self.file.as_fd())
+        self.file.pollable()
     }
     #[cfg(windows)]
     fn pollable_read(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
-        use io_extras::os::windows::AsHandleOrSocket;
-        Some(self.file.as_handle_or_socket())
+        self.file.pollable()
     }
     async fn read(&mut self, buf: &mut [u8]) -> anyhow::Result<(u64, bool)> {
-        use system_interface::fs::FileIoExt;
-        let (n, end) = read_result(self.file.read_at(buf, self.position))?;
-        self.position = self.position.wrapping_add(n);
-        Ok((n, end))
+        let (data, end) = self.read_blocking(buf.len()).await?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok((data.len() as u64, end))
     }
     async fn read_vectored<'a>(
         &mut self,
         bufs: &mut [std::io::IoSliceMut<'a>],
     ) -> anyhow::Result<(u64, bool)> {
-        use system_interface::fs::FileIoExt;
-        let (n, end) = read_result(self.file.read_vectored_at(bufs, self.position))?;
-        self.position = self.position.wrapping_add(n);
-        Ok((n, end))
+        let len = bufs.iter().map(|b| b.len()).sum();
+        let (data, end) = self.read_blocking(len).await?;
+        scatter(&data, bufs);
+        Ok((data.len() as u64, end))
     }
-    fn is_read_vectored(&self) -> bool {
-        use system_interface::fs::FileIoExt;
-        self.file.is_read_vectored_at()
+    #[cfg(unix)]
+    fn file_source(&self) -> Option<(rustix::fd::BorrowedFd, u64)> {
+        self.check_perms().ok()?;
+        let fd = self.file.host_fd()?;
+        Some((fd, self.position))
+    }
+    /// Skipping just moves the position forward, up to the end of the file.
+    async fn skip(&mut self, nelem: u64) -> anyhow::Result<(u64, bool)> {
+        self.check_perms()?;
+        let file = Arc::clone(&self.file);
+        let position = self.position;
+        let result = spawn_blocking(move || -> FsResult<_> {
+            Ok(file.stat()?.size.saturating_sub(position).min(nelem))
+        })
+        .await;
+        if let Some(audit) = &self.audit {
+            audit.record(FsOp::Read, &result, |n| *n);
+        }
+        let n = result?;
+        self.position = self.position.wrapping_add(n);
+        Ok((n, n < nelem))
     }
+    /// The rest of the file can be read without waiting on anything.
     async fn num_ready_bytes(&self) -> anyhow::Result<u64> {
-        // FIXME we ought to be able to do better than this
-        Ok(0)
+        self.check_perms()?;
+        let file = Arc::clone(&self.file);
+        let size = spawn_blocking(move || file.stat()).await?.size;
+        Ok(size.saturating_sub(self.position))
     }
+    /// Reads from a file never wait, even at its end, so a file is always
+    /// readable if its perms allow it.
     async fn readable(&self) -> anyhow::Result<()> {
-        // FIXME is this the spot to perform the permission check?
+        self.check_perms()?;
         Ok(())
     }
 }
 
-pub(crate) fn read_result(r: Result<usize, std::io::Error>) -> Result<(u64, bool), std::io::Error> {
-    match r {
-        Ok(0) => Ok((0, true)),
-        Ok(n) => Ok((n as u64, false)),
-        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => Ok((0, false)),
-        Err(e) => Err(e),
-    }
-}
+/// The most zeroes `FileOutputStream::write_zeroes` writes at a time.
+const ZEROES_CHUNK_SIZE: u64 = 64 * 1024;
 
 pub(crate) struct FileOutputStream {
-    file: Arc<cap_std::fs::File>,
+    file: Arc<dyn WasiFile>,
+    perms: FilePerms,
+    quota: Arc<FsQuota>,
+    audit: Option<StreamAudit>,
     position: u64,
 }
 impl FileOutputStream {
-    pub fn new(file: Arc<cap_std::fs::File>, position: u64) -> Self {
-        Self { file, position }
+    pub fn new(
+        file: Arc<dyn WasiFile>,
+        perms: FilePerms,
+        quota: Arc<FsQuota>,
+        audit: Option<StreamAudit>,
+        position: u64,
+    ) -> Self {
+        Self {
+            file,
+            perms,
+            quota,
+            audit,
+            position,
+        }
+    }
+
+    fn check_perms(&self) -> std::io::Result<()> {
+        if self.perms.contains(FilePerms::WRITE) {
+            Ok(())
+        } else {
+            Err(not_permitted())
+        }
+    }
+
+    /// Write `buf` at the current position, on the blocking thread pool.
+    async fn write_blocking(&mut self, buf: Vec<u8>) -> anyhow::Result<u64> {
+        self.check_perms()?;
+        let file = Arc::clone(&self.file);
+        let quota = Arc::clone(&self.quota);
+        let position = self.position;
+        let result = spawn_blocking(move || {
+            quota.write(
+                buf.len() as u64,
+                || Ok((position, file.stat()?.size)),
+                || file.write_at(&buf, position),
+            )
+        })
+        .await;
+        if let Some(audit) = &self.audit {
+            audit.record(FsOp::Write, &result, |n| *n);
+        }
+        let n = result?;
+        self.position = self.position.wrapping_add(n);
+        Ok(n)
     }
 }
 
@@ -168,54 +594,132 @@ impl OutputStream for FileOutputStream {
     /// that it can be polled with a host poll.
     #[cfg(unix)]
     fn pollable_write(&self) -> Option<rustix::fd::BorrowedFd> {
-        use cap_std::io_lifetimes::AsFd;
-        Some(self.file.as_fd())
+        self.file.pollable()
     }
 
     /// If this stream is writing from a host file descriptor, return it so
     /// that it can be polled with a host poll.
     #[cfg(windows)]
     fn pollable_write(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
-        use io_extras::os::windows::AsHandleOrSocket;
-        Some(self.file.as_handle_or_socket())
+        self.file.pollable()
     }
 
     /// Write bytes. On success, returns the number of bytes written.
     async fn write(&mut self, buf: &[u8]) -> anyhow::Result<u64> {
-        use system_interface::fs::FileIoExt;
-        let n = self.file.write_at(buf, self.position)? as i64 as u64;
-        self.position = self.position.wrapping_add(n);
-        Ok(n)
+        self.write_blocking(buf.to_vec()).await
     }
 
     /// Vectored-I/O form of `write`.
     async fn write_vectored<'a>(&mut self, bufs: &[std::io::IoSlice<'a>]) -> anyhow::Result<u64> {
-        use system_interface::fs::FileIoExt;
-        let n = self.file.write_vectored_at(bufs, self.position)? as i64 as u64;
-        self.position = self.position.wrapping_add(n);
-        Ok(n)
+        self.write_blocking(gather(bufs)).await
     }
 
-    /// Test whether vectored I/O writes are known to be optimized in the
-    /// underlying implementation.
-    fn is_write_vectored(&self) -> bool {
-        use system_interface::fs::FileIoExt;
-        self.file.is_write_vectored_at()
+    /// Zeroes that overwrite existing bytes are written, and the rest are
+    /// added by extending the file, which doesn't write them at all. The file
+    /// is only ever extended, never shortened.
+    async fn write_zeroes(&mut self, nelem: u64) -> anyhow::Result<u64> {
+        self.check_perms()?;
+        let file = Arc::clone(&self.file);
+        let size = spawn_blocking(move || file.stat()).await?.size;
+
+        let overlap = size.saturating_sub(self.position).min(nelem);
+        let mut nwritten = 0;
+        while nwritten < overlap {
+            let len = (overlap - nwritten).min(ZEROES_CHUNK_SIZE) as usize;
+            let n = self.write_blocking(vec![0; len]).await?;
+            if n == 0 {
+                return Ok(nwritten);
+            }
+            nwritten += n;
+        }
+
+        let rest = nelem - nwritten;
+        if rest == 0 {
+            return Ok(nwritten);
+        }
+        let file = Arc::clone(&self.file);
+        let quota = Arc::clone(&self.quota);
+        let position = self.position;
+        let result = spawn_blocking(move || {
+            quota.write(
+                rest,
+                || Ok((position, file.stat()?.size)),
+                || {
+                    let end = position.checked_add(rest).ok_or(ErrorCode::Overflow)?;
+                    // Another stream may have grown the file since it was
+                    // measured, and shrinking it would lose what it wrote.
+                    if file.stat()?.size < end {
+                        file.set_len(end)?;
+                    }
+                    Ok(rest)
+                },
+            )
+        })
+        .await;
+        if let Some(audit) = &self.audit {
+            audit.record(FsOp::Write, &result, |n| *n);
+        }
+        let n = result?;
+        self.position = self.position.wrapping_add(n);
+        Ok(nwritten + n)
     }
 
     /// Test whether this stream is writeable.
     async fn writable(&self) -> anyhow::Result<()> {
-        // FIXME perm check?
+        self.check_perms()?;
         Ok(())
     }
 }
 
 pub(crate) struct FileAppendStream {
-    file: Arc<cap_std::fs::File>,
+    file: Arc<dyn WasiFile>,
+    perms: FilePerms,
+    quota: Arc<FsQuota>,
+    audit: Option<StreamAudit>,
 }
 impl FileAppendStream {
-    pub fn new(file: Arc<cap_std::fs::File>) -> Self {
-        Self { file }
+    pub fn new(
+        file: Arc<dyn WasiFile>,
+        perms: FilePerms,
+        quota: Arc<FsQuota>,
+        audit: Option<StreamAudit>,
+    ) -> Self {
+        Self {
+            file,
+            perms,
+            quota,
+            audit,
+        }
+    }
+
+    fn check_perms(&self) -> std::io::Result<()> {
+        if self.perms.contains(FilePerms::WRITE) {
+            Ok(())
+        } else {
+            Err(not_permitted())
+        }
+    }
+
+    /// Write `buf` at the end of the file, on the blocking thread pool.
+    async fn append_blocking(&mut self, buf: Vec<u8>) -> anyhow::Result<u64> {
+        self.check_perms()?;
+        let file = Arc::clone(&self.file);
+        let quota = Arc::clone(&self.quota);
+        let result = spawn_blocking(move || {
+            quota.write(
+                buf.len() as u64,
+                || {
+                    let size = file.stat()?.size;
+                    Ok((size, size))
+                },
+                || file.append(&buf),
+            )
+        })
+        .await;
+        if let Some(audit) = &self.audit {
+            audit.record(FsOp::Write, &result, |n| *n);
+        }
+        Ok(result?)
     }
 }
 
@@ -229,41 +733,120 @@ impl OutputStream for FileAppendStream {
     /// that it can be polled with a host poll.
     #[cfg(unix)]
     fn pollable_write(&self) -> Option<rustix::fd::BorrowedFd> {
-        use cap_std::io_lifetimes::AsFd;
-        Some(self.file.as_fd())
+        self.file.pollable()
     }
 
     /// If this stream is writing from a host file descriptor, return it so
     /// that it can be polled with a host poll.
     #[cfg(windows)]
     fn pollable_write(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
-        use io_extras::os::windows::AsHandleOrSocket;
-        Some(self.file.as_handle_or_socket())
+        self.file.pollable()
     }
 
     /// Write bytes. On success, returns the number of bytes written.
     async fn write(&mut self, buf: &[u8]) -> anyhow::Result<u64> {
-        use system_interface::fs::FileIoExt;
-        Ok(self.file.append(buf)? as i64 as u64)
+        self.append_blocking(buf.to_vec()).await
     }
 
     /// Vectored-I/O form of `write`.
     async fn write_vectored<'a>(&mut self, bufs: &[std::io::IoSlice<'a>]) -> anyhow::Result<u64> {
-        use system_interface::fs::FileIoExt;
-        let n = self.file.append_vectored(bufs)? as i64 as u64;
-        Ok(n)
-    }
-
-    /// Test whether vectored I/O writes are known to be optimized in the
-    /// underlying implementation.
-    fn is_write_vectored(&self) -> bool {
-        use system_interface::fs::FileIoExt;
-        self.file.is_write_vectored_at()
+        self.append_blocking(gather(bufs)).await
     }
 
     /// Test whether this stream is writeable.
     async fn writable(&self) -> anyhow::Result<()> {
-        // FIXME perm check?
+        self.check_perms()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blocking_work_leaves_the_executor() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let executor = std::thread::current().id();
+        let worker = rt.block_on(spawn_blocking(|| std::thread::current().id()));
+        assert_ne!(worker, executor);
+    }
+
+    #[test]
+    fn file_streams_are_always_ready() {
+        let fs = mem::MemFs::new();
+        fs.write("bar.txt", "0123456789").unwrap();
+        let file = match fs
+            .root()
+            .open_at("bar.txt", false, OpenFlags::empty(), DescriptorFlags::READ)
+            .unwrap()
+        {
+            Opened::File(file) => Arc::from(file),
+            Opened::Dir(_) => unreachable!(),
+        };
+        let mut stream = FileInputStream::new(file, FilePerms::READ, None, 4);
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert_eq!(stream.num_ready_bytes().await.unwrap(), 6);
+            assert_eq!(stream.read(&mut [0; 10]).await.unwrap(), (6, false));
+            assert_eq!(stream.num_ready_bytes().await.unwrap(), 0);
+
+            // Polling at the end of the file doesn't wait or fail.
+            let mut poll = crate::sched::Poll::new();
+            poll.subscribe_read(&stream, 0u64.into());
+            crate::sched::sync::poll_oneoff(&mut poll).await.unwrap();
+            assert_eq!(poll.results().count(), 1);
+        });
+    }
+
+    #[test]
+    fn skip_and_write_zeroes_move_the_position() {
+        let fs = mem::MemFs::new();
+        fs.write("bar.txt", "0123456789").unwrap();
+        let file: Arc<dyn WasiFile> = match fs
+            .root()
+            .open_at(
+                "bar.txt",
+                false,
+                OpenFlags::empty(),
+                DescriptorFlags::READ | DescriptorFlags::WRITE,
+            )
+            .unwrap()
+        {
+            Opened::File(file) => Arc::from(file),
+            Opened::Dir(_) => unreachable!(),
+        };
+        let mut input = FileInputStream::new(Arc::clone(&file), FilePerms::READ, None, 0);
+        let mut output = FileOutputStream::new(
+            file,
+            FilePerms::WRITE,
+            Arc::new(FsQuota::default()),
+            None,
+            8,
+        );
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert_eq!(input.skip(4).await.unwrap(), (4, false));
+            let mut buf = [0; 2];
+            assert_eq!(input.read(&mut buf).await.unwrap(), (2, false));
+            assert_eq!(&buf, b"45");
+            assert_eq!(input.skip(10).await.unwrap(), (4, true));
+
+            // Two zeroes overwrite "89" and the other four extend the file.
+            assert_eq!(output.write_zeroes(6).await.unwrap(), 6);
+            assert_eq!(output.write(b"!").await.unwrap(), 1);
+        });
+        assert_eq!(
+            fs.read("bar.txt").unwrap(),
+            b"01234567\0\0\0\0\0\0!".to_vec()
+        );
+    }
+}
@@ -0,0 +1,976 @@
+//! Read-only preopens backed by tar and zip archives.
+//!
+//! An [`Archive`] reads the index of a tar or zip file when it's loaded, and
+//! reads file contents from the archive as the guest reads them, without
+//! extracting anything to disk. Compressed zip entries are decompressed into
+//! memory when they're opened, once for all of their open descriptors, and
+//! only up to a size limit. Preopen an archive with
+//! `WasiCtxBuilder::push_preopened_archive`.
+//!
+//! Every change to an archive fails with `read-only`. Symlinks in the archive
+//! are followed like those of a [`MemFs`](super::mem::MemFs): they can't leave
+//! the directory they're resolved from, and absolute targets can't be
+//! followed.
+
+use super::mem::{split_path, MAX_SYMLINKS, NEXT_DEVICE};
+use super::{FsResult, Opened, ReaddirIter, WasiDir, WasiFile};
+use crate::wasi::filesystem::{
+    DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry, ErrorCode, Modes,
+    NewTimestamp, OpenFlags,
+};
+use crate::wasi::wall_clock::Datetime;
+use anyhow::{anyhow, bail};
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use zip::ZipArchive;
+
+/// The default for [`Archive::with_max_inflated_size`].
+pub const DEFAULT_MAX_INFLATED_SIZE: u64 = 1 << 30;
+
+/// A tar or zip archive, to be preopened as a read-only directory.
+///
+/// Clones of an `Archive` share the same index and contents.
+#[derive(Clone)]
+pub struct Archive {
+    shared: Arc<Shared>,
+    root: Arc<Node>,
+}
+
+impl Archive {
+    /// Load the archive at `path` on the host. The file stays open, and
+    /// contents are read from it as needed.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)?;
+        Self::load(Source::File(file)).map_err(|e| e.context(format!("loading {path:?}")))
+    }
+
+    /// Load an archive from memory.
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> anyhow::Result<Self> {
+        Self::load(Source::Bytes(bytes.into()))
+    }
+
+    /// Fail to open compressed files that decompress to more than
+    /// `max_inflated_size` bytes, with `insufficient-memory`. Clones of the
+    /// archive share the limit.
+    pub fn with_max_inflated_size(self, max_inflated_size: u64) -> Self {
+        self.shared
+            .max_inflated_size
+            .store(max_inflated_size, Ordering::Relaxed);
+        self
+    }
+
+    /// The root directory of the archive, to use as a preopen.
+    pub fn root(&self) -> ArchiveDir {
+        ArchiveDir {
+            shared: Arc::clone(&self.shared),
+            node: Arc::clone(&self.root),
+        }
+    }
+
+    fn load(source: Source) -> anyhow::Result<Self> {
+        let source = Arc::new(source);
+        let mut magic = [0; 262];
+        let n = read_full(&source, &mut magic, 0)?;
+        let magic = &magic[..n];
+
+        let mut tree = Tree::default();
+        let zip = if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            let mut zip = ZipArchive::new(Reader::new(&source))?;
+            read_zip(&mut zip, &mut tree)?;
+            Some(Mutex::new(zip))
+        } else if magic.get(257..262) == Some(&b"ustar"[..]) {
+            read_tar(&source, &mut tree)?;
+            None
+        } else {
+            bail!("not a tar or zip archive");
+        };
+
+        let shared = Arc::new(Shared {
+            device: NEXT_DEVICE.fetch_add(1, Ordering::Relaxed),
+            source,
+            zip,
+            max_inflated_size: AtomicU64::new(DEFAULT_MAX_INFLATED_SIZE),
+        });
+        Ok(Archive {
+            shared,
+            root: tree.freeze(),
+        })
+    }
+}
+
+struct Shared {
+    device: u64,
+    source: Arc<Source>,
+    /// The zip index, for decompressing entries.
+    zip: Option<Mutex<ZipArchive<Reader>>>,
+    /// The most bytes a compressed file may decompress to.
+    max_inflated_size: AtomicU64,
+}
+
+impl Shared {
+    /// Decompress the zip entry at `index`, which should hold `len` bytes.
+    fn inflate(&self, index: usize, len: u64) -> FsResult<Vec<u8>> {
+        if len > self.max_inflated_size.load(Ordering::Relaxed) {
+            return Err(ErrorCode::InsufficientMemory.into());
+        }
+        let mut contents = Vec::new();
+        usize::try_from(len)
+            .ok()
+            .and_then(|len| contents.try_reserve_exact(len).ok())
+            .ok_or(ErrorCode::InsufficientMemory)?;
+
+        let mut zip = self.zip.as_ref().unwrap().lock().unwrap();
+        let file = zip.by_index(index).map_err(|_| ErrorCode::Io)?;
+        // Read a byte more than the declared size, to catch entries that
+        // decompress to more than they say.
+        file.take(len.saturating_add(1))
+            .read_to_end(&mut contents)?;
+        if contents.len() as u64 != len {
+            return Err(ErrorCode::Io.into());
+        }
+        Ok(contents)
+    }
+}
+
+enum Source {
+    File(std::fs::File),
+    Bytes(Vec<u8>),
+}
+
+impl Source {
+    fn len(&self) -> io::Result<u64> {
+        match self {
+            Source::File(file) => Ok(file.metadata()?.len()),
+            Source::Bytes(bytes) => Ok(bytes.len() as u64),
+        }
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        match self {
+            Source::File(file) => system_interface::fs::FileIoExt::read_at(file, buf, offset),
+            Source::Bytes(bytes) => {
+                let start = usize::try_from(offset).map_or(bytes.len(), |o| o.min(bytes.len()));
+                let n = buf.len().min(bytes.len() - start);
+                buf[..n].copy_from_slice(&bytes[start..start + n]);
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// Read into `buf` until it's full or the end of `source`.
+fn read_full(source: &Source, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match source.read_at(&mut buf[n..], offset + n as u64)? {
+            0 => break,
+            read => n += read,
+        }
+    }
+    Ok(n)
+}
+
+/// A `Read` and `Seek` cursor over a [`Source`], for the archive readers.
+struct Reader {
+    source: Arc<Source>,
+    pos: u64,
+}
+
+impl Reader {
+    fn new(source: &Arc<Source>) -> Self {
+        Reader {
+            source: Arc::clone(source),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.source.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for Reader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.source.len()?.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+fn read_tar(source: &Arc<Source>, tree: &mut Tree) -> anyhow::Result<()> {
+    let mut tar = tar::Archive::new(Reader::new(source));
+    for entry in tar.entries_with_seek()? {
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mtime = Datetime {
+            seconds: entry.header().mtime().unwrap_or(0),
+            nanoseconds: 0,
+        };
+        let link_name = || -> anyhow::Result<String> {
+            let target = entry
+                .link_name()?
+                .ok_or_else(|| anyhow!("archive entry {path:?} has no link target"))?;
+            Ok(utf8(&target)?.to_owned())
+        };
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => tree.add_dir(&path, mtime)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let contents = Contents::Stored {
+                    offset: entry.raw_file_position(),
+                    len: entry.size(),
+                };
+                tree.add_leaf(&path, Leaf::File(contents), mtime)?
+            }
+            tar::EntryType::Symlink => tree.add_leaf(&path, Leaf::Symlink(link_name()?), mtime)?,
+            tar::EntryType::Link => tree.add_hard_link(&path, Path::new(&link_name()?))?,
+            // Devices, fifos, and sparse files are left out.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_zip(zip: &mut ZipArchive<Reader>, tree: &mut Tree) -> anyhow::Result<()> {
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index)?;
+        let path = Path::new(file.name()).to_owned();
+        let mtime = zip_datetime(file.last_modified());
+        if file.is_dir() {
+            tree.add_dir(&path, mtime)?;
+        } else if file.unix_mode().map(|mode| mode & S_IFMT) == Some(S_IFLNK) {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            tree.add_leaf(&path, Leaf::Symlink(target), mtime)?;
+        } else {
+            let contents = match file.compression() {
+                zip::CompressionMethod::Stored => Contents::Stored {
+                    offset: file.data_start(),
+                    len: file.size(),
+                },
+                _ => Contents::Compressed {
+                    index,
+                    len: file.size(),
+                    inflated: Mutex::new(Weak::new()),
+                },
+            };
+            tree.add_leaf(&path, Leaf::File(contents), mtime)?;
+        }
+    }
+    Ok(())
+}
+
+/// Zip timestamps are in local time, which we take to be UTC.
+fn zip_datetime(t: zip::DateTime) -> Datetime {
+    // Days since the epoch of a date in the proleptic Gregorian calendar.
+    let (year, month, day) = (
+        i64::from(t.year()),
+        i64::from(t.month()),
+        i64::from(t.day()),
+    );
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds = days * 86400
+        + i64::from(t.hour()) * 3600
+        + i64::from(t.minute()) * 60
+        + i64::from(t.second());
+    Datetime {
+        seconds: seconds.try_into().unwrap_or(0),
+        nanoseconds: 0,
+    }
+}
+
+fn utf8(path: &Path) -> anyhow::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("archive entry {path:?} is not UTF-8"))
+}
+
+/// The names along an archive entry's path.
+fn components(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut components = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(utf8(Path::new(name))?.to_owned()),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                bail!("archive entry {path:?} is outside the archive")
+            }
+        }
+    }
+    Ok(components)
+}
+
+/// Where the contents of a file are in the archive.
+enum Contents {
+    /// Uncompressed, at `offset` in the archive.
+    Stored { offset: u64, len: u64 },
+    /// The compressed zip entry at `index`, and its contents while it's
+    /// open.
+    Compressed {
+        index: usize,
+        len: u64,
+        inflated: Mutex<Weak<Vec<u8>>>,
+    },
+}
+
+impl Contents {
+    fn len(&self) -> u64 {
+        match self {
+            Contents::Stored { len, .. } | Contents::Compressed { len, .. } => *len,
+        }
+    }
+}
+
+enum Leaf {
+    File(Contents),
+    Symlink(String),
+}
+
+/// The tree of an archive as it's being read. Files and symlinks are kept
+/// apart from the directories so that hard links can share them.
+#[derive(Default)]
+struct Tree {
+    root: TreeDir,
+    leaves: Vec<(Leaf, Datetime, u64)>,
+}
+
+struct TreeDir {
+    mtime: Datetime,
+    entries: BTreeMap<String, TreeEntry>,
+}
+
+impl Default for TreeDir {
+    fn default() -> Self {
+        TreeDir {
+            mtime: Datetime {
+                seconds: 0,
+                nanoseconds: 0,
+            },
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+enum TreeEntry {
+    Dir(TreeDir),
+    /// An index into `Tree::leaves`.
+    Leaf(usize),
+}
+
+impl Tree {
+    /// The directory at `components`, creating it and its parents if they
+    /// aren't in the archive.
+    fn dir(&mut self, components: &[String], path: &Path) -> anyhow::Result<&mut TreeDir> {
+        let mut dir = &mut self.root;
+        for name in components {
+            let entry = dir
+                .entries
+                .entry(name.clone())
+                .or_insert_with(|| TreeEntry::Dir(TreeDir::default()));
+            dir = match entry {
+                TreeEntry::Dir(dir) => dir,
+                TreeEntry::Leaf(_) => bail!("archive entry {path:?} is inside a file"),
+            };
+        }
+        Ok(dir)
+    }
+
+    fn add_dir(&mut self, path: &Path, mtime: Datetime) -> anyhow::Result<()> {
+        self.dir(&components(path)?, path)?.mtime = mtime;
+        Ok(())
+    }
+
+    fn add_leaf(&mut self, path: &Path, leaf: Leaf, mtime: Datetime) -> anyhow::Result<()> {
+        self.leaves.push((leaf, mtime, 1));
+        let index = self.leaves.len() - 1;
+        self.insert(path, index)
+    }
+
+    fn add_hard_link(&mut self, path: &Path, target: &Path) -> anyhow::Result<()> {
+        let components = components(target)?;
+        let (name, parent) = components
+            .split_last()
+            .ok_or_else(|| anyhow!("archive entry {path:?} links to a directory"))?;
+        let index = match self.dir(parent, target)?.entries.get(name) {
+            Some(TreeEntry::Leaf(index)) => *index,
+            Some(TreeEntry::Dir(_)) => bail!("archive entry {path:?} links to a directory"),
+            None => bail!("archive entry {path:?} links to {target:?}, which comes later"),
+        };
+        self.leaves[index].2 += 1;
+        self.insert(path, index)
+    }
+
+    /// Put a leaf at `path`. As when extracting, later entries replace
+    /// earlier ones.
+    fn insert(&mut self, path: &Path, index: usize) -> anyhow::Result<()> {
+        let components = components(path)?;
+        let (name, parent) = components
+            .split_last()
+            .ok_or_else(|| anyhow!("archive entry {path:?} has no name"))?;
+        let parent = self.dir(parent, path)?;
+        if let Some(TreeEntry::Dir(_)) = parent.entries.get(name) {
+            bail!("archive entry {path:?} replaces a directory");
+        }
+        parent.entries.insert(name.clone(), TreeEntry::Leaf(index));
+        Ok(())
+    }
+
+    fn freeze(self) -> Arc<Node> {
+        let mut inode = 0;
+        let mut next_inode = || {
+            inode += 1;
+            inode
+        };
+        let leaves = self
+            .leaves
+            .into_iter()
+            .map(|(leaf, mtime, nlink)| {
+                let kind = match leaf {
+                    Leaf::File(contents) => Kind::File(contents),
+                    Leaf::Symlink(target) => Kind::Symlink(target),
+                };
+                Arc::new(Node {
+                    inode: next_inode(),
+                    kind,
+                    mtime,
+                    nlink,
+                })
+            })
+            .collect::<Vec<_>>();
+        freeze_dir(self.root, &leaves, &mut next_inode)
+    }
+}
+
+fn freeze_dir(
+    dir: TreeDir,
+    leaves: &[Arc<Node>],
+    next_inode: &mut impl FnMut() -> u64,
+) -> Arc<Node> {
+    let inode = next_inode();
+    let entries = dir
+        .entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let node = match entry {
+                TreeEntry::Dir(dir) => freeze_dir(dir, leaves, next_inode),
+                TreeEntry::Leaf(index) => Arc::clone(&leaves[index]),
+            };
+            (name, node)
+        })
+        .collect();
+    Arc::new(Node {
+        inode,
+        kind: Kind::Dir(entries),
+        mtime: dir.mtime,
+        nlink: 1,
+    })
+}
+
+struct Node {
+    inode: u64,
+    kind: Kind,
+    mtime: Datetime,
+    nlink: u64,
+}
+
+enum Kind {
+    File(Contents),
+    Dir(BTreeMap<String, Arc<Node>>),
+    Symlink(String),
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, Kind::Dir(_))
+    }
+
+    fn entries(&self) -> FsResult<&BTreeMap<String, Arc<Node>>> {
+        match &self.kind {
+            Kind::Dir(entries) => Ok(entries),
+            _ => Err(ErrorCode::NotDirectory.into()),
+        }
+    }
+
+    fn descriptor_type(&self) -> DescriptorType {
+        match self.kind {
+            Kind::File(_) => DescriptorType::RegularFile,
+            Kind::Dir(_) => DescriptorType::Directory,
+            Kind::Symlink(_) => DescriptorType::SymbolicLink,
+        }
+    }
+
+    fn stat(&self, device: u64) -> DescriptorStat {
+        let size = match &self.kind {
+            Kind::File(contents) => contents.len(),
+            Kind::Dir(entries) => entries.len() as u64,
+            Kind::Symlink(target) => target.len() as u64,
+        };
+        DescriptorStat {
+            device,
+            inode: self.inode,
+            type_: self.descriptor_type(),
+            link_count: self.nlink,
+            size,
+            data_access_timestamp: self.mtime,
+            data_modification_timestamp: self.mtime,
+            status_change_timestamp: self.mtime,
+        }
+    }
+}
+
+/// Resolve `path` relative to the directory `base`, following a symlink in
+/// the last component only if `follow` is set.
+fn resolve(base: &Arc<Node>, path: &str, follow: bool) -> FsResult<Arc<Node>> {
+    if path.is_empty() {
+        return Err(ErrorCode::NoEntry.into());
+    }
+    if path.starts_with('/') {
+        return Err(ErrorCode::NotPermitted.into());
+    }
+    let trailing_slash = path.ends_with('/');
+
+    let mut components: VecDeque<String> = split_path(path).collect();
+    let mut stack = vec![Arc::clone(base)];
+    let mut symlinks = 0;
+
+    while let Some(name) = components.pop_front() {
+        if name == ".." {
+            if stack.len() == 1 {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            stack.pop();
+            continue;
+        }
+
+        let child = stack
+            .last()
+            .unwrap()
+            .entries()?
+            .get(&name)
+            .cloned()
+            .ok_or(ErrorCode::NoEntry)?;
+        let last = components.is_empty();
+
+        if let Kind::Symlink(target) = &child.kind {
+            if !last || follow || trailing_slash {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(ErrorCode::Loop.into());
+                }
+                if target.starts_with('/') {
+                    return Err(ErrorCode::NotPermitted.into());
+                }
+                for component in split_path(target).rev() {
+                    components.push_front(component);
+                }
+                continue;
+            }
+        }
+
+        if !child.is_dir() {
+            if !last || trailing_slash {
+                return Err(ErrorCode::NotDirectory.into());
+            }
+            return Ok(child);
+        }
+        stack.push(child);
+    }
+
+    Ok(stack.pop().unwrap())
+}
+
+/// A directory in an [`Archive`].
+pub struct ArchiveDir {
+    shared: Arc<Shared>,
+    node: Arc<Node>,
+}
+
+impl WasiDir for ArchiveDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(self.node.stat(self.shared.device))
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn set_times(&self, _atim: NewTimestamp, _mtim: NewTimestamp) -> FsResult<()> {
+        read_only()
+    }
+
+    fn open_at(
+        &self,
+        path: &str,
+        follow: bool,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Opened> {
+        if oflags.intersects(OpenFlags::CREATE | OpenFlags::TRUNCATE)
+            || flags.intersects(DescriptorFlags::WRITE | DescriptorFlags::MUTATE_DIRECTORY)
+        {
+            return read_only();
+        }
+        let node = resolve(&self.node, path, follow)?;
+        let inflated = match &node.kind {
+            Kind::Dir(_) => {
+                return Ok(Opened::Dir(Box::new(ArchiveDir {
+                    shared: Arc::clone(&self.shared),
+                    node,
+                })))
+            }
+            // A symlink we weren't asked to follow.
+            Kind::Symlink(_) => return Err(ErrorCode::Loop.into()),
+            _ if oflags.contains(OpenFlags::DIRECTORY) => {
+                return Err(ErrorCode::NotDirectory.into())
+            }
+            Kind::File(Contents::Stored { .. }) => None,
+            Kind::File(Contents::Compressed {
+                index,
+                len,
+                inflated,
+            }) => {
+                // Descriptors open at the same time share the contents.
+                let mut inflated = inflated.lock().unwrap();
+                match inflated.upgrade() {
+                    Some(contents) => Some(contents),
+                    None => {
+                        let contents = Arc::new(self.shared.inflate(*index, *len)?);
+                        *inflated = Arc::downgrade(&contents);
+                        Some(contents)
+                    }
+                }
+            }
+        };
+        Ok(Opened::File(Box::new(ArchiveFile {
+            shared: Arc::clone(&self.shared),
+            node,
+            inflated,
+        })))
+    }
+
+    fn create_dir_at(&self, _path: &str) -> FsResult<()> {
+        read_only()
+    }
+
+    fn read_dir(&self) -> FsResult<ReaddirIter> {
+        let entries = self
+            .node
+            .entries()?
+            .iter()
+            .map(|(name, node)| {
+                Ok(DirectoryEntry {
+                    inode: Some(node.inode),
+                    type_: node.descriptor_type(),
+                    name: name.clone(),
+                })
+            })
+            .collect::<Vec<FsResult<_>>>();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn stat_at(&self, path: &str, follow: bool) -> FsResult<DescriptorStat> {
+        Ok(resolve(&self.node, path, follow)?.stat(self.shared.device))
+    }
+
+    fn set_times_at(
+        &self,
+        _path: &str,
+        _follow: bool,
+        _atim: NewTimestamp,
+        _mtim: NewTimestamp,
+    ) -> FsResult<()> {
+        read_only()
+    }
+
+    fn link_at(&self, _old_path: &str, _new_dir: &dyn WasiDir, _new_path: &str) -> FsResult<()> {
+        read_only()
+    }
+
+    fn readlink_at(&self, path: &str) -> FsResult<String> {
+        match &resolve(&self.node, path, false)?.kind {
+            Kind::Symlink(target) => Ok(target.clone()),
+            _ => Err(ErrorCode::Invalid.into()),
+        }
+    }
+
+    fn remove_dir_at(&self, _path: &str) -> FsResult<()> {
+        read_only()
+    }
+
+    fn rename_at(&self, _old_path: &str, _new_dir: &dyn WasiDir, _new_path: &str) -> FsResult<()> {
+        read_only()
+    }
+
+    fn symlink_at(&self, _src_path: &str, _dest_path: &str) -> FsResult<()> {
+        read_only()
+    }
+
+    fn unlink_file_at(&self, _path: &str) -> FsResult<()> {
+        read_only()
+    }
+
+    fn set_permissions_at(
+        &self,
+        _path: &str,
+        _follow: bool,
+        _modes: Modes,
+        _is_dir: bool,
+    ) -> FsResult<()> {
+        read_only()
+    }
+}
+
+fn read_only<T>() -> FsResult<T> {
+    Err(ErrorCode::ReadOnly.into())
+}
+
+/// An open file in an [`Archive`].
+struct ArchiveFile {
+    shared: Arc<Shared>,
+    node: Arc<Node>,
+    /// The contents of a compressed file.
+    inflated: Option<Arc<Vec<u8>>>,
+}
+
+impl WasiFile for ArchiveFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(self.node.stat(self.shared.device))
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn set_len(&self, _size: u64) -> FsResult<()> {
+        read_only()
+    }
+
+    fn set_times(&self, _atim: NewTimestamp, _mtim: NewTimestamp) -> FsResult<()> {
+        read_only()
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> FsResult<(u64, bool)> {
+        let contents = match &self.node.kind {
+            Kind::File(contents) => contents,
+            _ => unreachable!("only files are opened as files"),
+        };
+        if offset >= contents.len() {
+            return Ok((0, true));
+        }
+        let n = usize::try_from(contents.len() - offset).map_or(buf.len(), |n| n.min(buf.len()));
+        let buf = &mut buf[..n];
+        match (contents, &self.inflated) {
+            (_, Some(inflated)) => {
+                let start = offset as usize;
+                buf.copy_from_slice(&inflated[start..start + n]);
+            }
+            (Contents::Stored { offset: base, .. }, None) => {
+                if read_full(&self.shared.source, buf, base + offset)? < n {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+            }
+            (Contents::Compressed { .. }, None) => unreachable!("inflated when opened"),
+        }
+        Ok((n as u64, false))
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> FsResult<u64> {
+        read_only()
+    }
+
+    fn append(&self, _buf: &[u8]) -> FsResult<u64> {
+        read_only()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn code<T>(r: FsResult<T>) -> ErrorCode {
+        match r {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.downcast().unwrap(),
+        }
+    }
+
+    fn read(dir: &dyn WasiDir, path: &str) -> Vec<u8> {
+        let file = match dir
+            .open_at(path, true, OpenFlags::empty(), DescriptorFlags::READ)
+            .unwrap()
+        {
+            Opened::File(file) => file,
+            Opened::Dir(_) => panic!("{path} is a directory"),
+        };
+        let mut contents = vec![0; file.stat().unwrap().size as usize];
+        let (n, _) = file.read_at(&mut contents, 0).unwrap();
+        assert_eq!(n, contents.len() as u64);
+        contents
+    }
+
+    fn names(dir: &dyn WasiDir) -> Vec<String> {
+        dir.read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect()
+    }
+
+    #[test]
+    fn tar_archive() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_mtime(1_000_000);
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder
+            .append_data(&mut header.clone(), "assets/hello.txt", &b"hello"[..])
+            .unwrap();
+
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Symlink);
+        builder
+            .append_link(&mut header.clone(), "assets/link", "hello.txt")
+            .unwrap();
+        header.set_entry_type(tar::EntryType::Link);
+        builder
+            .append_link(&mut header, "hard.txt", "assets/hello.txt")
+            .unwrap();
+
+        let archive = Archive::from_bytes(builder.into_inner().unwrap()).unwrap();
+        let root = archive.root();
+        assert_eq!(names(&root), ["assets", "hard.txt"]);
+        assert_eq!(read(&root, "assets/hello.txt"), b"hello");
+        assert_eq!(read(&root, "assets/link"), b"hello");
+        assert_eq!(read(&root, "hard.txt"), b"hello");
+        assert_eq!(root.readlink_at("assets/link").unwrap(), "hello.txt");
+
+        let stat = root.stat_at("hard.txt", false).unwrap();
+        assert_eq!(stat.link_count, 2);
+        assert_eq!(stat.data_modification_timestamp.seconds, 1_000_000);
+        assert_eq!(
+            stat.inode,
+            root.stat_at("assets/hello.txt", false).unwrap().inode
+        );
+
+        assert!(matches!(
+            code(root.open_at("new.txt", false, OpenFlags::CREATE, DescriptorFlags::WRITE)),
+            ErrorCode::ReadOnly
+        ));
+        assert!(matches!(
+            code(root.unlink_file_at("hard.txt")),
+            ErrorCode::ReadOnly
+        ));
+        assert!(matches!(
+            code(root.stat_at("../escape", false)),
+            ErrorCode::NotPermitted
+        ));
+    }
+
+    #[test]
+    fn zip_archive() {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let stored =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let deflated =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.add_directory("empty/", stored).unwrap();
+        writer.start_file("stored.txt", stored).unwrap();
+        writer.write_all(b"stored").unwrap();
+        writer.start_file("sub/deflated.txt", deflated).unwrap();
+        writer.write_all(&b"deflated ".repeat(100)).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let archive = Archive::from_bytes(bytes).unwrap();
+        let root = archive.root();
+        assert_eq!(names(&root), ["empty", "stored.txt", "sub"]);
+        assert_eq!(read(&root, "stored.txt"), b"stored");
+        assert_eq!(read(&root, "sub/deflated.txt"), b"deflated ".repeat(100));
+        assert!(matches!(
+            root.stat_at("empty", false).unwrap().type_,
+            DescriptorType::Directory
+        ));
+    }
+
+    #[test]
+    fn inflated_contents_are_shared_and_capped() {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let deflated =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("small.txt", deflated).unwrap();
+        writer.write_all(&[b'a'; 16]).unwrap();
+        writer.start_file("big.txt", deflated).unwrap();
+        writer.write_all(&[b'b'; 17]).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let archive = Archive::from_bytes(bytes)
+            .unwrap()
+            .with_max_inflated_size(16);
+        let root = archive.root();
+        let open = |path: &str| match root
+            .open_at(path, false, OpenFlags::empty(), DescriptorFlags::READ)
+            .unwrap()
+        {
+            Opened::File(file) => file,
+            Opened::Dir(_) => panic!("{path} is a directory"),
+        };
+        let inflated = |file: &dyn WasiFile| {
+            let file = file.as_any().downcast_ref::<ArchiveFile>().unwrap();
+            Arc::clone(file.inflated.as_ref().unwrap())
+        };
+
+        let first = open("small.txt");
+        let second = open("small.txt");
+        assert!(Arc::ptr_eq(&inflated(&*first), &inflated(&*second)));
+        assert_eq!(read(&root, "small.txt"), [b'a'; 16]);
+
+        assert!(matches!(
+            code(root.open_at("big.txt", false, OpenFlags::empty(), DescriptorFlags::READ)),
+            ErrorCode::InsufficientMemory
+        ));
+    }
+
+    #[test]
+    fn not_an_archive() {
+        assert!(Archive::from_bytes(&b"just some text"[..]).is_err());
+    }
+}
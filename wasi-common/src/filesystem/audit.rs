@@ -0,0 +1,192 @@
+//! Observing the filesystem operations a guest makes.
+
+use super::{FsResult, LockOp};
+use crate::wasi::filesystem::ErrorCode;
+use std::fmt;
+use std::sync::Arc;
+
+/// Observes the filesystem operations of a guest, set with
+/// `WasiCtxBuilder::set_fs_auditor`.
+///
+/// Every operation on a descriptor is reported once it has finished, as are
+/// reads and writes through the streams of a file, each entry read from a
+/// directory, and dropping a descriptor. Operations on descriptors that don't
+/// exist aren't reported, since they have no path to report.
+pub trait FsAuditor: Send + Sync {
+    fn record(&self, event: &FsEvent);
+}
+
+impl<F> FsAuditor for F
+where
+    F: Fn(&FsEvent) + Send + Sync,
+{
+    fn record(&self, event: &FsEvent) {
+        self(event)
+    }
+}
+
+/// A filesystem operation, mostly named after the `descriptor` function it
+/// comes from. Reads and writes through streams are `Read` and `Write`, and
+/// reading an entry from a directory stream is `ReadDirectoryEntry`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsOp {
+    Advise,
+    SyncData,
+    GetFlags,
+    GetType,
+    SetSize,
+    SetTimes,
+    Read,
+    Write,
+    ReadDirectory,
+    ReadDirectoryEntry,
+    Sync,
+    CreateDirectoryAt,
+    Stat,
+    StatAt,
+    SetTimesAt,
+    LinkAt,
+    OpenAt,
+    ReadlinkAt,
+    RemoveDirectoryAt,
+    RenameAt,
+    SymlinkAt,
+    UnlinkFileAt,
+    ChangePermissionsAt,
+    Lock(LockOp),
+    ReadViaStream,
+    WriteViaStream,
+    AppendViaStream,
+    DropDescriptor,
+}
+
+/// Where a file or directory is, as the guest sees it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FsPath {
+    /// The guest path of the preopen it was opened from.
+    pub preopen: String,
+    /// Its path relative to the preopen, such as `.` or `logs/today.txt`.
+    pub path: String,
+}
+
+impl FsPath {
+    pub(crate) fn preopen(preopen: &str) -> Self {
+        FsPath {
+            preopen: preopen.to_owned(),
+            path: ".".to_owned(),
+        }
+    }
+
+    /// The path of `path` relative to this directory. This is worked out
+    /// without looking at the filesystem, so `..` after a symlink goes back
+    /// to the directory containing the symlink.
+    pub(crate) fn join(&self, path: &str) -> Self {
+        let mut components = Vec::new();
+        for component in self.path.split('/').chain(path.split('/')) {
+            match component {
+                "" | "." => {}
+                ".." if matches!(components.last(), Some(c) if *c != "..") => {
+                    components.pop();
+                }
+                component => components.push(component),
+            }
+        }
+        FsPath {
+            preopen: self.preopen.clone(),
+            path: if components.is_empty() {
+                ".".to_owned()
+            } else {
+                components.join("/")
+            },
+        }
+    }
+}
+
+impl fmt::Display for FsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.preopen.ends_with('/'), self.path.as_str()) {
+            (_, ".") => write!(f, "{}", self.preopen),
+            (true, path) => write!(f, "{}{path}", self.preopen),
+            (false, path) => write!(f, "{}/{path}", self.preopen),
+        }
+    }
+}
+
+/// A finished filesystem operation.
+#[derive(Clone, Debug)]
+pub struct FsEvent {
+    pub op: FsOp,
+    /// The file or directory operated on.
+    pub path: FsPath,
+    /// Where `link-at` and `rename-at` linked or renamed it to.
+    pub target: Option<FsPath>,
+    /// How many bytes were read or written.
+    pub bytes: Option<u64>,
+    /// The error code the operation failed with, or `Err(None)` if it
+    /// trapped.
+    pub result: Result<(), Option<ErrorCode>>,
+}
+
+impl FsEvent {
+    pub(crate) fn new<T>(op: FsOp, path: FsPath, result: &FsResult<T>) -> Self {
+        FsEvent {
+            op,
+            path,
+            target: None,
+            bytes: None,
+            result: match result {
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.downcast_ref().copied()),
+            },
+        }
+    }
+
+    pub(crate) fn target(self, target: FsPath) -> Self {
+        FsEvent {
+            target: Some(target),
+            ..self
+        }
+    }
+
+    pub(crate) fn bytes(self, bytes: u64) -> Self {
+        FsEvent {
+            bytes: Some(bytes),
+            ..self
+        }
+    }
+}
+
+/// Reports the reads or writes of a file stream.
+pub(crate) struct StreamAudit {
+    pub auditor: Arc<dyn FsAuditor>,
+    pub path: FsPath,
+}
+
+impl StreamAudit {
+    /// Report a read or write, which read or wrote the number of bytes
+    /// `bytes` gets from its result.
+    pub fn record<T>(&self, op: FsOp, result: &FsResult<T>, bytes: impl FnOnce(&T) -> u64) {
+        let mut event = FsEvent::new(op, self.path.clone(), result);
+        if let Ok(t) = result {
+            event = event.bytes(bytes(t));
+        }
+        self.auditor.record(&event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn join() {
+        let root = FsPath::preopen("/data");
+        let dir = root.join("logs/./old/");
+        assert_eq!(dir.path, "logs/old");
+        assert_eq!(dir.join("../today.txt").path, "logs/today.txt");
+        assert_eq!(dir.join("../..").path, ".");
+        assert_eq!(root.join("../../etc").path, "../../etc");
+        assert_eq!(dir.join("x").to_string(), "/data/logs/old/x");
+        assert_eq!(FsPath::preopen("/").join("x").to_string(), "/x");
+    }
+}
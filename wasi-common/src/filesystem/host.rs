@@ -0,0 +1,539 @@
+//! The host filesystem backend, implemented with `cap-std`.
+
+use super::{FsResult, Opened, ReaddirIter, WasiDir, WasiFile};
+use crate::wasi;
+use std::any::Any;
+use wasi::filesystem::{
+    Advice, DescriptorFlags, DescriptorStat, ErrorCode, Modes, NewTimestamp, OpenFlags,
+};
+
+/// A file opened on the host filesystem.
+pub struct HostFile {
+    file: cap_std::fs::File,
+    /// Whether this is a regular file, looked up once when it's opened.
+    regular: bool,
+}
+
+impl HostFile {
+    pub fn new(file: cap_std::fs::File) -> Self {
+        let regular = file.metadata().map_or(false, |meta| meta.is_file());
+        Self { file, regular }
+    }
+}
+
+impl WasiFile for HostFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    // Regular files are always ready, and a host poll says nothing useful
+    // about them, so only pipes and devices are polled.
+    #[cfg(unix)]
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd> {
+        use cap_std::io_lifetimes::AsFd;
+        if self.regular {
+            return None;
+        }
+        Some(self.file.as_fd())
+    }
+
+    #[cfg(windows)]
+    fn pollable(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
+        use io_extras::os::windows::AsHandleOrSocket;
+        if self.regular {
+            return None;
+        }
+        Some(self.file.as_handle_or_socket())
+    }
+
+    #[cfg(unix)]
+    fn host_fd(&self) -> Option<rustix::fd::BorrowedFd> {
+        use cap_std::io_lifetimes::AsFd;
+        Some(self.file.as_fd())
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(descriptorstat_from(self.file.metadata()?))
+    }
+
+    fn sync_flags(&self) -> FsResult<DescriptorFlags> {
+        Ok(get_from_fdflags(&self.file)?)
+    }
+
+    fn advise(&self, offset: u64, len: u64, advice: Advice) -> FsResult<()> {
+        use system_interface::fs::{Advice as A, FileIoExt};
+
+        let advice = match advice {
+            Advice::Normal => A::Normal,
+            Advice::Sequential => A::Sequential,
+            Advice::Random => A::Random,
+            Advice::WillNeed => A::WillNeed,
+            Advice::DontNeed => A::DontNeed,
+            Advice::NoReuse => A::NoReuse,
+        };
+        FileIoExt::advise(&self.file, offset, len, advice)?;
+        Ok(())
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        ignore_access_denied(self.file.sync_data())
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        ignore_access_denied(self.file.sync_all())
+    }
+
+    fn set_len(&self, size: u64) -> FsResult<()> {
+        self.file.set_len(size)?;
+        Ok(())
+    }
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()> {
+        fs_set_times::SetTimes::set_times(
+            &self.file,
+            systemtimespec_from(atim)?,
+            systemtimespec_from(mtim)?,
+        )?;
+        Ok(())
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> FsResult<(u64, bool)> {
+        use system_interface::fs::FileIoExt;
+        Ok(read_result(FileIoExt::read_at(&self.file, buf, offset))?)
+    }
+
+    fn read_vectored_at(
+        &self,
+        bufs: &mut [std::io::IoSliceMut<'_>],
+        offset: u64,
+    ) -> FsResult<(u64, bool)> {
+        use system_interface::fs::FileIoExt;
+        Ok(read_result(FileIoExt::read_vectored_at(
+            &self.file, bufs, offset,
+        ))?)
+    }
+
+    fn is_read_vectored_at(&self) -> bool {
+        use system_interface::fs::FileIoExt;
+        FileIoExt::is_read_vectored_at(&self.file)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> FsResult<u64> {
+        use system_interface::fs::FileIoExt;
+        Ok(FileIoExt::write_at(&self.file, buf, offset)? as u64)
+    }
+
+    fn write_vectored_at(&self, bufs: &[std::io::IoSlice<'_>], offset: u64) -> FsResult<u64> {
+        use system_interface::fs::FileIoExt;
+        Ok(FileIoExt::write_vectored_at(&self.file, bufs, offset)? as u64)
+    }
+
+    fn is_write_vectored_at(&self) -> bool {
+        use system_interface::fs::FileIoExt;
+        FileIoExt::is_write_vectored_at(&self.file)
+    }
+
+    fn append(&self, buf: &[u8]) -> FsResult<u64> {
+        use system_interface::fs::FileIoExt;
+        Ok(FileIoExt::append(&self.file, buf)? as u64)
+    }
+
+    fn append_vectored(&self, bufs: &[std::io::IoSlice<'_>]) -> FsResult<u64> {
+        use system_interface::fs::FileIoExt;
+        Ok(FileIoExt::append_vectored(&self.file, bufs)? as u64)
+    }
+
+    #[cfg(unix)]
+    fn lock(&self, op: super::LockOp) -> FsResult<()> {
+        use super::LockOp;
+        use rustix::fs::FlockOperation;
+
+        let operation = match op {
+            LockOp::Shared => FlockOperation::LockShared,
+            LockOp::Exclusive => FlockOperation::LockExclusive,
+            LockOp::TryShared => FlockOperation::NonBlockingLockShared,
+            LockOp::TryExclusive => FlockOperation::NonBlockingLockExclusive,
+            LockOp::Unlock => FlockOperation::Unlock,
+        };
+        rustix::fs::flock(&self.file, operation).map_err(std::io::Error::from)?;
+        Ok(())
+    }
+
+    // FIXME implement `lock` with `LockFileEx` and `UnlockFileEx` on windows
+}
+
+impl WasiDir for cap_std::fs::Dir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(descriptorstat_from(self.dir_metadata()?))
+    }
+
+    fn sync_flags(&self) -> FsResult<DescriptorFlags> {
+        Ok(get_from_fdflags(self)?)
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        Ok(self.open(std::path::Component::CurDir)?.sync_data()?)
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(self.open(std::path::Component::CurDir)?.sync_all()?)
+    }
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()> {
+        fs_set_times::SetTimes::set_times(
+            self,
+            systemtimespec_from(atim)?,
+            systemtimespec_from(mtim)?,
+        )?;
+        Ok(())
+    }
+
+    fn open_at(
+        &self,
+        path: &str,
+        follow: bool,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Opened> {
+        use cap_fs_ext::{FollowSymlinks, OpenOptionsFollowExt, OpenOptionsMaybeDirExt};
+        use system_interface::fs::{FdFlags, GetSetFdFlags};
+
+        let mut opts = cap_std::fs::OpenOptions::new();
+        opts.maybe_dir(true);
+
+        if oflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) {
+            opts.create_new(true);
+            opts.write(true);
+        } else if oflags.contains(OpenFlags::CREATE) {
+            opts.create(true);
+            opts.write(true);
+        }
+        if oflags.contains(OpenFlags::TRUNCATE) {
+            opts.truncate(true);
+        }
+        if flags.contains(DescriptorFlags::READ) {
+            opts.read(true);
+        }
+        if flags.contains(DescriptorFlags::WRITE) {
+            opts.write(true);
+        } else {
+            // If not opened write, open read. This way the OS lets us open
+            // the file, but we can use perms to reject use of the file later.
+            opts.read(true);
+        }
+        if follow {
+            opts.follow(FollowSymlinks::Yes);
+        } else {
+            opts.follow(FollowSymlinks::No);
+        }
+
+        let mut opened = self.open_with(path, &opts)?;
+
+        if opened.metadata()?.is_dir() {
+            Ok(Opened::Dir(Box::new(cap_std::fs::Dir::from_std_file(
+                opened.into_std(),
+            ))))
+        } else if oflags.contains(OpenFlags::DIRECTORY) {
+            Err(ErrorCode::NotDirectory.into())
+        } else {
+            // FIXME cap-std needs a nonblocking open option so that files reads and writes
+            // are nonblocking. Instead we set it after opening here:
+            let set_fd_flags = opened.new_set_fd_flags(FdFlags::NONBLOCK)?;
+            opened.set_fd_flags(set_fd_flags)?;
+
+            Ok(Opened::File(Box::new(HostFile::new(opened))))
+        }
+    }
+
+    fn create_dir_at(&self, path: &str) -> FsResult<()> {
+        self.create_dir(path)?;
+        Ok(())
+    }
+
+    fn read_dir(&self) -> FsResult<ReaddirIter> {
+        use cap_fs_ext::{DirEntryExt, MetadataExt};
+
+        enum ReaddirError {
+            Io(std::io::Error),
+            IllegalSequence,
+        }
+        impl From<std::io::Error> for ReaddirError {
+            fn from(e: std::io::Error) -> ReaddirError {
+                ReaddirError::Io(e)
+            }
+        }
+
+        let entries = self.entries()?.map(|entry| {
+            let entry = entry?;
+            let meta = entry.full_metadata()?;
+            let inode = Some(meta.ino());
+            let type_ = descriptortype_from(meta.file_type());
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| ReaddirError::IllegalSequence)?;
+            Ok(wasi::filesystem::DirectoryEntry { inode, type_, name })
+        });
+        // On windows, filter out files like `C:\DumpStack.log.tmp` which we
+        // can't get full metadata for.
+        #[cfg(windows)]
+        let entries = entries.filter(|entry| {
+            use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION};
+            if let Err(ReaddirError::Io(err)) = entry {
+                if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION as i32)
+                    || err.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32)
+                {
+                    return false;
+                }
+            }
+            true
+        });
+        let entries = entries.map(|r| match r {
+            Ok(r) => Ok(r),
+            Err(ReaddirError::Io(e)) => Err(wasi::filesystem::Error::from(e)),
+            Err(ReaddirError::IllegalSequence) => Err(ErrorCode::IllegalByteSequence.into()),
+        });
+        Ok(Box::new(entries))
+    }
+
+    fn stat_at(&self, path: &str, follow: bool) -> FsResult<DescriptorStat> {
+        let meta = if follow {
+            self.metadata(path)?
+        } else {
+            self.symlink_metadata(path)?
+        };
+        Ok(descriptorstat_from(meta))
+    }
+
+    fn set_times_at(
+        &self,
+        path: &str,
+        follow: bool,
+        atim: NewTimestamp,
+        mtim: NewTimestamp,
+    ) -> FsResult<()> {
+        use cap_fs_ext::DirExt;
+
+        let atim = systemtimespec_from(atim)?.map(cap_fs_ext::SystemTimeSpec::from_std);
+        let mtim = systemtimespec_from(mtim)?.map(cap_fs_ext::SystemTimeSpec::from_std);
+        if follow {
+            DirExt::set_times(self, path, atim, mtim)?;
+        } else {
+            DirExt::set_symlink_times(self, path, atim, mtim)?;
+        }
+        Ok(())
+    }
+
+    fn link_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()> {
+        self.hard_link(old_path, same_backend(new_dir)?, new_path)?;
+        Ok(())
+    }
+
+    fn readlink_at(&self, path: &str) -> FsResult<String> {
+        let link = self.read_link(path)?;
+        Ok(link
+            .into_os_string()
+            .into_string()
+            .map_err(|_| ErrorCode::IllegalByteSequence)?)
+    }
+
+    fn remove_dir_at(&self, path: &str) -> FsResult<()> {
+        Ok(self.remove_dir(path)?)
+    }
+
+    fn rename_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()> {
+        self.rename(old_path, same_backend(new_dir)?, new_path)?;
+        Ok(())
+    }
+
+    fn symlink_at(&self, src_path: &str, dest_path: &str) -> FsResult<()> {
+        // On windows, Dir.symlink is provided by DirExt
+        #[cfg(windows)]
+        use cap_fs_ext::DirExt;
+
+        self.symlink(src_path, dest_path)?;
+        Ok(())
+    }
+
+    fn unlink_file_at(&self, path: &str) -> FsResult<()> {
+        cap_fs_ext::DirExt::remove_file_or_symlink(self, path)?;
+        Ok(())
+    }
+
+    fn set_permissions_at(
+        &self,
+        path: &str,
+        follow: bool,
+        modes: Modes,
+        is_dir: bool,
+    ) -> FsResult<()> {
+        let meta = if follow {
+            self.metadata(path)?
+        } else {
+            self.symlink_metadata(path)?
+        };
+
+        let mut perms = meta.permissions();
+        #[cfg(unix)]
+        {
+            use cap_std::fs::PermissionsExt;
+
+            let mut bits = 0;
+            if modes.contains(Modes::READABLE) {
+                // Reading a directory implies searching it.
+                bits |= if is_dir { 0o5 } else { 0o4 };
+            }
+            if modes.contains(Modes::WRITEABLE) {
+                bits |= 0o2;
+            }
+            if modes.contains(Modes::EXECUTABLE) {
+                bits |= 0o1;
+            }
+            // The owner gets exactly `modes`. Group and other keep only the
+            // permissions they already had that `modes` still grants.
+            let mode = (bits << 6) | (perms.mode() & ((bits << 3) | bits));
+            perms.set_mode(mode);
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = is_dir;
+            perms.set_readonly(!modes.contains(Modes::WRITEABLE));
+        }
+
+        if follow {
+            self.set_permissions(path, perms)?;
+        } else {
+            cap_fs_ext::DirExt::set_symlink_permissions(self, path, perms)?;
+        }
+        Ok(())
+    }
+}
+
+/// Links and renames only work within the host filesystem.
+fn same_backend(dir: &dyn WasiDir) -> FsResult<&cap_std::fs::Dir> {
+    dir.as_any()
+        .downcast_ref::<cap_std::fs::Dir>()
+        .ok_or_else(|| ErrorCode::CrossDevice.into())
+}
+
+// On windows, `sync_data` and `sync_all` use `FileFlushBuffers` which fails
+// with `ERROR_ACCESS_DENIED` if the file is not upen for writing. Ignore this
+// error, for POSIX compatibility.
+fn ignore_access_denied(r: std::io::Result<()>) -> FsResult<()> {
+    match r {
+        Ok(()) => Ok(()),
+        #[cfg(windows)]
+        Err(e)
+            if e.raw_os_error()
+                == Some(windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED as _) =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn get_from_fdflags(f: impl cap_std::io_lifetimes::AsFilelike) -> std::io::Result<DescriptorFlags> {
+    use system_interface::fs::{FdFlags, GetSetFdFlags};
+
+    let flags = f.as_filelike().get_fd_flags()?;
+    let mut out = DescriptorFlags::empty();
+    if flags.contains(FdFlags::DSYNC) {
+        out |= DescriptorFlags::REQUESTED_WRITE_SYNC;
+    }
+    if flags.contains(FdFlags::RSYNC) {
+        out |= DescriptorFlags::DATA_INTEGRITY_SYNC;
+    }
+    if flags.contains(FdFlags::SYNC) {
+        out |= DescriptorFlags::FILE_INTEGRITY_SYNC;
+    }
+    Ok(out)
+}
+
+fn read_result(r: Result<usize, std::io::Error>) -> Result<(u64, bool), std::io::Error> {
+    match r {
+        Ok(0) => Ok((0, true)),
+        Ok(n) => Ok((n as u64, false)),
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => Ok((0, false)),
+        Err(e) => Err(e),
+    }
+}
+
+fn descriptortype_from(ft: cap_std::fs::FileType) -> wasi::filesystem::DescriptorType {
+    use cap_fs_ext::FileTypeExt;
+    use wasi::filesystem::DescriptorType;
+    if ft.is_dir() {
+        DescriptorType::Directory
+    } else if ft.is_symlink() {
+        DescriptorType::SymbolicLink
+    } else if ft.is_block_device() {
+        DescriptorType::BlockDevice
+    } else if ft.is_char_device() {
+        DescriptorType::CharacterDevice
+    } else if ft.is_file() {
+        DescriptorType::RegularFile
+    } else {
+        DescriptorType::Unknown
+    }
+}
+
+fn systemtimespec_from(t: NewTimestamp) -> FsResult<Option<fs_set_times::SystemTimeSpec>> {
+    use fs_set_times::SystemTimeSpec;
+    match t {
+        NewTimestamp::NoChange => Ok(None),
+        NewTimestamp::Now => Ok(Some(SystemTimeSpec::SymbolicNow)),
+        NewTimestamp::Timestamp(st) => Ok(Some(SystemTimeSpec::Absolute(systemtime_from(st)?))),
+    }
+}
+
+fn systemtime_from(t: wasi::wall_clock::Datetime) -> FsResult<std::time::SystemTime> {
+    use std::time::{Duration, SystemTime};
+    SystemTime::UNIX_EPOCH
+        .checked_add(Duration::new(t.seconds, t.nanoseconds))
+        .ok_or_else(|| ErrorCode::Overflow.into())
+}
+
+fn datetime_from(t: std::time::SystemTime) -> wasi::wall_clock::Datetime {
+    // FIXME make this infallible or handle errors properly
+    wasi::wall_clock::Datetime::try_from(cap_std::time::SystemTime::from_std(t)).unwrap()
+}
+
+/// The device and inode are the host's `st_dev` and `st_ino`, so hard links
+/// to a file have the same ones, and they match the inodes of the entries
+/// returned by `read_dir`.
+fn descriptorstat_from(meta: cap_std::fs::Metadata) -> DescriptorStat {
+    use cap_fs_ext::MetadataExt;
+    DescriptorStat {
+        device: meta.dev(),
+        inode: meta.ino(),
+        type_: descriptortype_from(meta.file_type()),
+        link_count: meta.nlink(),
+        size: meta.len(),
+        // FIXME change the wit to make these timestamps optional
+        data_access_timestamp: meta
+            .accessed()
+            .map(|t| datetime_from(t.into_std()))
+            .unwrap_or(wasi::wall_clock::Datetime {
+                seconds: 0,
+                nanoseconds: 0,
+            }),
+        data_modification_timestamp: meta
+            .modified()
+            .map(|t| datetime_from(t.into_std()))
+            .unwrap_or(wasi::wall_clock::Datetime {
+                seconds: 0,
+                nanoseconds: 0,
+            }),
+        status_change_timestamp: meta
+            .created()
+            .map(|t| datetime_from(t.into_std()))
+            .unwrap_or(wasi::wall_clock::Datetime {
+                seconds: 0,
+                nanoseconds: 0,
+            }),
+    }
+}
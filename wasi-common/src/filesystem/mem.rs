@@ -0,0 +1,1088 @@
+//! An in-memory filesystem.
+//!
+//! A [`MemFs`] is a tree of files, directories, and symlinks in host memory.
+//! The host builds the tree, preopens [`MemFs::root`] with
+//! `WasiCtxBuilder::push_preopened_dir`, and can inspect the tree again once
+//! the guest is done. Clones of a `MemFs` share the same tree.
+//!
+//! Paths may not leave the directory they are resolved from, and symlinks
+//! with absolute targets can't be followed. There are no file permissions,
+//! so changing them only updates the status change timestamp.
+
+use super::{FsResult, LockOp, Opened, ReaddirIter, WasiDir, WasiFile};
+use crate::wasi::filesystem::{
+    DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry, Error, ErrorCode, Modes,
+    NewTimestamp, OpenFlags,
+};
+use crate::wasi::wall_clock::Datetime;
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// How many symlinks resolving a path may follow before failing with `loop`.
+pub(super) const MAX_SYMLINKS: usize = 40;
+
+/// The default for [`MemFs::with_max_file_size`].
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1 << 30;
+
+/// Each `MemFs` and archive gets its own device number.
+pub(super) static NEXT_DEVICE: AtomicU64 = AtomicU64::new(1);
+
+/// An in-memory filesystem.
+#[derive(Clone)]
+pub struct MemFs {
+    shared: Arc<Shared>,
+    root: Arc<Node>,
+}
+
+impl MemFs {
+    /// Create a filesystem with an empty root directory.
+    pub fn new() -> Self {
+        Self::with_max_file_size(DEFAULT_MAX_FILE_SIZE)
+    }
+
+    /// Create a filesystem with an empty root directory, whose files can't
+    /// grow beyond `max_file_size` bytes.
+    pub fn with_max_file_size(max_file_size: u64) -> Self {
+        let shared = Arc::new(Shared {
+            device: NEXT_DEVICE.fetch_add(1, Ordering::Relaxed),
+            max_file_size,
+            next_inode: AtomicU64::new(1),
+            next_handle: AtomicU64::new(1),
+            tree: Mutex::new(()),
+        });
+        let root = shared.new_node(Kind::Dir(Mutex::new(BTreeMap::new())));
+        MemFs { shared, root }
+    }
+
+    /// The root directory, to use as a preopen.
+    pub fn root(&self) -> MemDir {
+        MemDir {
+            shared: Arc::clone(&self.shared),
+            node: Arc::clone(&self.root),
+        }
+    }
+
+    /// Create a directory and any of its parents that don't exist yet.
+    pub fn create_dir_all(&self, path: &str) -> anyhow::Result<()> {
+        let root = self.root();
+        let mut prefix = String::new();
+        for component in relative(path).split('/').filter(|c| !c.is_empty()) {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(component);
+            match root.stat_at(&prefix, true) {
+                Ok(stat) if matches!(stat.type_, DescriptorType::Directory) => {}
+                Ok(_) => return Err(Error::from(ErrorCode::NotDirectory).into()),
+                Err(_) => root.create_dir_at(&prefix)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a file, creating it if it doesn't exist and replacing its
+    /// contents if it does. The parent directory must exist.
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+        let file = self.open_file(
+            path,
+            OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            DescriptorFlags::WRITE,
+        )?;
+        file.write_at(contents.as_ref(), 0)?;
+        Ok(())
+    }
+
+    /// Read the contents of a file.
+    pub fn read(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let file = self.open_file(path, OpenFlags::empty(), DescriptorFlags::READ)?;
+        let mut contents = vec![0; file.stat()?.size.try_into()?];
+        let (n, _) = file.read_at(&mut contents, 0)?;
+        contents.truncate(n.try_into()?);
+        Ok(contents)
+    }
+
+    /// Create a symlink at `path` pointing to `target`.
+    pub fn symlink(&self, target: &str, path: &str) -> anyhow::Result<()> {
+        self.root().symlink_at(target, relative(path))?;
+        Ok(())
+    }
+
+    /// Read the target of a symlink.
+    pub fn read_link(&self, path: &str) -> anyhow::Result<String> {
+        Ok(self.root().readlink_at(relative(path))?)
+    }
+
+    /// The names of the entries of a directory, in order.
+    pub fn read_dir(&self, path: &str) -> anyhow::Result<Vec<String>> {
+        let dir = match self.root().open_at(
+            relative(path),
+            true,
+            OpenFlags::DIRECTORY,
+            DescriptorFlags::READ,
+        )? {
+            Opened::Dir(dir) => dir,
+            Opened::File(_) => unreachable!("opened with `directory`"),
+        };
+        let mut names = Vec::new();
+        for entry in dir.read_dir()? {
+            names.push(entry?.name);
+        }
+        Ok(names)
+    }
+
+    /// The metadata of the file or directory at `path`, following symlinks.
+    pub fn metadata(&self, path: &str) -> anyhow::Result<DescriptorStat> {
+        Ok(self.root().stat_at(relative(path), true)?)
+    }
+
+    /// Set the access and modification timestamps of the file or directory
+    /// at `path`, following symlinks.
+    pub fn set_times(&self, path: &str, atime: Datetime, mtime: Datetime) -> anyhow::Result<()> {
+        self.root().set_times_at(
+            relative(path),
+            true,
+            NewTimestamp::Timestamp(atime),
+            NewTimestamp::Timestamp(mtime),
+        )?;
+        Ok(())
+    }
+
+    fn open_file(
+        &self,
+        path: &str,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> anyhow::Result<Box<dyn WasiFile>> {
+        match self.root().open_at(relative(path), true, oflags, flags)? {
+            Opened::File(file) => Ok(file),
+            Opened::Dir(_) => Err(Error::from(ErrorCode::IsDirectory).into()),
+        }
+    }
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Host paths are relative to the root, with or without a leading `/`.
+fn relative(path: &str) -> &str {
+    match path.trim_start_matches('/') {
+        "" => ".",
+        path => path,
+    }
+}
+
+struct Shared {
+    device: u64,
+    /// The most bytes a file may hold.
+    max_file_size: u64,
+    next_inode: AtomicU64,
+    next_handle: AtomicU64,
+    /// Held while changing the tree, so that renames and removals see a
+    /// consistent view of it.
+    tree: Mutex<()>,
+}
+
+impl Shared {
+    fn new_node(&self, kind: Kind) -> Arc<Node> {
+        let now = now();
+        Arc::new(Node {
+            inode: self.next_inode.fetch_add(1, Ordering::Relaxed),
+            kind,
+            meta: Mutex::new(Meta {
+                nlink: 1,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+        })
+    }
+
+    fn tree(&self) -> MutexGuard<()> {
+        self.tree.lock().unwrap()
+    }
+}
+
+struct Node {
+    inode: u64,
+    kind: Kind,
+    meta: Mutex<Meta>,
+}
+
+enum Kind {
+    File(FileNode),
+    Dir(Mutex<BTreeMap<String, Arc<Node>>>),
+    Symlink(String),
+}
+
+#[derive(Default)]
+struct FileNode {
+    contents: Mutex<Vec<u8>>,
+    locks: Mutex<Locks>,
+    unlocked: Condvar,
+}
+
+/// The handles holding locks on a file.
+#[derive(Default)]
+struct Locks {
+    shared: Vec<u64>,
+    exclusive: Option<u64>,
+}
+
+/// Timestamps are durations since the epoch.
+struct Meta {
+    nlink: u64,
+    atime: Duration,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, Kind::Dir(_))
+    }
+
+    fn entries(&self) -> FsResult<MutexGuard<BTreeMap<String, Arc<Node>>>> {
+        match &self.kind {
+            Kind::Dir(entries) => Ok(entries.lock().unwrap()),
+            _ => Err(ErrorCode::NotDirectory.into()),
+        }
+    }
+
+    fn meta(&self) -> MutexGuard<Meta> {
+        self.meta.lock().unwrap()
+    }
+
+    fn descriptor_type(&self) -> DescriptorType {
+        match self.kind {
+            Kind::File(_) => DescriptorType::RegularFile,
+            Kind::Dir(_) => DescriptorType::Directory,
+            Kind::Symlink(_) => DescriptorType::SymbolicLink,
+        }
+    }
+
+    fn stat(&self, device: u64) -> DescriptorStat {
+        let size = match &self.kind {
+            Kind::File(file) => file.contents.lock().unwrap().len() as u64,
+            Kind::Dir(entries) => entries.lock().unwrap().len() as u64,
+            Kind::Symlink(target) => target.len() as u64,
+        };
+        let meta = self.meta();
+        DescriptorStat {
+            device,
+            inode: self.inode,
+            type_: self.descriptor_type(),
+            link_count: meta.nlink,
+            size,
+            data_access_timestamp: datetime(meta.atime),
+            data_modification_timestamp: datetime(meta.mtime),
+            status_change_timestamp: datetime(meta.ctime),
+        }
+    }
+
+    /// Record a change to the contents of this node.
+    fn modified(&self) {
+        let now = now();
+        let mut meta = self.meta();
+        meta.mtime = now;
+        meta.ctime = now;
+    }
+
+    /// Record a change to the metadata of this node.
+    fn changed(&self) {
+        self.meta().ctime = now();
+    }
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()> {
+        let atim = timestamp(atim)?;
+        let mtim = timestamp(mtim)?;
+        let mut meta = self.meta();
+        if let Some(atim) = atim {
+            meta.atime = atim;
+        }
+        if let Some(mtim) = mtim {
+            meta.mtime = mtim;
+        }
+        meta.ctime = now();
+        Ok(())
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn datetime(t: Duration) -> Datetime {
+    Datetime {
+        seconds: t.as_secs(),
+        nanoseconds: t.subsec_nanos(),
+    }
+}
+
+fn timestamp(t: NewTimestamp) -> FsResult<Option<Duration>> {
+    match t {
+        NewTimestamp::NoChange => Ok(None),
+        NewTimestamp::Now => Ok(Some(now())),
+        NewTimestamp::Timestamp(t) => Duration::from_secs(t.seconds)
+            .checked_add(Duration::from_nanos(t.nanoseconds.into()))
+            .map(Some)
+            .ok_or_else(|| ErrorCode::Overflow.into()),
+    }
+}
+
+/// A resolved path.
+struct Resolved {
+    /// The directory containing the last component of the path.
+    parent: Arc<Node>,
+    /// The last component of the path, or `None` if the path names `parent`
+    /// itself, as `.` does.
+    name: Option<String>,
+    /// What the path names, if it exists.
+    node: Option<Arc<Node>>,
+}
+
+/// Resolve `path` relative to the directory `base`, following a symlink in
+/// the last component only if `follow` is set.
+fn resolve(base: &Arc<Node>, path: &str, follow: bool) -> FsResult<Resolved> {
+    if path.is_empty() {
+        return Err(ErrorCode::NoEntry.into());
+    }
+    if path.starts_with('/') {
+        return Err(ErrorCode::NotPermitted.into());
+    }
+    let trailing_slash = path.ends_with('/');
+
+    let mut components: VecDeque<String> = split_path(path).collect();
+    // The directories from `base` down to the one we're looking in, so that
+    // `..` can't go above `base`.
+    let mut stack = vec![Arc::clone(base)];
+    let mut symlinks = 0;
+
+    while let Some(name) = components.pop_front() {
+        if name == ".." {
+            if stack.len() == 1 {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            stack.pop();
+            continue;
+        }
+
+        let dir = Arc::clone(stack.last().unwrap());
+        let child = dir.entries()?.get(&name).cloned();
+        let last = components.is_empty();
+        let child = match child {
+            Some(child) => child,
+            None if last => {
+                return Ok(Resolved {
+                    parent: dir,
+                    name: Some(name),
+                    node: None,
+                })
+            }
+            None => return Err(ErrorCode::NoEntry.into()),
+        };
+
+        if let Kind::Symlink(target) = &child.kind {
+            if !last || follow || trailing_slash {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(ErrorCode::Loop.into());
+                }
+                if target.starts_with('/') {
+                    return Err(ErrorCode::NotPermitted.into());
+                }
+                for component in split_path(target).rev() {
+                    components.push_front(component);
+                }
+                continue;
+            }
+        }
+
+        if last {
+            if trailing_slash && !child.is_dir() {
+                return Err(ErrorCode::NotDirectory.into());
+            }
+            return Ok(Resolved {
+                parent: dir,
+                name: Some(name),
+                node: Some(child),
+            });
+        }
+        if !child.is_dir() {
+            return Err(ErrorCode::NotDirectory.into());
+        }
+        stack.push(child);
+    }
+
+    // The path ended with `.` or `..`, or a symlink to one of them.
+    let dir = stack.pop().unwrap();
+    Ok(Resolved {
+        parent: Arc::clone(&dir),
+        name: None,
+        node: Some(dir),
+    })
+}
+
+pub(super) fn split_path(path: &str) -> impl DoubleEndedIterator<Item = String> + '_ {
+    path.split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(str::to_owned)
+}
+
+/// Add `node` to the directory `parent`. The caller holds the tree lock.
+fn link(parent: &Node, name: String, node: &Arc<Node>) -> FsResult<()> {
+    // Nothing can be created in a directory that has been removed.
+    if parent.meta().nlink == 0 {
+        return Err(ErrorCode::NoEntry.into());
+    }
+    parent.entries()?.insert(name, Arc::clone(node));
+    parent.modified();
+    Ok(())
+}
+
+/// Remove `name` from the directory `parent`. The caller holds the tree lock.
+fn unlink(parent: &Node, name: &str) -> FsResult<()> {
+    if let Some(node) = parent.entries()?.remove(name) {
+        dropped_link(&node);
+    }
+    parent.modified();
+    Ok(())
+}
+
+fn dropped_link(node: &Node) {
+    let mut meta = node.meta();
+    meta.nlink = if node.is_dir() {
+        0
+    } else {
+        meta.nlink.saturating_sub(1)
+    };
+    meta.ctime = now();
+}
+
+/// Test whether `node` is the directory `dir` or is inside it.
+fn is_within(node: &Arc<Node>, dir: &Arc<Node>) -> bool {
+    if Arc::ptr_eq(node, dir) {
+        return true;
+    }
+    match &dir.kind {
+        Kind::Dir(entries) => entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|child| child.is_dir())
+            .any(|child| is_within(node, child)),
+        _ => false,
+    }
+}
+
+/// A directory in a [`MemFs`].
+pub struct MemDir {
+    shared: Arc<Shared>,
+    node: Arc<Node>,
+}
+
+impl MemDir {
+    /// Links and renames only work within the same `MemFs`.
+    fn same_fs<'a>(&self, dir: &'a dyn WasiDir) -> FsResult<&'a MemDir> {
+        match dir.as_any().downcast_ref::<MemDir>() {
+            Some(dir) if Arc::ptr_eq(&dir.shared, &self.shared) => Ok(dir),
+            _ => Err(ErrorCode::CrossDevice.into()),
+        }
+    }
+
+    fn resolve(&self, path: &str, follow: bool) -> FsResult<Resolved> {
+        resolve(&self.node, path, follow)
+    }
+
+    fn lookup(&self, path: &str, follow: bool) -> FsResult<Arc<Node>> {
+        self.resolve(path, follow)?
+            .node
+            .ok_or_else(|| ErrorCode::NoEntry.into())
+    }
+}
+
+impl WasiDir for MemDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(self.node.stat(self.shared.device))
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()> {
+        self.node.set_times(atim, mtim)
+    }
+
+    fn open_at(
+        &self,
+        path: &str,
+        follow: bool,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Opened> {
+        let _tree = self.shared.tree();
+        let resolved = self.resolve(path, follow)?;
+        let node = match resolved.node {
+            Some(_) if oflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+                return Err(ErrorCode::Exist.into())
+            }
+            Some(node) => node,
+            None if oflags.contains(OpenFlags::CREATE) => {
+                let node = self.shared.new_node(Kind::File(FileNode::default()));
+                link(&resolved.parent, resolved.name.unwrap(), &node)?;
+                node
+            }
+            None => return Err(ErrorCode::NoEntry.into()),
+        };
+
+        match &node.kind {
+            Kind::Dir(_) => {
+                if oflags.contains(OpenFlags::TRUNCATE) || flags.contains(DescriptorFlags::WRITE) {
+                    return Err(ErrorCode::IsDirectory.into());
+                }
+                Ok(Opened::Dir(Box::new(MemDir {
+                    shared: Arc::clone(&self.shared),
+                    node,
+                })))
+            }
+            // A symlink we weren't asked to follow.
+            Kind::Symlink(_) => Err(ErrorCode::Loop.into()),
+            Kind::File(file) => {
+                if oflags.contains(OpenFlags::DIRECTORY) {
+                    return Err(ErrorCode::NotDirectory.into());
+                }
+                if oflags.contains(OpenFlags::TRUNCATE) {
+                    file.contents.lock().unwrap().clear();
+                    node.modified();
+                }
+                Ok(Opened::File(Box::new(MemFile {
+                    handle: self.shared.next_handle.fetch_add(1, Ordering::Relaxed),
+                    shared: Arc::clone(&self.shared),
+                    node,
+                })))
+            }
+        }
+    }
+
+    fn create_dir_at(&self, path: &str) -> FsResult<()> {
+        let _tree = self.shared.tree();
+        let resolved = self.resolve(path, false)?;
+        match (resolved.name, resolved.node) {
+            (Some(name), None) => {
+                let node = self.shared.new_node(Kind::Dir(Mutex::new(BTreeMap::new())));
+                link(&resolved.parent, name, &node)
+            }
+            _ => Err(ErrorCode::Exist.into()),
+        }
+    }
+
+    fn read_dir(&self) -> FsResult<ReaddirIter> {
+        let entries = self
+            .node
+            .entries()?
+            .iter()
+            .map(|(name, node)| {
+                Ok(DirectoryEntry {
+                    inode: Some(node.inode),
+                    type_: node.descriptor_type(),
+                    name: name.clone(),
+                })
+            })
+            .collect::<Vec<FsResult<_>>>();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn stat_at(&self, path: &str, follow: bool) -> FsResult<DescriptorStat> {
+        Ok(self.lookup(path, follow)?.stat(self.shared.device))
+    }
+
+    fn set_times_at(
+        &self,
+        path: &str,
+        follow: bool,
+        atim: NewTimestamp,
+        mtim: NewTimestamp,
+    ) -> FsResult<()> {
+        self.lookup(path, follow)?.set_times(atim, mtim)
+    }
+
+    fn link_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()> {
+        let new_dir = self.same_fs(new_dir)?;
+        let _tree = self.shared.tree();
+        let node = self.lookup(old_path, false)?;
+        if node.is_dir() {
+            return Err(ErrorCode::NotPermitted.into());
+        }
+        let new = new_dir.resolve(new_path, false)?;
+        match (new.name, new.node) {
+            (Some(name), None) => {
+                link(&new.parent, name, &node)?;
+                let mut meta = node.meta();
+                meta.nlink += 1;
+                meta.ctime = now();
+                Ok(())
+            }
+            _ => Err(ErrorCode::Exist.into()),
+        }
+    }
+
+    fn readlink_at(&self, path: &str) -> FsResult<String> {
+        match &self.lookup(path, false)?.kind {
+            Kind::Symlink(target) => Ok(target.clone()),
+            _ => Err(ErrorCode::Invalid.into()),
+        }
+    }
+
+    fn remove_dir_at(&self, path: &str) -> FsResult<()> {
+        let _tree = self.shared.tree();
+        let resolved = self.resolve(path, false)?;
+        let node = resolved.node.ok_or(ErrorCode::NoEntry)?;
+        let name = resolved.name.ok_or(ErrorCode::Invalid)?;
+        if !node.entries()?.is_empty() {
+            return Err(ErrorCode::NotEmpty.into());
+        }
+        unlink(&resolved.parent, &name)
+    }
+
+    fn rename_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()> {
+        let new_dir = self.same_fs(new_dir)?;
+        let _tree = self.shared.tree();
+        let old = self.resolve(old_path, false)?;
+        let node = old.node.ok_or(ErrorCode::NoEntry)?;
+        let old_name = old.name.ok_or(ErrorCode::Invalid)?;
+        let new = new_dir.resolve(new_path, false)?;
+        let new_name = new.name.ok_or(ErrorCode::Invalid)?;
+
+        if let Some(existing) = &new.node {
+            if Arc::ptr_eq(existing, &node) {
+                return Ok(());
+            }
+            match (node.is_dir(), existing.is_dir()) {
+                (true, false) => return Err(ErrorCode::NotDirectory.into()),
+                (false, true) => return Err(ErrorCode::IsDirectory.into()),
+                (true, true) if !existing.entries()?.is_empty() => {
+                    return Err(ErrorCode::NotEmpty.into())
+                }
+                _ => {}
+            }
+        }
+        // A directory can't be moved inside itself.
+        if node.is_dir() && is_within(&new.parent, &node) {
+            return Err(ErrorCode::Invalid.into());
+        }
+
+        if new.node.is_some() {
+            unlink(&new.parent, &new_name)?;
+        }
+        link(&new.parent, new_name, &node)?;
+        old.parent.entries()?.remove(&old_name);
+        old.parent.modified();
+        node.changed();
+        Ok(())
+    }
+
+    fn symlink_at(&self, src_path: &str, dest_path: &str) -> FsResult<()> {
+        let _tree = self.shared.tree();
+        let resolved = self.resolve(dest_path, false)?;
+        match (resolved.name, resolved.node) {
+            (Some(name), None) => {
+                let node = self.shared.new_node(Kind::Symlink(src_path.to_owned()));
+                link(&resolved.parent, name, &node)
+            }
+            _ => Err(ErrorCode::Exist.into()),
+        }
+    }
+
+    fn unlink_file_at(&self, path: &str) -> FsResult<()> {
+        let _tree = self.shared.tree();
+        let resolved = self.resolve(path, false)?;
+        let node = resolved.node.ok_or(ErrorCode::NoEntry)?;
+        if node.is_dir() {
+            return Err(ErrorCode::IsDirectory.into());
+        }
+        unlink(&resolved.parent, &resolved.name.unwrap())
+    }
+
+    fn set_permissions_at(
+        &self,
+        path: &str,
+        follow: bool,
+        _modes: Modes,
+        _is_dir: bool,
+    ) -> FsResult<()> {
+        self.lookup(path, follow)?.changed();
+        Ok(())
+    }
+}
+
+/// A file opened from a [`MemDir`].
+struct MemFile {
+    /// Identifies this open file for the purpose of locking.
+    handle: u64,
+    shared: Arc<Shared>,
+    node: Arc<Node>,
+}
+
+impl MemFile {
+    fn file(&self) -> &FileNode {
+        match &self.node.kind {
+            Kind::File(file) => file,
+            _ => unreachable!("only regular files are opened as files"),
+        }
+    }
+
+    fn contents(&self) -> MutexGuard<Vec<u8>> {
+        self.file().contents.lock().unwrap()
+    }
+
+    /// Grow `contents` to `len` bytes, if it's shorter, failing cleanly if
+    /// that's beyond the size limit or the host is out of memory.
+    fn grow(&self, contents: &mut Vec<u8>, len: u64) -> FsResult<()> {
+        if len > self.shared.max_file_size {
+            return Err(ErrorCode::FileTooLarge.into());
+        }
+        let len = usize::try_from(len).map_err(|_| ErrorCode::FileTooLarge)?;
+        if let Some(additional) = len.checked_sub(contents.len()) {
+            contents
+                .try_reserve(additional)
+                .map_err(|_| ErrorCode::InsufficientSpace)?;
+            contents.resize(len, 0);
+        }
+        Ok(())
+    }
+}
+
+impl WasiFile for MemFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(self.node.stat(self.shared.device))
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn set_len(&self, size: u64) -> FsResult<()> {
+        let mut contents = self.contents();
+        self.grow(&mut contents, size)?;
+        // `size` fits, since it's no more than the current length.
+        contents.truncate(size as usize);
+        drop(contents);
+        self.node.modified();
+        Ok(())
+    }
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()> {
+        self.node.set_times(atim, mtim)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> FsResult<(u64, bool)> {
+        let contents = self.contents();
+        let start = match usize::try_from(offset) {
+            Ok(start) if start < contents.len() => start,
+            _ => return Ok((0, true)),
+        };
+        let n = buf.len().min(contents.len() - start);
+        buf[..n].copy_from_slice(&contents[start..start + n]);
+        Ok((n as u64, false))
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> FsResult<u64> {
+        let mut contents = self.contents();
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .ok_or(ErrorCode::FileTooLarge)?;
+        self.grow(&mut contents, end)?;
+        // Both fit, since `end` is within the contents.
+        let (start, end) = (offset as usize, end as usize);
+        contents[start..end].copy_from_slice(buf);
+        drop(contents);
+        self.node.modified();
+        Ok(buf.len() as u64)
+    }
+
+    fn append(&self, buf: &[u8]) -> FsResult<u64> {
+        let mut contents = self.contents();
+        let start = contents.len();
+        self.grow(&mut contents, start as u64 + buf.len() as u64)?;
+        contents[start..].copy_from_slice(buf);
+        drop(contents);
+        self.node.modified();
+        Ok(buf.len() as u64)
+    }
+
+    fn lock(&self, op: LockOp) -> FsResult<()> {
+        let file = self.file();
+        let me = self.handle;
+        let mut locks = file.locks.lock().unwrap();
+        match op {
+            LockOp::Shared | LockOp::TryShared => {
+                while matches!(locks.exclusive, Some(h) if h != me) {
+                    if op == LockOp::TryShared {
+                        return Err(ErrorCode::WouldBlock.into());
+                    }
+                    locks = file.unlocked.wait(locks).unwrap();
+                }
+                if locks.exclusive.take().is_some() {
+                    // Downgrading lets others take shared locks.
+                    file.unlocked.notify_all();
+                }
+                if !locks.shared.contains(&me) {
+                    locks.shared.push(me);
+                }
+            }
+            LockOp::Exclusive | LockOp::TryExclusive => {
+                while matches!(locks.exclusive, Some(h) if h != me)
+                    || locks.shared.iter().any(|&h| h != me)
+                {
+                    if op == LockOp::TryExclusive {
+                        return Err(ErrorCode::WouldBlock.into());
+                    }
+                    locks = file.unlocked.wait(locks).unwrap();
+                }
+                locks.shared.retain(|&h| h != me);
+                locks.exclusive = Some(me);
+            }
+            LockOp::Unlock => {
+                locks.shared.retain(|&h| h != me);
+                if locks.exclusive == Some(me) {
+                    locks.exclusive = None;
+                }
+                file.unlocked.notify_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MemFile {
+    fn drop(&mut self) {
+        // As with `flock`, closing a file releases its locks.
+        let _ = WasiFile::lock(self, LockOp::Unlock);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn open(dir: &MemDir, path: &str, oflags: OpenFlags) -> FsResult<Box<dyn WasiFile>> {
+        match dir.open_at(
+            path,
+            true,
+            oflags,
+            DescriptorFlags::READ | DescriptorFlags::WRITE,
+        )? {
+            Opened::File(file) => Ok(file),
+            Opened::Dir(_) => panic!("{path} is a directory"),
+        }
+    }
+
+    fn code<T>(r: FsResult<T>) -> ErrorCode {
+        match r {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.downcast().unwrap(),
+        }
+    }
+
+    #[test]
+    fn files_and_directories() {
+        let fs = MemFs::new();
+        fs.create_dir_all("/data/logs").unwrap();
+        fs.write("/data/hello.txt", "hello").unwrap();
+        let root = fs.root();
+
+        let file = open(&root, "data/hello.txt", OpenFlags::empty()).unwrap();
+        file.write_at(b", world", 5).unwrap();
+        let mut buf = [0; 32];
+        assert_eq!(file.read_at(&mut buf, 7).unwrap(), (5, false));
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(file.read_at(&mut buf, 12).unwrap(), (0, true));
+        assert_eq!(fs.read("data/hello.txt").unwrap(), b"hello, world");
+
+        open(&root, "data/logs/new.log", OpenFlags::CREATE)
+            .unwrap()
+            .append(b"appended")
+            .unwrap();
+        assert_eq!(fs.read("data/logs/new.log").unwrap(), b"appended");
+        assert!(matches!(
+            code(open(
+                &root,
+                "data/logs/new.log",
+                OpenFlags::CREATE | OpenFlags::EXCLUSIVE
+            )),
+            ErrorCode::Exist
+        ));
+        assert!(matches!(
+            code(root.remove_dir_at("data/logs")),
+            ErrorCode::NotEmpty
+        ));
+
+        root.rename_at("data/logs/new.log", &root, "data/old.log")
+            .unwrap();
+        root.remove_dir_at("data/logs").unwrap();
+        assert_eq!(fs.read_dir("data").unwrap(), ["hello.txt", "old.log"]);
+        assert!(matches!(
+            code(root.rename_at("data", &root, "data/inside")),
+            ErrorCode::Invalid
+        ));
+
+        root.link_at("data/old.log", &root, "linked.log").unwrap();
+        assert_eq!(fs.metadata("linked.log").unwrap().link_count, 2);
+        root.unlink_file_at("data/old.log").unwrap();
+        assert_eq!(fs.read("linked.log").unwrap(), b"appended");
+        assert_eq!(fs.metadata("linked.log").unwrap().link_count, 1);
+
+        // Other filesystems are other devices.
+        let other = MemFs::new();
+        assert!(matches!(
+            code(root.rename_at("linked.log", &other.root(), "linked.log")),
+            ErrorCode::CrossDevice
+        ));
+        assert_ne!(
+            fs.metadata(".").unwrap().device,
+            other.metadata(".").unwrap().device
+        );
+    }
+
+    #[test]
+    fn symlinks_and_escapes() {
+        let fs = MemFs::new();
+        fs.create_dir_all("a/b").unwrap();
+        fs.write("a/b/file", "contents").unwrap();
+        fs.symlink("b/file", "a/link").unwrap();
+        fs.symlink("../..", "a/b/up").unwrap();
+        fs.symlink("loop", "loop").unwrap();
+        fs.symlink("/etc/passwd", "absolute").unwrap();
+        let root = fs.root();
+
+        assert_eq!(fs.read("a/link").unwrap(), b"contents");
+        assert_eq!(fs.read_link("a/link").unwrap(), "b/file");
+        assert!(matches!(
+            root.stat_at("a/link", false).unwrap().type_,
+            DescriptorType::SymbolicLink
+        ));
+        assert!(matches!(
+            code(root.open_at("a/link", false, OpenFlags::empty(), DescriptorFlags::READ)),
+            ErrorCode::Loop
+        ));
+        assert_eq!(fs.read("a/b/up/a/b/file").unwrap(), b"contents");
+        assert!(matches!(code(root.stat_at("loop", true)), ErrorCode::Loop));
+
+        assert!(matches!(
+            code(root.stat_at("..", true)),
+            ErrorCode::NotPermitted
+        ));
+        assert!(matches!(
+            code(root.stat_at("absolute", true)),
+            ErrorCode::NotPermitted
+        ));
+
+        // `..` can't leave a directory opened below the root either.
+        let a = match root
+            .open_at("a", true, OpenFlags::DIRECTORY, DescriptorFlags::READ)
+            .unwrap()
+        {
+            Opened::Dir(dir) => dir,
+            Opened::File(_) => unreachable!(),
+        };
+        assert!(matches!(
+            code(a.stat_at("b/up", true)),
+            ErrorCode::NotPermitted
+        ));
+        assert_eq!(
+            a.stat_at("b/..", true).unwrap().inode,
+            fs.metadata("a").unwrap().inode
+        );
+    }
+
+    #[test]
+    fn locks() {
+        let fs = MemFs::new();
+        fs.write("file", "").unwrap();
+        let root = fs.root();
+        let a = open(&root, "file", OpenFlags::empty()).unwrap();
+        let b = open(&root, "file", OpenFlags::empty()).unwrap();
+
+        a.lock(LockOp::Exclusive).unwrap();
+        assert!(matches!(
+            code(b.lock(LockOp::TryShared)),
+            ErrorCode::WouldBlock
+        ));
+        a.lock(LockOp::Unlock).unwrap();
+        b.lock(LockOp::TryShared).unwrap();
+        a.lock(LockOp::TryShared).unwrap();
+        assert!(matches!(
+            code(a.lock(LockOp::TryExclusive)),
+            ErrorCode::WouldBlock
+        ));
+
+        // Closing a file releases its locks.
+        drop(b);
+        a.lock(LockOp::TryExclusive).unwrap();
+    }
+
+    #[test]
+    fn huge_files_fail_cleanly() {
+        let fs = MemFs::with_max_file_size(16);
+        fs.write("file", "hello").unwrap();
+        let file = open(&fs.root(), "file", OpenFlags::empty()).unwrap();
+
+        assert!(matches!(
+            code(file.set_len(u64::MAX)),
+            ErrorCode::FileTooLarge
+        ));
+        assert!(matches!(code(file.set_len(17)), ErrorCode::FileTooLarge));
+        assert!(matches!(
+            code(file.write_at(b"!", u64::MAX)),
+            ErrorCode::FileTooLarge
+        ));
+        assert!(matches!(
+            code(file.write_at(b"!", 16)),
+            ErrorCode::FileTooLarge
+        ));
+        assert!(matches!(
+            code(file.append(&[0; 12])),
+            ErrorCode::FileTooLarge
+        ));
+        assert_eq!(fs.read("file").unwrap(), b"hello");
+
+        // Up to the limit is fine, and so is shrinking.
+        file.set_len(16).unwrap();
+        file.write_at(b"!", 15).unwrap();
+        file.set_len(2).unwrap();
+        assert_eq!(fs.read("file").unwrap(), b"he");
+
+        // The default limit rejects sizes the host can't allocate.
+        let fs = MemFs::new();
+        fs.write("file", "").unwrap();
+        let file = open(&fs.root(), "file", OpenFlags::empty()).unwrap();
+        assert!(matches!(
+            code(file.set_len(u64::MAX / 2)),
+            ErrorCode::FileTooLarge
+        ));
+    }
+}
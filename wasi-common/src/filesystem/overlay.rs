@@ -0,0 +1,762 @@
+//! Copy-on-write preopens.
+//!
+//! An [`Overlay`] presents a read-only lower directory and an upper
+//! directory as one directory. Everything is read from the upper directory
+//! if it's there and from the lower one otherwise. Every change goes to the
+//! upper directory: files are copied up from the lower directory before
+//! they're changed, and removing something from the lower directory only
+//! hides it. The upper directory can be a [`MemFs`](super::mem::MemFs) or a
+//! scratch directory on the host.
+//!
+//! Paths are resolved lexically, and symlinks are only followed within the
+//! layer they're in. Renaming a directory that is in the lower directory
+//! fails with `cross-device`, as it does with Linux's overlayfs, and tools
+//! like `mv` fall back to copying.
+
+use super::mem::MAX_SYMLINKS;
+use super::{FsResult, Opened, ReaddirIter, WasiDir, WasiFile};
+use crate::wasi::filesystem::{
+    DescriptorFlags, DescriptorStat, DescriptorType, DirectoryEntry, Error, ErrorCode, Modes,
+    NewTimestamp, OpenFlags,
+};
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+/// A change an [`Overlay`]'s upper directory makes to its lower directory,
+/// as listed by [`Overlay::changes`]. Paths are relative to the overlay.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// A file, directory, or symlink that isn't in the lower directory.
+    Added(String),
+    /// A file or symlink from the lower directory whose contents or metadata
+    /// changed.
+    Modified(String),
+    /// A file, directory, or symlink from the lower directory that was
+    /// removed.
+    Removed(String),
+}
+
+/// A copy-on-write view of a directory.
+///
+/// Clones share the same layers, so the host can keep one to look at the
+/// changes after giving another to a guest.
+#[derive(Clone)]
+pub struct Overlay {
+    inner: Arc<Inner>,
+    /// The path of this directory relative to the root of the overlay.
+    path: String,
+}
+
+struct Inner {
+    lower: Box<dyn WasiDir>,
+    upper: Box<dyn WasiDir>,
+    /// Paths in the lower directory that have been removed.
+    whiteouts: Mutex<BTreeSet<String>>,
+}
+
+/// Where a path was found.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    Upper,
+    Lower,
+}
+
+impl Overlay {
+    /// Overlay `upper` on `lower`. `lower` is never changed. `upper` should
+    /// start out empty.
+    pub fn new(lower: impl WasiDir + 'static, upper: impl WasiDir + 'static) -> Self {
+        Overlay {
+            inner: Arc::new(Inner {
+                lower: Box::new(lower),
+                upper: Box::new(upper),
+                whiteouts: Mutex::new(BTreeSet::new()),
+            }),
+            path: ".".to_owned(),
+        }
+    }
+
+    /// List what has been added to, modified in, and removed from the lower
+    /// directory, sorted by path.
+    pub fn changes(&self) -> FsResult<Vec<Change>> {
+        let mut changes = Vec::new();
+        self.upper_changes(".", &mut changes)?;
+        for path in self.inner.whiteouts.lock().unwrap().iter() {
+            changes.push(Change::Removed(path.clone()));
+        }
+        changes.sort_by(|a, b| change_path(a).cmp(change_path(b)));
+        Ok(changes)
+    }
+
+    /// Throw away all changes, leaving the upper directory empty.
+    ///
+    /// Files and directories the guest still has open keep referring to
+    /// whatever they referred to before.
+    pub fn discard(&self) -> FsResult<()> {
+        remove_contents(&*self.inner.upper, ".")?;
+        self.inner.whiteouts.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn upper_changes(&self, dir: &str, changes: &mut Vec<Change>) -> FsResult<()> {
+        let upper = match self.open_layer_dir(Layer::Upper, dir)? {
+            Some(upper) => upper,
+            None => return Ok(()),
+        };
+        for entry in upper.read_dir()? {
+            let entry = entry?;
+            let path = child(dir, &entry.name);
+            let in_lower = optional(self.inner.lower.stat_at(&path, false))?.is_some();
+            if matches!(entry.type_, DescriptorType::Directory) {
+                if !in_lower {
+                    changes.push(Change::Added(path.clone()));
+                }
+                self.upper_changes(&path, changes)?;
+            } else if in_lower {
+                changes.push(Change::Modified(path));
+            } else {
+                changes.push(Change::Added(path));
+            }
+        }
+        Ok(())
+    }
+
+    fn upper(&self) -> &dyn WasiDir {
+        &*self.inner.upper
+    }
+
+    fn lower(&self) -> &dyn WasiDir {
+        &*self.inner.lower
+    }
+
+    fn layer(&self, layer: Layer) -> &dyn WasiDir {
+        match layer {
+            Layer::Upper => self.upper(),
+            Layer::Lower => self.lower(),
+        }
+    }
+
+    /// Resolve `path` relative to this directory, giving a path relative to
+    /// the root of the overlay. As with other preopens, `..` can't leave
+    /// this directory.
+    fn join(&self, path: &str) -> FsResult<String> {
+        join(&self.path, path)
+    }
+
+    fn is_whited_out(&self, full: &str) -> bool {
+        let whiteouts = self.inner.whiteouts.lock().unwrap();
+        full.match_indices('/')
+            .map(|(i, _)| &full[..i])
+            .chain(std::iter::once(full))
+            .any(|path| whiteouts.contains(path))
+    }
+
+    fn white_out(&self, full: &str) {
+        self.inner.whiteouts.lock().unwrap().insert(full.to_owned());
+    }
+
+    fn unwhite_out(&self, full: &str) {
+        self.inner.whiteouts.lock().unwrap().remove(full);
+    }
+
+    /// Find the layer `full` is in.
+    fn locate(&self, full: &str, follow: bool) -> FsResult<(Layer, DescriptorStat)> {
+        if self.is_whited_out(full) {
+            return Err(ErrorCode::NoEntry.into());
+        }
+        if let Some(stat) = optional(self.upper().stat_at(full, follow))? {
+            return Ok((Layer::Upper, stat));
+        }
+        match optional(self.lower().stat_at(full, follow))? {
+            Some(stat) => Ok((Layer::Lower, stat)),
+            None => Err(ErrorCode::NoEntry.into()),
+        }
+    }
+
+    fn exists(&self, full: &str) -> FsResult<bool> {
+        Ok(optional(self.locate(full, false))?.is_some())
+    }
+
+    fn in_lower(&self, full: &str) -> FsResult<bool> {
+        Ok(optional(self.lower().stat_at(full, false))?.is_some())
+    }
+
+    fn open_layer_dir(&self, layer: Layer, full: &str) -> FsResult<Option<Box<dyn WasiDir>>> {
+        let opened = optional(self.layer(layer).open_at(
+            full,
+            false,
+            OpenFlags::DIRECTORY,
+            DescriptorFlags::READ,
+        ))?;
+        match opened {
+            Some(Opened::Dir(dir)) => Ok(Some(dir)),
+            Some(Opened::File(_)) => Err(ErrorCode::NotDirectory.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// Make sure `full` is in the upper directory, copying it and the
+    /// directories containing it from the lower directory if necessary.
+    fn copy_up(&self, full: &str) -> FsResult<()> {
+        let (layer, stat) = self.locate(full, false)?;
+        if layer == Layer::Upper {
+            return Ok(());
+        }
+        if let Some(parent) = parent(full) {
+            self.copy_up(parent)?;
+        }
+
+        match stat.type_ {
+            DescriptorType::Directory => self.upper().create_dir_at(full)?,
+            DescriptorType::SymbolicLink => {
+                let target = self.lower().readlink_at(full)?;
+                self.upper().symlink_at(&target, full)?;
+            }
+            _ => {
+                let from = open_file(
+                    self.lower(),
+                    full,
+                    OpenFlags::empty(),
+                    DescriptorFlags::READ,
+                )?;
+                let to = open_file(
+                    self.upper(),
+                    full,
+                    OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                    DescriptorFlags::WRITE,
+                )?;
+                let mut buf = vec![0; 64 * 1024];
+                let mut offset = 0;
+                loop {
+                    let (n, end) = from.read_at(&mut buf, offset)?;
+                    let mut written = 0;
+                    while written < n {
+                        written +=
+                            to.write_at(&buf[written as usize..n as usize], offset + written)?;
+                    }
+                    offset += n;
+                    if end || n == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.upper().set_times_at(
+            full,
+            false,
+            NewTimestamp::Timestamp(stat.data_access_timestamp),
+            NewTimestamp::Timestamp(stat.data_modification_timestamp),
+        )
+    }
+
+    /// Copy up the symlinks that the last component of `full` resolves
+    /// through, so that they lead to the same place in the upper directory,
+    /// and return the path they lead to.
+    fn copy_up_links(&self, full: &str) -> FsResult<String> {
+        let mut full = full.to_owned();
+        for _ in 0..MAX_SYMLINKS {
+            let (layer, stat) = self.locate(&full, false)?;
+            if !matches!(stat.type_, DescriptorType::SymbolicLink) {
+                return Ok(full);
+            }
+            let target = self.layer(layer).readlink_at(&full)?;
+            self.copy_up(&full)?;
+            if target.starts_with('/') {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            full = join(".", &child(parent(&full).unwrap_or("."), &target))?;
+        }
+        Err(ErrorCode::Loop.into())
+    }
+
+    /// Get ready to create `full`, which doesn't exist yet, in the upper
+    /// directory.
+    fn prepare_create(&self, full: &str) -> FsResult<()> {
+        let parent = parent(full).ok_or(ErrorCode::Exist)?;
+        let (_, stat) = self.locate(parent, true)?;
+        if !matches!(stat.type_, DescriptorType::Directory) {
+            return Err(ErrorCode::NotDirectory.into());
+        }
+        self.copy_up(parent)
+    }
+
+    fn read_merged(&self, full: &str) -> FsResult<BTreeMap<String, DirectoryEntry>> {
+        let mut entries = BTreeMap::new();
+        if let Some(upper) = self.open_layer_dir(Layer::Upper, full)? {
+            for entry in upper.read_dir()? {
+                let entry = entry?;
+                entries.insert(entry.name.clone(), entry);
+            }
+        }
+        if let Some(lower) = self.open_layer_dir(Layer::Lower, full)? {
+            for entry in lower.read_dir()? {
+                let entry = entry?;
+                if !entries.contains_key(&entry.name)
+                    && !self.is_whited_out(&child(full, &entry.name))
+                {
+                    entries.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Links and renames only work within the same overlay.
+    fn same_overlay<'a>(&self, dir: &'a dyn WasiDir) -> FsResult<&'a Overlay> {
+        match dir.as_any().downcast_ref::<Overlay>() {
+            Some(dir) if Arc::ptr_eq(&dir.inner, &self.inner) => Ok(dir),
+            _ => Err(ErrorCode::CrossDevice.into()),
+        }
+    }
+}
+
+impl WasiDir for Overlay {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn stat(&self) -> FsResult<DescriptorStat> {
+        Ok(self.locate(&self.path, true)?.1)
+    }
+
+    fn sync_data(&self) -> FsResult<()> {
+        match self.open_layer_dir(Layer::Upper, &self.path)? {
+            Some(upper) => upper.sync_data(),
+            None => Ok(()),
+        }
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        match self.open_layer_dir(Layer::Upper, &self.path)? {
+            Some(upper) => upper.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn set_times(&self, atim: NewTimestamp, mtim: NewTimestamp) -> FsResult<()> {
+        self.copy_up(&self.path)?;
+        self.upper().set_times_at(&self.path, false, atim, mtim)
+    }
+
+    fn open_at(
+        &self,
+        path: &str,
+        follow: bool,
+        oflags: OpenFlags,
+        flags: DescriptorFlags,
+    ) -> FsResult<Opened> {
+        let full = self.join(path)?;
+        let layer = match optional(self.locate(&full, follow))? {
+            Some(_) if oflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => {
+                return Err(ErrorCode::Exist.into())
+            }
+            Some((Layer::Lower, stat))
+                if !matches!(stat.type_, DescriptorType::Directory)
+                    && (flags.contains(DescriptorFlags::WRITE)
+                        || oflags.contains(OpenFlags::TRUNCATE)) =>
+            {
+                let target = if follow {
+                    self.copy_up_links(&full)?
+                } else {
+                    full.clone()
+                };
+                self.copy_up(&target)?;
+                Layer::Upper
+            }
+            Some((layer, _)) => layer,
+            None if oflags.contains(OpenFlags::CREATE) => {
+                self.prepare_create(&full)?;
+                Layer::Upper
+            }
+            None => return Err(ErrorCode::NoEntry.into()),
+        };
+
+        match self.layer(layer).open_at(&full, follow, oflags, flags)? {
+            Opened::File(file) => {
+                self.unwhite_out(&full);
+                Ok(Opened::File(file))
+            }
+            Opened::Dir(_) => Ok(Opened::Dir(Box::new(Overlay {
+                inner: Arc::clone(&self.inner),
+                path: full,
+            }))),
+        }
+    }
+
+    fn create_dir_at(&self, path: &str) -> FsResult<()> {
+        let full = self.join(path)?;
+        if self.exists(&full)? {
+            return Err(ErrorCode::Exist.into());
+        }
+        self.prepare_create(&full)?;
+        self.upper().create_dir_at(&full)?;
+        self.unwhite_out(&full);
+        Ok(())
+    }
+
+    fn read_dir(&self) -> FsResult<ReaddirIter> {
+        let entries = self.read_merged(&self.path)?;
+        Ok(Box::new(entries.into_values().map(Ok)))
+    }
+
+    fn stat_at(&self, path: &str, follow: bool) -> FsResult<DescriptorStat> {
+        Ok(self.locate(&self.join(path)?, follow)?.1)
+    }
+
+    fn set_times_at(
+        &self,
+        path: &str,
+        follow: bool,
+        atim: NewTimestamp,
+        mtim: NewTimestamp,
+    ) -> FsResult<()> {
+        let full = self.join(path)?;
+        self.copy_up(&full)?;
+        self.upper().set_times_at(&full, follow, atim, mtim)
+    }
+
+    fn link_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()> {
+        let new_dir = self.same_overlay(new_dir)?;
+        let old = self.join(old_path)?;
+        let new = new_dir.join(new_path)?;
+        let (_, stat) = self.locate(&old, false)?;
+        if matches!(stat.type_, DescriptorType::Directory) {
+            return Err(ErrorCode::NotPermitted.into());
+        }
+        if self.exists(&new)? {
+            return Err(ErrorCode::Exist.into());
+        }
+        self.copy_up(&old)?;
+        self.prepare_create(&new)?;
+        self.upper().link_at(&old, self.upper(), &new)?;
+        self.unwhite_out(&new);
+        Ok(())
+    }
+
+    fn readlink_at(&self, path: &str) -> FsResult<String> {
+        let full = self.join(path)?;
+        let (layer, _) = self.locate(&full, false)?;
+        self.layer(layer).readlink_at(&full)
+    }
+
+    fn remove_dir_at(&self, path: &str) -> FsResult<()> {
+        let full = self.join(path)?;
+        if full == "." {
+            return Err(ErrorCode::Invalid.into());
+        }
+        let (layer, stat) = self.locate(&full, false)?;
+        if !matches!(stat.type_, DescriptorType::Directory) {
+            return Err(ErrorCode::NotDirectory.into());
+        }
+        if !self.read_merged(&full)?.is_empty() {
+            return Err(ErrorCode::NotEmpty.into());
+        }
+        if layer == Layer::Upper {
+            self.upper().remove_dir_at(&full)?;
+        }
+        if self.in_lower(&full)? {
+            self.white_out(&full);
+        }
+        Ok(())
+    }
+
+    fn rename_at(&self, old_path: &str, new_dir: &dyn WasiDir, new_path: &str) -> FsResult<()> {
+        let new_dir = self.same_overlay(new_dir)?;
+        let old = self.join(old_path)?;
+        let new = new_dir.join(new_path)?;
+        if old == "." || new == "." {
+            return Err(ErrorCode::Invalid.into());
+        }
+        let (_, stat) = self.locate(&old, false)?;
+        let is_dir = matches!(stat.type_, DescriptorType::Directory);
+        if is_dir && self.in_lower(&old)? {
+            return Err(ErrorCode::CrossDevice.into());
+        }
+        if let Some((_, existing)) = optional(self.locate(&new, false))? {
+            if matches!(existing.type_, DescriptorType::Directory) && self.in_lower(&new)? {
+                return Err(ErrorCode::CrossDevice.into());
+            }
+        }
+
+        self.copy_up(&old)?;
+        self.prepare_create(&new)?;
+        self.upper().rename_at(&old, self.upper(), &new)?;
+        self.unwhite_out(&new);
+        if self.in_lower(&old)? {
+            self.white_out(&old);
+        }
+        Ok(())
+    }
+
+    fn symlink_at(&self, src_path: &str, dest_path: &str) -> FsResult<()> {
+        let full = self.join(dest_path)?;
+        if self.exists(&full)? {
+            return Err(ErrorCode::Exist.into());
+        }
+        self.prepare_create(&full)?;
+        self.upper().symlink_at(src_path, &full)?;
+        self.unwhite_out(&full);
+        Ok(())
+    }
+
+    fn unlink_file_at(&self, path: &str) -> FsResult<()> {
+        let full = self.join(path)?;
+        let (layer, stat) = self.locate(&full, false)?;
+        if matches!(stat.type_, DescriptorType::Directory) {
+            return Err(ErrorCode::IsDirectory.into());
+        }
+        if layer == Layer::Upper {
+            self.upper().unlink_file_at(&full)?;
+        }
+        if self.in_lower(&full)? {
+            self.white_out(&full);
+        }
+        Ok(())
+    }
+
+    fn set_permissions_at(
+        &self,
+        path: &str,
+        follow: bool,
+        modes: Modes,
+        is_dir: bool,
+    ) -> FsResult<()> {
+        let full = self.join(path)?;
+        self.copy_up(&full)?;
+        self.upper()
+            .set_permissions_at(&full, follow, modes, is_dir)
+    }
+}
+
+fn change_path(change: &Change) -> &str {
+    match change {
+        Change::Added(path) | Change::Modified(path) | Change::Removed(path) => path,
+    }
+}
+
+fn child(dir: &str, name: &str) -> String {
+    if dir == "." {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// Resolve `path` relative to `dir`, both relative to the root of the
+/// overlay. `..` can't leave `dir`.
+fn join(dir: &str, path: &str) -> FsResult<String> {
+    if path.is_empty() {
+        return Err(ErrorCode::NoEntry.into());
+    }
+    if path.starts_with('/') {
+        return Err(ErrorCode::NotPermitted.into());
+    }
+    let mut components = dir
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect::<Vec<_>>();
+    let base = components.len();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.len() == base {
+                    return Err(ErrorCode::NotPermitted.into());
+                }
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        Ok(".".to_owned())
+    } else {
+        Ok(components.join("/"))
+    }
+}
+
+fn parent(full: &str) -> Option<&str> {
+    match full.rsplit_once('/') {
+        Some((parent, _)) => Some(parent),
+        None if full == "." => None,
+        None => Some("."),
+    }
+}
+
+fn open_file(
+    dir: &dyn WasiDir,
+    path: &str,
+    oflags: OpenFlags,
+    flags: DescriptorFlags,
+) -> FsResult<Box<dyn WasiFile>> {
+    match dir.open_at(path, false, oflags, flags)? {
+        Opened::File(file) => Ok(file),
+        Opened::Dir(_) => Err(ErrorCode::IsDirectory.into()),
+    }
+}
+
+/// Remove everything in the directory at `path` in `dir`.
+fn remove_contents(dir: &dyn WasiDir, path: &str) -> FsResult<()> {
+    let opened = match dir.open_at(path, false, OpenFlags::DIRECTORY, DescriptorFlags::READ)? {
+        Opened::Dir(opened) => opened,
+        Opened::File(_) => return Err(ErrorCode::NotDirectory.into()),
+    };
+    for entry in opened.read_dir()? {
+        let entry = entry?;
+        let path = child(path, &entry.name);
+        if matches!(entry.type_, DescriptorType::Directory) {
+            remove_contents(dir, &path)?;
+            dir.remove_dir_at(&path)?;
+        } else {
+            dir.unlink_file_at(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Turn a `no-entry` error into `None`.
+fn optional<T>(r: FsResult<T>) -> FsResult<Option<T>> {
+    match r {
+        Ok(t) => Ok(Some(t)),
+        Err(e) => match e.downcast() {
+            Ok(ErrorCode::NoEntry) => Ok(None),
+            Ok(code) => Err(code.into()),
+            Err(trap) => Err(Error::trap(trap)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filesystem::mem::MemFs;
+
+    fn code<T>(r: FsResult<T>) -> ErrorCode {
+        match r {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.downcast().unwrap(),
+        }
+    }
+
+    fn names(dir: &dyn WasiDir) -> Vec<String> {
+        dir.read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect()
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let lower = MemFs::new();
+        lower.create_dir_all("data/old").unwrap();
+        lower.write("data/a.txt", "lower a").unwrap();
+        lower.write("data/b.txt", "lower b").unwrap();
+        lower.write("data/old/c.txt", "lower c").unwrap();
+        let upper = MemFs::new();
+        let overlay = Overlay::new(lower.root(), upper.root());
+
+        // Writing copies the file up.
+        let file = open_file(
+            &overlay,
+            "data/a.txt",
+            OpenFlags::empty(),
+            DescriptorFlags::WRITE,
+        )
+        .unwrap();
+        file.write_at(b"upper", 0).unwrap();
+        assert_eq!(upper.read("data/a.txt").unwrap(), b"upper a");
+        assert_eq!(lower.read("data/a.txt").unwrap(), b"lower a");
+
+        // Removing hides the lower file.
+        overlay.unlink_file_at("data/b.txt").unwrap();
+        assert!(matches!(
+            code(overlay.stat_at("data/b.txt", true)),
+            ErrorCode::NoEntry
+        ));
+        overlay.unlink_file_at("data/old/c.txt").unwrap();
+        overlay.remove_dir_at("data/old").unwrap();
+        overlay.create_dir_at("data/new").unwrap();
+        overlay
+            .rename_at("data/a.txt", &overlay, "data/new/a.txt")
+            .unwrap();
+
+        let data = match overlay
+            .open_at("data", false, OpenFlags::DIRECTORY, DescriptorFlags::READ)
+            .unwrap()
+        {
+            Opened::Dir(dir) => dir,
+            Opened::File(_) => unreachable!(),
+        };
+        assert_eq!(names(&*data), ["new"]);
+        assert_eq!(lower.read_dir("data").unwrap(), ["a.txt", "b.txt", "old"]);
+
+        // Recreating something that was removed doesn't bring back what
+        // was in it.
+        overlay.create_dir_at("data/old").unwrap();
+        assert!(names(&*data).contains(&"old".to_owned()));
+        assert!(matches!(
+            code(data.stat_at("old/c.txt", false)),
+            ErrorCode::NoEntry
+        ));
+
+        assert_eq!(
+            overlay.changes().unwrap(),
+            [
+                Change::Removed("data/a.txt".to_owned()),
+                Change::Removed("data/b.txt".to_owned()),
+                Change::Added("data/new".to_owned()),
+                Change::Added("data/new/a.txt".to_owned()),
+                Change::Removed("data/old/c.txt".to_owned()),
+            ]
+        );
+
+        overlay.discard().unwrap();
+        assert!(overlay.changes().unwrap().is_empty());
+        assert!(upper.read_dir(".").unwrap().is_empty());
+        assert_eq!(names(&*data), ["a.txt", "b.txt", "old"]);
+        assert!(data.stat_at("old/c.txt", false).is_ok());
+    }
+
+    #[test]
+    fn writing_through_a_lower_symlink_copies_up_its_target() {
+        let lower = MemFs::new();
+        lower.create_dir_all("dir").unwrap();
+        lower.write("dir/file.txt", "lower").unwrap();
+        lower.root().symlink_at("dir/file.txt", "link").unwrap();
+        let upper = MemFs::new();
+        let overlay = Overlay::new(lower.root(), upper.root());
+
+        let file = match overlay
+            .open_at("link", true, OpenFlags::empty(), DescriptorFlags::WRITE)
+            .unwrap()
+        {
+            Opened::File(file) => file,
+            Opened::Dir(_) => unreachable!(),
+        };
+        file.write_at(b"upper", 0).unwrap();
+
+        assert_eq!(upper.read("dir/file.txt").unwrap(), b"upper");
+        assert_eq!(upper.root().readlink_at("link").unwrap(), "dir/file.txt");
+        assert_eq!(lower.read("dir/file.txt").unwrap(), b"lower");
+        assert!(matches!(
+            overlay.stat_at("link", false).unwrap().type_,
+            DescriptorType::SymbolicLink
+        ));
+    }
+
+    #[test]
+    fn lower_directories_cannot_be_renamed() {
+        let lower = MemFs::new();
+        lower.create_dir_all("dir").unwrap();
+        let overlay = Overlay::new(lower.root(), MemFs::new().root());
+        assert!(matches!(
+            code(overlay.rename_at("dir", &overlay, "moved")),
+            ErrorCode::CrossDevice
+        ));
+        assert!(matches!(
+            code(overlay.stat_at("../escape", false)),
+            ErrorCode::NotPermitted
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+//! Limits on how much a guest can write through its preopens.
+
+use super::FsResult;
+use crate::wasi::filesystem::ErrorCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Limits on the filesystem changes a guest can make, set with
+/// `WasiCtxBuilder::set_fs_limits`. `None` means unlimited.
+///
+/// Going over a limit fails with `insufficient-space` before anything is
+/// written. Streams can't return filesystem error codes, so writes through
+/// them fail with a plain stream error instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FsLimits {
+    /// The total number of bytes the guest may add to files, by writing
+    /// past their end or growing them with `set-size`. Overwriting existing
+    /// bytes doesn't count.
+    pub max_bytes_written: Option<u64>,
+    /// The largest size the guest may write or grow a file to.
+    pub max_file_size: Option<u64>,
+    /// The number of files, directories, and symlinks the guest may create.
+    pub max_created_inodes: Option<u64>,
+}
+
+/// The [`FsLimits`] of a `WasiCtx`, and how much of them the guest has used.
+#[derive(Debug, Default)]
+pub struct FsQuota {
+    limits: FsLimits,
+    bytes_written: AtomicU64,
+    created_inodes: AtomicU64,
+}
+
+impl FsQuota {
+    pub fn new(limits: FsLimits) -> Self {
+        FsQuota {
+            limits,
+            ..FsQuota::default()
+        }
+    }
+
+    pub fn limits(&self) -> FsLimits {
+        self.limits
+    }
+
+    /// The number of bytes the guest has added to files so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::SeqCst)
+    }
+
+    /// The number of files, directories, and symlinks the guest has created
+    /// so far. Removing them doesn't give any back.
+    pub fn created_inodes(&self) -> u64 {
+        self.created_inodes.load(Ordering::SeqCst)
+    }
+
+    /// Write `len` bytes with `write`, which returns how many bytes it
+    /// wrote. `position` returns the offset of the write and the size of the
+    /// file before it. As with `set-size`, only the bytes that grow the file
+    /// count.
+    pub(crate) fn write(
+        &self,
+        len: u64,
+        position: impl FnOnce() -> FsResult<(u64, u64)>,
+        write: impl FnOnce() -> FsResult<u64>,
+    ) -> FsResult<u64> {
+        if len == 0 {
+            return write();
+        }
+        let (offset, size) = position()?;
+        let end = offset.saturating_add(len);
+        if let Some(max) = self.limits.max_file_size {
+            if end > max {
+                return Err(ErrorCode::InsufficientSpace.into());
+            }
+        }
+        let growth = end.saturating_sub(size);
+        reserve(&self.bytes_written, self.limits.max_bytes_written, growth)?;
+        match write() {
+            Ok(n) => {
+                let grown = offset.saturating_add(n).saturating_sub(size).min(growth);
+                self.bytes_written
+                    .fetch_sub(growth - grown, Ordering::SeqCst);
+                Ok(n)
+            }
+            Err(e) => {
+                self.bytes_written.fetch_sub(growth, Ordering::SeqCst);
+                Err(e)
+            }
+        }
+    }
+
+    /// Create a file, directory, or symlink with `create`.
+    pub(crate) fn create<T>(&self, create: impl FnOnce() -> FsResult<T>) -> FsResult<T> {
+        reserve(&self.created_inodes, self.limits.max_created_inodes, 1)?;
+        let result = create();
+        if result.is_err() {
+            self.created_inodes.fetch_sub(1, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+/// Add `n` to `used`, unless that would take it over `limit`.
+fn reserve(used: &AtomicU64, limit: Option<u64>, n: u64) -> FsResult<()> {
+    used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+        let used = used.checked_add(n)?;
+        match limit {
+            Some(limit) if used > limit => None,
+            _ => Some(used),
+        }
+    })
+    .map(drop)
+    .map_err(|_| ErrorCode::InsufficientSpace.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let quota = FsQuota::new(FsLimits {
+            max_bytes_written: Some(10),
+            max_file_size: Some(8),
+            max_created_inodes: Some(1),
+        });
+
+        // Short writes only use what they wrote.
+        assert_eq!(quota.write(6, || Ok((0, 0)), || Ok(4)).unwrap(), 4);
+        assert_eq!(quota.bytes_written(), 4);
+
+        // Overwriting doesn't count, and extending only counts what's added.
+        assert!(quota.write(4, || Ok((0, 4)), || Ok(4)).is_ok());
+        assert!(quota.write(4, || Ok((2, 4)), || Ok(4)).is_ok());
+        assert_eq!(quota.bytes_written(), 6);
+
+        // Too big for the file.
+        assert!(quota.write(4, || Ok((6, 6)), || unreachable!()).is_err());
+        // Too much in total.
+        assert!(quota.write(5, || Ok((0, 0)), || unreachable!()).is_err());
+        assert!(quota.write(4, || Ok((0, 0)), || Ok(4)).is_ok());
+        assert_eq!(quota.bytes_written(), 10);
+
+        // Failed creations don't count.
+        assert!(quota
+            .create(|| Err::<(), _>(ErrorCode::Exist.into()))
+            .is_err());
+        assert!(quota.create(|| Ok(())).is_ok());
+        assert!(quota.create(|| -> FsResult<()> { unreachable!() }).is_err());
+        assert_eq!(quota.created_inodes(), 1);
+    }
+}
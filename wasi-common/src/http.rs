@@ -0,0 +1,1005 @@
+//! Host-side resources for the `wasi:http` interfaces.
+//!
+//! The `wasi:http/types` interface models fields, requests, responses and
+//! bodies as `u32` handles. The types in this module are the values those
+//! handles refer to in the [`Table`]. Bodies are exposed to the guest as
+//! regular `wasi:io/streams` streams, so they live in the table as
+//! `Box<dyn InputStream>` and `Box<dyn OutputStream>`.
+
+use crate::wasi::types::{Error as HttpError, Method, Scheme};
+use crate::{InputStream, OutputStream, Table, TableError, WasiView};
+use anyhow::Error;
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+pub mod client;
+pub mod policy;
+pub mod record;
+pub mod server;
+mod wire;
+
+pub use client::WasiHttpClient;
+pub use policy::{HttpPolicy, WasiHttpPolicy};
+
+/// Per-instance state for the `wasi:http` interfaces.
+#[derive(Default)]
+pub struct WasiHttpCtx {
+    /// The `response-outparam` that `set-response-outparam` writes to.
+    response_outparam: Option<u32>,
+    /// The policy applied to outgoing requests, if any.
+    pub(crate) policy: Option<Box<dyn WasiHttpPolicy>>,
+    /// The client that sends outgoing requests, if not the default.
+    pub(crate) client: Option<Arc<dyn WasiHttpClient>>,
+    /// The most bytes an outgoing request body holds before the host takes
+    /// them, if limited.
+    pub(crate) request_buffer_limit: Option<u64>,
+    /// The most bytes an outgoing response body holds before the host takes
+    /// them, if limited.
+    pub(crate) response_buffer_limit: Option<u64>,
+}
+
+impl WasiHttpCtx {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `response-outparam` passed to the current call of the
+    /// `incoming-handler`, if any.
+    pub fn response_outparam(&self) -> Option<u32> {
+        self.response_outparam
+    }
+
+    /// The buffer limit for outgoing request bodies, if any.
+    pub fn request_buffer_limit(&self) -> Option<u64> {
+        self.request_buffer_limit
+    }
+
+    /// The buffer limit for outgoing response bodies, if any.
+    pub fn response_buffer_limit(&self) -> Option<u64> {
+        self.response_buffer_limit
+    }
+
+    /// Apply the outgoing request policy, if any, to `request`.
+    pub fn check(&self, request: &mut OutgoingRequest) -> Result<(), HttpError> {
+        match &self.policy {
+            Some(policy) => policy.check(request),
+            None => Ok(()),
+        }
+    }
+
+    /// Start sending `request` with the configured client, on a blocking
+    /// thread. The returned future resolves once the head of the response
+    /// has arrived.
+    pub fn send(
+        &self,
+        request: OutgoingRequest,
+        options: client::RequestOptions,
+    ) -> FutureIncomingResponse {
+        let client = self.client.clone();
+        FutureIncomingResponse::spawn(move || match &client {
+            Some(client) => client.send(&request, &options),
+            None => client::send(&request, &options),
+        })
+    }
+}
+
+/// HTTP fields, used for both headers and trailers.
+///
+/// Field names are compared case-insensitively, and the order in which
+/// entries were added is preserved.
+#[derive(Clone, Debug, Default)]
+pub struct Fields(Vec<(String, String)>);
+
+impl Fields {
+    pub fn new(entries: Vec<(String, String)>) -> Self {
+        Self(entries)
+    }
+
+    /// Return all values for the given field name.
+    pub fn get(&self, name: &str) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// Replace all values for the given field name.
+    pub fn set(&mut self, name: &str, values: Vec<String>) {
+        self.delete(name);
+        self.0
+            .extend(values.into_iter().map(|v| (name.to_owned(), v)));
+    }
+
+    /// Remove all values for the given field name.
+    pub fn delete(&mut self, name: &str) {
+        self.0.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+    }
+
+    /// Add a value for the given field name, keeping any existing values.
+    pub fn append(&mut self, name: &str, value: String) {
+        self.0.push((name.to_owned(), value));
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.0
+    }
+}
+
+/// The body of an incoming request or response.
+///
+/// This wraps the stream the body is read from, and carries the trailers
+/// returned by `finish-incoming-stream`.
+pub struct IncomingBody {
+    stream: Box<dyn InputStream>,
+    trailers: Arc<Mutex<Option<Fields>>>,
+}
+
+impl IncomingBody {
+    pub fn new(stream: impl InputStream + 'static) -> Self {
+        Self {
+            stream: Box::new(stream),
+            trailers: Arc::default(),
+        }
+    }
+
+    pub fn with_trailers(self, trailers: Fields) -> Self {
+        *self.trailers.lock().unwrap() = Some(trailers);
+        self
+    }
+
+    /// Share the trailers with the stream, for trailers that only become
+    /// known once the stream has been read to the end.
+    pub(crate) fn with_shared_trailers(mut self, trailers: Arc<Mutex<Option<Fields>>>) -> Self {
+        self.trailers = trailers;
+        self
+    }
+
+    pub fn trailers(&self) -> Option<Fields> {
+        self.trailers.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl InputStream for IncomingBody {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    #[cfg(unix)]
+    fn pollable_read(&self) -> Option<rustix::fd::BorrowedFd> {
+        self.stream.pollable_read()
+    }
+    #[cfg(windows)]
+    fn pollable_read(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
+        self.stream.pollable_read()
+    }
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(u64, bool), Error> {
+        self.stream.read(buf).await
+    }
+    async fn read_vectored<'a>(
+        &mut self,
+        bufs: &mut [std::io::IoSliceMut<'a>],
+    ) -> Result<(u64, bool), Error> {
+        self.stream.read_vectored(bufs).await
+    }
+    fn is_read_vectored(&self) -> bool {
+        self.stream.is_read_vectored()
+    }
+    async fn skip(&mut self, nelem: u64) -> Result<(u64, bool), Error> {
+        self.stream.skip(nelem).await
+    }
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.stream.num_ready_bytes().await
+    }
+    async fn readable(&self) -> Result<(), Error> {
+        self.stream.readable().await
+    }
+    async fn read_ready(&self) -> Result<bool, Error> {
+        self.stream.read_ready().await
+    }
+}
+
+#[derive(Default)]
+struct OutgoingBodyState {
+    buffer: Vec<u8>,
+    /// The most bytes `buffer` may hold, if it's limited.
+    limit: Option<u64>,
+    finished: bool,
+    trailers: Option<Fields>,
+    /// Whether the guest holds a stream for writing the body.
+    writing: bool,
+}
+
+impl OutgoingBodyState {
+    /// How many more bytes the buffer can take.
+    fn room(&self) -> u64 {
+        self.limit.map_or(u64::MAX, |limit| {
+            limit.saturating_sub(self.buffer.len() as u64)
+        })
+    }
+
+    /// Test whether the guest can't write any more of the body.
+    fn complete(&self) -> bool {
+        self.finished || !self.writing
+    }
+}
+
+#[derive(Default)]
+struct OutgoingBodyShared {
+    state: Mutex<OutgoingBodyState>,
+    /// Notified when bytes are written or taken, or the body is finished.
+    changed: tokio::sync::Notify,
+    /// The same as `changed`, for hosts waiting on a blocking thread.
+    changed_sync: std::sync::Condvar,
+}
+
+/// The body of an outgoing request or response.
+///
+/// The guest writes to this through an output stream; the host keeps a clone
+/// on the request or response so that it can collect the contents. With a
+/// buffer limit, the guest can only write once the host has taken what was
+/// written before.
+#[derive(Default)]
+pub struct OutgoingBody {
+    shared: Arc<OutgoingBodyShared>,
+    /// Whether this is the guest's stream, whose drop ends the body.
+    writer: bool,
+}
+
+impl Clone for OutgoingBody {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+            writer: false,
+        }
+    }
+}
+
+impl Drop for OutgoingBody {
+    fn drop(&mut self) {
+        if self.writer {
+            self.state().writing = false;
+            self.notify();
+        }
+    }
+}
+
+impl OutgoingBody {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return a finished body holding `contents`.
+    pub(crate) fn written(contents: Vec<u8>, trailers: Option<Fields>) -> Self {
+        let body = Self::new();
+        {
+            let mut state = body.state();
+            state.buffer = contents;
+            state.finished = true;
+            state.trailers = trailers;
+        }
+        body
+    }
+
+    /// Return the stream the guest writes the body through. The body is
+    /// complete once that stream is finished or dropped.
+    fn writer(&self) -> Self {
+        self.state().writing = true;
+        Self {
+            shared: Arc::clone(&self.shared),
+            writer: true,
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<OutgoingBodyState> {
+        self.shared.state.lock().unwrap()
+    }
+
+    fn notify(&self) {
+        self.shared.changed.notify_waiters();
+        self.shared.changed_sync.notify_all();
+    }
+
+    /// Limit how many written bytes the body holds before the host takes
+    /// them, or remove the limit with `None`.
+    pub fn set_buffer_limit(&self, limit: Option<u64>) {
+        self.state().limit = limit;
+        self.notify();
+    }
+
+    /// Return a copy of the bytes written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.state().buffer.clone()
+    }
+
+    /// Remove and return the bytes written so far.
+    pub fn take_contents(&self) -> Vec<u8> {
+        let contents = std::mem::take(&mut self.state().buffer);
+        self.notify();
+        contents
+    }
+
+    /// Block until there are bytes to take or the body is complete, and then
+    /// remove and return the bytes, along with whether the body is complete.
+    /// The body is complete once it's finished, or once the guest has
+    /// dropped its stream or never asked for one.
+    pub fn blocking_take_contents(&self) -> (Vec<u8>, bool) {
+        let mut state = self.state();
+        loop {
+            let complete = state.complete();
+            if !state.buffer.is_empty() || complete {
+                let contents = std::mem::take(&mut state.buffer);
+                drop(state);
+                self.notify();
+                return (contents, complete);
+            }
+            state = self.shared.changed_sync.wait(state).unwrap();
+        }
+    }
+
+    /// Block until the body is complete, and then remove and return all of
+    /// it.
+    pub fn blocking_take_all(&self) -> Vec<u8> {
+        let mut all = Vec::new();
+        loop {
+            let (contents, complete) = self.blocking_take_contents();
+            all.extend_from_slice(&contents);
+            if complete {
+                return all;
+            }
+        }
+    }
+
+    /// Test whether `finish-outgoing-stream` has been called for this body.
+    pub fn is_finished(&self) -> bool {
+        self.state().finished
+    }
+
+    /// Return the trailers passed to `finish-outgoing-stream`, if any.
+    pub fn trailers(&self) -> Option<Fields> {
+        self.state().trailers.clone()
+    }
+
+    /// Wait until there are bytes to take, or the body is finished.
+    pub async fn wait_for_contents(&self) {
+        loop {
+            let changed = self.shared.changed.notified();
+            {
+                let state = self.state();
+                if !state.buffer.is_empty() || state.finished {
+                    return;
+                }
+            }
+            changed.await;
+        }
+    }
+
+    pub(crate) fn finish(&self, trailers: Option<Fields>) -> Result<(), Error> {
+        let mut state = self.state();
+        if state.finished {
+            anyhow::bail!("outgoing stream already finished");
+        }
+        state.finished = true;
+        state.trailers = trailers;
+        drop(state);
+        self.notify();
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl OutputStream for OutgoingBody {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> Result<u64, Error> {
+        let mut state = self.state();
+        if state.finished {
+            anyhow::bail!("write to finished outgoing stream");
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(state.room()).unwrap_or(usize::MAX));
+        state.buffer.extend_from_slice(&buf[..len]);
+        drop(state);
+        if len != 0 {
+            self.notify();
+        }
+        Ok(len as u64)
+    }
+
+    async fn write_budget(&self) -> Result<u64, Error> {
+        Ok(self.state().room())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        loop {
+            let changed = self.shared.changed.notified();
+            {
+                let state = self.state();
+                if state.finished {
+                    anyhow::bail!("outgoing stream already finished");
+                }
+                if state.room() != 0 {
+                    return Ok(());
+                }
+            }
+            changed.await;
+        }
+    }
+
+    async fn write_ready(&self) -> Result<bool, Error> {
+        let state = self.state();
+        if state.finished {
+            anyhow::bail!("outgoing stream already finished");
+        }
+        Ok(state.room() != 0)
+    }
+}
+
+/// A request received by the host, to be handled by the guest.
+pub struct IncomingRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: String,
+    pub scheme: Option<Scheme>,
+    pub authority: String,
+    pub headers: Fields,
+    body: Option<IncomingBody>,
+}
+
+impl IncomingRequest {
+    pub fn new(
+        method: Method,
+        path: impl Into<String>,
+        query: impl Into<String>,
+        scheme: Option<Scheme>,
+        authority: impl Into<String>,
+        headers: Fields,
+        body: IncomingBody,
+    ) -> Self {
+        Self {
+            method,
+            path: path.into(),
+            query: query.into(),
+            scheme,
+            authority: authority.into(),
+            headers,
+            body: Some(body),
+        }
+    }
+
+    /// Take the body. This succeeds only once.
+    pub fn take_body(&mut self) -> Option<IncomingBody> {
+        self.body.take()
+    }
+}
+
+/// A request built by the guest, to be sent by the host.
+#[derive(Clone)]
+pub struct OutgoingRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: String,
+    pub scheme: Option<Scheme>,
+    pub authority: String,
+    pub headers: Fields,
+    pub body: OutgoingBody,
+    body_written: bool,
+}
+
+impl OutgoingRequest {
+    pub fn new(
+        method: Method,
+        path: impl Into<String>,
+        query: impl Into<String>,
+        scheme: Option<Scheme>,
+        authority: impl Into<String>,
+        headers: Fields,
+    ) -> Self {
+        Self {
+            method,
+            path: path.into(),
+            query: query.into(),
+            scheme,
+            authority: authority.into(),
+            headers,
+            body: OutgoingBody::new(),
+            body_written: false,
+        }
+    }
+
+    /// Return a stream for writing the body. This succeeds only once.
+    pub fn write_body(&mut self) -> Option<OutgoingBody> {
+        if std::mem::replace(&mut self.body_written, true) {
+            None
+        } else {
+            Some(self.body.writer())
+        }
+    }
+
+    /// Stop the guest from asking for a body stream, once the request has
+    /// been handed to a client. A request sent without one has an empty
+    /// body.
+    pub(crate) fn seal_body(&mut self) {
+        self.body_written = true;
+    }
+}
+
+/// A response received by the host, to be read by the guest.
+pub struct IncomingResponse {
+    pub status: u16,
+    pub headers: Fields,
+    body: Option<IncomingBody>,
+}
+
+impl IncomingResponse {
+    pub fn new(status: u16, headers: Fields, body: IncomingBody) -> Self {
+        Self {
+            status,
+            headers,
+            body: Some(body),
+        }
+    }
+
+    /// Take the body. This succeeds only once.
+    pub fn take_body(&mut self) -> Option<IncomingBody> {
+        self.body.take()
+    }
+}
+
+/// A response built by the guest, to be sent by the host.
+#[derive(Clone)]
+pub struct OutgoingResponse {
+    pub status: u16,
+    pub headers: Fields,
+    pub body: OutgoingBody,
+    body_written: bool,
+}
+
+impl OutgoingResponse {
+    pub fn new(status: u16, headers: Fields) -> Self {
+        Self {
+            status,
+            headers,
+            body: OutgoingBody::new(),
+            body_written: false,
+        }
+    }
+
+    /// Return a stream for writing the body. This succeeds only once.
+    pub fn write_body(&mut self) -> Option<OutgoingBody> {
+        if std::mem::replace(&mut self.body_written, true) {
+            None
+        } else {
+            Some(self.body.writer())
+        }
+    }
+}
+
+/// The slot an `incoming-handler` writes its response into.
+#[derive(Default)]
+pub struct ResponseOutparam {
+    response: Option<Result<u32, HttpError>>,
+    /// Where to send the response as soon as it is set, for hosts that start
+    /// sending it while the guest is still writing the body.
+    sender: Option<oneshot::Sender<Option<OutgoingResponse>>>,
+}
+
+impl ResponseOutparam {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an outparam that also sends the response to the returned
+    /// receiver as soon as the guest sets it, or `None` if the guest sets an
+    /// error instead.
+    pub fn with_receiver() -> (Self, oneshot::Receiver<Option<OutgoingResponse>>) {
+        let (sender, receiver) = oneshot::channel();
+        let outparam = Self {
+            response: None,
+            sender: Some(sender),
+        };
+        (outparam, receiver)
+    }
+
+    /// Return the response set by the guest: either the handle of an
+    /// `OutgoingResponse` in the table, or an error.
+    pub fn response(&self) -> Option<&Result<u32, HttpError>> {
+        self.response.as_ref()
+    }
+
+    /// Set the response, whose handle is `response`, or an error. `sent` is
+    /// the response itself, for the receiver, if any.
+    pub(crate) fn set(
+        &mut self,
+        response: Result<u32, HttpError>,
+        sent: Option<OutgoingResponse>,
+    ) -> Result<(), ()> {
+        if self.response.is_some() {
+            return Err(());
+        }
+        self.response = Some(response);
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(sent);
+        }
+        Ok(())
+    }
+}
+
+/// The eventual result of an outgoing request.
+pub struct FutureIncomingResponse(Arc<ResponseSlot>);
+
+enum ResponseState {
+    /// The request is still being sent.
+    Pending,
+    /// The response, or the error that prevented one, is available.
+    Ready(Result<IncomingResponse, HttpError>),
+    /// The result has already been handed to the guest.
+    Consumed,
+}
+
+/// Where the result of an outgoing request is left for the guest.
+struct ResponseSlot {
+    state: Mutex<ResponseState>,
+    /// Notified when the result becomes available.
+    ready: tokio::sync::Notify,
+}
+
+impl ResponseSlot {
+    fn new(state: ResponseState) -> Self {
+        Self {
+            state: Mutex::new(state),
+            ready: tokio::sync::Notify::new(),
+        }
+    }
+}
+
+impl FutureIncomingResponse {
+    pub fn ready(result: Result<IncomingResponse, HttpError>) -> Self {
+        Self(Arc::new(ResponseSlot::new(ResponseState::Ready(result))))
+    }
+
+    /// Run `send` on a blocking thread, and resolve the future with its
+    /// result.
+    pub fn spawn(
+        send: impl FnOnce() -> Result<IncomingResponse, HttpError> + Send + 'static,
+    ) -> Self {
+        let slot = Arc::new(ResponseSlot::new(ResponseState::Pending));
+        let task = {
+            let slot = Arc::clone(&slot);
+            move || {
+                let result = send();
+                *slot.state.lock().unwrap() = ResponseState::Ready(result);
+                slot.ready.notify_waiters();
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(task)),
+            Err(_) => drop(std::thread::spawn(task)),
+        }
+        Self(slot)
+    }
+
+    pub fn is_ready(&self) -> bool {
+        !matches!(*self.0.state.lock().unwrap(), ResponseState::Pending)
+    }
+
+    /// Take the result, or return `None` if it isn't available yet.
+    pub(crate) fn take(&mut self) -> Option<Result<IncomingResponse, HttpError>> {
+        let mut state = self.0.state.lock().unwrap();
+        match std::mem::replace(&mut *state, ResponseState::Consumed) {
+            ResponseState::Pending => {
+                *state = ResponseState::Pending;
+                None
+            }
+            ResponseState::Ready(result) => Some(result),
+            ResponseState::Consumed => Some(Err(HttpError::UnexpectedError(
+                "response already consumed".to_owned(),
+            ))),
+        }
+    }
+
+    /// A stream that is ready to read once the result is available, so that
+    /// the future can be polled along with streams.
+    pub(crate) fn pollable(&self) -> &dyn InputStream {
+        &*self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl InputStream for ResponseSlot {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        loop {
+            let ready = self.ready.notified();
+            if self.read_ready().await? {
+                return Ok(());
+            }
+            ready.await;
+        }
+    }
+
+    async fn read_ready(&self) -> Result<bool, Error> {
+        Ok(!matches!(
+            *self.state.lock().unwrap(),
+            ResponseState::Pending
+        ))
+    }
+}
+
+/// Create a `response-outparam` for a call to the guest's `incoming-handler`,
+/// and make it the target of the guest's `set-response-outparam`.
+pub fn push_response_outparam<T: WasiView>(view: &mut T) -> Result<u32, TableError> {
+    let outparam = view
+        .table_mut()
+        .push_response_outparam(ResponseOutparam::new())?;
+    view.ctx_mut().http.response_outparam = Some(outparam);
+    Ok(outparam)
+}
+
+/// Like `push_response_outparam`, but also return a receiver for the response
+/// as soon as the guest sets it. See `ResponseOutparam::with_receiver`.
+pub fn push_streaming_response_outparam<T: WasiView>(
+    view: &mut T,
+) -> Result<(u32, oneshot::Receiver<Option<OutgoingResponse>>), TableError> {
+    let (outparam, receiver) = ResponseOutparam::with_receiver();
+    let outparam = view.table_mut().push_response_outparam(outparam)?;
+    view.ctx_mut().http.response_outparam = Some(outparam);
+    Ok((outparam, receiver))
+}
+
+pub trait TableHttpExt {
+    fn push_fields(&mut self, fields: Fields) -> Result<u32, TableError>;
+    fn get_fields(&self, fd: u32) -> Result<&Fields, TableError>;
+    fn get_fields_mut(&mut self, fd: u32) -> Result<&mut Fields, TableError>;
+    fn delete_fields(&mut self, fd: u32) -> Result<(), TableError>;
+
+    fn push_incoming_request(&mut self, request: IncomingRequest) -> Result<u32, TableError>;
+    fn get_incoming_request(&self, fd: u32) -> Result<&IncomingRequest, TableError>;
+    fn get_incoming_request_mut(&mut self, fd: u32) -> Result<&mut IncomingRequest, TableError>;
+    fn delete_incoming_request(&mut self, fd: u32) -> Result<(), TableError>;
+
+    fn push_outgoing_request(&mut self, request: OutgoingRequest) -> Result<u32, TableError>;
+    fn get_outgoing_request(&self, fd: u32) -> Result<&OutgoingRequest, TableError>;
+    fn get_outgoing_request_mut(&mut self, fd: u32) -> Result<&mut OutgoingRequest, TableError>;
+    fn delete_outgoing_request(&mut self, fd: u32) -> Result<(), TableError>;
+
+    fn push_incoming_response(&mut self, response: IncomingResponse) -> Result<u32, TableError>;
+    fn get_incoming_response(&self, fd: u32) -> Result<&IncomingResponse, TableError>;
+    fn get_incoming_response_mut(&mut self, fd: u32) -> Result<&mut IncomingResponse, TableError>;
+    fn delete_incoming_response(&mut self, fd: u32) -> Result<(), TableError>;
+
+    fn push_outgoing_response(&mut self, response: OutgoingResponse) -> Result<u32, TableError>;
+    fn get_outgoing_response(&self, fd: u32) -> Result<&OutgoingResponse, TableError>;
+    fn get_outgoing_response_mut(&mut self, fd: u32) -> Result<&mut OutgoingResponse, TableError>;
+    fn delete_outgoing_response(&mut self, fd: u32) -> Result<(), TableError>;
+
+    fn push_response_outparam(&mut self, outparam: ResponseOutparam) -> Result<u32, TableError>;
+    fn get_response_outparam(&self, fd: u32) -> Result<&ResponseOutparam, TableError>;
+    fn get_response_outparam_mut(&mut self, fd: u32) -> Result<&mut ResponseOutparam, TableError>;
+    fn delete_response_outparam(&mut self, fd: u32) -> Result<(), TableError>;
+
+    fn push_future_incoming_response(
+        &mut self,
+        future: FutureIncomingResponse,
+    ) -> Result<u32, TableError>;
+    fn get_future_incoming_response(&self, fd: u32) -> Result<&FutureIncomingResponse, TableError>;
+    fn get_future_incoming_response_mut(
+        &mut self,
+        fd: u32,
+    ) -> Result<&mut FutureIncomingResponse, TableError>;
+    fn delete_future_incoming_response(&mut self, fd: u32) -> Result<(), TableError>;
+}
+
+impl TableHttpExt for Table {
+    fn push_fields(&mut self, fields: Fields) -> Result<u32, TableError> {
+        self.push(Box::new(fields))
+    }
+    fn get_fields(&self, fd: u32) -> Result<&Fields, TableError> {
+        self.get::<Fields>(fd)
+    }
+    fn get_fields_mut(&mut self, fd: u32) -> Result<&mut Fields, TableError> {
+        self.get_mut::<Fields>(fd)
+    }
+    fn delete_fields(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<Fields>(fd)
+    }
+
+    fn push_incoming_request(&mut self, request: IncomingRequest) -> Result<u32, TableError> {
+        self.push(Box::new(request))
+    }
+    fn get_incoming_request(&self, fd: u32) -> Result<&IncomingRequest, TableError> {
+        self.get::<IncomingRequest>(fd)
+    }
+    fn get_incoming_request_mut(&mut self, fd: u32) -> Result<&mut IncomingRequest, TableError> {
+        self.get_mut::<IncomingRequest>(fd)
+    }
+    fn delete_incoming_request(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<IncomingRequest>(fd)
+    }
+
+    fn push_outgoing_request(&mut self, request: OutgoingRequest) -> Result<u32, TableError> {
+        self.push(Box::new(request))
+    }
+    fn get_outgoing_request(&self, fd: u32) -> Result<&OutgoingRequest, TableError> {
+        self.get::<OutgoingRequest>(fd)
+    }
+    fn get_outgoing_request_mut(&mut self, fd: u32) -> Result<&mut OutgoingRequest, TableError> {
+        self.get_mut::<OutgoingRequest>(fd)
+    }
+    fn delete_outgoing_request(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<OutgoingRequest>(fd)
+    }
+
+    fn push_incoming_response(&mut self, response: IncomingResponse) -> Result<u32, TableError> {
+        self.push(Box::new(response))
+    }
+    fn get_incoming_response(&self, fd: u32) -> Result<&IncomingResponse, TableError> {
+        self.get::<IncomingResponse>(fd)
+    }
+    fn get_incoming_response_mut(&mut self, fd: u32) -> Result<&mut IncomingResponse, TableError> {
+        self.get_mut::<IncomingResponse>(fd)
+    }
+    fn delete_incoming_response(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<IncomingResponse>(fd)
+    }
+
+    fn push_outgoing_response(&mut self, response: OutgoingResponse) -> Result<u32, TableError> {
+        self.push(Box::new(response))
+    }
+    fn get_outgoing_response(&self, fd: u32) -> Result<&OutgoingResponse, TableError> {
+        self.get::<OutgoingResponse>(fd)
+    }
+    fn get_outgoing_response_mut(&mut self, fd: u32) -> Result<&mut OutgoingResponse, TableError> {
+        self.get_mut::<OutgoingResponse>(fd)
+    }
+    fn delete_outgoing_response(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<OutgoingResponse>(fd)
+    }
+
+    fn push_response_outparam(&mut self, outparam: ResponseOutparam) -> Result<u32, TableError> {
+        self.push(Box::new(outparam))
+    }
+    fn get_response_outparam(&self, fd: u32) -> Result<&ResponseOutparam, TableError> {
+        self.get::<ResponseOutparam>(fd)
+    }
+    fn get_response_outparam_mut(&mut self, fd: u32) -> Result<&mut ResponseOutparam, TableError> {
+        self.get_mut::<ResponseOutparam>(fd)
+    }
+    fn delete_response_outparam(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<ResponseOutparam>(fd)
+    }
+
+    fn push_future_incoming_response(
+        &mut self,
+        future: FutureIncomingResponse,
+    ) -> Result<u32, TableError> {
+        self.push(Box::new(future))
+    }
+    fn get_future_incoming_response(&self, fd: u32) -> Result<&FutureIncomingResponse, TableError> {
+        self.get::<FutureIncomingResponse>(fd)
+    }
+    fn get_future_incoming_response_mut(
+        &mut self,
+        fd: u32,
+    ) -> Result<&mut FutureIncomingResponse, TableError> {
+        self.get_mut::<FutureIncomingResponse>(fd)
+    }
+    fn delete_future_incoming_response(&mut self, fd: u32) -> Result<(), TableError> {
+        self.delete::<FutureIncomingResponse>(fd)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn fields_are_case_insensitive() {
+        let mut fields = Fields::new(vec![
+            ("Content-Type".to_owned(), "text/plain".to_owned()),
+            ("x-a".to_owned(), "1".to_owned()),
+        ]);
+        fields.append("X-A", "2".to_owned());
+        assert_eq!(fields.get("content-type"), vec!["text/plain"]);
+        assert_eq!(fields.get("x-a"), vec!["1", "2"]);
+        fields.set("x-A", vec!["3".to_owned()]);
+        assert_eq!(fields.get("X-a"), vec!["3"]);
+        fields.delete("CONTENT-TYPE");
+        assert!(fields.get("content-type").is_empty());
+    }
+
+    #[test]
+    fn fields_in_table() {
+        let mut table = Table::new();
+        let ix = table.push_fields(Fields::default()).unwrap();
+        let _ = table.get_fields(ix).unwrap();
+        let _ = table.get_fields_mut(ix).unwrap();
+        table.delete_fields(ix).unwrap();
+        let _ = table.get_fields(ix).err().unwrap();
+    }
+    #[test]
+    fn response_future_resolves_when_the_send_finishes() {
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut future = FutureIncomingResponse::spawn(move || {
+                wait.recv().unwrap();
+                Err(HttpError::TimeoutError("test".to_owned()))
+            });
+            assert!(!future.is_ready());
+            assert!(!future.pollable().read_ready().await.unwrap());
+            assert!(future.take().is_none());
+
+            release.send(()).unwrap();
+            while !future.pollable().read_ready().await.unwrap() {
+                tokio::task::yield_now().await;
+            }
+            assert!(matches!(
+                future.take(),
+                Some(Err(HttpError::TimeoutError(_)))
+            ));
+            assert!(matches!(
+                future.take(),
+                Some(Err(HttpError::UnexpectedError(_)))
+            ));
+        });
+    }
+
+    #[test]
+    fn outgoing_body_limit_is_per_stream() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut limited = OutgoingBody::new();
+            limited.set_buffer_limit(Some(4));
+            let mut unlimited = OutgoingBody::new();
+
+            assert_eq!(limited.write_budget().await.unwrap(), 4);
+            assert_eq!(limited.write(b"abcdef").await.unwrap(), 4);
+            assert_eq!(limited.write_budget().await.unwrap(), 0);
+            assert!(!limited.write_ready().await.unwrap());
+            assert_eq!(unlimited.write(b"abcdef").await.unwrap(), 6);
+            assert_eq!(unlimited.write_budget().await.unwrap(), u64::MAX);
+
+            // Taking the contents makes room for the rest.
+            assert_eq!(limited.take_contents(), b"abcd");
+            assert!(limited.write_ready().await.unwrap());
+            limited.writable().await.unwrap();
+            assert_eq!(limited.write(b"ef").await.unwrap(), 2);
+            assert_eq!(limited.contents(), b"ef");
+        });
+    }
+
+    #[test]
+    fn outgoing_body_writable_waits_for_room() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut body = OutgoingBody::new();
+            body.set_buffer_limit(Some(1));
+            body.write(b"a").await.unwrap();
+
+            let host = body.clone();
+            let taker = tokio::task::spawn(async move {
+                host.wait_for_contents().await;
+                host.take_contents()
+            });
+            body.writable().await.unwrap();
+            assert_eq!(taker.await.unwrap(), b"a");
+
+            body.finish(None).unwrap();
+            assert!(body.writable().await.is_err());
+            assert!(body.write_ready().await.is_err());
+        });
+    }
+}
@@ -0,0 +1,381 @@
+//! A minimal HTTP/1.1 client, used by the default `outgoing-handler`.
+//!
+//! Requests are sent over plain TCP with `connection: close`, so a response
+//! body without a length or chunked framing ends when the server closes the
+//! connection. HTTPS is not supported.
+
+use super::wire::{self, io_error, Framing, WireBody};
+use super::{Fields, IncomingBody, IncomingResponse, OutgoingRequest};
+use crate::wasi::types::{Error as HttpError, Method, Scheme};
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The connect timeout used when a request doesn't set one.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// The first-byte and between-bytes timeout used when a request doesn't set
+/// one.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Timeouts for an outgoing request. Unset timeouts fall back to
+/// [`DEFAULT_CONNECT_TIMEOUT`] and [`DEFAULT_READ_TIMEOUT`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestOptions {
+    /// The timeout for the initial connect.
+    pub connect_timeout: Option<Duration>,
+    /// The timeout for receiving the first byte of the response.
+    pub first_byte_timeout: Option<Duration>,
+    /// The timeout for receiving the next chunk of bytes in the response body.
+    pub between_bytes_timeout: Option<Duration>,
+}
+
+impl From<crate::wasi::types::RequestOptions> for RequestOptions {
+    fn from(options: crate::wasi::types::RequestOptions) -> Self {
+        let ms = |t: Option<u32>| t.map(|ms| Duration::from_millis(ms.into()));
+        Self {
+            connect_timeout: ms(options.connect_timeout_ms),
+            first_byte_timeout: ms(options.first_byte_timeout_ms),
+            between_bytes_timeout: ms(options.between_bytes_timeout_ms),
+        }
+    }
+}
+
+/// A client for the requests guests send through the default
+/// `outgoing-handler`.
+pub trait WasiHttpClient: Send + Sync {
+    fn send(
+        &self,
+        request: &OutgoingRequest,
+        options: &RequestOptions,
+    ) -> Result<IncomingResponse, HttpError>;
+}
+
+/// Send `request` and wait for the head of the response. The response body
+/// is read from the connection as the guest consumes it.
+pub fn send(
+    request: &OutgoingRequest,
+    options: &RequestOptions,
+) -> Result<IncomingResponse, HttpError> {
+    let (status, headers, body) = send_request(request, options)?;
+    let trailers = Arc::clone(&body.trailers);
+    Ok(IncomingResponse::new(
+        status,
+        headers,
+        IncomingBody::new(body).with_shared_trailers(trailers),
+    ))
+}
+
+/// Send `request` and read the whole response, returning its status,
+/// headers, body, and trailers.
+pub(super) fn send_buffered(
+    request: &OutgoingRequest,
+    options: &RequestOptions,
+) -> Result<(u16, Fields, Vec<u8>, Option<Fields>), HttpError> {
+    let (status, headers, body) = send_request(request, options)?;
+    let (contents, trailers) = body.read_to_end().map_err(wire::read_error)?;
+    Ok((status, headers, contents, trailers))
+}
+
+fn send_request(
+    request: &OutgoingRequest,
+    options: &RequestOptions,
+) -> Result<(u16, Fields, WireBody), HttpError> {
+    match &request.scheme {
+        None | Some(Scheme::Http) => {}
+        Some(Scheme::Https) => {
+            return Err(HttpError::UnexpectedError(
+                "HTTPS is not supported".to_owned(),
+            ))
+        }
+        Some(Scheme::Other(s)) => return Err(HttpError::InvalidUrl(format!("scheme {s:?}"))),
+    }
+
+    let mut stream = connect(
+        &request.authority,
+        options.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+    )?;
+    let first_byte_timeout = options.first_byte_timeout.unwrap_or(DEFAULT_READ_TIMEOUT);
+    let between_bytes_timeout = options
+        .between_bytes_timeout
+        .unwrap_or(DEFAULT_READ_TIMEOUT);
+
+    stream
+        .set_write_timeout(Some(between_bytes_timeout))
+        .map_err(io_error)?;
+    write_request(&mut stream, request)?;
+
+    stream
+        .set_read_timeout(Some(first_byte_timeout))
+        .map_err(io_error)?;
+    let mut reader = BufReader::new(stream);
+    let (status, headers) = read_response_head(&mut reader)?;
+    reader
+        .get_ref()
+        .set_read_timeout(Some(between_bytes_timeout))
+        .map_err(io_error)?;
+
+    let framing = if matches!(request.method, Method::Head) || status == 204 || status == 304 {
+        Framing::Length(0)
+    } else {
+        Framing::from_headers(&headers)?.unwrap_or(Framing::Eof)
+    };
+    let body = WireBody::new(reader, framing).map_err(io_error)?;
+    Ok((status, headers, body))
+}
+
+fn socket_addrs(authority: &str) -> io::Result<Vec<SocketAddr>> {
+    let has_port = match authority.rsplit_once(':') {
+        Some((host, port)) => {
+            port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+        }
+        None => false,
+    };
+    if has_port {
+        Ok(authority.to_socket_addrs()?.collect())
+    } else {
+        let host = authority.trim_start_matches('[').trim_end_matches(']');
+        Ok((host, 80).to_socket_addrs()?.collect())
+    }
+}
+
+fn connect(authority: &str, timeout: Duration) -> Result<TcpStream, HttpError> {
+    if authority.is_empty() {
+        return Err(HttpError::InvalidUrl("missing authority".to_owned()));
+    }
+    let addrs =
+        socket_addrs(authority).map_err(|e| HttpError::InvalidUrl(format!("{authority}: {e}")))?;
+
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => io_error(e),
+        None => HttpError::InvalidUrl(format!("{authority}: no addresses")),
+    })
+}
+
+/// Write `request` to `stream`, sending the body as the guest writes it. A
+/// body that's already complete is sent in one piece; any other is sent
+/// chunked until the guest finishes it or drops its stream.
+fn write_request(stream: &mut TcpStream, request: &OutgoingRequest) -> Result<(), HttpError> {
+    let mut out = encode_head(request)?;
+    let (mut contents, mut complete) = request.body.blocking_take_contents();
+    if complete {
+        wire::write_contents(
+            &mut out,
+            &contents,
+            request.body.trailers().as_ref(),
+            matches!(request.method, Method::Post | Method::Put | Method::Patch),
+        )?;
+    } else {
+        out.extend_from_slice(b"transfer-encoding: chunked\r\n\r\n");
+        loop {
+            wire::write_chunk(&mut out, &contents);
+            if complete {
+                wire::write_last_chunk(&mut out, request.body.trailers().as_ref())?;
+                break;
+            }
+            stream.write_all(&out).map_err(io_error)?;
+            out.clear();
+            (contents, complete) = request.body.blocking_take_contents();
+        }
+    }
+    stream.write_all(&out).map_err(io_error)?;
+    stream.flush().map_err(io_error)
+}
+
+/// Return the request line and headers of `request`, without the blank line
+/// that ends the head.
+fn encode_head(request: &OutgoingRequest) -> Result<Vec<u8>, HttpError> {
+    let target = wire::origin_form(request)?;
+
+    // We frame the body and manage the connection ourselves.
+    let mut headers = request.headers.clone();
+    headers.delete("connection");
+    headers.delete("content-length");
+    headers.delete("transfer-encoding");
+    if headers.get("host").is_empty() {
+        headers.append("host", request.authority.clone());
+    }
+
+    let mut out = format!(
+        "{} {} HTTP/1.1\r\n",
+        wire::method_str(&request.method)?,
+        target
+    )
+    .into_bytes();
+    wire::write_fields(&mut out, &headers)?;
+    out.extend_from_slice(b"connection: close\r\n");
+    Ok(out)
+}
+
+fn read_response_head(reader: &mut BufReader<TcpStream>) -> Result<(u16, Fields), HttpError> {
+    loop {
+        let (line, headers) = wire::read_head(reader)?;
+        let mut parts = line.splitn(3, ' ');
+        let version = parts.next().unwrap_or("");
+        if !version.starts_with("HTTP/1.") {
+            return Err(HttpError::ProtocolError(format!(
+                "unexpected status line {line:?}"
+            )));
+        }
+        let status: u16 = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| HttpError::ProtocolError(format!("invalid status line {line:?}")))?;
+
+        // Skip over informational responses such as `100 Continue`.
+        if (100..200).contains(&status) {
+            continue;
+        }
+        return Ok((status, headers));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+
+    fn serve_once(response: &'static [u8]) -> (String, std::thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = Vec::new();
+            loop {
+                let n = reader.read_until(b'\n', &mut request).unwrap();
+                if n == 0 || request.ends_with(b"\r\n\r\n") {
+                    break;
+                }
+            }
+            reader.get_mut().write_all(response).unwrap();
+            request
+        });
+        (authority, server)
+    }
+
+    #[test]
+    fn chunked_response_with_trailers() {
+        let (authority, server) = serve_once(
+            b"HTTP/1.1 200 OK\r\nx-test: yes\r\ntransfer-encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n7\r\n, world\r\n0\r\nx-trailer: done\r\n\r\n",
+        );
+        let request = OutgoingRequest::new(
+            Method::Get,
+            "/greeting",
+            "lang=en",
+            Some(Scheme::Http),
+            authority,
+            Fields::new(vec![("accept".to_owned(), "*/*".to_owned())]),
+        );
+        let (status, headers, mut body) =
+            send_request(&request, &RequestOptions::default()).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(headers.get("X-Test"), vec!["yes"]);
+
+        let sent = String::from_utf8(server.join().unwrap()).unwrap();
+        assert!(sent.starts_with("GET /greeting?lang=en HTTP/1.1\r\n"));
+        assert!(sent.contains("accept: */*\r\n"));
+        assert!(sent.contains("connection: close\r\n"));
+
+        let mut contents = Vec::new();
+        let mut buf = [0; 4];
+        loop {
+            let (n, end) = body.read_body(&mut buf).unwrap();
+            contents.extend_from_slice(&buf[..n as usize]);
+            if end {
+                break;
+            }
+        }
+        assert_eq!(contents, b"hello, world");
+        let trailers = body.trailers.lock().unwrap().clone().unwrap();
+        assert_eq!(trailers.get("x-trailer"), vec!["done"]);
+    }
+
+    #[test]
+    fn body_written_after_send() {
+        use crate::OutputStream;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                reader.read_until(b'\n', &mut head).unwrap();
+            }
+            let framing = Framing::Chunked {
+                remaining: 0,
+                done: false,
+            };
+            let (body, _) = framing.read_to_end(&mut reader).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            (String::from_utf8(head).unwrap(), body)
+        });
+
+        let mut request = OutgoingRequest::new(
+            Method::Post,
+            "/upload",
+            "",
+            Some(Scheme::Http),
+            authority,
+            Fields::default(),
+        );
+        let mut body = request.write_body().unwrap();
+        request.seal_body();
+        let sender = std::thread::spawn(move || {
+            send(&request, &RequestOptions::default()).map(|response| response.status)
+        });
+
+        // The body is written after the request is sent, and ends when the
+        // stream is dropped.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert_eq!(body.write(b"hello, ").await.unwrap(), 7);
+            assert_eq!(body.write(b"world").await.unwrap(), 5);
+        });
+        drop(body);
+
+        assert_eq!(sender.join().unwrap().unwrap(), 200);
+        let (head, body) = server.join().unwrap();
+        assert!(head.starts_with("POST /upload HTTP/1.1\r\n"));
+        assert!(head.contains("transfer-encoding: chunked\r\n"));
+        assert_eq!(body, b"hello, world");
+    }
+
+    #[test]
+    fn first_byte_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let request = OutgoingRequest::new(
+            Method::Get,
+            "/",
+            "",
+            None,
+            listener.local_addr().unwrap().to_string(),
+            Fields::default(),
+        );
+        let options = RequestOptions {
+            first_byte_timeout: Some(Duration::from_millis(50)),
+            ..RequestOptions::default()
+        };
+        match send(&request, &options) {
+            Err(HttpError::TimeoutError(_)) => {}
+            Err(e) => panic!("unexpected error {e:?}"),
+            Ok(_) => panic!("unexpected response"),
+        }
+    }
+}
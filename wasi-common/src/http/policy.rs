@@ -0,0 +1,192 @@
+//! Host policy for requests sent through the default `outgoing-handler`.
+
+use super::wire;
+use super::OutgoingRequest;
+use crate::wasi::types::{Error as HttpError, Scheme};
+
+/// A policy applied to every request a guest sends.
+///
+/// The policy may modify the request before it is sent. Returning an error
+/// denies the request, and the error becomes the guest's response.
+pub trait WasiHttpPolicy: Send + Sync {
+    fn check(&self, request: &mut OutgoingRequest) -> Result<(), HttpError>;
+}
+
+/// A [`WasiHttpPolicy`] built from allowlists and rewrite rules.
+///
+/// Each allowlist that is left empty allows everything. Allowlists are
+/// checked against the request as the guest made it, before any rewrites.
+/// Authorities are compared without the default port of the request's
+/// scheme, so `"example.com"` and `"example.com:80"` are the same for `http`.
+#[derive(Clone, Debug, Default)]
+pub struct HttpPolicy {
+    schemes: Vec<String>,
+    authorities: Vec<String>,
+    methods: Vec<String>,
+    rewrites: Vec<(String, String)>,
+    strip_headers: Vec<String>,
+    set_headers: Vec<(String, String)>,
+}
+
+impl HttpPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow requests with the given scheme, such as `"http"`.
+    pub fn allow_scheme(mut self, scheme: impl AsRef<str>) -> Self {
+        self.schemes.push(scheme.as_ref().to_ascii_lowercase());
+        self
+    }
+
+    /// Allow requests to the given authority, such as `"example.com:8080"`.
+    pub fn allow_authority(mut self, authority: impl AsRef<str>) -> Self {
+        self.authorities
+            .push(authority.as_ref().to_ascii_lowercase());
+        self
+    }
+
+    /// Allow requests with the given method, such as `"GET"`.
+    pub fn allow_method(mut self, method: impl AsRef<str>) -> Self {
+        self.methods.push(method.as_ref().to_ascii_uppercase());
+        self
+    }
+
+    /// Send requests for authority `from` to authority `to` instead.
+    pub fn rewrite_authority(mut self, from: impl AsRef<str>, to: impl AsRef<str>) -> Self {
+        self.rewrites
+            .push((from.as_ref().to_ascii_lowercase(), to.as_ref().to_owned()));
+        self
+    }
+
+    /// Remove all values of the given header from requests.
+    pub fn strip_header(mut self, name: impl AsRef<str>) -> Self {
+        self.strip_headers.push(name.as_ref().to_owned());
+        self
+    }
+
+    /// Set the given header on requests, replacing any values the guest set.
+    pub fn set_header(mut self, name: impl AsRef<str>, value: impl AsRef<str>) -> Self {
+        self.set_headers
+            .push((name.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+}
+
+impl WasiHttpPolicy for HttpPolicy {
+    fn check(&self, request: &mut OutgoingRequest) -> Result<(), HttpError> {
+        let scheme = match &request.scheme {
+            None | Some(Scheme::Http) => "http",
+            Some(Scheme::Https) => "https",
+            Some(Scheme::Other(s)) => s.as_str(),
+        };
+        if !self.schemes.is_empty() && !self.schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+        {
+            return Err(denied(format!("scheme {scheme:?}")));
+        }
+
+        let authority = normalize_authority(&request.authority, scheme);
+        if !self.authorities.is_empty()
+            && !self
+                .authorities
+                .iter()
+                .any(|a| normalize_authority(a, scheme) == authority)
+        {
+            return Err(denied(format!("authority {:?}", request.authority)));
+        }
+
+        let method = wire::method_str(&request.method)?;
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m == method) {
+            return Err(denied(format!("method {method:?}")));
+        }
+
+        if let Some((_, to)) = self
+            .rewrites
+            .iter()
+            .find(|(from, _)| normalize_authority(from, scheme) == authority)
+        {
+            request.authority = to.clone();
+        }
+        for name in &self.strip_headers {
+            request.headers.delete(name);
+        }
+        for (name, value) in &self.set_headers {
+            request.headers.set(name, vec![value.clone()]);
+        }
+        Ok(())
+    }
+}
+
+/// Lowercase `authority` and remove the default port of `scheme` from it.
+fn normalize_authority(authority: &str, scheme: &str) -> String {
+    let authority = authority.to_ascii_lowercase();
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => ":80",
+        "https" => ":443",
+        _ => return authority,
+    };
+    match authority.strip_suffix(default_port) {
+        Some(host) => host.to_owned(),
+        None => authority,
+    }
+}
+
+fn denied(what: String) -> HttpError {
+    HttpError::InvalidUrl(format!("{what} denied by policy"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::Fields;
+    use crate::wasi::types::Method;
+
+    fn request(method: Method, authority: &str) -> OutgoingRequest {
+        let headers = Fields::new(vec![
+            ("authorization".to_owned(), "secret".to_owned()),
+            ("user-agent".to_owned(), "guest".to_owned()),
+        ]);
+        OutgoingRequest::new(method, "/", "", Some(Scheme::Http), authority, headers)
+    }
+
+    #[test]
+    fn allowlists() {
+        let policy = HttpPolicy::new()
+            .allow_scheme("http")
+            .allow_authority("example.com")
+            .allow_method("GET");
+        assert!(policy
+            .check(&mut request(Method::Get, "Example.com"))
+            .is_ok());
+        assert!(policy
+            .check(&mut request(Method::Get, "example.com:80"))
+            .is_ok());
+        assert!(matches!(
+            policy.check(&mut request(Method::Get, "other.com")),
+            Err(HttpError::InvalidUrl(_))
+        ));
+        assert!(policy
+            .check(&mut request(Method::Get, "example.com:8080"))
+            .is_err());
+        assert!(policy
+            .check(&mut request(Method::Post, "example.com"))
+            .is_err());
+
+        let mut https = request(Method::Get, "example.com");
+        https.scheme = Some(Scheme::Https);
+        assert!(policy.check(&mut https).is_err());
+    }
+
+    #[test]
+    fn rewrites() {
+        let policy = HttpPolicy::new()
+            .rewrite_authority("example.com", "127.0.0.1:8080")
+            .strip_header("authorization")
+            .set_header("user-agent", "host");
+        let mut req = request(Method::Get, "example.com");
+        policy.check(&mut req).unwrap();
+        assert_eq!(req.authority, "127.0.0.1:8080");
+        assert!(req.headers.get("authorization").is_empty());
+        assert_eq!(req.headers.get("user-agent"), vec!["host"]);
+    }
+}
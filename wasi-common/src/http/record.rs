@@ -0,0 +1,309 @@
+//! Recording and replaying outgoing HTTP exchanges.
+//!
+//! A [`Recorder`] sends requests over the network and appends each request
+//! and its response to a file. A [`Replayer`] answers requests from such a
+//! file without touching the network, which makes tests of guests that send
+//! requests hermetic.
+//!
+//! Recordings use the HTTP/1.1 wire format: each exchange is a request with
+//! an absolute-form target, followed by its response.
+
+use super::client::{self, RequestOptions, WasiHttpClient};
+use super::wire::{self, io_error, Framing};
+use super::{Fields, IncomingBody, IncomingResponse, OutgoingBody, OutgoingRequest};
+use crate::pipe::ReadPipe;
+use crate::wasi::types::{Error as HttpError, Scheme};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// A [`WasiHttpClient`] that sends requests over the network and records
+/// every response it receives.
+///
+/// Requests that fail without a response are not recorded.
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Record to a new file at `path`, replacing any existing file.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            file: Mutex::new(File::create(path)?),
+        })
+    }
+}
+
+impl WasiHttpClient for Recorder {
+    fn send(
+        &self,
+        request: &OutgoingRequest,
+        options: &RequestOptions,
+    ) -> Result<IncomingResponse, HttpError> {
+        // The whole body is recorded, so it's collected before it's sent.
+        let body = request.body.blocking_take_all();
+        let body_trailers = request.body.trailers();
+        let sent = OutgoingRequest {
+            body: OutgoingBody::written(body.clone(), body_trailers.clone()),
+            ..request.clone()
+        };
+        let (status, headers, contents, trailers) = client::send_buffered(&sent, options)?;
+
+        let mut out = Vec::new();
+        encode_request(&mut out, request, &body, body_trailers.as_ref())?;
+        encode_response(&mut out, status, &headers, &contents, trailers.as_ref())?;
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&out).map_err(io_error)?;
+        file.flush().map_err(io_error)?;
+
+        Ok(response(status, headers, contents, trailers))
+    }
+}
+
+struct Exchange {
+    method: String,
+    target: String,
+    request_headers: Fields,
+    body: Vec<u8>,
+    status: u16,
+    headers: Fields,
+    contents: Vec<u8>,
+    trailers: Option<Fields>,
+}
+
+/// A [`WasiHttpClient`] that answers requests from a recording.
+///
+/// A request is answered by the first unused exchange in the recording with
+/// the same method, target, and body, and the same values for each header
+/// passed to [`Replayer::match_header`]. Each exchange answers one request.
+pub struct Replayer {
+    exchanges: Mutex<Vec<Option<Exchange>>>,
+    match_headers: Vec<String>,
+}
+
+impl Replayer {
+    /// Load the recording at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut exchanges = Vec::new();
+        while !reader.fill_buf()?.is_empty() {
+            exchanges.push(Some(read_exchange(&mut reader)?));
+        }
+        Ok(Self {
+            exchanges: Mutex::new(exchanges),
+            match_headers: Vec::new(),
+        })
+    }
+
+    /// Also require the values of the given header to match, such as
+    /// `"accept"`.
+    pub fn match_header(mut self, name: impl AsRef<str>) -> Self {
+        self.match_headers.push(name.as_ref().to_owned());
+        self
+    }
+}
+
+impl WasiHttpClient for Replayer {
+    fn send(
+        &self,
+        request: &OutgoingRequest,
+        _options: &RequestOptions,
+    ) -> Result<IncomingResponse, HttpError> {
+        let method = wire::method_str(&request.method)?;
+        let target = absolute_form(request)?;
+        let body = request.body.blocking_take_all();
+
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let exchange = exchanges
+            .iter_mut()
+            .find(|e| {
+                e.as_ref().map_or(false, |e| {
+                    e.method == method
+                        && e.target == target
+                        && e.body == body
+                        && self
+                            .match_headers
+                            .iter()
+                            .all(|name| e.request_headers.get(name) == request.headers.get(name))
+                })
+            })
+            .and_then(Option::take)
+            .ok_or_else(|| {
+                HttpError::UnexpectedError(format!("no recorded response for {method} {target}"))
+            })?;
+
+        Ok(response(
+            exchange.status,
+            exchange.headers,
+            exchange.contents,
+            exchange.trailers,
+        ))
+    }
+}
+
+fn response(
+    status: u16,
+    headers: Fields,
+    contents: Vec<u8>,
+    trailers: Option<Fields>,
+) -> IncomingResponse {
+    let mut body = IncomingBody::new(ReadPipe::from(contents));
+    if let Some(trailers) = trailers {
+        body = body.with_trailers(trailers);
+    }
+    IncomingResponse::new(status, headers, body)
+}
+
+fn absolute_form(request: &OutgoingRequest) -> Result<String, HttpError> {
+    let scheme = match &request.scheme {
+        None | Some(Scheme::Http) => "http",
+        Some(Scheme::Https) => "https",
+        Some(Scheme::Other(s)) => s.as_str(),
+    };
+    Ok(format!(
+        "{scheme}://{}{}",
+        request.authority,
+        wire::origin_form(request)?
+    ))
+}
+
+/// Remove the headers that the recording's own framing replaces.
+fn without_framing(headers: &Fields) -> Fields {
+    let mut headers = headers.clone();
+    headers.delete("content-length");
+    headers.delete("transfer-encoding");
+    headers
+}
+
+fn encode_request(
+    out: &mut Vec<u8>,
+    request: &OutgoingRequest,
+    body: &[u8],
+    trailers: Option<&Fields>,
+) -> Result<(), HttpError> {
+    let method = wire::method_str(&request.method)?;
+    out.extend_from_slice(format!("{method} {} HTTP/1.1\r\n", absolute_form(request)?).as_bytes());
+    wire::write_fields(out, &without_framing(&request.headers))?;
+    wire::write_contents(out, body, trailers, true)
+}
+
+fn encode_response(
+    out: &mut Vec<u8>,
+    status: u16,
+    headers: &Fields,
+    contents: &[u8],
+    trailers: Option<&Fields>,
+) -> Result<(), HttpError> {
+    out.extend_from_slice(format!("HTTP/1.1 {status} \r\n").as_bytes());
+    wire::write_fields(out, &without_framing(headers))?;
+    wire::write_contents(out, contents, trailers, true)
+}
+
+fn read_exchange(reader: &mut impl BufRead) -> io::Result<Exchange> {
+    let invalid = |e: HttpError| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}"));
+
+    let (line, request_headers) = wire::read_head(reader).map_err(invalid)?;
+    let (method, target) = match line.split(' ').collect::<Vec<_>>()[..] {
+        [method, target, _version] => (method.to_owned(), target.to_owned()),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid request line {line:?}"),
+            ))
+        }
+    };
+    let framing = Framing::from_headers(&request_headers)
+        .map_err(invalid)?
+        .unwrap_or(Framing::Length(0));
+    let (body, _) = framing.read_to_end(reader)?;
+
+    let (line, headers) = wire::read_head(reader).map_err(invalid)?;
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid status line {line:?}"),
+            )
+        })?;
+    let framing = Framing::from_headers(&headers)
+        .map_err(invalid)?
+        .unwrap_or(Framing::Length(0));
+    let (contents, trailers) = framing.read_to_end(reader)?;
+
+    Ok(Exchange {
+        method,
+        target,
+        request_headers: without_framing(&request_headers),
+        body,
+        status,
+        headers: without_framing(&headers),
+        contents,
+        trailers,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasi::types::Method;
+    use std::net::TcpListener;
+
+    #[test]
+    fn record_and_replay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let authority = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                reader.read_until(b'\n', &mut request).unwrap();
+            }
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nx-test: yes\r\ncontent-length: 2\r\n\r\nok")
+                .unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording");
+        let request = |accept: &str| {
+            OutgoingRequest::new(
+                Method::Get,
+                "/recorded",
+                "",
+                Some(Scheme::Http),
+                authority.clone(),
+                Fields::new(vec![("accept".to_owned(), accept.to_owned())]),
+            )
+        };
+
+        let recorder = Recorder::create(&path).unwrap();
+        let response = recorder
+            .send(&request("text/plain"), &RequestOptions::default())
+            .unwrap();
+        assert_eq!(response.status, 200);
+        server.join().unwrap();
+
+        // The server is gone, so these responses come from the recording.
+        let replayer = Replayer::open(&path).unwrap().match_header("accept");
+        assert!(replayer
+            .send(&request("application/json"), &RequestOptions::default())
+            .is_err());
+        let response = replayer
+            .send(&request("text/plain"), &RequestOptions::default())
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("x-test"), vec!["yes"]);
+        assert!(response.headers.get("content-length").is_empty());
+
+        // Each exchange answers only one request.
+        assert!(replayer
+            .send(&request("text/plain"), &RequestOptions::default())
+            .is_err());
+    }
+}
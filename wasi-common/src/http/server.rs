@@ -0,0 +1,197 @@
+//! A minimal HTTP/1.1 server side, used by hosts that serve the proxy world.
+//!
+//! Each connection carries a single request: responses are sent with
+//! `connection: close`.
+
+use super::wire::{self, io_error, Framing, WireBody};
+use super::{Fields, IncomingBody, IncomingRequest, OutgoingResponse};
+use crate::wasi::types::{Error as HttpError, Scheme};
+use std::io::{BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+/// Read the head of a request from `stream`. The request body is read from
+/// the connection as the guest consumes it.
+pub fn read_request(stream: &TcpStream) -> Result<IncomingRequest, HttpError> {
+    let mut reader = BufReader::new(stream.try_clone().map_err(io_error)?);
+    let (line, headers) = wire::read_head(&mut reader)?;
+
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) if !method.is_empty() => {
+            (method, target, version)
+        }
+        _ => {
+            return Err(HttpError::ProtocolError(format!(
+                "invalid request line {line:?}"
+            )))
+        }
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(HttpError::ProtocolError(format!(
+            "unsupported version {version:?}"
+        )));
+    }
+    if !target.starts_with('/') {
+        return Err(HttpError::InvalidUrl(format!(
+            "unsupported request target {target:?}"
+        )));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let authority = headers.get("host").into_iter().next().unwrap_or_default();
+
+    // Requests without framing headers have no body.
+    let framing = Framing::from_headers(&headers)?.unwrap_or(Framing::Length(0));
+    let body = WireBody::new(reader, framing).map_err(io_error)?;
+    let trailers = Arc::clone(&body.trailers);
+
+    Ok(IncomingRequest::new(
+        wire::parse_method(method),
+        path,
+        query,
+        Some(Scheme::Http),
+        authority,
+        headers,
+        IncomingBody::new(body).with_shared_trailers(trailers),
+    ))
+}
+
+/// Write `response`, including everything written to its body so far, to
+/// `stream`.
+pub fn write_response(
+    stream: &mut impl Write,
+    response: &OutgoingResponse,
+) -> Result<(), HttpError> {
+    let mut out = response_head(response)?;
+    wire::write_body(&mut out, &response.body, true)?;
+
+    stream.write_all(&out).map_err(io_error)?;
+    stream.flush().map_err(io_error)
+}
+
+/// Write the head of `response` to `stream`, announcing a chunked body. The
+/// body is then sent with `write_chunk` and `write_last_chunk` as the guest
+/// writes it.
+pub fn write_response_head(
+    stream: &mut impl Write,
+    response: &OutgoingResponse,
+) -> Result<(), HttpError> {
+    let mut out = response_head(response)?;
+    out.extend_from_slice(b"transfer-encoding: chunked\r\n\r\n");
+
+    stream.write_all(&out).map_err(io_error)?;
+    stream.flush().map_err(io_error)
+}
+
+/// Write one chunk of a body announced by `write_response_head`.
+pub fn write_chunk(stream: &mut impl Write, contents: &[u8]) -> Result<(), HttpError> {
+    if contents.is_empty() {
+        return Ok(());
+    }
+    let mut out = Vec::new();
+    wire::write_chunk(&mut out, contents);
+
+    stream.write_all(&out).map_err(io_error)?;
+    stream.flush().map_err(io_error)
+}
+
+/// End a body announced by `write_response_head`, with optional trailers.
+pub fn write_last_chunk(
+    stream: &mut impl Write,
+    trailers: Option<&Fields>,
+) -> Result<(), HttpError> {
+    let mut out = Vec::new();
+    wire::write_last_chunk(&mut out, trailers)?;
+
+    stream.write_all(&out).map_err(io_error)?;
+    stream.flush().map_err(io_error)
+}
+
+/// Return the status line and headers of `response`, without the blank line
+/// that ends the head.
+fn response_head(response: &OutgoingResponse) -> Result<Vec<u8>, HttpError> {
+    if !(100..1000).contains(&response.status) {
+        return Err(HttpError::ProtocolError(format!(
+            "invalid status {}",
+            response.status
+        )));
+    }
+
+    // We frame the body and manage the connection ourselves.
+    let mut headers = response.headers.clone();
+    headers.delete("connection");
+    headers.delete("content-length");
+    headers.delete("transfer-encoding");
+
+    let mut out = format!("HTTP/1.1 {} \r\n", response.status).into_bytes();
+    wire::write_fields(&mut out, &headers)?;
+    out.extend_from_slice(b"connection: close\r\n");
+    Ok(out)
+}
+
+/// Write an empty response with the given status, for when the guest did
+/// not produce one.
+pub fn write_error_response(stream: &mut impl Write, status: u16) -> Result<(), HttpError> {
+    write_response(stream, &OutgoingResponse::new(status, Default::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasi::types::Method;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn request_and_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(
+                b"POST /submit?id=7 HTTP/1.1\r\nhost: example.com\r\n\
+                  content-length: 5\r\n\r\nhello",
+            )
+            .unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let mut request = read_request(&server).unwrap();
+        assert!(matches!(request.method, Method::Post));
+        assert_eq!(request.path, "/submit");
+        assert_eq!(request.query, "id=7");
+        assert_eq!(request.authority, "example.com");
+        assert!(request.take_body().is_some());
+
+        let response = OutgoingResponse::new(
+            201,
+            Fields::new(vec![("x-test".to_owned(), "yes".to_owned())]),
+        );
+        response.body.state().buffer.extend_from_slice(b"done");
+        write_response(&mut &server, &response).unwrap();
+        drop(server);
+
+        let mut sent = String::new();
+        client.read_to_string(&mut sent).unwrap();
+        assert_eq!(
+            sent,
+            "HTTP/1.1 201 \r\nx-test: yes\r\nconnection: close\r\n\
+             content-length: 4\r\n\r\ndone"
+        );
+    }
+
+    #[test]
+    fn chunked_response() {
+        let response = OutgoingResponse::new(200, Fields::default());
+        let mut sent = Vec::new();
+        write_response_head(&mut sent, &response).unwrap();
+        write_chunk(&mut sent, b"hello").unwrap();
+        write_chunk(&mut sent, b"").unwrap();
+        write_chunk(&mut sent, b" world").unwrap();
+        let trailers = Fields::new(vec![("x-sum".to_owned(), "1".to_owned())]);
+        write_last_chunk(&mut sent, Some(&trailers)).unwrap();
+        assert_eq!(
+            String::from_utf8(sent).unwrap(),
+            "HTTP/1.1 200 \r\nconnection: close\r\ntransfer-encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n6\r\n world\r\n0\r\nx-sum: 1\r\n\r\n"
+        );
+    }
+}
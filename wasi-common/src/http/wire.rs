@@ -0,0 +1,492 @@
+//! The HTTP/1.1 wire format, shared by the client and server.
+
+use super::{Fields, OutgoingBody, OutgoingRequest};
+use crate::wasi::types::{Error as HttpError, Method};
+use crate::InputStream;
+use anyhow::Error;
+use std::any::Any;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+/// The most bytes we'll accept in a request or status line and its headers.
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+pub(super) fn io_error(e: io::Error) -> HttpError {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            HttpError::TimeoutError(e.to_string())
+        }
+        _ => HttpError::UnexpectedError(e.to_string()),
+    }
+}
+
+/// Map an error reading a request or response, where malformed or truncated
+/// input is the peer's fault.
+pub(super) fn read_error(e: io::Error) -> HttpError {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+            HttpError::ProtocolError(e.to_string())
+        }
+        _ => io_error(e),
+    }
+}
+
+pub(super) fn method_str(method: &Method) -> Result<&str, HttpError> {
+    Ok(match method {
+        Method::Get => "GET",
+        Method::Head => "HEAD",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Connect => "CONNECT",
+        Method::Options => "OPTIONS",
+        Method::Trace => "TRACE",
+        Method::Patch => "PATCH",
+        Method::Other(s) => {
+            if s.is_empty()
+                || !s
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.!~*'".contains(&b))
+            {
+                return Err(HttpError::ProtocolError(format!("invalid method {s:?}")));
+            }
+            s
+        }
+    })
+}
+
+pub(super) fn parse_method(s: &str) -> Method {
+    match s {
+        "GET" => Method::Get,
+        "HEAD" => Method::Head,
+        "POST" => Method::Post,
+        "PUT" => Method::Put,
+        "DELETE" => Method::Delete,
+        "CONNECT" => Method::Connect,
+        "OPTIONS" => Method::Options,
+        "TRACE" => Method::Trace,
+        "PATCH" => Method::Patch,
+        other => Method::Other(other.to_owned()),
+    }
+}
+
+/// Return the origin-form request target, such as `/path?query`.
+pub(super) fn origin_form(request: &OutgoingRequest) -> Result<String, HttpError> {
+    let mut target = if request.path.is_empty() {
+        "/".to_owned()
+    } else {
+        request.path.clone()
+    };
+    if !request.query.is_empty() {
+        target.push('?');
+        target.push_str(request.query.trim_start_matches('?'));
+    }
+    if target
+        .bytes()
+        .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+    {
+        return Err(HttpError::InvalidUrl(format!("invalid path {target:?}")));
+    }
+    Ok(target)
+}
+
+pub(super) fn write_fields(out: &mut Vec<u8>, fields: &Fields) -> Result<(), HttpError> {
+    for (name, value) in fields.entries() {
+        if name.is_empty()
+            || name.bytes().any(|b| b.is_ascii_whitespace() || b == b':')
+            || value.bytes().any(|b| b == b'\r' || b == b'\n')
+        {
+            return Err(HttpError::ProtocolError(format!("invalid field {name:?}")));
+        }
+        out.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+    }
+    Ok(())
+}
+
+/// Write the framing headers, the end of the head, and then `body`.
+pub(super) fn write_body(
+    out: &mut Vec<u8>,
+    body: &OutgoingBody,
+    always_length: bool,
+) -> Result<(), HttpError> {
+    write_contents(
+        out,
+        &body.contents(),
+        body.trailers().as_ref(),
+        always_length,
+    )
+}
+
+/// Write one chunk of a chunked body. An empty chunk would end the body, so
+/// nothing is written for empty `contents`.
+pub(super) fn write_chunk(out: &mut Vec<u8>, contents: &[u8]) {
+    if !contents.is_empty() {
+        out.extend_from_slice(format!("{:x}\r\n", contents.len()).as_bytes());
+        out.extend_from_slice(contents);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// End a chunked body, with optional trailers.
+pub(super) fn write_last_chunk(
+    out: &mut Vec<u8>,
+    trailers: Option<&Fields>,
+) -> Result<(), HttpError> {
+    out.extend_from_slice(b"0\r\n");
+    if let Some(trailers) = trailers {
+        write_fields(out, trailers)?;
+    }
+    out.extend_from_slice(b"\r\n");
+    Ok(())
+}
+
+/// Write the framing headers, the end of the head, and then `contents`.
+///
+/// Bodies with trailers are sent chunked; otherwise a `content-length` is
+/// sent if the body is non-empty or `always_length` is set.
+pub(super) fn write_contents(
+    out: &mut Vec<u8>,
+    contents: &[u8],
+    trailers: Option<&Fields>,
+    always_length: bool,
+) -> Result<(), HttpError> {
+    match trailers {
+        Some(trailers) => {
+            out.extend_from_slice(b"transfer-encoding: chunked\r\n\r\n");
+            write_chunk(out, contents);
+            write_last_chunk(out, Some(trailers))?;
+        }
+        None => {
+            if !contents.is_empty() || always_length {
+                out.extend_from_slice(format!("content-length: {}\r\n", contents.len()).as_bytes());
+            }
+            out.extend_from_slice(b"\r\n");
+            out.extend_from_slice(contents);
+        }
+    }
+    Ok(())
+}
+
+/// Read one CRLF-terminated line, without the line ending.
+pub(super) fn read_line(reader: &mut impl BufRead, limit: &mut usize) -> io::Result<String> {
+    let mut line = Vec::new();
+    let n = Read::take(&mut *reader, *limit as u64 + 1).read_until(b'\n', &mut line)?;
+    if n > *limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "header too long",
+        ));
+    }
+    *limit -= n;
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8"))
+}
+
+pub(super) fn read_fields(reader: &mut impl BufRead, limit: &mut usize) -> io::Result<Fields> {
+    let mut fields = Fields::default();
+    loop {
+        let line = read_line(reader, limit)?;
+        if line.is_empty() {
+            return Ok(fields);
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed field"))?;
+        fields.append(name.trim(), value.trim().to_owned());
+    }
+}
+
+/// Read a request or status line followed by headers.
+pub(super) fn read_head(reader: &mut impl BufRead) -> Result<(String, Fields), HttpError> {
+    let mut limit = MAX_HEAD_LEN;
+    let line = read_line(reader, &mut limit).map_err(read_error)?;
+    let headers = read_fields(reader, &mut limit).map_err(read_error)?;
+    Ok((line, headers))
+}
+
+pub(super) enum Framing {
+    /// The body has a known number of bytes remaining.
+    Length(u64),
+    /// The body uses chunked transfer encoding.
+    Chunked { remaining: u64, done: bool },
+    /// The body ends when the peer closes the connection.
+    Eof,
+}
+
+impl Framing {
+    /// Determine the framing from a message's `transfer-encoding` and
+    /// `content-length` headers, or `None` if neither is present.
+    pub(super) fn from_headers(headers: &Fields) -> Result<Option<Framing>, HttpError> {
+        if headers
+            .get("transfer-encoding")
+            .iter()
+            .any(|v| v.to_ascii_lowercase().contains("chunked"))
+        {
+            return Ok(Some(Framing::Chunked {
+                remaining: 0,
+                done: false,
+            }));
+        }
+        match headers.get("content-length").first() {
+            Some(len) => {
+                let len = len.trim().parse().map_err(|_| {
+                    HttpError::ProtocolError(format!("invalid content-length {len:?}"))
+                })?;
+                Ok(Some(Framing::Length(len)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read body bytes from `reader` into `buf`, decoding as needed. Any
+    /// trailers are stored in `trailers` once the end of the body is reached.
+    pub(super) fn read(
+        &mut self,
+        reader: &mut impl BufRead,
+        buf: &mut [u8],
+        trailers: &Mutex<Option<Fields>>,
+    ) -> io::Result<(u64, bool)> {
+        if buf.is_empty() {
+            return Ok((0, false));
+        }
+        match self {
+            Framing::Length(0) => Ok((0, true)),
+            Framing::Length(remaining) => {
+                let len = buf
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                let n = reader.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the end of the body",
+                    ));
+                }
+                *remaining -= n as u64;
+                Ok((n as u64, false))
+            }
+            Framing::Chunked { done: true, .. } => Ok((0, true)),
+            Framing::Chunked { remaining, done } => {
+                if *remaining == 0 {
+                    let mut limit = MAX_HEAD_LEN;
+                    let line = read_line(reader, &mut limit)?;
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    let size = u64::from_str_radix(size, 16).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size")
+                    })?;
+                    if size == 0 {
+                        let fields = read_fields(reader, &mut limit)?;
+                        if !fields.entries().is_empty() {
+                            *trailers.lock().unwrap() = Some(fields);
+                        }
+                        *done = true;
+                        return Ok((0, true));
+                    }
+                    *remaining = size;
+                }
+                let len = buf
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                let n = reader.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed in the middle of a chunk",
+                    ));
+                }
+                *remaining -= n as u64;
+                if *remaining == 0 {
+                    let mut limit = 2;
+                    if !read_line(reader, &mut limit)?.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "missing chunk terminator",
+                        ));
+                    }
+                }
+                Ok((n as u64, false))
+            }
+            Framing::Eof => match reader.read(buf)? {
+                0 => Ok((0, true)),
+                n => Ok((n as u64, false)),
+            },
+        }
+    }
+
+    /// Read the rest of the body from `reader`, returning it along with any
+    /// trailers.
+    pub(super) fn read_to_end(
+        mut self,
+        reader: &mut impl BufRead,
+    ) -> io::Result<(Vec<u8>, Option<Fields>)> {
+        let trailers = Mutex::new(None);
+        let mut contents = Vec::new();
+        let mut buf = [0; 8192];
+        loop {
+            match self.read(reader, &mut buf, &trailers) {
+                Ok((_, true)) => break,
+                Ok((n, false)) => contents.extend_from_slice(&buf[..n as usize]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((contents, trailers.into_inner().unwrap()))
+    }
+}
+
+/// The body of a request or response, read from the connection as it's
+/// consumed.
+///
+/// A read returns nothing rather than wait for the peer, and `readable` does
+/// the waiting. Reads and waits happen on the blocking thread pool, so a slow
+/// peer doesn't stall the executor.
+pub(super) struct WireBody {
+    conn: Arc<Mutex<Connection>>,
+    /// A handle to the connection's socket, for polling while a read may be
+    /// holding `conn`.
+    socket: TcpStream,
+    pub(super) trailers: Arc<Mutex<Option<Fields>>>,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    framing: Framing,
+}
+
+impl Connection {
+    /// Test whether a read can make progress without waiting for the peer.
+    fn has_data(&self) -> bool {
+        !self.reader.buffer().is_empty()
+            || matches!(
+                self.framing,
+                Framing::Length(0) | Framing::Chunked { done: true, .. }
+            )
+    }
+}
+
+impl WireBody {
+    pub(super) fn new(reader: BufReader<TcpStream>, framing: Framing) -> io::Result<Self> {
+        let socket = reader.get_ref().try_clone()?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(Connection { reader, framing })),
+            socket,
+            trailers: Arc::default(),
+        })
+    }
+
+    /// Test whether the next read can make progress without the socket.
+    fn buffered(&self) -> bool {
+        self.conn.try_lock().map_or(false, |conn| conn.has_data())
+    }
+
+    pub(super) fn read_body(&mut self, buf: &mut [u8]) -> io::Result<(u64, bool)> {
+        let conn = &mut *self.conn.lock().unwrap();
+        conn.framing.read(&mut conn.reader, buf, &self.trailers)
+    }
+
+    pub(super) fn read_to_end(self) -> io::Result<(Vec<u8>, Option<Fields>)> {
+        let conn = &mut *self.conn.lock().unwrap();
+        let framing = std::mem::replace(&mut conn.framing, Framing::Length(0));
+        framing.read_to_end(&mut conn.reader)
+    }
+}
+
+#[async_trait::async_trait]
+impl InputStream for WireBody {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    // Only offer the socket for polling when nothing is buffered; otherwise
+    // the scheduler asks `read_ready`.
+    #[cfg(unix)]
+    fn pollable_read(&self) -> Option<rustix::fd::BorrowedFd> {
+        use cap_std::io_lifetimes::AsFd;
+        if self.buffered() {
+            None
+        } else {
+            Some(self.socket.as_fd())
+        }
+    }
+
+    #[cfg(windows)]
+    fn pollable_read(&self) -> Option<io_extras::os::windows::BorrowedHandleOrSocket> {
+        use io_extras::os::windows::AsHandleOrSocket;
+        if self.buffered() {
+            None
+        } else {
+            Some(self.socket.as_handle_or_socket())
+        }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(u64, bool), Error> {
+        if !self.read_ready().await? {
+            return Ok((0, false));
+        }
+        let conn = Arc::clone(&self.conn);
+        let trailers = Arc::clone(&self.trailers);
+        let len = buf.len();
+        let (result, data) = crate::filesystem::spawn_blocking(move || {
+            let conn = &mut *conn.lock().unwrap();
+            let mut data = vec![0; len];
+            let result = conn.framing.read(&mut conn.reader, &mut data, &trailers);
+            (result, data)
+        })
+        .await;
+        match result {
+            Ok((n, end)) => {
+                buf[..n as usize].copy_from_slice(&data[..n as usize]);
+                Ok((n, end))
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok((0, false)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(match self.conn.try_lock() {
+            Ok(conn) => conn.reader.buffer().len() as u64,
+            Err(_) => 0,
+        })
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        use rustix::io::{PollFd, PollFlags};
+        if self.read_ready().await? {
+            return Ok(());
+        }
+        let socket = self.socket.try_clone()?;
+        let result = crate::filesystem::spawn_blocking(move || {
+            let mut fds = [PollFd::new(&socket, PollFlags::IN)];
+            rustix::io::poll(&mut fds, -1)
+        })
+        .await;
+        match result {
+            Ok(_) | Err(rustix::io::Errno::INTR) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_ready(&self) -> Result<bool, Error> {
+        // A read still in progress holds the connection, and the next one
+        // has to wait for it.
+        match self.conn.try_lock() {
+            Ok(conn) if conn.has_data() => return Ok(true),
+            Ok(_) => {}
+            Err(_) => return Ok(false),
+        }
+        use rustix::io::{PollFd, PollFlags};
+        let mut fds = [PollFd::new(&self.socket, PollFlags::IN)];
+        Ok(rustix::io::poll(&mut fds, 0)? != 0)
+    }
+}
@@ -1,7 +1,8 @@
 pub mod clocks;
 mod ctx;
 mod error;
-pub(crate) mod filesystem;
+pub mod filesystem;
+pub mod http;
 pub mod pipe;
 #[cfg(feature = "preview1")]
 pub mod preview1;
@@ -18,7 +19,7 @@ pub use cap_rand::RngCore;
 pub use clocks::{WasiClocks, WasiMonotonicClock, WasiWallClock};
 pub use ctx::{WasiCtx, WasiCtxBuilder, WasiView};
 pub use error::I32Exit;
-pub use filesystem::{DirPerms, FilePerms};
+pub use filesystem::{DirPerms, FilePerms, FsLimits, PreopenPerms, WasiDir, WasiFile};
 pub use sched::{Poll, WasiSched};
 pub use stream::{InputStream, OutputStream};
 pub use table::{Table, TableError};
@@ -7,13 +7,18 @@
 //! Some convenience constructors are included for common backing types like `Vec<u8>` and `String`,
 //! but the virtual pipes can be instantiated with any `Read` or `Write` type.
 //!
+//! [`channel`] instead makes a connected pair of in-memory pipe ends, for connecting the streams
+//! of different instances.
+//!
 use crate::stream::{InputStream, OutputStream};
 use anyhow::Error;
 use std::any::Any;
+use std::collections::VecDeque;
 use std::convert::TryInto;
 use std::io::{self, Read, Write};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use system_interface::io::ReadReady;
+use tokio::sync::Notify;
 
 /// A virtual pipe read end.
 ///
@@ -231,3 +236,260 @@ impl<W: Write + Any + Send + Sync> OutputStream for WritePipe<W> {
         Ok(())
     }
 }
+
+/// Create a connected pair of in-memory pipe ends, holding at most
+/// `capacity` bytes that have been written and not yet read.
+///
+/// The ends can be given to different instances, for example as the stdout
+/// of one and the stdin of the next, to chain them like a shell pipeline.
+/// Reads and writes never block: a write when the pipe is full writes what
+/// fits, and `readable` and `writable` wait for the other end to catch up.
+///
+/// ```
+/// use wasi_common::{pipe, WasiCtx};
+/// let (reader, writer) = pipe::channel(64 * 1024);
+/// let producer = WasiCtx::builder().set_stdout(writer);
+/// let consumer = WasiCtx::builder().set_stdin(reader);
+/// ```
+pub fn channel(capacity: usize) -> (ReadChannel, WriteChannel) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            buf: VecDeque::new(),
+            capacity,
+            reader_closed: false,
+            writer_closed: false,
+        }),
+        changed: Notify::new(),
+    });
+    (ReadChannel(Arc::clone(&channel)), WriteChannel(channel))
+}
+
+struct Channel {
+    state: Mutex<ChannelState>,
+    /// Woken whenever either end reads, writes, or is dropped.
+    changed: Notify,
+}
+
+struct ChannelState {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+impl Channel {
+    fn lock(&self) -> std::sync::MutexGuard<ChannelState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Wait until `ready` holds for the state of the channel.
+    async fn wait<T>(&self, ready: impl Fn(&ChannelState) -> Option<T>) -> T {
+        loop {
+            // Start listening before checking, so a change in between
+            // isn't missed.
+            let changed = self.changed.notified();
+            let result = ready(&*self.lock());
+            if let Some(t) = result {
+                return t;
+            }
+            changed.await;
+        }
+    }
+}
+
+/// The read end of a [`channel`].
+pub struct ReadChannel(Arc<Channel>);
+
+/// The write end of a [`channel`].
+pub struct WriteChannel(Arc<Channel>);
+
+impl Drop for ReadChannel {
+    fn drop(&mut self) {
+        self.0.lock().reader_closed = true;
+        self.0.changed.notify_waiters();
+    }
+}
+
+impl Drop for WriteChannel {
+    fn drop(&mut self) {
+        self.0.lock().writer_closed = true;
+        self.0.changed.notify_waiters();
+    }
+}
+
+#[async_trait::async_trait]
+impl InputStream for ReadChannel {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(u64, bool), Error> {
+        let mut state = self.0.lock();
+        let n = buf.len().min(state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..n)) {
+            *dst = src;
+        }
+        let end = n == 0 && !buf.is_empty() && state.writer_closed;
+        drop(state);
+        self.0.changed.notify_waiters();
+        Ok((n as u64, end))
+    }
+
+    async fn skip(&mut self, nelem: u64) -> Result<(u64, bool), Error> {
+        let mut state = self.0.lock();
+        let n = usize::try_from(nelem).map_or(state.buf.len(), |n| n.min(state.buf.len()));
+        state.buf.drain(..n);
+        let end = n == 0 && nelem != 0 && state.writer_closed;
+        drop(state);
+        self.0.changed.notify_waiters();
+        Ok((n as u64, end))
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.0.lock().buf.len() as u64)
+    }
+
+    /// Wait until there are bytes to read, or the write end is dropped.
+    async fn readable(&self) -> Result<(), Error> {
+        self.0
+            .wait(|state| (!state.buf.is_empty() || state.writer_closed).then_some(()))
+            .await;
+        Ok(())
+    }
+
+    async fn read_ready(&self) -> Result<bool, Error> {
+        let state = self.0.lock();
+        Ok(!state.buf.is_empty() || state.writer_closed)
+    }
+}
+
+#[async_trait::async_trait]
+impl OutputStream for WriteChannel {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> Result<u64, Error> {
+        let mut state = self.0.lock();
+        if state.reader_closed {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe).into());
+        }
+        let n = buf.len().min(state.capacity - state.buf.len());
+        state.buf.extend(&buf[..n]);
+        drop(state);
+        self.0.changed.notify_waiters();
+        Ok(n as u64)
+    }
+
+    async fn write_budget(&self) -> Result<u64, Error> {
+        let state = self.0.lock();
+        Ok((state.capacity - state.buf.len()) as u64)
+    }
+
+    /// Wait until there's room to write, or fail if the read end is dropped.
+    async fn writable(&self) -> Result<(), Error> {
+        let closed = self
+            .0
+            .wait(|state| {
+                if state.reader_closed {
+                    Some(true)
+                } else {
+                    (state.buf.len() < state.capacity).then_some(false)
+                }
+            })
+            .await;
+        if closed {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe).into());
+        }
+        Ok(())
+    }
+
+    /// Ready once there's room, or once the read end is dropped, so that the
+    /// write reports the broken pipe.
+    async fn write_ready(&self) -> Result<bool, Error> {
+        let state = self.0.lock();
+        Ok(state.reader_closed || state.buf.len() < state.capacity)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn channel_has_bounded_capacity_and_ends() {
+        let (mut reader, mut writer) = channel(4);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert_eq!(writer.write(b"hello").await.unwrap(), 4);
+            assert_eq!(writer.write_budget().await.unwrap(), 0);
+            assert_eq!(reader.num_ready_bytes().await.unwrap(), 4);
+
+            let mut buf = [0; 3];
+            assert_eq!(reader.read(&mut buf).await.unwrap(), (3, false));
+            assert_eq!(&buf, b"hel");
+            writer.writable().await.unwrap();
+            assert_eq!(writer.write(b"o!").await.unwrap(), 2);
+
+            // The rest can still be read after the writer is gone, and then
+            // the reader sees the end.
+            drop(writer);
+            reader.readable().await.unwrap();
+            assert_eq!(reader.read(&mut buf).await.unwrap(), (3, false));
+            assert_eq!(&buf, b"lo!");
+            assert_eq!(reader.read(&mut buf).await.unwrap(), (0, true));
+        });
+    }
+
+    #[test]
+    fn channel_readiness_works_with_poll_oneoff() {
+        let (mut reader, mut writer) = channel(16);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let task = tokio::spawn(async move {
+                writer.write(b"ping").await.unwrap();
+            });
+
+            let mut poll = crate::sched::Poll::new();
+            poll.subscribe_read(&reader, 0u64.into());
+            crate::sched::sync::poll_oneoff(&mut poll).await.unwrap();
+            assert_eq!(poll.results().count(), 1);
+
+            task.await.unwrap();
+            let mut buf = [0; 8];
+            assert_eq!(reader.read(&mut buf).await.unwrap(), (4, false));
+            assert_eq!(reader.read(&mut buf).await.unwrap(), (0, true));
+        });
+    }
+
+    #[test]
+    fn empty_channel_does_not_hold_up_a_clock() {
+        use crate::sched::subscription::SubscriptionResult;
+
+        let (reader, _writer) = channel(16);
+        let clock = crate::clocks::host::MonotonicClock::new(cap_std::ambient_authority());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let start = std::time::Instant::now();
+            let mut poll = crate::sched::Poll::new();
+            poll.subscribe_read(&reader, 0u64.into());
+            poll.subscribe_monotonic_clock(&clock, 10_000_000, false, 1u64.into());
+            crate::sched::sync::poll_oneoff(&mut poll).await.unwrap();
+            assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+
+            let results: Vec<_> = poll.results().collect();
+            assert_eq!(results.len(), 1);
+            assert!(matches!(
+                results[0].0,
+                SubscriptionResult::MonotonicClock(Ok(()))
+            ));
+            assert_eq!(u64::from(results[0].1), 1);
+        });
+    }
+}
@@ -1,20 +1,531 @@
-// Temporary for scaffolding this module out:
-#![allow(unused_variables)]
-
+use crate::stream::TableStreamExt;
 use crate::wasi;
 
 use core::borrow::Borrow;
+use std::collections::BTreeMap;
 
 use anyhow::{anyhow, Context};
-use wiggle::GuestPtr;
+use wiggle::{GuestError, GuestPtr};
 
-pub struct WasiPreview1Adapter {/* all members private and only used inside this module. also, this struct should be Send. */}
+pub struct WasiPreview1Adapter {
+    /// The preview 1 descriptor table. Populated lazily from the preview 2
+    /// preopens on first use, so access it with `descriptors`.
+    descriptors: Option<Descriptors>,
+}
 impl WasiPreview1Adapter {
     // This should be the only public interface of this struct. It should take
     // no parameters: anything it needs from the preview 2 implementation
     // should be retrieved lazily.
     pub fn new() -> Self {
-        Self {}
+        Self { descriptors: None }
+    }
+}
+
+/// A preview 1 file or directory, backed by a preview 2 descriptor.
+#[derive(Clone, Copy, Debug)]
+struct File {
+    /// The handle to the preview 2 descriptor that this file is referencing.
+    fd: wasi::filesystem::Descriptor,
+
+    /// The descriptor type, as supplied by `get-type` at opening.
+    descriptor_type: wasi::filesystem::DescriptorType,
+
+    /// The current-position pointer.
+    position: wasi::filesystem::Filesize,
+
+    /// In append mode, all writes append to the file.
+    append: bool,
+
+    /// In blocking mode, reads and writes use the blocking stream calls.
+    blocking: bool,
+}
+
+impl File {
+    fn is_dir(&self) -> bool {
+        matches!(
+            self.descriptor_type,
+            wasi::filesystem::DescriptorType::Directory
+        )
+    }
+}
+
+/// A preview 1 socket, backed by a socket from `WasiPreview1Sockets`.
+#[derive(Clone, Copy, Debug)]
+struct Socket {
+    /// The handle to the socket.
+    socket: u32,
+
+    /// The streams of a connection. Listeners have none.
+    streams: Option<(wasi::streams::InputStream, wasi::streams::OutputStream)>,
+
+    /// In blocking mode, `accept`, `recv`, and `send` wait until they can
+    /// make progress.
+    blocking: bool,
+}
+
+#[derive(Debug)]
+enum Descriptor {
+    Stdin(wasi::streams::InputStream),
+    Stdout(wasi::streams::OutputStream),
+    Stderr(wasi::streams::OutputStream),
+    /// A preopened directory, along with the path it is mapped to.
+    PreopenDirectory(wasi::filesystem::Descriptor, String),
+    File(File),
+    Socket(Socket),
+}
+
+/// Mapping from preview 1 file descriptors to the preview 2 resources that
+/// implement them.
+#[derive(Debug, Default)]
+struct Descriptors {
+    used: BTreeMap<u32, Descriptor>,
+}
+
+impl Descriptors {
+    /// Build the initial table: stdio at 0, 1, and 2, followed by the
+    /// preopened sockets and then the preopened directories.
+    async fn new(
+        host: &mut (impl wasi::preopens::Host + WasiPreview1View),
+    ) -> Result<Self, types::Error> {
+        let stdio = host
+            .get_stdio()
+            .await
+            .context("failed to call `get-stdio`")
+            .map_err(types::Error::trap)?;
+        let mut descriptors = Self::default();
+        descriptors.push(Descriptor::Stdin(stdio.stdin))?;
+        descriptors.push(Descriptor::Stdout(stdio.stdout))?;
+        descriptors.push(Descriptor::Stderr(stdio.stderr))?;
+        let sockets = match host.sockets() {
+            Some(sockets) => sockets
+                .preopened_sockets()
+                .await
+                .context("failed to get the preopened sockets")
+                .map_err(types::Error::trap)?,
+            None => Vec::new(),
+        };
+        for socket in sockets {
+            descriptors.push(Descriptor::Socket(Socket {
+                socket,
+                streams: None,
+                blocking: true,
+            }))?;
+        }
+        for (fd, path) in host
+            .get_directories()
+            .await
+            .context("failed to call `get-directories`")
+            .map_err(types::Error::trap)?
+        {
+            descriptors.push(Descriptor::PreopenDirectory(fd, path))?;
+        }
+        Ok(descriptors)
+    }
+
+    /// Insert `desc` at the lowest unused descriptor number.
+    fn push(&mut self, desc: Descriptor) -> ErrnoResult<types::Fd> {
+        let mut fd = 0;
+        for used in self.used.keys() {
+            if *used != fd {
+                break;
+            }
+            fd = fd.checked_add(1).ok_or(types::Errno::Nfile)?;
+        }
+        self.used.insert(fd, desc);
+        Ok(fd.into())
+    }
+
+    fn get(&self, fd: types::Fd) -> ErrnoResult<&Descriptor> {
+        self.used.get(&fd.into()).ok_or(types::Errno::Badf)
+    }
+
+    fn get_mut(&mut self, fd: types::Fd) -> ErrnoResult<&mut Descriptor> {
+        self.used.get_mut(&fd.into()).ok_or(types::Errno::Badf)
+    }
+
+    fn remove(&mut self, fd: types::Fd) -> ErrnoResult<Descriptor> {
+        self.used.remove(&fd.into()).ok_or(types::Errno::Badf)
+    }
+
+    /// Get a file or directory. Stdio fails with `error`.
+    fn get_file_or_dir(&self, fd: types::Fd, error: types::Errno) -> ErrnoResult<File> {
+        match self.get(fd)? {
+            Descriptor::File(file) => Ok(*file),
+            Descriptor::PreopenDirectory(fd, _) => Ok(File {
+                fd: *fd,
+                descriptor_type: wasi::filesystem::DescriptorType::Directory,
+                position: 0,
+                append: false,
+                blocking: true,
+            }),
+            Descriptor::Stdin(_)
+            | Descriptor::Stdout(_)
+            | Descriptor::Stderr(_)
+            | Descriptor::Socket(_) => Err(error),
+        }
+    }
+
+    /// Get a file which is not a directory. Directories fail with
+    /// `errno::badf`, as they did in preview 1, and stdio fails with `error`.
+    fn get_file_with_error(&self, fd: types::Fd, error: types::Errno) -> ErrnoResult<File> {
+        match self.get_file_or_dir(fd, error)? {
+            file if file.is_dir() => Err(types::Errno::Badf),
+            file => Ok(file),
+        }
+    }
+
+    fn get_file(&self, fd: types::Fd) -> ErrnoResult<File> {
+        self.get_file_with_error(fd, types::Errno::Inval)
+    }
+
+    fn get_seekable_file(&self, fd: types::Fd) -> ErrnoResult<File> {
+        self.get_file_with_error(fd, types::Errno::Spipe)
+    }
+
+    fn get_dir(&self, fd: types::Fd) -> ErrnoResult<wasi::filesystem::Descriptor> {
+        match self.get_file_or_dir(fd, types::Errno::Badf)? {
+            file if file.is_dir() => Ok(file.fd),
+            _ => Err(types::Errno::Notdir),
+        }
+    }
+
+    fn get_socket(&self, fd: types::Fd) -> ErrnoResult<Socket> {
+        match self.get(fd)? {
+            Descriptor::Socket(socket) => Ok(*socket),
+            _ => Err(types::Errno::Notsock),
+        }
+    }
+
+    /// Get what an `fd_read` or `fd_write` subscription to `fd` polls.
+    fn get_poll_source(&self, fd: types::Fd, write: bool) -> ErrnoResult<PollSource> {
+        match (self.get(fd)?, write) {
+            (Descriptor::Stdin(stream), false) => Ok(PollSource::Read(*stream)),
+            (Descriptor::Stdout(stream) | Descriptor::Stderr(stream), true) => {
+                Ok(PollSource::Write(*stream))
+            }
+            (Descriptor::File(file), write) if !file.is_dir() => {
+                Ok(PollSource::File { file: *file, write })
+            }
+            (Descriptor::Socket(socket), write) => match socket.streams {
+                Some((input, _)) if !write => Ok(PollSource::Read(input)),
+                Some((_, output)) => Ok(PollSource::Write(output)),
+                // Preview 2 can't poll listeners yet.
+                None => Err(types::Errno::Notsup),
+            },
+            _ => Err(types::Errno::Badf),
+        }
+    }
+
+    fn set_blocking(&mut self, fd: types::Fd, blocking: bool) {
+        if let Ok(Descriptor::Socket(socket)) = self.get_mut(fd) {
+            socket.blocking = blocking;
+        }
+    }
+
+    fn set_position(&mut self, fd: types::Fd, position: wasi::filesystem::Filesize) {
+        if let Ok(Descriptor::File(file)) = self.get_mut(fd) {
+            file.position = position;
+        }
+    }
+}
+
+/// What a preview 1 subscription polls.
+#[derive(Clone, Copy, Debug)]
+enum PollSource {
+    /// A monotonic-clock timeout, and whether it is absolute.
+    Clock(wasi::monotonic_clock::Instant, bool),
+    Read(wasi::streams::InputStream),
+    Write(wasi::streams::OutputStream),
+    /// A file, polled through a temporary stream at its current position.
+    File {
+        file: File,
+        write: bool,
+    },
+}
+
+/// The pollables and temporary streams created for one `poll_oneoff` call.
+#[derive(Default)]
+struct PollResources {
+    pollables: Vec<wasi::poll::Pollable>,
+    input_streams: Vec<wasi::streams::InputStream>,
+    output_streams: Vec<wasi::streams::OutputStream>,
+}
+
+/// The stream a subscription is polled through.
+#[derive(Clone, Copy)]
+enum PolledStream {
+    Input(wasi::streams::InputStream),
+    Output(wasi::streams::OutputStream),
+}
+
+/// What `poll_oneoff` reports for one subscription: the event type, or
+/// `None` if it isn't ready, and the error, byte count, and flags.
+type PollEvent = (
+    Option<types::Eventtype>,
+    types::Errno,
+    types::Filesize,
+    types::Eventrwflags,
+);
+
+/// Subscribe to `sources`, recording everything created in `resources`, and
+/// wait until one of them is ready.
+async fn poll_sources<
+    T: crate::WasiView
+        + wasi::filesystem::Host
+        + wasi::monotonic_clock::Host
+        + wasi::poll::Host
+        + wasi::streams::Host,
+>(
+    view: &mut T,
+    sources: &[PollSource],
+    resources: &mut PollResources,
+) -> Result<Vec<PollEvent>, types::Error> {
+    // The stream each source is polled through, if any.
+    let mut streams = Vec::with_capacity(sources.len());
+    for source in sources {
+        let (pollable, stream) = match *source {
+            PollSource::Clock(timeout, absolute) => (
+                wasi::monotonic_clock::Host::subscribe(view, timeout, absolute)
+                    .await
+                    .context("failed to call `monotonic_clock::subscribe`"),
+                None,
+            ),
+            PollSource::Read(stream) => (
+                view.subscribe_to_input_stream(stream)
+                    .await
+                    .context("failed to call `subscribe-to-input-stream`"),
+                Some(PolledStream::Input(stream)),
+            ),
+            PollSource::Write(stream) => (
+                view.subscribe_to_output_stream(stream)
+                    .await
+                    .context("failed to call `subscribe-to-output-stream`"),
+                Some(PolledStream::Output(stream)),
+            ),
+            PollSource::File { file, write: false } => {
+                let stream = view
+                    .read_via_stream(file.fd, file.position)
+                    .await
+                    .context("failed to call `read-via-stream`")
+                    .map_err(types::Error::trap)?;
+                resources.input_streams.push(stream);
+                (
+                    view.subscribe_to_input_stream(stream)
+                        .await
+                        .context("failed to call `subscribe-to-input-stream`"),
+                    Some(PolledStream::Input(stream)),
+                )
+            }
+            PollSource::File { file, write: true } => {
+                let stream = if file.append {
+                    view.append_via_stream(file.fd)
+                        .await
+                        .context("failed to call `append-via-stream`")
+                } else {
+                    view.write_via_stream(file.fd, file.position)
+                        .await
+                        .context("failed to call `write-via-stream`")
+                }
+                .map_err(types::Error::trap)?;
+                resources.output_streams.push(stream);
+                (
+                    view.subscribe_to_output_stream(stream)
+                        .await
+                        .context("failed to call `subscribe-to-output-stream`"),
+                    Some(PolledStream::Output(stream)),
+                )
+            }
+        };
+        resources
+            .pollables
+            .push(pollable.map_err(types::Error::trap)?);
+        streams.push(stream);
+    }
+
+    let ready = wasi::poll::Host::poll_oneoff(view, resources.pollables.clone())
+        .await
+        .context("failed to call `poll-oneoff`")
+        .map_err(types::Error::trap)?;
+
+    let mut events = Vec::with_capacity(sources.len());
+    for ((source, stream), ready) in sources.iter().zip(streams).zip(ready) {
+        let none = types::Eventrwflags::empty();
+        if ready == 0 {
+            events.push((None, types::Errno::Success, 0, none));
+            continue;
+        }
+        let event = match (source, stream) {
+            (PollSource::Clock(..), _) => (types::Eventtype::Clock, types::Errno::Success, 0, none),
+            (PollSource::File { file, write: false }, _) => {
+                // Report the bytes between the position and the end of the
+                // file, and a hangup once there are none left.
+                match wasi::filesystem::Host::stat(view, file.fd).await {
+                    Ok(stat) => {
+                        let nbytes = stat.size.saturating_sub(file.position);
+                        let flags = if nbytes == 0 {
+                            types::Eventrwflags::FD_READWRITE_HANGUP
+                        } else {
+                            none
+                        };
+                        (
+                            types::Eventtype::FdRead,
+                            types::Errno::Success,
+                            nbytes,
+                            flags,
+                        )
+                    }
+                    Err(err) => match err.downcast() {
+                        Ok(code) => (types::Eventtype::FdRead, types::Errno::from(code), 0, none),
+                        Err(trap) => return Err(types::Error::trap(trap)),
+                    },
+                }
+            }
+            // Other streams report what they can take or give right now.
+            (_, Some(PolledStream::Input(stream))) => {
+                let nbytes = crate::WasiView::table(view)
+                    .get_input_stream(stream)
+                    .map_err(|e| types::Error::trap(e.into()))?
+                    .num_ready_bytes()
+                    .await
+                    .map_err(types::Error::trap)?;
+                (
+                    types::Eventtype::FdRead,
+                    types::Errno::Success,
+                    nbytes,
+                    none,
+                )
+            }
+            (_, Some(PolledStream::Output(stream))) => {
+                let nbytes = crate::WasiView::table(view)
+                    .get_output_stream(stream)
+                    .map_err(|e| types::Error::trap(e.into()))?
+                    .write_budget()
+                    .await
+                    .map_err(types::Error::trap)?;
+                (
+                    types::Eventtype::FdWrite,
+                    types::Errno::Success,
+                    nbytes,
+                    none,
+                )
+            }
+            (_, None) => unreachable!("only clocks are polled without a stream"),
+        };
+        let (type_, error, nbytes, flags) = event;
+        events.push((Some(type_), error, nbytes, flags));
+    }
+    Ok(events)
+}
+
+/// Drop everything `poll_sources` created, even if dropping some of it
+/// fails, and report the first failure.
+async fn drop_poll_resources<T: wasi::poll::Host + wasi::streams::Host>(
+    view: &mut T,
+    resources: PollResources,
+) -> Result<(), types::Error> {
+    let mut result = Ok(());
+    for pollable in resources.pollables {
+        let dropped = view
+            .drop_pollable(pollable)
+            .await
+            .context("failed to call `drop-pollable`");
+        result = result.and(dropped);
+    }
+    for stream in resources.input_streams {
+        let dropped = view
+            .drop_input_stream(stream)
+            .await
+            .context("failed to call `drop-input-stream`");
+        result = result.and(dropped);
+    }
+    for stream in resources.output_streams {
+        let dropped = view
+            .drop_output_stream(stream)
+            .await
+            .context("failed to call `drop-output-stream`");
+        result = result.and(dropped);
+    }
+    result.map_err(types::Error::trap)
+}
+
+/// Get the descriptor table of `view`, initializing it on first use.
+async fn descriptors<T: WasiPreview1View + wasi::preopens::Host>(
+    view: &mut T,
+) -> Result<&mut Descriptors, types::Error> {
+    if view.adapter().descriptors.is_none() {
+        let descriptors = Descriptors::new(view).await?;
+        view.adapter_mut().descriptors = Some(descriptors);
+    }
+    Ok(view
+        .adapter_mut()
+        .descriptors
+        .as_mut()
+        .expect("descriptors are initialized"))
+}
+
+/// Drop the preview 2 resources that `desc` owns.
+async fn close<T: WasiPreview1View + wasi::filesystem::Host + wasi::streams::Host>(
+    view: &mut T,
+    desc: Descriptor,
+) -> Result<(), types::Error> {
+    match desc {
+        Descriptor::File(File { fd, .. }) | Descriptor::PreopenDirectory(fd, _) => view
+            .drop_descriptor(fd)
+            .await
+            .context("failed to call `drop-descriptor`")
+            .map_err(types::Error::trap)?,
+        Descriptor::Socket(socket) => {
+            if let Some((input, output)) = socket.streams {
+                view.drop_input_stream(input)
+                    .await
+                    .context("failed to call `drop-input-stream`")
+                    .map_err(types::Error::trap)?;
+                view.drop_output_stream(output)
+                    .await
+                    .context("failed to call `drop-output-stream`")
+                    .map_err(types::Error::trap)?;
+            }
+            sockets(view)?
+                .drop_socket(socket.socket)
+                .await
+                .context("failed to drop socket")
+                .map_err(types::Error::trap)?;
+        }
+        // The stdio streams belong to the preview 2 context.
+        Descriptor::Stdin(_) | Descriptor::Stdout(_) | Descriptor::Stderr(_) => {}
+    }
+    Ok(())
+}
+
+/// Read up to `len` bytes from a connected socket.
+async fn socket_recv<T: wasi::streams::Host>(
+    view: &mut T,
+    socket: Socket,
+    len: u64,
+) -> Result<(Vec<u8>, bool), types::Error> {
+    let (input, _) = socket.streams.ok_or(types::Errno::Notconn)?;
+    if socket.blocking {
+        return Ok(wasi::streams::Host::blocking_read(view, input, len).await?);
+    }
+    match wasi::streams::Host::read(view, input, len).await? {
+        (data, false) if data.is_empty() => Err(types::Errno::Again.into()),
+        result => Ok(result),
+    }
+}
+
+/// Write `buf` to a connected socket, returning the number of bytes written.
+async fn socket_send<T: wasi::streams::Host>(
+    view: &mut T,
+    socket: Socket,
+    buf: Vec<u8>,
+) -> Result<u64, types::Error> {
+    let (_, output) = socket.streams.ok_or(types::Errno::Notconn)?;
+    if socket.blocking {
+        return Ok(wasi::streams::Host::blocking_write(view, output, buf).await?);
+    }
+    match wasi::streams::Host::write(view, output, buf).await? {
+        0 => Err(types::Errno::Again.into()),
+        n => Ok(n),
     }
 }
 
@@ -23,12 +534,56 @@ impl WasiPreview1Adapter {
 pub trait WasiPreview1View: Send {
     fn adapter(&self) -> &WasiPreview1Adapter;
     fn adapter_mut(&mut self) -> &mut WasiPreview1Adapter;
+
+    /// The sockets for the `sock_*` calls. Contexts without sockets keep
+    /// this default, and the guest sees no socket descriptors.
+    fn sockets(&mut self) -> Option<&mut dyn WasiPreview1Sockets> {
+        None
+    }
+}
+
+/// TCP sockets for the preview 1 `sock_*` calls.
+///
+/// Sockets are identified by their handle in the context's table. Preview 2
+/// has no way to preopen sockets, so contexts that want them implement this
+/// trait, e.g. by forwarding to `wasmtime_wasi_sockets::preview1`, and
+/// return themselves from `WasiPreview1View::sockets`.
+#[async_trait::async_trait]
+pub trait WasiPreview1Sockets: Send {
+    /// Return the preopened listeners, which are given the file descriptors
+    /// after stdio.
+    async fn preopened_sockets(&mut self) -> anyhow::Result<Vec<u32>>;
+
+    /// Accept a connection on a listener, returning the connection and its
+    /// input and output streams. `nonblocking` is the mode of the
+    /// connection.
+    async fn accept(
+        &mut self,
+        socket: u32,
+        nonblocking: bool,
+    ) -> anyhow::Result<(u32, wasi::streams::InputStream, wasi::streams::OutputStream)>;
+
+    async fn set_nonblocking(&mut self, socket: u32, nonblocking: bool) -> anyhow::Result<()>;
+
+    async fn shutdown(&mut self, socket: u32, how: std::net::Shutdown) -> anyhow::Result<()>;
+
+    async fn drop_socket(&mut self, socket: u32) -> anyhow::Result<()>;
+}
+
+/// Get the sockets of `view`. Only contexts with sockets have socket
+/// descriptors, so this traps otherwise.
+fn sockets<T: WasiPreview1View>(
+    view: &mut T,
+) -> Result<&mut dyn WasiPreview1Sockets, types::Error> {
+    view.sockets()
+        .ok_or_else(|| types::Error::trap(anyhow!("sockets are not supported")))
 }
 
 // This becomes the only way to add preview 1 support to a wasmtime (module)
 // Linker:
 pub fn add_to_linker<
     T: WasiPreview1View
+        + crate::WasiView
         + wasi::environment::Host
         + wasi::exit::Host
         + wasi::filesystem::Host
@@ -76,8 +631,270 @@ impl TryFrom<wasi::wall_clock::Datetime> for types::Timestamp {
     }
 }
 
+impl From<GuestError> for types::Error {
+    fn from(err: GuestError) -> Self {
+        use wiggle::GuestError::*;
+        match err {
+            InvalidFlagValue { .. } | InvalidEnumValue { .. } => types::Errno::Inval.into(),
+            // Out-of-bounds and misaligned pointers trap, as the witx docs
+            // require.
+            PtrOverflow { .. } | PtrOutOfBounds { .. } | PtrNotAligned { .. } => {
+                types::Error::trap(err.into())
+            }
+            PtrBorrowed { .. } | SliceLengthsDiffer { .. } | BorrowCheckerOutOfHandles { .. } => {
+                types::Errno::Fault.into()
+            }
+            InvalidUtf8 { .. } => types::Errno::Ilseq.into(),
+            TryFromIntError { .. } => types::Errno::Overflow.into(),
+            InFunc { err, .. } => types::Error::from(*err),
+        }
+    }
+}
+
+impl From<std::num::TryFromIntError> for types::Error {
+    fn from(_: std::num::TryFromIntError) -> Self {
+        types::Errno::Overflow.into()
+    }
+}
+
+impl From<wasi::filesystem::ErrorCode> for types::Errno {
+    fn from(code: wasi::filesystem::ErrorCode) -> Self {
+        use wasi::filesystem::ErrorCode;
+        match code {
+            ErrorCode::Access => types::Errno::Acces,
+            ErrorCode::WouldBlock => types::Errno::Again,
+            ErrorCode::Already => types::Errno::Already,
+            ErrorCode::BadDescriptor => types::Errno::Badf,
+            ErrorCode::Busy => types::Errno::Busy,
+            ErrorCode::Deadlock => types::Errno::Deadlk,
+            ErrorCode::Quota => types::Errno::Dquot,
+            ErrorCode::Exist => types::Errno::Exist,
+            ErrorCode::FileTooLarge => types::Errno::Fbig,
+            ErrorCode::IllegalByteSequence => types::Errno::Ilseq,
+            ErrorCode::InProgress => types::Errno::Inprogress,
+            ErrorCode::Interrupted => types::Errno::Intr,
+            ErrorCode::Invalid => types::Errno::Inval,
+            ErrorCode::Io => types::Errno::Io,
+            ErrorCode::IsDirectory => types::Errno::Isdir,
+            ErrorCode::Loop => types::Errno::Loop,
+            ErrorCode::TooManyLinks => types::Errno::Mlink,
+            ErrorCode::MessageSize => types::Errno::Msgsize,
+            ErrorCode::NameTooLong => types::Errno::Nametoolong,
+            ErrorCode::NoDevice => types::Errno::Nodev,
+            ErrorCode::NoEntry => types::Errno::Noent,
+            ErrorCode::NoLock => types::Errno::Nolck,
+            ErrorCode::InsufficientMemory => types::Errno::Nomem,
+            ErrorCode::InsufficientSpace => types::Errno::Nospc,
+            ErrorCode::Unsupported => types::Errno::Notsup,
+            ErrorCode::NotDirectory => types::Errno::Notdir,
+            ErrorCode::NotEmpty => types::Errno::Notempty,
+            ErrorCode::NotRecoverable => types::Errno::Notrecoverable,
+            ErrorCode::NoTty => types::Errno::Notty,
+            ErrorCode::NoSuchDevice => types::Errno::Nxio,
+            ErrorCode::Overflow => types::Errno::Overflow,
+            ErrorCode::NotPermitted => types::Errno::Perm,
+            ErrorCode::Pipe => types::Errno::Pipe,
+            ErrorCode::ReadOnly => types::Errno::Rofs,
+            ErrorCode::InvalidSeek => types::Errno::Spipe,
+            ErrorCode::TextFileBusy => types::Errno::Txtbsy,
+            ErrorCode::CrossDevice => types::Errno::Xdev,
+        }
+    }
+}
+
+impl From<wasi::filesystem::Error> for types::Error {
+    fn from(err: wasi::filesystem::Error) -> Self {
+        match err.downcast() {
+            Ok(code) => types::Errno::from(code).into(),
+            Err(trap) => types::Error::trap(trap),
+        }
+    }
+}
+
+impl From<wasi::streams::Error> for types::Error {
+    fn from(err: wasi::streams::Error) -> Self {
+        match err.downcast() {
+            // Preview 2 streams don't say what went wrong.
+            Ok(wasi::streams::StreamError {}) => types::Errno::Io.into(),
+            Err(trap) => types::Error::trap(trap),
+        }
+    }
+}
+
+/// Convert an error from `WasiPreview1Sockets`. I/O errors become errnos,
+/// and anything else traps.
+fn socket_error(err: anyhow::Error) -> types::Error {
+    match err.downcast::<std::io::Error>() {
+        Ok(err) => wasi::filesystem::Error::from(err).into(),
+        Err(err) => types::Error::trap(err),
+    }
+}
+
+impl From<wasi::filesystem::DescriptorType> for types::Filetype {
+    fn from(ty: wasi::filesystem::DescriptorType) -> Self {
+        use wasi::filesystem::DescriptorType;
+        match ty {
+            DescriptorType::RegularFile => types::Filetype::RegularFile,
+            DescriptorType::Directory => types::Filetype::Directory,
+            DescriptorType::BlockDevice => types::Filetype::BlockDevice,
+            DescriptorType::CharacterDevice => types::Filetype::CharacterDevice,
+            DescriptorType::SymbolicLink => types::Filetype::SymbolicLink,
+            // Preview 1 has no code for FIFOs, and preview 2 doesn't say
+            // whether a socket is a stream or datagram socket.
+            DescriptorType::Fifo | DescriptorType::Socket | DescriptorType::Unknown => {
+                types::Filetype::Unknown
+            }
+        }
+    }
+}
+
+impl TryFrom<wasi::filesystem::DescriptorStat> for types::Filestat {
+    type Error = types::Errno;
+
+    fn try_from(stat: wasi::filesystem::DescriptorStat) -> Result<Self, Self::Error> {
+        Ok(types::Filestat {
+            dev: stat.device,
+            ino: stat.inode,
+            filetype: stat.type_.into(),
+            nlink: stat.link_count,
+            size: stat.size,
+            atim: stat.data_access_timestamp.try_into()?,
+            mtim: stat.data_modification_timestamp.try_into()?,
+            ctim: stat.status_change_timestamp.try_into()?,
+        })
+    }
+}
+
 type ErrnoResult<T> = Result<T, types::Errno>;
 
+fn read_string<'a>(ptr: &GuestPtr<'a, str>) -> Result<String, types::Error> {
+    Ok(ptr.as_cow()?.to_string())
+}
+
+/// Find the first non-empty buffer, returning its address and length.
+/// Like the component adapter, reads and writes only use that one buffer.
+fn first_non_empty_iovec<'a>(
+    iovs: &types::IovecArray<'a>,
+) -> Result<Option<(GuestPtr<'a, u8>, types::Size)>, types::Error> {
+    for iov in iovs.iter() {
+        let types::Iovec { buf, buf_len } = iov?.read()?;
+        if buf_len != 0 {
+            return Ok(Some((buf, buf_len)));
+        }
+    }
+    Ok(None)
+}
+
+/// Read the contents of the first non-empty buffer.
+fn first_non_empty_ciovec(ciovs: &types::CiovecArray<'_>) -> Result<Vec<u8>, types::Error> {
+    for ciov in ciovs.iter() {
+        let types::Ciovec { buf, buf_len } = ciov?.read()?;
+        if buf_len != 0 {
+            return Ok(buf.as_array(buf_len).to_vec()?);
+        }
+    }
+    Ok(Vec::new())
+}
+
+fn path_flags_from_lookupflags(flags: types::Lookupflags) -> wasi::filesystem::PathFlags {
+    if flags.contains(types::Lookupflags::SYMLINK_FOLLOW) {
+        wasi::filesystem::PathFlags::SYMLINK_FOLLOW
+    } else {
+        wasi::filesystem::PathFlags::empty()
+    }
+}
+
+fn open_flags_from_oflags(oflags: types::Oflags) -> wasi::filesystem::OpenFlags {
+    use wasi::filesystem::OpenFlags;
+    let mut flags = OpenFlags::empty();
+    if oflags.contains(types::Oflags::CREAT) {
+        flags |= OpenFlags::CREATE;
+    }
+    if oflags.contains(types::Oflags::DIRECTORY) {
+        flags |= OpenFlags::DIRECTORY;
+    }
+    if oflags.contains(types::Oflags::EXCL) {
+        flags |= OpenFlags::EXCLUSIVE;
+    }
+    if oflags.contains(types::Oflags::TRUNC) {
+        flags |= OpenFlags::TRUNCATE;
+    }
+    flags
+}
+
+fn descriptor_flags_from(
+    rights: types::Rights,
+    fdflags: types::Fdflags,
+) -> wasi::filesystem::DescriptorFlags {
+    use wasi::filesystem::DescriptorFlags;
+    let mut flags = DescriptorFlags::empty();
+    if rights.contains(types::Rights::FD_READ) {
+        flags |= DescriptorFlags::READ;
+    }
+    if rights.contains(types::Rights::FD_WRITE) {
+        flags |= DescriptorFlags::WRITE;
+    }
+    if fdflags.contains(types::Fdflags::SYNC) {
+        flags |= DescriptorFlags::FILE_INTEGRITY_SYNC;
+    }
+    if fdflags.contains(types::Fdflags::DSYNC) {
+        flags |= DescriptorFlags::DATA_INTEGRITY_SYNC;
+    }
+    if fdflags.contains(types::Fdflags::RSYNC) {
+        flags |= DescriptorFlags::REQUESTED_WRITE_SYNC;
+    }
+    flags
+}
+
+fn new_timestamp(
+    set: bool,
+    ts: types::Timestamp,
+    now: bool,
+) -> ErrnoResult<wasi::filesystem::NewTimestamp> {
+    match (set, now) {
+        (true, true) => Err(types::Errno::Inval),
+        (true, false) => Ok(wasi::filesystem::NewTimestamp::Timestamp(
+            wasi::filesystem::Datetime {
+                seconds: ts / 1_000_000_000,
+                nanoseconds: (ts % 1_000_000_000) as _,
+            },
+        )),
+        (false, true) => Ok(wasi::filesystem::NewTimestamp::Now),
+        (false, false) => Ok(wasi::filesystem::NewTimestamp::NoChange),
+    }
+}
+
+fn new_timestamps(
+    atim: types::Timestamp,
+    mtim: types::Timestamp,
+    fst_flags: types::Fstflags,
+) -> ErrnoResult<(
+    wasi::filesystem::NewTimestamp,
+    wasi::filesystem::NewTimestamp,
+)> {
+    let atim = new_timestamp(
+        fst_flags.contains(types::Fstflags::ATIM),
+        atim,
+        fst_flags.contains(types::Fstflags::ATIM_NOW),
+    )?;
+    let mtim = new_timestamp(
+        fst_flags.contains(types::Fstflags::MTIM),
+        mtim,
+        fst_flags.contains(types::Fstflags::MTIM_NOW),
+    )?;
+    Ok((atim, mtim))
+}
+
+/// Serialize `dirent` in its guest memory layout.
+fn dirent_bytes(dirent: &types::Dirent) -> [u8; 24] {
+    let mut bytes = [0; 24];
+    bytes[0..8].copy_from_slice(&dirent.d_next.to_le_bytes());
+    bytes[8..16].copy_from_slice(&dirent.d_ino.to_le_bytes());
+    bytes[16..20].copy_from_slice(&dirent.d_namlen.to_le_bytes());
+    bytes[20] = dirent.d_type as u8;
+    bytes
+}
+
 fn write_bytes<'a>(
     ptr: impl Borrow<GuestPtr<'a, u8>>,
     buf: impl AsRef<[u8]>,
@@ -106,6 +923,7 @@ fn write_byte<'a>(ptr: impl Borrow<GuestPtr<'a, u8>>, byte: u8) -> ErrnoResult<G
 #[wiggle::async_trait]
 impl<
         T: WasiPreview1View
+            + crate::WasiView
             + wasi::environment::Host
             + wasi::exit::Host
             + wasi::filesystem::Host
@@ -260,7 +1078,17 @@ impl<
         len: types::Filesize,
         advice: types::Advice,
     ) -> Result<(), types::Error> {
-        todo!()
+        let advice = match advice {
+            types::Advice::Normal => wasi::filesystem::Advice::Normal,
+            types::Advice::Sequential => wasi::filesystem::Advice::Sequential,
+            types::Advice::Random => wasi::filesystem::Advice::Random,
+            types::Advice::Willneed => wasi::filesystem::Advice::WillNeed,
+            types::Advice::Dontneed => wasi::filesystem::Advice::DontNeed,
+            types::Advice::Noreuse => wasi::filesystem::Advice::NoReuse,
+        };
+        let file = descriptors(self).await?.get_seekable_file(fd)?;
+        self.advise(file.fd, offset, len, advice).await?;
+        Ok(())
     }
 
     async fn fd_allocate(
@@ -269,19 +1097,89 @@ impl<
         _offset: types::Filesize,
         _len: types::Filesize,
     ) -> Result<(), types::Error> {
-        todo!()
+        descriptors(self).await?.get_file(fd)?;
+        // This call does not exist in preview 2.
+        Err(types::Errno::Notsup.into())
     }
 
     async fn fd_close(&mut self, fd: types::Fd) -> Result<(), types::Error> {
-        todo!()
+        let desc = descriptors(self).await?.remove(fd)?;
+        close(self, desc).await
     }
 
     async fn fd_datasync(&mut self, fd: types::Fd) -> Result<(), types::Error> {
-        todo!()
+        let file = descriptors(self).await?.get_file(fd)?;
+        self.sync_data(file.fd).await?;
+        Ok(())
     }
 
     async fn fd_fdstat_get(&mut self, fd: types::Fd) -> Result<types::Fdstat, types::Error> {
-        todo!()
+        let descriptors = descriptors(self).await?;
+        let stdio_rights = match descriptors.get(fd)? {
+            Descriptor::Stdin(_) => Some(types::Rights::FD_READ),
+            Descriptor::Stdout(_) | Descriptor::Stderr(_) => Some(types::Rights::FD_WRITE),
+            Descriptor::Socket(socket) => {
+                let rights = types::Rights::FD_READ
+                    | types::Rights::FD_WRITE
+                    | types::Rights::FD_FDSTAT_SET_FLAGS
+                    | types::Rights::POLL_FD_READWRITE
+                    | types::Rights::SOCK_SHUTDOWN
+                    | types::Rights::SOCK_ACCEPT;
+                let mut fs_flags = types::Fdflags::empty();
+                if !socket.blocking {
+                    fs_flags |= types::Fdflags::NONBLOCK;
+                }
+                return Ok(types::Fdstat {
+                    fs_filetype: types::Filetype::SocketStream,
+                    fs_flags,
+                    fs_rights_base: rights,
+                    fs_rights_inheriting: rights,
+                });
+            }
+            Descriptor::PreopenDirectory(..) | Descriptor::File(_) => None,
+        };
+        if let Some(rights) = stdio_rights {
+            return Ok(types::Fdstat {
+                fs_filetype: types::Filetype::CharacterDevice,
+                fs_flags: types::Fdflags::empty(),
+                fs_rights_base: rights,
+                fs_rights_inheriting: rights,
+            });
+        }
+
+        let file = descriptors.get_file_or_dir(fd, types::Errno::Badf)?;
+        let flags = self.get_flags(file.fd).await?;
+        let descriptor_type = self.get_type(file.fd).await?;
+
+        let mut fs_flags = types::Fdflags::empty();
+        let mut fs_rights_base = types::Rights::all();
+        if !flags.contains(wasi::filesystem::DescriptorFlags::READ) {
+            fs_rights_base &= !types::Rights::FD_READ;
+        }
+        if !flags.contains(wasi::filesystem::DescriptorFlags::WRITE) {
+            fs_rights_base &= !types::Rights::FD_WRITE;
+        }
+        if flags.contains(wasi::filesystem::DescriptorFlags::DATA_INTEGRITY_SYNC) {
+            fs_flags |= types::Fdflags::DSYNC;
+        }
+        if flags.contains(wasi::filesystem::DescriptorFlags::REQUESTED_WRITE_SYNC) {
+            fs_flags |= types::Fdflags::RSYNC;
+        }
+        if flags.contains(wasi::filesystem::DescriptorFlags::FILE_INTEGRITY_SYNC) {
+            fs_flags |= types::Fdflags::SYNC;
+        }
+        if file.append {
+            fs_flags |= types::Fdflags::APPEND;
+        }
+        if !file.blocking {
+            fs_flags |= types::Fdflags::NONBLOCK;
+        }
+        Ok(types::Fdstat {
+            fs_filetype: descriptor_type.into(),
+            fs_flags,
+            fs_rights_base,
+            fs_rights_inheriting: fs_rights_base,
+        })
     }
 
     async fn fd_fdstat_set_flags(
@@ -289,20 +1187,70 @@ impl<
         fd: types::Fd,
         flags: types::Fdflags,
     ) -> Result<(), types::Error> {
-        todo!()
+        // Only the NONBLOCK and APPEND flags can be changed.
+        if flags.intersects(!(types::Fdflags::NONBLOCK | types::Fdflags::APPEND)) {
+            return Err(types::Errno::Inval.into());
+        }
+        let nonblocking = flags.contains(types::Fdflags::NONBLOCK);
+        match descriptors(self).await?.get_mut(fd)? {
+            Descriptor::File(file) if !file.is_dir() => {
+                file.append = flags.contains(types::Fdflags::APPEND);
+                file.blocking = !nonblocking;
+                Ok(())
+            }
+            Descriptor::Socket(_) if flags.contains(types::Fdflags::APPEND) => {
+                Err(types::Errno::Inval.into())
+            }
+            Descriptor::Socket(socket) => {
+                let handle = socket.socket;
+                sockets(self)?
+                    .set_nonblocking(handle, nonblocking)
+                    .await
+                    .map_err(socket_error)?;
+                descriptors(self).await?.set_blocking(fd, !nonblocking);
+                Ok(())
+            }
+            _ => Err(types::Errno::Badf.into()),
+        }
     }
 
     async fn fd_fdstat_set_rights(
         &mut self,
         fd: types::Fd,
-        fs_rights_base: types::Rights,
-        fs_rights_inheriting: types::Rights,
+        _fs_rights_base: types::Rights,
+        _fs_rights_inheriting: types::Rights,
     ) -> Result<(), types::Error> {
-        todo!()
+        // Preview 2 has no rights to drop, so this only checks that `fd` is
+        // open.
+        descriptors(self).await?.get(fd)?;
+        Ok(())
     }
 
     async fn fd_filestat_get(&mut self, fd: types::Fd) -> Result<types::Filestat, types::Error> {
-        todo!()
+        let descriptors = descriptors(self).await?;
+        let filetype = match descriptors.get(fd)? {
+            Descriptor::Stdin(_) | Descriptor::Stdout(_) | Descriptor::Stderr(_) => {
+                Some(types::Filetype::CharacterDevice)
+            }
+            Descriptor::Socket(_) => Some(types::Filetype::SocketStream),
+            Descriptor::PreopenDirectory(..) | Descriptor::File(_) => None,
+        };
+        if let Some(filetype) = filetype {
+            // Stdio and sockets are all zero fields, except for the filetype.
+            return Ok(types::Filestat {
+                dev: 0,
+                ino: 0,
+                filetype,
+                nlink: 0,
+                size: 0,
+                atim: 0,
+                mtim: 0,
+                ctim: 0,
+            });
+        }
+        let file = descriptors.get_file_or_dir(fd, types::Errno::Badf)?;
+        let stat = wasi::filesystem::Host::stat(self, file.fd).await?;
+        Ok(stat.try_into()?)
     }
 
     async fn fd_filestat_set_size(
@@ -310,7 +1258,9 @@ impl<
         fd: types::Fd,
         size: types::Filesize,
     ) -> Result<(), types::Error> {
-        todo!()
+        let file = descriptors(self).await?.get_file(fd)?;
+        self.set_size(file.fd, size).await?;
+        Ok(())
     }
 
     async fn fd_filestat_set_times(
@@ -320,7 +1270,12 @@ impl<
         mtim: types::Timestamp,
         fst_flags: types::Fstflags,
     ) -> Result<(), types::Error> {
-        todo!()
+        let (atim, mtim) = new_timestamps(atim, mtim, fst_flags)?;
+        let file = descriptors(self)
+            .await?
+            .get_file_or_dir(fd, types::Errno::Badf)?;
+        self.set_times(file.fd, atim, mtim).await?;
+        Ok(())
     }
 
     async fn fd_read<'a>(
@@ -328,7 +1283,38 @@ impl<
         fd: types::Fd,
         iovs: &types::IovecArray<'a>,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let (buf, buf_len) = match first_non_empty_iovec(iovs)? {
+            Some(iov) => iov,
+            None => return Ok(0),
+        };
+
+        let (data, end) = match descriptors(self).await?.get(fd)? {
+            Descriptor::Stdin(stream) => {
+                let stream = *stream;
+                wasi::streams::Host::blocking_read(self, stream, buf_len.into()).await?
+            }
+            Descriptor::File(file) if !file.is_dir() => {
+                let file = *file;
+                let (data, end) =
+                    wasi::filesystem::Host::read(self, file.fd, buf_len.into(), file.position)
+                        .await?;
+                // Keep the current-position pointer up to date.
+                let position = file.position + data.len() as wasi::filesystem::Filesize;
+                descriptors(self).await?.set_position(fd, position);
+                (data, end)
+            }
+            Descriptor::Socket(socket) => {
+                let socket = *socket;
+                socket_recv(self, socket, buf_len.into()).await?
+            }
+            _ => return Err(types::Errno::Badf.into()),
+        };
+
+        if !end && data.is_empty() {
+            return Err(types::Errno::Intr.into());
+        }
+        write_bytes(buf, &data)?;
+        Ok(data.len().try_into()?)
     }
 
     async fn fd_pread<'a>(
@@ -337,7 +1323,19 @@ impl<
         iovs: &types::IovecArray<'a>,
         offset: types::Filesize,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let (buf, buf_len) = match first_non_empty_iovec(iovs)? {
+            Some(iov) => iov,
+            None => return Ok(0),
+        };
+
+        let file = descriptors(self).await?.get_seekable_file(fd)?;
+        let (data, end) =
+            wasi::filesystem::Host::read(self, file.fd, buf_len.into(), offset).await?;
+        if !end && data.is_empty() {
+            return Err(types::Errno::Intr.into());
+        }
+        write_bytes(buf, &data)?;
+        Ok(data.len().try_into()?)
     }
 
     async fn fd_write<'a>(
@@ -345,7 +1343,46 @@ impl<
         fd: types::Fd,
         ciovs: &types::CiovecArray<'a>,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let buf = first_non_empty_ciovec(ciovs)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let n = match descriptors(self).await?.get(fd)? {
+            Descriptor::Stdout(stream) | Descriptor::Stderr(stream) => {
+                let stream = *stream;
+                wasi::streams::Host::blocking_write(self, stream, buf).await?
+            }
+            Descriptor::File(file) if !file.is_dir() && file.append => {
+                // The position is left alone: there is no way to learn the
+                // new end of the file atomically.
+                let file = *file;
+                let stream = self
+                    .append_via_stream(file.fd)
+                    .await
+                    .context("failed to call `append-via-stream`")
+                    .map_err(types::Error::trap)?;
+                let result = wasi::streams::Host::blocking_write(self, stream, buf).await;
+                self.drop_output_stream(stream)
+                    .await
+                    .context("failed to call `drop-output-stream`")
+                    .map_err(types::Error::trap)?;
+                result?
+            }
+            Descriptor::File(file) if !file.is_dir() => {
+                let file = *file;
+                let n = wasi::filesystem::Host::write(self, file.fd, buf, file.position).await?;
+                // Keep the current-position pointer up to date.
+                descriptors(self).await?.set_position(fd, file.position + n);
+                n
+            }
+            Descriptor::Socket(socket) => {
+                let socket = *socket;
+                socket_send(self, socket, buf).await?
+            }
+            _ => return Err(types::Errno::Badf.into()),
+        };
+        Ok(n.try_into()?)
     }
 
     async fn fd_pwrite<'a>(
@@ -354,11 +1391,23 @@ impl<
         ciovs: &types::CiovecArray<'a>,
         offset: types::Filesize,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let file = descriptors(self).await?.get_seekable_file(fd)?;
+        let buf = first_non_empty_ciovec(ciovs)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = wasi::filesystem::Host::write(self, file.fd, buf, offset).await?;
+        Ok(n.try_into()?)
     }
 
     async fn fd_prestat_get(&mut self, fd: types::Fd) -> Result<types::Prestat, types::Error> {
-        todo!()
+        match descriptors(self).await?.get(fd)? {
+            Descriptor::PreopenDirectory(_, path) => {
+                let pr_name_len = path.len().try_into()?;
+                Ok(types::Prestat::Dir(types::PrestatDir { pr_name_len }))
+            }
+            _ => Err(types::Errno::Badf.into()),
+        }
     }
 
     async fn fd_prestat_dir_name<'a>(
@@ -367,10 +1416,30 @@ impl<
         path: &GuestPtr<'a, u8>,
         path_max_len: types::Size,
     ) -> Result<(), types::Error> {
-        todo!()
+        match descriptors(self).await?.get(fd)? {
+            Descriptor::PreopenDirectory(_, name) => {
+                if name.len() > usize::try_from(path_max_len)? {
+                    return Err(types::Errno::Nametoolong.into());
+                }
+                write_bytes(path, name)?;
+                Ok(())
+            }
+            _ => Err(types::Errno::Notdir.into()),
+        }
     }
+
     async fn fd_renumber(&mut self, from: types::Fd, to: types::Fd) -> Result<(), types::Error> {
-        todo!()
+        let descriptors = descriptors(self).await?;
+        descriptors.get(from)?;
+        descriptors.get(to)?;
+        if u32::from(from) == u32::from(to) {
+            return Ok(());
+        }
+        let desc = descriptors.remove(from)?;
+        if let Some(replaced) = descriptors.used.insert(to.into(), desc) {
+            close(self, replaced).await?;
+        }
+        Ok(())
     }
 
     async fn fd_seek(
@@ -379,15 +1448,32 @@ impl<
         offset: types::Filedelta,
         whence: types::Whence,
     ) -> Result<types::Filesize, types::Error> {
-        todo!()
+        let file = descriptors(self).await?.get_seekable_file(fd)?;
+        let position = match whence {
+            types::Whence::Set => Some(offset),
+            types::Whence::Cur => (file.position as i64).checked_add(offset),
+            types::Whence::End => {
+                let stat = wasi::filesystem::Host::stat(self, file.fd).await?;
+                (stat.size as i64).checked_add(offset)
+            }
+        };
+        let position = match position {
+            Some(position) if position >= 0 => position as types::Filesize,
+            _ => return Err(types::Errno::Inval.into()),
+        };
+        descriptors(self).await?.set_position(fd, position);
+        Ok(position)
     }
 
     async fn fd_sync(&mut self, fd: types::Fd) -> Result<(), types::Error> {
-        todo!()
+        let file = descriptors(self).await?.get_file(fd)?;
+        wasi::filesystem::Host::sync(self, file.fd).await?;
+        Ok(())
     }
 
     async fn fd_tell(&mut self, fd: types::Fd) -> Result<types::Filesize, types::Error> {
-        todo!()
+        let file = descriptors(self).await?.get_seekable_file(fd)?;
+        Ok(file.position)
     }
 
     async fn fd_readdir<'a>(
@@ -397,7 +1483,58 @@ impl<
         buf_len: types::Size,
         cookie: types::Dircookie,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(fd)?;
+
+        // Preview 1 programs expect to see `.` and `..` in the traversal, but
+        // preview 2 excludes them, so re-add them.
+        let stat = wasi::filesystem::Host::stat(self, dir).await?;
+        // The parent of a preopen is outside of it, so it has no inode.
+        let parent = self
+            .stat_at(dir, wasi::filesystem::PathFlags::empty(), "..".to_owned())
+            .await
+            .map_or(0, |stat| stat.inode);
+        let mut entries = vec![
+            (stat.inode, types::Filetype::Directory, ".".to_owned()),
+            (parent, types::Filetype::Directory, "..".to_owned()),
+        ];
+
+        let stream = self.read_directory(dir).await?;
+        let result = loop {
+            match self.read_directory_entry(stream).await {
+                Ok(Some(entry)) => {
+                    entries.push((entry.inode.unwrap_or(0), entry.type_.into(), entry.name))
+                }
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.drop_directory_entry_stream(stream)
+            .await
+            .context("failed to call `drop-directory-entry-stream`")
+            .map_err(types::Error::trap)?;
+        result?;
+
+        // The cookie of an entry is its index in the traversal. The last
+        // entry is truncated if it doesn't fit in the caller's buffer.
+        let buf_len = usize::try_from(buf_len)?;
+        let mut bytes = Vec::new();
+        let skip = usize::try_from(cookie).unwrap_or(usize::MAX);
+        for (d_next, (d_ino, d_type, name)) in (1..).zip(entries).skip(skip) {
+            let dirent = types::Dirent {
+                d_next,
+                d_ino,
+                d_namlen: name.len().try_into()?,
+                d_type,
+            };
+            bytes.extend_from_slice(&dirent_bytes(&dirent));
+            bytes.extend_from_slice(name.as_bytes());
+            if bytes.len() >= buf_len {
+                break;
+            }
+        }
+        bytes.truncate(buf_len);
+        write_bytes(buf, &bytes)?;
+        Ok(bytes.len().try_into()?)
     }
 
     async fn path_create_directory<'a>(
@@ -405,7 +1542,9 @@ impl<
         dirfd: types::Fd,
         path: &GuestPtr<'a, str>,
     ) -> Result<(), types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        self.create_directory_at(dir, read_string(path)?).await?;
+        Ok(())
     }
 
     async fn path_filestat_get<'a>(
@@ -414,7 +1553,11 @@ impl<
         flags: types::Lookupflags,
         path: &GuestPtr<'a, str>,
     ) -> Result<types::Filestat, types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        let stat = self
+            .stat_at(dir, path_flags_from_lookupflags(flags), read_string(path)?)
+            .await?;
+        Ok(stat.try_into()?)
     }
 
     async fn path_filestat_set_times<'a>(
@@ -426,7 +1569,17 @@ impl<
         mtim: types::Timestamp,
         fst_flags: types::Fstflags,
     ) -> Result<(), types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        let (atim, mtim) = new_timestamps(atim, mtim, fst_flags)?;
+        self.set_times_at(
+            dir,
+            path_flags_from_lookupflags(flags),
+            read_string(path)?,
+            atim,
+            mtim,
+        )
+        .await?;
+        Ok(())
     }
 
     async fn path_link<'a>(
@@ -437,7 +1590,18 @@ impl<
         target_fd: types::Fd,
         target_path: &GuestPtr<'a, str>,
     ) -> Result<(), types::Error> {
-        todo!()
+        let descriptors = descriptors(self).await?;
+        let src_dir = descriptors.get_dir(src_fd)?;
+        let target_dir = descriptors.get_dir(target_fd)?;
+        self.link_at(
+            src_dir,
+            path_flags_from_lookupflags(src_flags),
+            read_string(src_path)?,
+            target_dir,
+            read_string(target_path)?,
+        )
+        .await?;
+        Ok(())
     }
 
     async fn path_open<'a>(
@@ -447,10 +1611,38 @@ impl<
         path: &GuestPtr<'a, str>,
         oflags: types::Oflags,
         fs_rights_base: types::Rights,
-        fs_rights_inheriting: types::Rights,
+        _fs_rights_inheriting: types::Rights,
         fdflags: types::Fdflags,
     ) -> Result<types::Fd, types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        let fd = self
+            .open_at(
+                dir,
+                path_flags_from_lookupflags(dirflags),
+                read_string(path)?,
+                open_flags_from_oflags(oflags),
+                descriptor_flags_from(fs_rights_base, fdflags),
+                wasi::filesystem::Modes::READABLE | wasi::filesystem::Modes::WRITEABLE,
+            )
+            .await?;
+        let descriptor_type = match self.get_type(fd).await {
+            Ok(descriptor_type) => descriptor_type,
+            Err(e) => {
+                self.drop_descriptor(fd)
+                    .await
+                    .context("failed to call `drop-descriptor`")
+                    .map_err(types::Error::trap)?;
+                return Err(e.into());
+            }
+        };
+        let file = File {
+            fd,
+            descriptor_type,
+            position: 0,
+            append: fdflags.contains(types::Fdflags::APPEND),
+            blocking: !fdflags.contains(types::Fdflags::NONBLOCK),
+        };
+        Ok(descriptors(self).await?.push(Descriptor::File(file))?)
     }
 
     async fn path_readlink<'a>(
@@ -460,7 +1652,13 @@ impl<
         buf: &GuestPtr<'a, u8>,
         buf_len: types::Size,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        let target = self.readlink_at(dir, read_string(path)?).await?;
+        // Preview 1 follows POSIX in truncating the returned path if it
+        // doesn't fit.
+        let target = &target.as_bytes()[..target.len().min(usize::try_from(buf_len)?)];
+        write_bytes(buf, target)?;
+        Ok(target.len().try_into()?)
     }
 
     async fn path_remove_directory<'a>(
@@ -468,7 +1666,9 @@ impl<
         dirfd: types::Fd,
         path: &GuestPtr<'a, str>,
     ) -> Result<(), types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        self.remove_directory_at(dir, read_string(path)?).await?;
+        Ok(())
     }
 
     async fn path_rename<'a>(
@@ -478,7 +1678,17 @@ impl<
         dest_fd: types::Fd,
         dest_path: &GuestPtr<'a, str>,
     ) -> Result<(), types::Error> {
-        todo!()
+        let descriptors = descriptors(self).await?;
+        let src_dir = descriptors.get_dir(src_fd)?;
+        let dest_dir = descriptors.get_dir(dest_fd)?;
+        self.rename_at(
+            src_dir,
+            read_string(src_path)?,
+            dest_dir,
+            read_string(dest_path)?,
+        )
+        .await?;
+        Ok(())
     }
 
     async fn path_symlink<'a>(
@@ -487,7 +1697,10 @@ impl<
         dirfd: types::Fd,
         dest_path: &GuestPtr<'a, str>,
     ) -> Result<(), types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        self.symlink_at(dir, read_string(src_path)?, read_string(dest_path)?)
+            .await?;
+        Ok(())
     }
 
     async fn path_unlink_file<'a>(
@@ -495,7 +1708,9 @@ impl<
         dirfd: types::Fd,
         path: &GuestPtr<'a, str>,
     ) -> Result<(), types::Error> {
-        todo!()
+        let dir = descriptors(self).await?.get_dir(dirfd)?;
+        self.unlink_file_at(dir, read_string(path)?).await?;
+        Ok(())
     }
 
     async fn poll_oneoff<'a>(
@@ -504,7 +1719,80 @@ impl<
         events: &GuestPtr<'a, types::Event>,
         nsubscriptions: types::Size,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        // Indefinite sleeping is not supported in preview1.
+        if nsubscriptions == 0 {
+            return Err(types::Errno::Inval.into());
+        }
+        let subs = subs
+            .as_array(nsubscriptions)
+            .iter()
+            .map(|sub| Ok(sub?.read()?))
+            .collect::<Result<Vec<types::Subscription>, types::Error>>()?;
+
+        // Resolve every subscription before subscribing to any of them, so
+        // that an invalid one fails the call without leaking pollables.
+        let mut sources = Vec::with_capacity(subs.len());
+        for sub in &subs {
+            let source = match &sub.u {
+                types::SubscriptionU::Clock(clock) => {
+                    let absolute = clock
+                        .flags
+                        .contains(types::Subclockflags::SUBSCRIPTION_CLOCK_ABSTIME);
+                    match clock.id {
+                        types::Clockid::Realtime => {
+                            // Poll the monotonic clock for the time remaining
+                            // until the deadline.
+                            let timeout = if absolute {
+                                let now: types::Timestamp = wasi::wall_clock::Host::now(self)
+                                    .await
+                                    .context("failed to call `wall_clock::now`")
+                                    .map_err(types::Error::trap)?
+                                    .try_into()?;
+                                clock.timeout.saturating_sub(now)
+                            } else {
+                                clock.timeout
+                            };
+                            PollSource::Clock(timeout, false)
+                        }
+                        types::Clockid::Monotonic => PollSource::Clock(clock.timeout, absolute),
+                        types::Clockid::ProcessCputimeId | types::Clockid::ThreadCputimeId => {
+                            return Err(types::Errno::Inval.into())
+                        }
+                    }
+                }
+                types::SubscriptionU::FdRead(fd_read) => descriptors(self)
+                    .await?
+                    .get_poll_source(fd_read.file_descriptor, false)?,
+                types::SubscriptionU::FdWrite(fd_write) => descriptors(self)
+                    .await?
+                    .get_poll_source(fd_write.file_descriptor, true)?,
+            };
+            sources.push(source);
+        }
+
+        // Files are polled through streams which only live for this call.
+        // Whatever was created is dropped again even if a step fails.
+        let mut resources = PollResources::default();
+        let ready = poll_sources(self, &sources, &mut resources).await;
+        let dropped = drop_poll_resources(self, resources).await;
+        let ready = ready?;
+        dropped?;
+
+        let mut count: types::Size = 0;
+        for (sub, (type_, error, nbytes, flags)) in subs.iter().zip(ready) {
+            let type_ = match type_ {
+                Some(type_) => type_,
+                None => continue,
+            };
+            events.add(count)?.write(types::Event {
+                userdata: sub.userdata,
+                error,
+                type_,
+                fd_readwrite: types::EventFdReadwrite { nbytes, flags },
+            })?;
+            count += 1;
+        }
+        Ok(count)
     }
 
     async fn proc_exit(&mut self, status: types::Exitcode) -> anyhow::Error {
@@ -546,7 +1834,24 @@ impl<
         fd: types::Fd,
         flags: types::Fdflags,
     ) -> Result<types::Fd, types::Error> {
-        todo!()
+        if flags.intersects(!types::Fdflags::NONBLOCK) {
+            return Err(types::Errno::Inval.into());
+        }
+        let listener = descriptors(self).await?.get_socket(fd)?;
+        if listener.streams.is_some() {
+            return Err(types::Errno::Inval.into());
+        }
+        let nonblocking = flags.contains(types::Fdflags::NONBLOCK);
+        let (socket, input, output) = sockets(self)?
+            .accept(listener.socket, nonblocking)
+            .await
+            .map_err(socket_error)?;
+        let socket = Socket {
+            socket,
+            streams: Some((input, output)),
+            blocking: !nonblocking,
+        };
+        Ok(descriptors(self).await?.push(Descriptor::Socket(socket))?)
     }
 
     async fn sock_recv<'a>(
@@ -555,7 +1860,22 @@ impl<
         ri_data: &types::IovecArray<'a>,
         ri_flags: types::Riflags,
     ) -> Result<(types::Size, types::Roflags), types::Error> {
-        todo!()
+        // Streams can't peek, or wait for the whole buffer.
+        if !ri_flags.is_empty() {
+            return Err(types::Errno::Notsup.into());
+        }
+        let socket = descriptors(self).await?.get_socket(fd)?;
+        let (buf, buf_len) = match first_non_empty_iovec(ri_data)? {
+            Some(iov) => iov,
+            None => return Ok((0, types::Roflags::empty())),
+        };
+
+        let (data, end) = socket_recv(self, socket, buf_len.into()).await?;
+        if !end && data.is_empty() {
+            return Err(types::Errno::Intr.into());
+        }
+        write_bytes(buf, &data)?;
+        Ok((data.len().try_into()?, types::Roflags::empty()))
     }
 
     async fn sock_send<'a>(
@@ -564,7 +1884,13 @@ impl<
         si_data: &types::CiovecArray<'a>,
         _si_flags: types::Siflags,
     ) -> Result<types::Size, types::Error> {
-        todo!()
+        let socket = descriptors(self).await?.get_socket(fd)?;
+        let buf = first_non_empty_ciovec(si_data)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = socket_send(self, socket, buf).await?;
+        Ok(n.try_into()?)
     }
 
     async fn sock_shutdown(
@@ -572,6 +1898,23 @@ impl<
         fd: types::Fd,
         how: types::Sdflags,
     ) -> Result<(), types::Error> {
-        todo!()
+        let how = match (
+            how.contains(types::Sdflags::RD),
+            how.contains(types::Sdflags::WR),
+        ) {
+            (true, true) => std::net::Shutdown::Both,
+            (true, false) => std::net::Shutdown::Read,
+            (false, true) => std::net::Shutdown::Write,
+            (false, false) => return Err(types::Errno::Inval.into()),
+        };
+        let socket = descriptors(self).await?.get_socket(fd)?;
+        if socket.streams.is_none() {
+            return Err(types::Errno::Notconn.into());
+        }
+        sockets(self)?
+            .shutdown(socket.socket, how)
+            .await
+            .map_err(socket_error)?;
+        Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use crate::{
+    http::{self, TableHttpExt},
     wasi,
     wasi::types::{FutureIncomingResponse as Response, OutgoingRequest as Request, RequestOptions},
     WasiView,
@@ -8,9 +9,24 @@ use crate::{
 impl<T: WasiView> wasi::default_outgoing_http::Host for T {
     async fn handle(
         &mut self,
-        _req: Request,
-        _options: Option<RequestOptions>,
+        req: Request,
+        options: Option<RequestOptions>,
     ) -> wasmtime::Result<Response> {
-        anyhow::bail!("not implemented")
+        let options = options
+            .map(http::client::RequestOptions::from)
+            .unwrap_or_default();
+        // The body is sent as the guest writes it, through the stream it
+        // already has, if any.
+        let mut request = {
+            let request = self.table_mut().get_outgoing_request_mut(req)?;
+            request.seal_body();
+            request.clone()
+        };
+        let http = &self.ctx().http;
+        let response = match http.check(&mut request) {
+            Ok(()) => http.send(request, options),
+            Err(e) => http::FutureIncomingResponse::ready(Err(e)),
+        };
+        Ok(self.table_mut().push_future_incoming_response(response)?)
     }
 }
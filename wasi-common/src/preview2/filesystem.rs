@@ -1,6 +1,9 @@
-use crate::filesystem::{Dir, File, TableFsExt};
+use crate::filesystem::{
+    spawn_blocking, Dir, File, FsEvent, FsOp, FsPath, FsQuota, LockOp, Opened, TableFsExt,
+};
 use crate::stream::TableStreamExt;
-use crate::{wasi, DirPerms, FilePerms, Table, TableError, WasiView};
+use crate::{wasi, DirPerms, FilePerms, Table, TableError, WasiCtx, WasiDir, WasiView};
+use std::sync::Arc;
 
 use wasi::filesystem::ErrorCode;
 
@@ -8,7 +11,9 @@ impl From<TableError> for wasi::filesystem::Error {
     fn from(error: TableError) -> wasi::filesystem::Error {
         match error {
             TableError::Full => wasi::filesystem::Error::trap(anyhow::anyhow!(error)),
-            TableError::NotPresent | TableError::WrongType => ErrorCode::BadDescriptor.into(),
+            TableError::NotPresent | TableError::WrongType | TableError::AlreadyBorrowed => {
+                ErrorCode::BadDescriptor.into()
+            }
         }
     }
 }
@@ -22,21 +27,13 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         len: wasi::filesystem::Filesize,
         advice: wasi::filesystem::Advice,
     ) -> Result<(), wasi::filesystem::Error> {
-        use system_interface::fs::{Advice as A, FileIoExt};
-        use wasi::filesystem::Advice;
-
-        let advice = match advice {
-            Advice::Normal => A::Normal,
-            Advice::Sequential => A::Sequential,
-            Advice::Random => A::Random,
-            Advice::WillNeed => A::WillNeed,
-            Advice::DontNeed => A::DontNeed,
-            Advice::NoReuse => A::NoReuse,
-        };
-
         let f = self.table().get_file(fd)?;
-        f.file.advise(offset, len, advice)?;
-        Ok(())
+        audited(
+            self.ctx(),
+            FsOp::Advise,
+            || Some(f.location.clone()),
+            || f.file.advise(offset, len, advice),
+        )
     }
 
     async fn sync_data(
@@ -44,79 +41,65 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
         let table = self.table();
-        if table.is_file(fd) {
-            match table.get_file(fd)?.file.sync_data() {
-                Ok(()) => Ok(()),
-                // On windows, `sync_data` uses `FileFlushBuffers` which fails with
-                // `ERROR_ACCESS_DENIED` if the file is not upen for writing. Ignore
-                // this error, for POSIX compatibility.
-                #[cfg(windows)]
-                Err(e)
-                    if e.raw_os_error()
-                        == Some(windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED as _) =>
-                {
-                    Ok(())
-                }
-                Err(e) => Err(e.into()),
-            }
+        let result = if table.is_file(fd) {
+            let file = Arc::clone(&table.get_file(fd)?.file);
+            spawn_blocking(move || file.sync_data()).await
         } else if table.is_dir(fd) {
-            Ok(table
-                .get_dir(fd)?
-                .dir
-                .open(std::path::Component::CurDir)?
-                .sync_data()?)
+            let dir = Arc::clone(&table.get_dir(fd)?.dir);
+            spawn_blocking(move || dir.sync_data()).await
         } else {
             Err(ErrorCode::BadDescriptor.into())
-        }
+        };
+        audited(
+            self.ctx(),
+            FsOp::SyncData,
+            || location(table, fd),
+            || result,
+        )
     }
 
     async fn get_flags(
         &mut self,
         fd: wasi::filesystem::Descriptor,
     ) -> Result<wasi::filesystem::DescriptorFlags, wasi::filesystem::Error> {
-        use cap_std::io_lifetimes::AsFilelike;
-        use system_interface::fs::{FdFlags, GetSetFdFlags};
         use wasi::filesystem::DescriptorFlags;
 
-        fn get_from_fdflags(f: impl AsFilelike) -> std::io::Result<DescriptorFlags> {
-            let flags = f.as_filelike().get_fd_flags()?;
-            let mut out = DescriptorFlags::empty();
-            if flags.contains(FdFlags::DSYNC) {
-                out |= DescriptorFlags::REQUESTED_WRITE_SYNC;
-            }
-            if flags.contains(FdFlags::RSYNC) {
-                out |= DescriptorFlags::DATA_INTEGRITY_SYNC;
-            }
-            if flags.contains(FdFlags::SYNC) {
-                out |= DescriptorFlags::FILE_INTEGRITY_SYNC;
-            }
-            Ok(out)
-        }
-
         let table = self.table();
-        if table.is_file(fd) {
+        let result = if table.is_file(fd) {
             let f = table.get_file(fd)?;
-            let mut flags = get_from_fdflags(&*f.file)?;
+            let file = Arc::clone(&f.file);
+            let mut perms = DescriptorFlags::empty();
             if f.perms.contains(FilePerms::READ) {
-                flags |= DescriptorFlags::READ;
+                perms |= DescriptorFlags::READ;
             }
             if f.perms.contains(FilePerms::WRITE) {
-                flags |= DescriptorFlags::WRITE;
+                perms |= DescriptorFlags::WRITE;
             }
-            Ok(flags)
+            spawn_blocking(move || file.sync_flags())
+                .await
+                .map(|flags| flags | perms)
         } else if table.is_dir(fd) {
             let d = table.get_dir(fd)?;
-            let mut flags = get_from_fdflags(&d.dir)?;
+            let dir = Arc::clone(&d.dir);
+            let mut perms = DescriptorFlags::empty();
             if d.perms.contains(DirPerms::READ) {
-                flags |= DescriptorFlags::READ;
+                perms |= DescriptorFlags::READ;
             }
             if d.perms.contains(DirPerms::MUTATE) {
-                flags |= DescriptorFlags::MUTATE_DIRECTORY;
+                perms |= DescriptorFlags::MUTATE_DIRECTORY;
             }
-            Ok(flags)
+            spawn_blocking(move || dir.sync_flags())
+                .await
+                .map(|flags| flags | perms)
         } else {
             Err(ErrorCode::BadDescriptor.into())
-        }
+        };
+        audited(
+            self.ctx(),
+            FsOp::GetFlags,
+            || location(table, fd),
+            || result,
+        )
     }
 
     async fn get_type(
@@ -124,15 +107,17 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
     ) -> Result<wasi::filesystem::DescriptorType, wasi::filesystem::Error> {
         let table = self.table();
-
-        if table.is_file(fd) {
-            let meta = table.get_file(fd)?.file.metadata()?;
-            Ok(descriptortype_from(meta.file_type()))
+        let result = if table.is_file(fd) {
+            let file = Arc::clone(&table.get_file(fd)?.file);
+            spawn_blocking(move || file.stat())
+                .await
+                .map(|stat| stat.type_)
         } else if table.is_dir(fd) {
             Ok(wasi::filesystem::DescriptorType::Directory)
         } else {
             Err(ErrorCode::BadDescriptor.into())
-        }
+        };
+        audited(self.ctx(), FsOp::GetType, || location(table, fd), || result)
     }
 
     async fn set_size(
@@ -141,11 +126,32 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         size: wasi::filesystem::Filesize,
     ) -> Result<(), wasi::filesystem::Error> {
         let f = self.table().get_file(fd)?;
-        if !f.perms.contains(FilePerms::WRITE) {
-            Err(ErrorCode::NotPermitted)?;
-        }
-        f.file.set_len(size)?;
-        Ok(())
+        let result = if f.perms.contains(FilePerms::WRITE) {
+            let file = Arc::clone(&f.file);
+            let quota = Arc::clone(&self.ctx().fs_quota);
+            spawn_blocking(move || {
+                let current = file.stat()?.size;
+                if size <= current {
+                    return file.set_len(size);
+                }
+                // Growing the file counts as writing the bytes it adds.
+                quota.write(
+                    size - current,
+                    || Ok((current, current)),
+                    || file.set_len(size).map(|()| size - current),
+                )?;
+                Ok(())
+            })
+            .await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(
+            self.ctx(),
+            FsOp::SetSize,
+            || Some(f.location.clone()),
+            || result,
+        )
     }
 
     async fn set_times(
@@ -154,30 +160,32 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         atim: wasi::filesystem::NewTimestamp,
         mtim: wasi::filesystem::NewTimestamp,
     ) -> Result<(), wasi::filesystem::Error> {
-        use fs_set_times::SetTimes;
-
         let table = self.table();
-        if table.is_file(fd) {
+        let result = if table.is_file(fd) {
             let f = table.get_file(fd)?;
-            if !f.perms.contains(FilePerms::WRITE) {
-                return Err(ErrorCode::NotPermitted.into());
+            if f.perms.contains(FilePerms::WRITE) {
+                let file = Arc::clone(&f.file);
+                spawn_blocking(move || file.set_times(atim, mtim)).await
+            } else {
+                Err(ErrorCode::NotPermitted.into())
             }
-            let atim = systemtimespec_from(atim)?;
-            let mtim = systemtimespec_from(mtim)?;
-            f.file.set_times(atim, mtim)?;
-            Ok(())
         } else if table.is_dir(fd) {
             let d = table.get_dir(fd)?;
-            if !d.perms.contains(DirPerms::MUTATE) {
-                return Err(ErrorCode::NotPermitted.into());
+            if d.perms.contains(DirPerms::MUTATE) {
+                let dir = Arc::clone(&d.dir);
+                spawn_blocking(move || dir.set_times(atim, mtim)).await
+            } else {
+                Err(ErrorCode::NotPermitted.into())
             }
-            let atim = systemtimespec_from(atim)?;
-            let mtim = systemtimespec_from(mtim)?;
-            d.dir.set_times(atim, mtim)?;
-            Ok(())
         } else {
             Err(ErrorCode::BadDescriptor.into())
-        }
+        };
+        audited(
+            self.ctx(),
+            FsOp::SetTimes,
+            || location(table, fd),
+            || result,
+        )
     }
 
     async fn read(
@@ -186,29 +194,35 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         len: wasi::filesystem::Filesize,
         offset: wasi::filesystem::Filesize,
     ) -> Result<(Vec<u8>, bool), wasi::filesystem::Error> {
-        use std::io::IoSliceMut;
-        use system_interface::fs::FileIoExt;
-
         let table = self.table();
 
         let f = table.get_file(fd)?;
-        if !f.perms.contains(FilePerms::READ) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-
-        let mut buffer = vec![0; len.try_into().unwrap_or(usize::MAX)];
-        let (bytes_read, end) = crate::filesystem::read_result(
-            f.file
-                .read_vectored_at(&mut [IoSliceMut::new(&mut buffer)], offset),
-        )?;
-
-        buffer.truncate(
-            bytes_read
-                .try_into()
-                .expect("bytes read into memory as u64 fits in usize"),
-        );
+        let result = if f.perms.contains(FilePerms::READ) {
+            let file = Arc::clone(&f.file);
+            spawn_blocking(move || {
+                let mut buffer = vec![0; len.try_into().unwrap_or(usize::MAX)];
+                file.read_at(&mut buffer, offset).map(|(bytes_read, end)| {
+                    buffer.truncate(
+                        bytes_read
+                            .try_into()
+                            .expect("bytes read into memory as u64 fits in usize"),
+                    );
+                    (buffer, end)
+                })
+            })
+            .await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
 
-        Ok((buffer, end))
+        self.ctx().audit(|| {
+            let event = FsEvent::new(FsOp::Read, f.location.clone(), &result);
+            match &result {
+                Ok((buffer, _)) => event.bytes(buffer.len() as u64),
+                Err(_) => event,
+            }
+        });
+        result
     }
 
     async fn write(
@@ -217,82 +231,62 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         buf: Vec<u8>,
         offset: wasi::filesystem::Filesize,
     ) -> Result<wasi::filesystem::Filesize, wasi::filesystem::Error> {
-        use std::io::IoSlice;
-        use system_interface::fs::FileIoExt;
-
         let table = self.table();
         let f = table.get_file(fd)?;
-        if !f.perms.contains(FilePerms::WRITE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-
-        let bytes_written = f.file.write_vectored_at(&[IoSlice::new(&buf)], offset)?;
+        let result = if f.perms.contains(FilePerms::WRITE) {
+            let file = Arc::clone(&f.file);
+            let quota = Arc::clone(&self.ctx().fs_quota);
+            spawn_blocking(move || {
+                quota.write(
+                    buf.len() as u64,
+                    || Ok((offset, file.stat()?.size)),
+                    || file.write_at(&buf, offset),
+                )
+            })
+            .await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
 
-        Ok(wasi::filesystem::Filesize::try_from(bytes_written).expect("usize fits in Filesize"))
+        self.ctx().audit(|| {
+            let event = FsEvent::new(FsOp::Write, f.location.clone(), &result);
+            match &result {
+                Ok(n) => event.bytes(*n),
+                Err(_) => event,
+            }
+        });
+        result
     }
 
     async fn read_directory(
         &mut self,
         fd: wasi::filesystem::Descriptor,
     ) -> Result<wasi::filesystem::DirectoryEntryStream, wasi::filesystem::Error> {
-        use cap_fs_ext::{DirEntryExt, MetadataExt};
-
-        let table = self.table_mut();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::READ) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-
-        enum ReaddirError {
-            Io(std::io::Error),
-            IllegalSequence,
-        }
-        impl From<std::io::Error> for ReaddirError {
-            fn from(e: std::io::Error) -> ReaddirError {
-                ReaddirError::Io(e)
-            }
-        }
+        let d = self.table().get_dir(fd)?;
+        let result = if d.perms.contains(DirPerms::READ) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || dir.read_dir()).await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        let location = d.location.clone();
+        self.ctx()
+            .audit(|| FsEvent::new(FsOp::ReadDirectory, location.clone(), &result));
 
-        let entries = d.dir.entries()?.map(|entry| {
-            let entry = entry?;
-            let meta = entry.full_metadata()?;
-            let inode = Some(meta.ino());
-            let type_ = descriptortype_from(meta.file_type());
-            let name = entry
-                .file_name()
-                .into_string()
-                .map_err(|_| ReaddirError::IllegalSequence)?;
-            Ok(wasi::filesystem::DirectoryEntry { inode, type_, name })
-        });
-        // On windows, filter out files like `C:\DumpStack.log.tmp` which we
-        // can't get full metadata for.
-        #[cfg(windows)]
-        let entries = entries.filter(|entry| {
-            use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION};
-            if let Err(ReaddirError::Io(err)) = entry {
-                if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION as i32)
-                    || err.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32)
-                {
-                    return false;
-                }
-            }
-            true
-        });
-        let entries = entries.map(|r| match r {
-            Ok(r) => Ok(r),
-            Err(ReaddirError::Io(e)) => Err(wasi::filesystem::Error::from(e)),
-            Err(ReaddirError::IllegalSequence) => Err(ErrorCode::IllegalByteSequence.into()),
-        });
-        Ok(table.push_readdir(ReaddirIterator::new(entries))?)
+        Ok(self
+            .table_mut()
+            .push_readdir(ReaddirIterator::new(location, result?))?)
     }
 
     async fn read_directory_entry(
         &mut self,
         stream: wasi::filesystem::DirectoryEntryStream,
     ) -> Result<Option<wasi::filesystem::DirectoryEntry>, wasi::filesystem::Error> {
-        let table = self.table();
-        let readdir = table.get_readdir(stream)?;
-        readdir.next()
+        let readdir = self.table().get_readdir(stream)?;
+        let result = readdir.next();
+        self.ctx()
+            .audit(|| FsEvent::new(FsOp::ReadDirectoryEntry, readdir.location.clone(), &result));
+        result
     }
 
     async fn drop_directory_entry_stream(
@@ -308,30 +302,16 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
         let table = self.table();
-        if table.is_file(fd) {
-            match table.get_file(fd)?.file.sync_all() {
-                Ok(()) => Ok(()),
-                // On windows, `sync_data` uses `FileFlushBuffers` which fails with
-                // `ERROR_ACCESS_DENIED` if the file is not upen for writing. Ignore
-                // this error, for POSIX compatibility.
-                #[cfg(windows)]
-                Err(e)
-                    if e.raw_os_error()
-                        == Some(windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED as _) =>
-                {
-                    Ok(())
-                }
-                Err(e) => Err(e.into()),
-            }
+        let result = if table.is_file(fd) {
+            let file = Arc::clone(&table.get_file(fd)?.file);
+            spawn_blocking(move || file.sync()).await
         } else if table.is_dir(fd) {
-            Ok(table
-                .get_dir(fd)?
-                .dir
-                .open(std::path::Component::CurDir)?
-                .sync_all()?)
+            let dir = Arc::clone(&table.get_dir(fd)?.dir);
+            spawn_blocking(move || dir.sync()).await
         } else {
             Err(ErrorCode::BadDescriptor.into())
-        }
+        };
+        audited(self.ctx(), FsOp::Sync, || location(table, fd), || result)
     }
 
     async fn create_directory_at(
@@ -339,13 +319,21 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
         path: String,
     ) -> Result<(), wasi::filesystem::Error> {
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        d.dir.create_dir(&path)?;
-        Ok(())
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            let quota = Arc::clone(&self.ctx().fs_quota);
+            spawn_blocking(move || quota.create(|| dir.create_dir_at(&path))).await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(
+            self.ctx(),
+            FsOp::CreateDirectoryAt,
+            || Some(location),
+            || result,
+        )
     }
 
     async fn stat(
@@ -353,23 +341,26 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
     ) -> Result<wasi::filesystem::DescriptorStat, wasi::filesystem::Error> {
         let table = self.table();
-        if table.is_file(fd) {
+        let result = if table.is_file(fd) {
             let f = table.get_file(fd)?;
-            if !f.perms.contains(FilePerms::READ) {
-                return Err(ErrorCode::NotPermitted.into());
+            if f.perms.contains(FilePerms::READ) {
+                let file = Arc::clone(&f.file);
+                spawn_blocking(move || file.stat()).await
+            } else {
+                Err(ErrorCode::NotPermitted.into())
             }
-            let meta = f.file.metadata()?;
-            Ok(descriptorstat_from(meta))
         } else if table.is_dir(fd) {
             let d = table.get_dir(fd)?;
-            if !d.perms.contains(DirPerms::READ) {
-                return Err(ErrorCode::NotPermitted.into());
+            if d.perms.contains(DirPerms::READ) {
+                let dir = Arc::clone(&d.dir);
+                spawn_blocking(move || dir.stat()).await
+            } else {
+                Err(ErrorCode::NotPermitted.into())
             }
-            let meta = d.dir.dir_metadata()?;
-            Ok(descriptorstat_from(meta))
         } else {
             Err(ErrorCode::BadDescriptor.into())
-        }
+        };
+        audited(self.ctx(), FsOp::Stat, || location(table, fd), || result)
     }
 
     async fn stat_at(
@@ -378,18 +369,15 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         path_flags: wasi::filesystem::PathFlags,
         path: String,
     ) -> Result<wasi::filesystem::DescriptorStat, wasi::filesystem::Error> {
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::READ) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-
-        let meta = if symlink_follow(path_flags) {
-            d.dir.metadata(&path)?
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::READ) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || dir.stat_at(&path, symlink_follow(path_flags))).await
         } else {
-            d.dir.symlink_metadata(&path)?
+            Err(ErrorCode::NotPermitted.into())
         };
-        Ok(descriptorstat_from(meta))
+        audited(self.ctx(), FsOp::StatAt, || Some(location), || result)
     }
 
     async fn set_times_at(
@@ -400,29 +388,16 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         atim: wasi::filesystem::NewTimestamp,
         mtim: wasi::filesystem::NewTimestamp,
     ) -> Result<(), wasi::filesystem::Error> {
-        use cap_fs_ext::DirExt;
-
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        let atim = systemtimespec_from(atim)?;
-        let mtim = systemtimespec_from(mtim)?;
-        if symlink_follow(path_flags) {
-            d.dir.set_times(
-                &path,
-                atim.map(cap_fs_ext::SystemTimeSpec::from_std),
-                mtim.map(cap_fs_ext::SystemTimeSpec::from_std),
-            )?;
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || dir.set_times_at(&path, symlink_follow(path_flags), atim, mtim))
+                .await
         } else {
-            d.dir.set_symlink_times(
-                &path,
-                atim.map(cap_fs_ext::SystemTimeSpec::from_std),
-                mtim.map(cap_fs_ext::SystemTimeSpec::from_std),
-            )?;
-        }
-        Ok(())
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(self.ctx(), FsOp::SetTimesAt, || Some(location), || result)
     }
 
     async fn link_at(
@@ -436,18 +411,32 @@ impl<T: WasiView> wasi::filesystem::Host for T {
     ) -> Result<(), wasi::filesystem::Error> {
         let table = self.table();
         let old_dir = table.get_dir(fd)?;
-        if !old_dir.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
         let new_dir = table.get_dir(new_descriptor)?;
-        if !new_dir.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        if symlink_follow(old_path_flags) {
-            return Err(ErrorCode::Invalid.into());
-        }
-        old_dir.dir.hard_link(&old_path, &new_dir.dir, &new_path)?;
-        Ok(())
+        let checked = (|| {
+            if !old_dir.perms.contains(DirPerms::MUTATE) {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            if !new_dir.perms.contains(DirPerms::MUTATE) {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            if symlink_follow(old_path_flags) {
+                return Err(ErrorCode::Invalid.into());
+            }
+            Ok(())
+        })();
+        let result = match checked {
+            Ok(()) => {
+                let (old, new) = (Arc::clone(&old_dir.dir), Arc::clone(&new_dir.dir));
+                let (old_path, new_path) = (old_path.clone(), new_path.clone());
+                spawn_blocking(move || old.link_at(&old_path, &*new, &new_path)).await
+            }
+            Err(e) => Err(e),
+        };
+        self.ctx().audit(|| {
+            FsEvent::new(FsOp::LinkAt, old_dir.location.join(&old_path), &result)
+                .target(new_dir.location.join(&new_path))
+        });
+        result
     }
 
     async fn open_at(
@@ -461,98 +450,108 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         // Not implemented yet.
         _mode: wasi::filesystem::Modes,
     ) -> Result<wasi::filesystem::Descriptor, wasi::filesystem::Error> {
-        use cap_fs_ext::{FollowSymlinks, OpenOptionsFollowExt, OpenOptionsMaybeDirExt};
-        use system_interface::fs::{FdFlags, GetSetFdFlags};
         use wasi::filesystem::{DescriptorFlags, OpenFlags};
 
-        let table = self.table_mut();
+        let table = self.table();
         if table.is_file(fd) {
             Err(ErrorCode::NotDirectory)?;
         }
         let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::READ) {
-            Err(ErrorCode::NotPermitted)?;
-        }
+        let location = d.location.join(&path);
 
-        if !d.perms.contains(DirPerms::MUTATE) {
-            if oflags.contains(OpenFlags::CREATE) || oflags.contains(OpenFlags::TRUNCATE) {
+        let checked = (|| -> Result<(), wasi::filesystem::Error> {
+            if !d.perms.contains(DirPerms::READ) {
                 Err(ErrorCode::NotPermitted)?;
             }
-            if flags.contains(DescriptorFlags::WRITE) {
+
+            // Creating a file changes the directory, while truncating or
+            // writing changes the file.
+            if !d.perms.contains(DirPerms::MUTATE) && oflags.contains(OpenFlags::CREATE) {
+                Err(ErrorCode::NotPermitted)?;
+            }
+            // Directories are read under the directory's perms, checked
+            // above, so the file perms only apply once it's clear that this
+            // opens a file: before the open if it creates one, and after it
+            // otherwise.
+            if flags.contains(DescriptorFlags::READ)
+                && oflags.contains(OpenFlags::CREATE)
+                && !d.file_perms.contains(FilePerms::READ)
+            {
+                Err(ErrorCode::NotPermitted)?;
+            }
+            if (flags.contains(DescriptorFlags::WRITE) || oflags.contains(OpenFlags::TRUNCATE))
+                && !d.file_perms.contains(FilePerms::WRITE)
+            {
                 Err(ErrorCode::NotPermitted)?;
             }
-        }
-
-        let mut opts = cap_std::fs::OpenOptions::new();
-        opts.maybe_dir(true);
-
-        if oflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) {
-            opts.create_new(true);
-            opts.write(true);
-        } else if oflags.contains(OpenFlags::CREATE) {
-            opts.create(true);
-            opts.write(true);
-        }
-        if oflags.contains(OpenFlags::TRUNCATE) {
-            opts.truncate(true);
-        }
-        if flags.contains(DescriptorFlags::READ) {
-            opts.read(true);
-        }
-        if flags.contains(DescriptorFlags::WRITE) {
-            opts.write(true);
-        } else {
-            // If not opened write, open read. This way the OS lets us open
-            // the file, but we can use perms to reject use of the file later.
-            opts.read(true);
-        }
-        if symlink_follow(path_flags) {
-            opts.follow(FollowSymlinks::Yes);
-        } else {
-            opts.follow(FollowSymlinks::No);
-        }
-
-        // These flags are not yet supported in cap-std:
-        if flags.contains(DescriptorFlags::FILE_INTEGRITY_SYNC)
-            | flags.contains(DescriptorFlags::DATA_INTEGRITY_SYNC)
-            | flags.contains(DescriptorFlags::REQUESTED_WRITE_SYNC)
-        {
-            Err(ErrorCode::Unsupported)?;
-        }
 
-        if oflags.contains(OpenFlags::DIRECTORY) {
-            if oflags.contains(OpenFlags::CREATE)
-                || oflags.contains(OpenFlags::EXCLUSIVE)
-                || oflags.contains(OpenFlags::TRUNCATE)
+            // These flags are not yet supported in cap-std:
+            if flags.contains(DescriptorFlags::FILE_INTEGRITY_SYNC)
+                | flags.contains(DescriptorFlags::DATA_INTEGRITY_SYNC)
+                | flags.contains(DescriptorFlags::REQUESTED_WRITE_SYNC)
             {
-                Err(ErrorCode::Invalid)?;
+                Err(ErrorCode::Unsupported)?;
             }
-        }
-        let mut opened = d.dir.open_with(&path, &opts)?;
 
-        if opened.metadata()?.is_dir() {
-            Ok(table.push_dir(Dir::new(
-                cap_std::fs::Dir::from_std_file(opened.into_std()),
-                d.perms,
-                d.file_perms,
-            ))?)
-        } else if oflags.contains(OpenFlags::DIRECTORY) {
-            Err(ErrorCode::NotDirectory)?
-        } else {
-            // FIXME cap-std needs a nonblocking open option so that files reads and writes
-            // are nonblocking. Instead we set it after opening here:
-            let set_fd_flags = opened.new_set_fd_flags(FdFlags::NONBLOCK)?;
-            opened.set_fd_flags(set_fd_flags)?;
+            if oflags.contains(OpenFlags::DIRECTORY) {
+                if oflags.contains(OpenFlags::CREATE)
+                    || oflags.contains(OpenFlags::EXCLUSIVE)
+                    || oflags.contains(OpenFlags::TRUNCATE)
+                {
+                    Err(ErrorCode::Invalid)?;
+                }
+            }
+            Ok(())
+        })();
+
+        let opened = match checked {
+            Ok(()) => {
+                let dir = Arc::clone(&d.dir);
+                let quota = Arc::clone(&self.ctx().fs_quota);
+                let follow = symlink_follow(path_flags);
+                spawn_blocking(move || open_counted(&*dir, &quota, &path, follow, oflags, flags))
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let opened = opened.and_then(|opened| match opened {
+            Opened::File(_)
+                if flags.contains(DescriptorFlags::READ)
+                    && !d.file_perms.contains(FilePerms::READ) =>
+            {
+                Err(ErrorCode::NotPermitted.into())
+            }
+            opened => Ok(opened),
+        });
+        self.ctx()
+            .audit(|| FsEvent::new(FsOp::OpenAt, location.clone(), &opened));
 
-            Ok(table.push_file(File::new(opened, d.file_perms))?)
+        let (perms, file_perms) = (d.perms, d.file_perms);
+        let table = self.table_mut();
+        match opened? {
+            Opened::Dir(dir) => {
+                let mut dir = Dir::new(dir, perms, file_perms);
+                dir.location = location;
+                Ok(table.push_dir(dir)?)
+            }
+            Opened::File(file) => {
+                let mut file = File::new(file, file_perms);
+                file.location = location;
+                Ok(table.push_file(file)?)
+            }
         }
     }
 
     async fn drop_descriptor(&mut self, fd: wasi::filesystem::Descriptor) -> anyhow::Result<()> {
+        let location = location(self.table(), fd);
         let table = self.table_mut();
         if table.delete_file(fd).is_err() {
             table.delete_dir(fd)?;
         }
+        if let Some(location) = location {
+            self.ctx()
+                .audit(|| FsEvent::new(FsOp::DropDescriptor, location, &Ok(())));
+        }
         Ok(())
     }
 
@@ -561,16 +560,15 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
         path: String,
     ) -> Result<String, wasi::filesystem::Error> {
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::READ) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        let link = d.dir.read_link(&path)?;
-        Ok(link
-            .into_os_string()
-            .into_string()
-            .map_err(|_| ErrorCode::IllegalByteSequence)?)
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::READ) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || dir.readlink_at(&path)).await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(self.ctx(), FsOp::ReadlinkAt, || Some(location), || result)
     }
 
     async fn remove_directory_at(
@@ -578,12 +576,20 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
         path: String,
     ) -> Result<(), wasi::filesystem::Error> {
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        Ok(d.dir.remove_dir(&path)?)
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || dir.remove_dir_at(&path)).await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(
+            self.ctx(),
+            FsOp::RemoveDirectoryAt,
+            || Some(location),
+            || result,
+        )
     }
 
     async fn rename_at(
@@ -595,15 +601,29 @@ impl<T: WasiView> wasi::filesystem::Host for T {
     ) -> Result<(), wasi::filesystem::Error> {
         let table = self.table();
         let old_dir = table.get_dir(fd)?;
-        if !old_dir.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
         let new_dir = table.get_dir(new_fd)?;
-        if !new_dir.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        old_dir.dir.rename(&old_path, &new_dir.dir, &new_path)?;
-        Ok(())
+        let checked = (|| {
+            if !old_dir.perms.contains(DirPerms::MUTATE) {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            if !new_dir.perms.contains(DirPerms::MUTATE) {
+                return Err(ErrorCode::NotPermitted.into());
+            }
+            Ok(())
+        })();
+        let result = match checked {
+            Ok(()) => {
+                let (old, new) = (Arc::clone(&old_dir.dir), Arc::clone(&new_dir.dir));
+                let (old_path, new_path) = (old_path.clone(), new_path.clone());
+                spawn_blocking(move || old.rename_at(&old_path, &*new, &new_path)).await
+            }
+            Err(e) => Err(e),
+        };
+        self.ctx().audit(|| {
+            FsEvent::new(FsOp::RenameAt, old_dir.location.join(&old_path), &result)
+                .target(new_dir.location.join(&new_path))
+        });
+        result
     }
 
     async fn symlink_at(
@@ -612,17 +632,16 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         src_path: String,
         dest_path: String,
     ) -> Result<(), wasi::filesystem::Error> {
-        // On windows, Dir.symlink is provided by DirExt
-        #[cfg(windows)]
-        use cap_fs_ext::DirExt;
-
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        d.dir.symlink(&src_path, &dest_path)?;
-        Ok(())
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&dest_path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            let quota = Arc::clone(&self.ctx().fs_quota);
+            spawn_blocking(move || quota.create(|| dir.symlink_at(&src_path, &dest_path))).await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(self.ctx(), FsOp::SymlinkAt, || Some(location), || result)
     }
 
     async fn unlink_file_at(
@@ -630,70 +649,97 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
         path: String,
     ) -> Result<(), wasi::filesystem::Error> {
-        use cap_fs_ext::DirExt;
-
-        let table = self.table();
-        let d = table.get_dir(fd)?;
-        if !d.perms.contains(DirPerms::MUTATE) {
-            return Err(ErrorCode::NotPermitted.into());
-        }
-        d.dir.remove_file_or_symlink(&path)?;
-        Ok(())
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || dir.unlink_file_at(&path)).await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(self.ctx(), FsOp::UnlinkFileAt, || Some(location), || result)
     }
-
     async fn change_file_permissions_at(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
-        _path_flags: wasi::filesystem::PathFlags,
-        _path: String,
-        _mode: wasi::filesystem::Modes,
+        fd: wasi::filesystem::Descriptor,
+        path_flags: wasi::filesystem::PathFlags,
+        path: String,
+        modes: wasi::filesystem::Modes,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || change_permissions_at(&*dir, path_flags, &path, modes, false))
+                .await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(
+            self.ctx(),
+            FsOp::ChangePermissionsAt,
+            || Some(location),
+            || result,
+        )
     }
 
     async fn change_directory_permissions_at(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
-        _path_flags: wasi::filesystem::PathFlags,
-        _path: String,
-        _mode: wasi::filesystem::Modes,
+        fd: wasi::filesystem::Descriptor,
+        path_flags: wasi::filesystem::PathFlags,
+        path: String,
+        modes: wasi::filesystem::Modes,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        let d = self.table().get_dir(fd)?;
+        let location = d.location.join(&path);
+        let result = if d.perms.contains(DirPerms::MUTATE) {
+            let dir = Arc::clone(&d.dir);
+            spawn_blocking(move || change_permissions_at(&*dir, path_flags, &path, modes, true))
+                .await
+        } else {
+            Err(ErrorCode::NotPermitted.into())
+        };
+        audited(
+            self.ctx(),
+            FsOp::ChangePermissionsAt,
+            || Some(location),
+            || result,
+        )
     }
 
     async fn lock_shared(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
+        fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        audited_lock(self.ctx(), self.table(), fd, LockOp::Shared).await
     }
 
     async fn lock_exclusive(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
+        fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        audited_lock(self.ctx(), self.table(), fd, LockOp::Exclusive).await
     }
 
     async fn try_lock_shared(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
+        fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        audited_lock(self.ctx(), self.table(), fd, LockOp::TryShared).await
     }
 
     async fn try_lock_exclusive(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
+        fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        audited_lock(self.ctx(), self.table(), fd, LockOp::TryExclusive).await
     }
 
     async fn unlock(
         &mut self,
-        _fd: wasi::filesystem::Descriptor,
+        fd: wasi::filesystem::Descriptor,
     ) -> Result<(), wasi::filesystem::Error> {
-        todo!()
+        audited_lock(self.ctx(), self.table(), fd, LockOp::Unlock).await
     }
 
     async fn read_via_stream(
@@ -701,17 +747,18 @@ impl<T: WasiView> wasi::filesystem::Host for T {
         fd: wasi::filesystem::Descriptor,
         offset: wasi::filesystem::Filesize,
     ) -> anyhow::Result<wasi::streams::InputStream> {
-        // FIXME: this skips the perm check. We can't return a NotPermitted
-        // error code here. Do we need to change the interface?
-
         // Trap if fd lookup fails:
         let f = self.table().get_file(fd)?;
 
         // Duplicate the file descriptor so that we get an indepenent lifetime.
         let clone = std::sync::Arc::clone(&f.file);
+        let audit = self.ctx().stream_audit(&f.location);
+        self.ctx()
+            .audit(|| FsEvent::new(FsOp::ReadViaStream, f.location.clone(), &Ok(())));
 
-        // Create a stream view for it.
-        let reader = crate::filesystem::FileInputStream::new(clone, offset);
+        // Create a stream view for it. We can't return a NotPermitted error
+        // code here, so the stream checks the perms on each operation.
+        let reader = crate::filesystem::FileInputStream::new(clone, f.perms, audit, offset);
 
         // Insert the stream view into the table. Trap if the table is full.
         let index = self.table_mut().push_input_stream(Box::new(reader))?;
@@ -729,9 +776,14 @@ impl<T: WasiView> wasi::filesystem::Host for T {
 
         // Duplicate the file descriptor so that we get an indepenent lifetime.
         let clone = std::sync::Arc::clone(&f.file);
+        let quota = Arc::clone(&self.ctx().fs_quota);
+        let audit = self.ctx().stream_audit(&f.location);
+        self.ctx()
+            .audit(|| FsEvent::new(FsOp::WriteViaStream, f.location.clone(), &Ok(())));
 
-        // Create a stream view for it.
-        let writer = crate::filesystem::FileOutputStream::new(clone, offset);
+        // Create a stream view for it. We can't return a NotPermitted error
+        // code here, so the stream checks the perms on each operation.
+        let writer = crate::filesystem::FileOutputStream::new(clone, f.perms, quota, audit, offset);
 
         // Insert the stream view into the table. Trap if the table is full.
         let index = self.table_mut().push_output_stream(Box::new(writer))?;
@@ -748,9 +800,14 @@ impl<T: WasiView> wasi::filesystem::Host for T {
 
         // Duplicate the file descriptor so that we get an indepenent lifetime.
         let clone = std::sync::Arc::clone(&f.file);
+        let quota = Arc::clone(&self.ctx().fs_quota);
+        let audit = self.ctx().stream_audit(&f.location);
+        self.ctx()
+            .audit(|| FsEvent::new(FsOp::AppendViaStream, f.location.clone(), &Ok(())));
 
-        // Create a stream view for it.
-        let appender = crate::filesystem::FileAppendStream::new(clone);
+        // Create a stream view for it. We can't return a NotPermitted error
+        // code here, so the stream checks the perms on each operation.
+        let appender = crate::filesystem::FileAppendStream::new(clone, f.perms, quota, audit);
 
         // Insert the stream view into the table. Trap if the table is full.
         let index = self.table_mut().push_output_stream(Box::new(appender))?;
@@ -759,6 +816,130 @@ impl<T: WasiView> wasi::filesystem::Host for T {
     }
 }
 
+/// Run `f`, and report it to the auditor, if there is one, as `op` on the
+/// file or directory at `path`.
+fn audited<R>(
+    ctx: &WasiCtx,
+    op: FsOp,
+    path: impl FnOnce() -> Option<FsPath>,
+    f: impl FnOnce() -> Result<R, wasi::filesystem::Error>,
+) -> Result<R, wasi::filesystem::Error> {
+    let result = f();
+    if let Some(auditor) = &ctx.fs_auditor {
+        if let Some(path) = path() {
+            auditor.record(&FsEvent::new(op, path, &result));
+        }
+    }
+    result
+}
+
+/// Where the descriptor `fd` was opened, if it exists.
+fn location(table: &Table, fd: wasi::filesystem::Descriptor) -> Option<FsPath> {
+    if let Ok(f) = table.get_file(fd) {
+        Some(f.location.clone())
+    } else if let Ok(d) = table.get_dir(fd) {
+        Some(d.location.clone())
+    } else {
+        None
+    }
+}
+
+async fn audited_lock(
+    ctx: &WasiCtx,
+    table: &Table,
+    fd: wasi::filesystem::Descriptor,
+    op: LockOp,
+) -> Result<(), wasi::filesystem::Error> {
+    let result = lock(table, fd, op).await;
+    audited(ctx, FsOp::Lock(op), || location(table, fd), || result)
+}
+
+/// Open `path`, counting the file against `quota` only if this creates it.
+/// A non-exclusive create is tried as an exclusive one first, so that a file
+/// that appears or goes away in between is counted correctly.
+fn open_counted(
+    dir: &dyn WasiDir,
+    quota: &FsQuota,
+    path: &str,
+    follow: bool,
+    oflags: wasi::filesystem::OpenFlags,
+    flags: wasi::filesystem::DescriptorFlags,
+) -> Result<Opened, wasi::filesystem::Error> {
+    use wasi::filesystem::OpenFlags;
+
+    let open = |oflags| dir.open_at(path, follow, oflags, flags);
+    if !oflags.contains(OpenFlags::CREATE) {
+        return open(oflags);
+    }
+    if oflags.contains(OpenFlags::EXCLUSIVE) {
+        return quota.create(|| open(oflags));
+    }
+
+    // An existing file fails the exclusive open with `exist`, or with
+    // `insufficient-space` if the quota is used up, and is opened without
+    // creating it instead.
+    let created = quota.create(|| open(oflags | OpenFlags::EXCLUSIVE));
+    let code = match &created {
+        Err(e) => e.downcast_ref::<ErrorCode>().copied(),
+        Ok(_) => None,
+    };
+    if !matches!(code, Some(ErrorCode::Exist | ErrorCode::InsufficientSpace)) {
+        return created;
+    }
+    match open(oflags & !OpenFlags::CREATE) {
+        // It went away since, or is a dangling symlink, whose target the
+        // plain open creates.
+        Err(e) if matches!(e.downcast_ref::<ErrorCode>(), Some(ErrorCode::NoEntry)) => match code {
+            Some(ErrorCode::Exist) => quota.create(|| open(oflags)),
+            _ => created,
+        },
+        opened => opened,
+    }
+}
+
+/// Set the permissions of the file, or directory if `is_dir`, at `path` from
+/// `modes`.
+fn change_permissions_at(
+    dir: &dyn WasiDir,
+    path_flags: wasi::filesystem::PathFlags,
+    path: &str,
+    modes: wasi::filesystem::Modes,
+    is_dir: bool,
+) -> Result<(), wasi::filesystem::Error> {
+    use wasi::filesystem::{DescriptorType, Modes};
+
+    let follow = symlink_follow(path_flags);
+    let stat = dir.stat_at(path, follow)?;
+    let path_is_dir = matches!(stat.type_, DescriptorType::Directory);
+    if is_dir {
+        if !path_is_dir {
+            return Err(ErrorCode::NotDirectory.into());
+        }
+        // `executable` is not valid for directories.
+        if modes.contains(Modes::EXECUTABLE) {
+            return Err(ErrorCode::Invalid.into());
+        }
+    } else if path_is_dir {
+        return Err(ErrorCode::IsDirectory.into());
+    }
+
+    dir.set_permissions_at(path, follow, modes, is_dir)
+}
+
+async fn lock(
+    table: &Table,
+    fd: wasi::filesystem::Descriptor,
+    op: LockOp,
+) -> Result<(), wasi::filesystem::Error> {
+    let file = Arc::clone(&table.get_file(fd)?.file);
+    match op {
+        // Waiting for another holder to release the lock can take
+        // arbitrarily long.
+        LockOp::Shared | LockOp::Exclusive => spawn_blocking(move || file.lock(op)).await,
+        LockOp::TryShared | LockOp::TryExclusive | LockOp::Unlock => file.lock(op),
+    }
+}
+
 #[cfg(unix)]
 fn from_raw_os_error(err: Option<i32>) -> Option<wasi::filesystem::Error> {
     use rustix::io::Errno as RustixErrno;
@@ -791,10 +972,13 @@ fn from_raw_os_error(err: Option<i32>) -> Option<wasi::filesystem::Error> {
         RustixErrno::ALREADY => ErrorCode::Already.into(),
         RustixErrno::INPROGRESS => ErrorCode::InProgress.into(),
         RustixErrno::INTR => ErrorCode::Interrupted.into(),
+        RustixErrno::AGAIN => ErrorCode::WouldBlock.into(),
 
         // On some platforms.into(), these have the same value as other errno values.
         #[allow(unreachable_patterns)]
         RustixErrno::OPNOTSUPP => ErrorCode::Unsupported.into(),
+        #[allow(unreachable_patterns)]
+        RustixErrno::WOULDBLOCK => ErrorCode::WouldBlock.into(),
 
         _ => return None,
     })
@@ -860,109 +1044,36 @@ impl From<std::num::TryFromIntError> for wasi::filesystem::Error {
     }
 }
 
-fn descriptortype_from(ft: cap_std::fs::FileType) -> wasi::filesystem::DescriptorType {
-    use cap_fs_ext::FileTypeExt;
-    use wasi::filesystem::DescriptorType;
-    if ft.is_dir() {
-        DescriptorType::Directory
-    } else if ft.is_symlink() {
-        DescriptorType::SymbolicLink
-    } else if ft.is_block_device() {
-        DescriptorType::BlockDevice
-    } else if ft.is_char_device() {
-        DescriptorType::CharacterDevice
-    } else if ft.is_file() {
-        DescriptorType::RegularFile
-    } else {
-        DescriptorType::Unknown
-    }
-}
-
-fn systemtimespec_from(
-    t: wasi::filesystem::NewTimestamp,
-) -> Result<Option<fs_set_times::SystemTimeSpec>, wasi::filesystem::Error> {
-    use fs_set_times::SystemTimeSpec;
-    use wasi::filesystem::NewTimestamp;
-    match t {
-        NewTimestamp::NoChange => Ok(None),
-        NewTimestamp::Now => Ok(Some(SystemTimeSpec::SymbolicNow)),
-        NewTimestamp::Timestamp(st) => Ok(Some(SystemTimeSpec::Absolute(systemtime_from(st)?))),
-    }
-}
-
-fn systemtime_from(
-    t: wasi::wall_clock::Datetime,
-) -> Result<std::time::SystemTime, wasi::filesystem::Error> {
-    use std::time::{Duration, SystemTime};
-    SystemTime::UNIX_EPOCH
-        .checked_add(Duration::new(t.seconds, t.nanoseconds))
-        .ok_or_else(|| ErrorCode::Overflow.into())
-}
-
-fn datetime_from(t: std::time::SystemTime) -> wasi::wall_clock::Datetime {
-    // FIXME make this infallible or handle errors properly
-    wasi::wall_clock::Datetime::try_from(cap_std::time::SystemTime::from_std(t)).unwrap()
-}
-
-fn descriptorstat_from(meta: cap_std::fs::Metadata) -> wasi::filesystem::DescriptorStat {
-    use cap_fs_ext::MetadataExt;
-    wasi::filesystem::DescriptorStat {
-        // FIXME didn't we agree that the wit could be changed to make the device and ino fields
-        // optional?
-        device: meta.dev(),
-        inode: meta.ino(),
-        type_: descriptortype_from(meta.file_type()),
-        link_count: meta.nlink(),
-        size: meta.len(),
-        // FIXME change the wit to make these timestamps optional
-        data_access_timestamp: meta
-            .accessed()
-            .map(|t| datetime_from(t.into_std()))
-            .unwrap_or(wasi::wall_clock::Datetime {
-                seconds: 0,
-                nanoseconds: 0,
-            }),
-        data_modification_timestamp: meta
-            .modified()
-            .map(|t| datetime_from(t.into_std()))
-            .unwrap_or(wasi::wall_clock::Datetime {
-                seconds: 0,
-                nanoseconds: 0,
-            }),
-        status_change_timestamp: meta
-            .created()
-            .map(|t| datetime_from(t.into_std()))
-            .unwrap_or(wasi::wall_clock::Datetime {
-                seconds: 0,
-                nanoseconds: 0,
-            }),
-    }
-}
-
 fn symlink_follow(path_flags: wasi::filesystem::PathFlags) -> bool {
     path_flags.contains(wasi::filesystem::PathFlags::SYMLINK_FOLLOW)
 }
 
-struct ReaddirIterator(
-    std::sync::Mutex<
+struct ReaddirIterator {
+    entries: std::sync::Mutex<
         Box<
             dyn Iterator<Item = Result<wasi::filesystem::DirectoryEntry, wasi::filesystem::Error>>
                 + Send
                 + 'static,
         >,
     >,
-);
+    /// The directory being read, for auditing.
+    location: FsPath,
+}
 
 impl ReaddirIterator {
     fn new(
+        location: FsPath,
         i: impl Iterator<Item = Result<wasi::filesystem::DirectoryEntry, wasi::filesystem::Error>>
             + Send
             + 'static,
     ) -> Self {
-        ReaddirIterator(std::sync::Mutex::new(Box::new(i)))
+        ReaddirIterator {
+            entries: std::sync::Mutex::new(Box::new(i)),
+            location,
+        }
     }
     fn next(&self) -> Result<Option<wasi::filesystem::DirectoryEntry>, wasi::filesystem::Error> {
-        self.0.lock().unwrap().next().transpose()
+        self.entries.lock().unwrap().next().transpose()
     }
 }
 
@@ -991,10 +1102,471 @@ mod test {
     fn table_readdir_works() {
         let mut table = Table::new();
         let ix = table
-            .push_readdir(ReaddirIterator::new(std::iter::empty()))
+            .push_readdir(ReaddirIterator::new(FsPath::default(), std::iter::empty()))
             .unwrap();
         let _ = table.get_readdir(ix).unwrap();
         table.delete_readdir(ix).unwrap();
         let _ = table.get_readdir(ix).err().unwrap();
     }
+
+    /// Open two descriptors for the same host file.
+    #[cfg(unix)]
+    fn two_descriptors(table: &mut Table, path: &std::path::Path) -> (u32, u32) {
+        let open = || {
+            let file = cap_std::fs::File::from_std(std::fs::File::open(path).unwrap());
+            File::new(
+                Box::new(crate::filesystem::HostFile::new(file)),
+                FilePerms::READ,
+            )
+        };
+        (
+            table.push_file(open()).unwrap(),
+            table.push_file(open()).unwrap(),
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn try_lock_would_block() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut table = Table::new();
+        let (a, b) = two_descriptors(&mut table, file.path());
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            lock(&table, a, LockOp::Exclusive).await.unwrap();
+            let err = lock(&table, b, LockOp::TryShared).await.err().unwrap();
+            assert!(matches!(err.downcast(), Ok(ErrorCode::WouldBlock)));
+
+            lock(&table, a, LockOp::Unlock).await.unwrap();
+            lock(&table, b, LockOp::TryShared).await.unwrap();
+            lock(&table, a, LockOp::TryShared).await.unwrap();
+            let err = lock(&table, a, LockOp::TryExclusive).await.err().unwrap();
+            assert!(matches!(err.downcast(), Ok(ErrorCode::WouldBlock)));
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn contended_lock_waits_off_the_executor() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut table = Table::new();
+        let (a, b) = two_descriptors(&mut table, file.path());
+
+        // Both descriptors are used from one task on a single-threaded
+        // runtime, so the release can only happen if the waiting lock
+        // doesn't block the executor.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let released = AtomicBool::new(false);
+        rt.block_on(async {
+            lock(&table, a, LockOp::Exclusive).await.unwrap();
+            let waiter = async {
+                lock(&table, b, LockOp::Shared).await.unwrap();
+                released.load(Ordering::SeqCst)
+            };
+            let releaser = async {
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                released.store(true, Ordering::SeqCst);
+                lock(&table, a, LockOp::Unlock).await.unwrap();
+            };
+            let (acquired_after_release, ()) = tokio::join!(waiter, releaser);
+            assert!(acquired_after_release);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_share_an_inode() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path();
+        std::fs::write(path.join("a"), b"").unwrap();
+        std::fs::hard_link(path.join("a"), path.join("b")).unwrap();
+        std::fs::create_dir(path.join("sub")).unwrap();
+        let dir: Box<dyn crate::filesystem::WasiDir> = Box::new(
+            cap_std::fs::Dir::open_ambient_dir(path, cap_std::ambient_authority()).unwrap(),
+        );
+
+        let a = dir.stat_at("a", false).unwrap();
+        let b = dir.stat_at("b", false).unwrap();
+        assert_eq!((a.device, a.inode), (b.device, b.inode));
+        assert_eq!(a.link_count, 2);
+        assert_ne!(a.inode, dir.stat_at("sub", false).unwrap().inode);
+        assert_eq!(
+            dir.stat_at("sub/..", false).unwrap().inode,
+            dir.stat().unwrap().inode
+        );
+
+        // Directory entries have the same inodes as `stat` reports.
+        for entry in dir.read_dir().unwrap() {
+            let entry = entry.unwrap();
+            let stat = dir.stat_at(&entry.name, false).unwrap();
+            assert_eq!(entry.inode, Some(stat.inode));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn change_permissions() {
+        use cap_std::fs::PermissionsExt;
+        use wasi::filesystem::{Modes, PathFlags};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir =
+            cap_std::fs::Dir::open_ambient_dir(tmp.path(), cap_std::ambient_authority()).unwrap();
+        dir.write("file", b"").unwrap();
+        dir.create_dir("sub").unwrap();
+        dir.set_permissions("file", cap_std::fs::Permissions::from_mode(0o644))
+            .unwrap();
+        let host_dir = dir.try_clone().unwrap();
+        let d = Dir::new(Box::new(dir), DirPerms::all(), FilePerms::all());
+
+        let mode = |name: &str| host_dir.metadata(name).unwrap().permissions().mode() & 0o777;
+
+        change_permissions_at(&*d.dir, PathFlags::empty(), "file", Modes::READABLE, false).unwrap();
+        assert_eq!(mode("file"), 0o444);
+        change_permissions_at(
+            &*d.dir,
+            PathFlags::empty(),
+            "file",
+            Modes::READABLE | Modes::WRITEABLE | Modes::EXECUTABLE,
+            false,
+        )
+        .unwrap();
+        assert_eq!(mode("file"), 0o744);
+
+        change_permissions_at(&*d.dir, PathFlags::empty(), "sub", Modes::READABLE, true).unwrap();
+        assert_eq!(mode("sub") & 0o700, 0o500);
+        assert!(
+            change_permissions_at(&*d.dir, PathFlags::empty(), "sub", Modes::EXECUTABLE, true)
+                .is_err()
+        );
+        assert!(
+            change_permissions_at(&*d.dir, PathFlags::empty(), "sub", Modes::READABLE, false)
+                .is_err()
+        );
+        assert!(
+            change_permissions_at(&*d.dir, PathFlags::empty(), "file", Modes::READABLE, true)
+                .is_err()
+        );
+
+        change_permissions_at(
+            &*d.dir,
+            PathFlags::empty(),
+            "sub",
+            Modes::READABLE | Modes::WRITEABLE,
+            true,
+        )
+        .unwrap();
+    }
+
+    struct TestView {
+        table: Table,
+        ctx: WasiCtx,
+    }
+
+    impl WasiView for TestView {
+        fn table(&self) -> &Table {
+            &self.table
+        }
+        fn table_mut(&mut self) -> &mut Table {
+            &mut self.table
+        }
+        fn ctx(&self) -> &WasiCtx {
+            &self.ctx
+        }
+        fn ctx_mut(&mut self) -> &mut WasiCtx {
+            &mut self.ctx
+        }
+    }
+
+    /// Open `path` in the first preopen of `view`.
+    async fn open(
+        view: &mut TestView,
+        path: &str,
+        oflags: wasi::filesystem::OpenFlags,
+        flags: wasi::filesystem::DescriptorFlags,
+    ) -> Result<u32, ErrorCode> {
+        use wasi::filesystem::{Modes, PathFlags};
+
+        let root = view.ctx.preopens[0].0;
+        wasi::filesystem::Host::open_at(
+            view,
+            root,
+            PathFlags::empty(),
+            path.to_owned(),
+            oflags,
+            flags,
+            Modes::empty(),
+        )
+        .await
+        .map_err(|e| e.downcast().unwrap())
+    }
+
+    #[test]
+    fn creating_opens_count_once() {
+        use crate::filesystem::mem::MemFs;
+        use crate::FsLimits;
+        use wasi::filesystem::{DescriptorFlags, OpenFlags};
+
+        let fs = MemFs::new();
+        fs.write("file", "hello").unwrap();
+        let mut table = Table::new();
+        let ctx = crate::WasiCtxBuilder::new()
+            .push_preopened_dir(fs.root(), DirPerms::all(), FilePerms::all(), "/")
+            .set_fs_limits(FsLimits {
+                max_created_inodes: Some(1),
+                ..FsLimits::default()
+            })
+            .build(&mut table)
+            .unwrap();
+        let mut view = TestView { table, ctx };
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let create = OpenFlags::CREATE;
+            let write = DescriptorFlags::WRITE;
+            open(&mut view, "file", create, write).await.unwrap();
+            assert_eq!(view.ctx.fs_quota.created_inodes(), 0);
+            open(&mut view, "new", create, write).await.unwrap();
+            assert_eq!(view.ctx.fs_quota.created_inodes(), 1);
+
+            // Existing files can still be opened with the quota used up.
+            open(&mut view, "new", create, write).await.unwrap();
+            open(&mut view, "file", create | OpenFlags::TRUNCATE, write)
+                .await
+                .unwrap();
+            assert!(matches!(
+                open(&mut view, "other", create, write).await,
+                Err(ErrorCode::InsufficientSpace)
+            ));
+            assert!(matches!(
+                open(&mut view, "file", create | OpenFlags::EXCLUSIVE, write).await,
+                Err(ErrorCode::Exist | ErrorCode::InsufficientSpace)
+            ));
+            assert_eq!(view.ctx.fs_quota.created_inodes(), 1);
+        });
+    }
+
+    #[test]
+    fn preset_perms() {
+        use crate::filesystem::mem::MemFs;
+        use crate::PreopenPerms;
+        use wasi::filesystem::{DescriptorFlags, OpenFlags};
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for preset in [
+            PreopenPerms::ReadWrite,
+            PreopenPerms::ReadOnly,
+            PreopenPerms::WriteOnly,
+            PreopenPerms::NoCreate,
+        ] {
+            let fs = MemFs::new();
+            fs.write("file", "hello").unwrap();
+            fs.create_dir_all("dir").unwrap();
+            let mut table = Table::new();
+            let ctx = crate::WasiCtxBuilder::new()
+                .push_preopened_dir_with(fs.root(), preset, "/")
+                .build(&mut table)
+                .unwrap();
+            let mut view = TestView { table, ctx };
+            let readable = preset.file_perms().contains(FilePerms::READ);
+            let writable = preset.file_perms().contains(FilePerms::WRITE);
+            let creatable = preset.dir_perms().contains(DirPerms::MUTATE);
+
+            rt.block_on(async {
+                let none = OpenFlags::empty();
+                let opened = open(&mut view, "file", none, DescriptorFlags::READ).await;
+                assert_eq!(opened.is_ok(), readable, "{preset:?}");
+                let opened = open(&mut view, "file", none, DescriptorFlags::WRITE).await;
+                assert_eq!(opened.is_ok(), writable, "{preset:?}");
+
+                // Directories can be read whatever the file perms are.
+                open(&mut view, "dir", none, DescriptorFlags::READ)
+                    .await
+                    .unwrap();
+
+                let opened = open(&mut view, "a", OpenFlags::CREATE, DescriptorFlags::WRITE).await;
+                assert_eq!(opened.is_ok(), creatable && writable, "{preset:?}");
+                let opened = open(&mut view, "b", OpenFlags::CREATE, DescriptorFlags::READ).await;
+                assert_eq!(opened.is_ok(), creatable && readable, "{preset:?}");
+                assert_eq!(fs.metadata("b").is_ok(), opened.is_ok(), "{preset:?}");
+
+                // Streams check the file perms on each operation.
+                let flags = match (readable, writable) {
+                    (true, true) => DescriptorFlags::READ | DescriptorFlags::WRITE,
+                    (true, false) => DescriptorFlags::READ,
+                    _ => DescriptorFlags::WRITE,
+                };
+                let file = open(&mut view, "file", none, flags).await.unwrap();
+                let input = wasi::filesystem::Host::read_via_stream(&mut view, file, 0)
+                    .await
+                    .unwrap();
+                let read = wasi::streams::Host::read(&mut view, input, 5).await;
+                match read {
+                    Ok((data, _)) => {
+                        assert!(readable, "{preset:?}");
+                        assert_eq!(data, b"hello");
+                    }
+                    Err(_) => assert!(!readable, "{preset:?}"),
+                }
+                let output = wasi::filesystem::Host::write_via_stream(&mut view, file, 0)
+                    .await
+                    .unwrap();
+                let written =
+                    wasi::streams::Host::write(&mut view, output, b"HELLO".to_vec()).await;
+                assert_eq!(written.is_ok(), writable, "{preset:?}");
+                let expected: &[u8] = if writable { b"HELLO" } else { b"hello" };
+                assert_eq!(fs.read("file").unwrap(), expected, "{preset:?}");
+            });
+        }
+    }
+
+    /// A directory whose `readlink_at` waits for a message, and fails if
+    /// none arrives.
+    struct WaitingDir(std::sync::Mutex<std::sync::mpsc::Receiver<()>>);
+
+    impl WasiDir for WaitingDir {
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+        fn readlink_at(&self, _path: &str) -> crate::filesystem::FsResult<String> {
+            let received = self
+                .0
+                .lock()
+                .unwrap()
+                .recv_timeout(std::time::Duration::from_secs(10));
+            match received {
+                Ok(()) => Ok("target".to_owned()),
+                Err(_) => Err(ErrorCode::Io.into()),
+            }
+        }
+        fn stat(&self) -> crate::filesystem::FsResult<wasi::filesystem::DescriptorStat> {
+            unreachable!()
+        }
+        fn sync_data(&self) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn sync(&self) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn set_times(
+            &self,
+            _atim: wasi::filesystem::NewTimestamp,
+            _mtim: wasi::filesystem::NewTimestamp,
+        ) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn open_at(
+            &self,
+            _path: &str,
+            _follow: bool,
+            _oflags: wasi::filesystem::OpenFlags,
+            _flags: wasi::filesystem::DescriptorFlags,
+        ) -> crate::filesystem::FsResult<Opened> {
+            unreachable!()
+        }
+        fn create_dir_at(&self, _path: &str) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn read_dir(&self) -> crate::filesystem::FsResult<crate::filesystem::ReaddirIter> {
+            unreachable!()
+        }
+        fn stat_at(
+            &self,
+            _path: &str,
+            _follow: bool,
+        ) -> crate::filesystem::FsResult<wasi::filesystem::DescriptorStat> {
+            unreachable!()
+        }
+        fn set_times_at(
+            &self,
+            _path: &str,
+            _follow: bool,
+            _atim: wasi::filesystem::NewTimestamp,
+            _mtim: wasi::filesystem::NewTimestamp,
+        ) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn link_at(
+            &self,
+            _old_path: &str,
+            _new_dir: &dyn WasiDir,
+            _new_path: &str,
+        ) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn remove_dir_at(&self, _path: &str) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn rename_at(
+            &self,
+            _old_path: &str,
+            _new_dir: &dyn WasiDir,
+            _new_path: &str,
+        ) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn symlink_at(&self, _src_path: &str, _dest_path: &str) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn unlink_file_at(&self, _path: &str) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+        fn set_permissions_at(
+            &self,
+            _path: &str,
+            _follow: bool,
+            _modes: wasi::filesystem::Modes,
+            _is_dir: bool,
+        ) -> crate::filesystem::FsResult<()> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn path_calls_wait_off_the_executor() {
+        let (release, wait) = std::sync::mpsc::channel();
+        let mut table = Table::new();
+        let ctx = crate::WasiCtxBuilder::new()
+            .push_preopened_dir(
+                WaitingDir(std::sync::Mutex::new(wait)),
+                DirPerms::all(),
+                FilePerms::all(),
+                "/",
+            )
+            .build(&mut table)
+            .unwrap();
+        let mut view = TestView { table, ctx };
+        let root = view.ctx.preopens[0].0;
+
+        // The release comes from another task on a single-threaded runtime,
+        // so it can only happen if the waiting call doesn't block the
+        // executor.
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let waiter = wasi::filesystem::Host::readlink_at(&mut view, root, "link".to_owned());
+            let releaser = async {
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                release.send(()).unwrap();
+            };
+            let (target, ()) = tokio::join!(waiter, releaser);
+            assert_eq!(target.unwrap(), "target");
+        });
+    }
 }
@@ -1,4 +1,7 @@
 use crate::{
+    http::{self, TableHttpExt},
+    preview2::poll::PollableEntry,
+    stream::TableStreamExt,
     wasi,
     wasi::poll::Pollable,
     wasi::types::{
@@ -11,193 +14,269 @@ use crate::{
 
 #[async_trait::async_trait]
 impl<T: WasiView> wasi::types::Host for T {
-    async fn drop_fields(&mut self, _fields: Fields) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+    async fn drop_fields(&mut self, fields: Fields) -> wasmtime::Result<()> {
+        self.table_mut().delete_fields(fields)?;
+        Ok(())
     }
-    async fn new_fields(&mut self, _entries: Vec<(String, String)>) -> wasmtime::Result<Fields> {
-        anyhow::bail!("not implemented")
+    async fn new_fields(&mut self, entries: Vec<(String, String)>) -> wasmtime::Result<Fields> {
+        Ok(self.table_mut().push_fields(http::Fields::new(entries))?)
     }
-    async fn fields_get(
-        &mut self,
-        _fields: Fields,
-        _name: String,
-    ) -> wasmtime::Result<Vec<String>> {
-        anyhow::bail!("not implemented")
+    async fn fields_get(&mut self, fields: Fields, name: String) -> wasmtime::Result<Vec<String>> {
+        Ok(self.table().get_fields(fields)?.get(&name))
     }
     async fn fields_set(
         &mut self,
-        _fields: Fields,
-        _name: String,
-        _value: Vec<String>,
+        fields: Fields,
+        name: String,
+        value: Vec<String>,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        self.table_mut().get_fields_mut(fields)?.set(&name, value);
+        Ok(())
     }
-    async fn fields_delete(&mut self, _fields: Fields, _name: String) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+    async fn fields_delete(&mut self, fields: Fields, name: String) -> wasmtime::Result<()> {
+        self.table_mut().get_fields_mut(fields)?.delete(&name);
+        Ok(())
     }
     async fn fields_append(
         &mut self,
-        _fields: Fields,
-        _name: String,
-        _value: String,
+        fields: Fields,
+        name: String,
+        value: String,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        self.table_mut().get_fields_mut(fields)?.append(&name, value);
+        Ok(())
     }
-    async fn fields_entries(&mut self, _fields: Fields) -> wasmtime::Result<Vec<(String, String)>> {
-        anyhow::bail!("not implemented")
+    async fn fields_entries(&mut self, fields: Fields) -> wasmtime::Result<Vec<(String, String)>> {
+        Ok(self.table().get_fields(fields)?.entries().to_vec())
     }
-    async fn fields_clone(&mut self, _fields: Fields) -> wasmtime::Result<Fields> {
-        anyhow::bail!("not implemented")
+    async fn fields_clone(&mut self, fields: Fields) -> wasmtime::Result<Fields> {
+        let clone = self.table().get_fields(fields)?.clone();
+        Ok(self.table_mut().push_fields(clone)?)
     }
     async fn finish_incoming_stream(
         &mut self,
-        _s: IncomingStream,
+        s: IncomingStream,
     ) -> wasmtime::Result<Option<Trailers>> {
-        anyhow::bail!("not implemented")
+        let trailers = self
+            .table()
+            .get_input_stream(s)?
+            .as_any()
+            .downcast_ref::<http::IncomingBody>()
+            .ok_or_else(|| anyhow::anyhow!("{s} is not an incoming HTTP body"))?
+            .trailers();
+        match trailers {
+            Some(trailers) => Ok(Some(self.table_mut().push_fields(trailers)?)),
+            None => Ok(None),
+        }
     }
     async fn finish_outgoing_stream(
         &mut self,
-        _s: OutgoingStream,
-        _trailers: Option<Trailers>,
+        s: OutgoingStream,
+        trailers: Option<Trailers>,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
-    }
-    async fn drop_incoming_request(&mut self, _request: IncomingRequest) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
-    }
-    async fn drop_outgoing_request(&mut self, _request: OutgoingRequest) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        let trailers = match trailers {
+            Some(trailers) => Some(self.table().get_fields(trailers)?.clone()),
+            None => None,
+        };
+        self.table()
+            .get_output_stream(s)?
+            .as_any()
+            .downcast_ref::<http::OutgoingBody>()
+            .ok_or_else(|| anyhow::anyhow!("{s} is not an outgoing HTTP body"))?
+            .finish(trailers)
+    }
+    async fn drop_incoming_request(&mut self, request: IncomingRequest) -> wasmtime::Result<()> {
+        self.table_mut().delete_incoming_request(request)?;
+        Ok(())
+    }
+    async fn drop_outgoing_request(&mut self, request: OutgoingRequest) -> wasmtime::Result<()> {
+        self.table_mut().delete_outgoing_request(request)?;
+        Ok(())
     }
     async fn incoming_request_method(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<Method> {
-        anyhow::bail!("not implemented")
+        Ok(self.table().get_incoming_request(request)?.method.clone())
     }
     async fn incoming_request_path(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<String> {
-        anyhow::bail!("not implemented")
+        Ok(self.table().get_incoming_request(request)?.path.clone())
     }
     async fn incoming_request_scheme(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<Option<Scheme>> {
-        anyhow::bail!("not implemented")
+        Ok(self.table().get_incoming_request(request)?.scheme.clone())
     }
     async fn incoming_request_authority(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<String> {
-        anyhow::bail!("not implemented")
+        Ok(self.table().get_incoming_request(request)?.authority.clone())
     }
     async fn incoming_request_headers(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<Headers> {
-        anyhow::bail!("not implemented")
+        let headers = self.table().get_incoming_request(request)?.headers.clone();
+        Ok(self.table_mut().push_fields(headers)?)
     }
     async fn incoming_request_consume(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<Result<IncomingStream, ()>> {
-        anyhow::bail!("not implemented")
+        let table = self.table_mut();
+        match table.get_incoming_request_mut(request)?.take_body() {
+            Some(body) => Ok(Ok(table.push_input_stream(Box::new(body))?)),
+            None => Ok(Err(())),
+        }
     }
     async fn incoming_request_query(
         &mut self,
-        _request: IncomingRequest,
+        request: IncomingRequest,
     ) -> wasmtime::Result<String> {
-        anyhow::bail!("not implemented")
+        Ok(self.table().get_incoming_request(request)?.query.clone())
     }
     async fn new_outgoing_request(
         &mut self,
-        _method: Method,
-        _path: String,
-        _query: String,
-        _scheme: Option<Scheme>,
-        _authority: String,
-        _headers: Headers,
+        method: Method,
+        path: String,
+        query: String,
+        scheme: Option<Scheme>,
+        authority: String,
+        headers: Headers,
     ) -> wasmtime::Result<OutgoingRequest> {
-        anyhow::bail!("not implemented")
+        let headers = self.table().get_fields(headers)?.clone();
+        let request = http::OutgoingRequest::new(method, path, query, scheme, authority, headers);
+        Ok(self.table_mut().push_outgoing_request(request)?)
     }
     async fn outgoing_request_write(
         &mut self,
-        _request: OutgoingRequest,
+        request: OutgoingRequest,
     ) -> wasmtime::Result<Result<OutgoingStream, ()>> {
-        anyhow::bail!("not implemented")
+        let limit = self.ctx().http.request_buffer_limit();
+        let table = self.table_mut();
+        match table.get_outgoing_request_mut(request)?.write_body() {
+            Some(body) => {
+                body.set_buffer_limit(limit);
+                Ok(Ok(table.push_output_stream(Box::new(body))?))
+            }
+            None => Ok(Err(())),
+        }
     }
     async fn drop_response_outparam(
         &mut self,
-        _response: ResponseOutparam,
+        response: ResponseOutparam,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        self.table_mut().delete_response_outparam(response)?;
+        Ok(())
     }
     async fn set_response_outparam(
         &mut self,
-        _response: Result<OutgoingResponse, Error>,
+        response: Result<OutgoingResponse, Error>,
     ) -> wasmtime::Result<Result<(), ()>> {
-        anyhow::bail!("not implemented")
+        let outparam = match self.ctx().http.response_outparam() {
+            Some(outparam) => outparam,
+            None => return Ok(Err(())),
+        };
+        let sent = match response {
+            // Trap if the response handle is not valid:
+            Ok(response) => Some(self.table().get_outgoing_response(response)?.clone()),
+            Err(_) => None,
+        };
+        Ok(self
+            .table_mut()
+            .get_response_outparam_mut(outparam)?
+            .set(response, sent))
     }
     async fn drop_incoming_response(
         &mut self,
-        _response: IncomingResponse,
+        response: IncomingResponse,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        self.table_mut().delete_incoming_response(response)?;
+        Ok(())
     }
     async fn drop_outgoing_response(
         &mut self,
-        _response: OutgoingResponse,
+        response: OutgoingResponse,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        self.table_mut().delete_outgoing_response(response)?;
+        Ok(())
     }
     async fn incoming_response_status(
         &mut self,
-        _response: IncomingResponse,
+        response: IncomingResponse,
     ) -> wasmtime::Result<StatusCode> {
-        anyhow::bail!("not implemented")
+        Ok(self.table().get_incoming_response(response)?.status)
     }
     async fn incoming_response_headers(
         &mut self,
-        _response: IncomingResponse,
+        response: IncomingResponse,
     ) -> wasmtime::Result<Headers> {
-        anyhow::bail!("not implemented")
+        let headers = self.table().get_incoming_response(response)?.headers.clone();
+        Ok(self.table_mut().push_fields(headers)?)
     }
     async fn incoming_response_consume(
         &mut self,
-        _response: IncomingResponse,
+        response: IncomingResponse,
     ) -> wasmtime::Result<Result<IncomingStream, ()>> {
-        anyhow::bail!("not implemented")
+        let table = self.table_mut();
+        match table.get_incoming_response_mut(response)?.take_body() {
+            Some(body) => Ok(Ok(table.push_input_stream(Box::new(body))?)),
+            None => Ok(Err(())),
+        }
     }
     async fn new_outgoing_response(
         &mut self,
-        _status_code: StatusCode,
-        _headers: Headers,
+        status_code: StatusCode,
+        headers: Headers,
     ) -> wasmtime::Result<OutgoingResponse> {
-        anyhow::bail!("not implemented")
+        let headers = self.table().get_fields(headers)?.clone();
+        let response = http::OutgoingResponse::new(status_code, headers);
+        Ok(self.table_mut().push_outgoing_response(response)?)
     }
     async fn outgoing_response_write(
         &mut self,
-        _response: OutgoingResponse,
+        response: OutgoingResponse,
     ) -> wasmtime::Result<Result<OutgoingStream, ()>> {
-        anyhow::bail!("not implemented")
+        let limit = self.ctx().http.response_buffer_limit();
+        let table = self.table_mut();
+        match table.get_outgoing_response_mut(response)?.write_body() {
+            Some(body) => {
+                body.set_buffer_limit(limit);
+                Ok(Ok(table.push_output_stream(Box::new(body))?))
+            }
+            None => Ok(Err(())),
+        }
     }
     async fn drop_future_incoming_response(
         &mut self,
-        _f: FutureIncomingResponse,
+        f: FutureIncomingResponse,
     ) -> wasmtime::Result<()> {
-        anyhow::bail!("not implemented")
+        self.table_mut().delete_future_incoming_response(f)?;
+        Ok(())
     }
     async fn future_incoming_response_get(
         &mut self,
-        _f: FutureIncomingResponse,
+        f: FutureIncomingResponse,
     ) -> wasmtime::Result<Option<Result<IncomingResponse, Error>>> {
-        anyhow::bail!("not implemented")
+        let table = self.table_mut();
+        match table.get_future_incoming_response_mut(f)?.take() {
+            Some(Ok(response)) => Ok(Some(Ok(table.push_incoming_response(response)?))),
+            Some(Err(e)) => Ok(Some(Err(e))),
+            None => Ok(None),
+        }
     }
     async fn listen_to_future_incoming_response(
         &mut self,
-        _f: FutureIncomingResponse,
+        f: FutureIncomingResponse,
     ) -> wasmtime::Result<Pollable> {
-        anyhow::bail!("not implemented")
+        let _ = self.table().get_future_incoming_response(f)?;
+        Ok(self
+            .table_mut()
+            .push(Box::new(PollableEntry::FutureIncomingResponse(f)))?)
     }
 }
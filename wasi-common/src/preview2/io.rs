@@ -1,9 +1,10 @@
 use crate::{
     preview2::poll::PollableEntry,
+    sched::{subscription::SubscriptionResult, Poll, Userdata},
     stream::TableStreamExt,
     wasi::poll::Pollable,
     wasi::streams::{self, InputStream, OutputStream, StreamError},
-    WasiView,
+    Table, WasiCtx, WasiView,
 };
 use anyhow::anyhow;
 
@@ -20,7 +21,9 @@ impl From<crate::TableError> for streams::Error {
     fn from(error: crate::TableError) -> streams::Error {
         match error {
             crate::TableError::Full => streams::Error::trap(anyhow!(error)),
-            crate::TableError::NotPresent | crate::TableError::WrongType => {
+            crate::TableError::NotPresent
+            | crate::TableError::WrongType
+            | crate::TableError::AlreadyBorrowed => {
                 // wit definition needs to define a badf-equiv variant:
                 StreamError {}.into()
             }
@@ -28,6 +31,67 @@ impl From<crate::TableError> for streams::Error {
     }
 }
 
+/// How many of `len` bytes `stream` can take now, given its write budget.
+async fn write_len(stream: &dyn crate::OutputStream, len: u64) -> anyhow::Result<u64> {
+    Ok(len.min(stream.write_budget().await?))
+}
+
+/// Write as much of `bytes` as `stream` can take now.
+async fn write_some(stream: &mut dyn crate::OutputStream, bytes: &[u8]) -> anyhow::Result<u64> {
+    let len = write_len(stream, bytes.len() as u64).await?;
+    stream.write(&bytes[..len as usize]).await
+}
+
+/// Splice from `src` to `dst` a chunk at a time until the end of the input.
+/// Stop early if the input has nothing ready or the output has no room,
+/// rather than spinning until they do. Chunks are cut to the output's budget,
+/// so that nothing is read from the input that the output can't take.
+async fn forward_some(
+    src: &mut dyn crate::InputStream,
+    dst: &mut dyn crate::OutputStream,
+) -> anyhow::Result<u64> {
+    let mut bytes_forwarded = 0;
+    loop {
+        let len = write_len(dst, 0x400000).await?;
+        if len == 0 {
+            break;
+        }
+        let (n, end) = dst.splice(src, len).await?;
+        bytes_forwarded += n;
+        if end || n == 0 {
+            break;
+        }
+    }
+    Ok(bytes_forwarded)
+}
+
+/// Wait until `stream` can be read from, with the context's scheduler. Host
+/// streams are polled, and others are ready when they say they're readable.
+async fn wait_readable(table: &Table, ctx: &WasiCtx, stream: InputStream) -> anyhow::Result<()> {
+    let mut poll = Poll::new();
+    poll.subscribe_read(table.get_input_stream(stream)?, Userdata::from(0));
+    ctx.sched.poll_oneoff(&mut poll).await?;
+    wait_result(poll)
+}
+
+/// Wait until `stream` can be written to, with the context's scheduler.
+async fn wait_writable(table: &Table, ctx: &WasiCtx, stream: OutputStream) -> anyhow::Result<()> {
+    let mut poll = Poll::new();
+    poll.subscribe_write(table.get_output_stream(stream)?, Userdata::from(0));
+    ctx.sched.poll_oneoff(&mut poll).await?;
+    wait_result(poll)
+}
+
+/// Fail if the one subscription of `poll` did.
+fn wait_result(poll: Poll) -> anyhow::Result<()> {
+    for (result, _) in poll.results() {
+        if let SubscriptionResult::ReadWrite(Err(e)) = result {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
 #[async_trait::async_trait]
 impl<T: WasiView> streams::Host for T {
     async fn drop_input_stream(&mut self, stream: InputStream) -> anyhow::Result<()> {
@@ -67,7 +131,13 @@ impl<T: WasiView> streams::Host for T {
         stream: InputStream,
         len: u64,
     ) -> Result<(Vec<u8>, bool), streams::Error> {
-        // TODO: When this is really async make this block.
+        let (bytes, end) = self.read(stream, len).await?;
+        if !bytes.is_empty() || end || len == 0 {
+            return Ok((bytes, end));
+        }
+        wait_readable(self.table(), self.ctx(), stream).await?;
+        // A stream that says it's ready and still has nothing isn't waited
+        // on again, so that it can't make this spin.
         self.read(stream, len).await
     }
 
@@ -75,7 +145,7 @@ impl<T: WasiView> streams::Host for T {
         let s: &mut Box<dyn crate::OutputStream> =
             self.table_mut().get_output_stream_mut(stream)?;
 
-        let bytes_written: u64 = s.write(&bytes).await?;
+        let bytes_written: u64 = write_some(&mut **s, &bytes).await?;
 
         Ok(u64::try_from(bytes_written).unwrap())
     }
@@ -85,8 +155,17 @@ impl<T: WasiView> streams::Host for T {
         stream: OutputStream,
         bytes: Vec<u8>,
     ) -> Result<u64, streams::Error> {
-        // TODO: When this is really async make this block.
-        self.write(stream, bytes).await
+        let s: &mut Box<dyn crate::OutputStream> =
+            self.table_mut().get_output_stream_mut(stream)?;
+        let n = write_some(&mut **s, &bytes).await?;
+        if n != 0 || bytes.is_empty() {
+            return Ok(n);
+        }
+        wait_writable(self.table(), self.ctx(), stream).await?;
+        // As in `blocking_read`, a stream that says it's ready gets one try.
+        let s: &mut Box<dyn crate::OutputStream> =
+            self.table_mut().get_output_stream_mut(stream)?;
+        Ok(write_some(&mut **s, &bytes).await?)
     }
 
     async fn skip(&mut self, stream: InputStream, len: u64) -> Result<(u64, bool), streams::Error> {
@@ -102,7 +181,11 @@ impl<T: WasiView> streams::Host for T {
         stream: InputStream,
         len: u64,
     ) -> Result<(u64, bool), streams::Error> {
-        // TODO: When this is really async make this block.
+        let (n, end) = self.skip(stream, len).await?;
+        if n != 0 || end || len == 0 {
+            return Ok((n, end));
+        }
+        wait_readable(self.table(), self.ctx(), stream).await?;
         self.skip(stream, len).await
     }
 
@@ -114,6 +197,7 @@ impl<T: WasiView> streams::Host for T {
         let s: &mut Box<dyn crate::OutputStream> =
             self.table_mut().get_output_stream_mut(stream)?;
 
+        let len = write_len(&**s, len).await?;
         let bytes_written: u64 = s.write_zeroes(len).await?;
 
         Ok(bytes_written)
@@ -124,37 +208,27 @@ impl<T: WasiView> streams::Host for T {
         stream: OutputStream,
         len: u64,
     ) -> Result<u64, streams::Error> {
-        // TODO: When this is really async make this block.
+        let n = self.write_zeroes(stream, len).await?;
+        if n != 0 || len == 0 {
+            return Ok(n);
+        }
+        wait_writable(self.table(), self.ctx(), stream).await?;
         self.write_zeroes(stream, len).await
     }
 
     async fn splice(
         &mut self,
-        _src: InputStream,
-        _dst: OutputStream,
-        _len: u64,
+        src: InputStream,
+        dst: OutputStream,
+        len: u64,
     ) -> Result<(u64, bool), streams::Error> {
-        // TODO: We can't get two streams at the same time because they both
-        // carry the exclusive lifetime of `ctx`. When [`get_many_mut`] is
-        // stabilized, that could allow us to add a `get_many_stream_mut` or
-        // so which lets us do this.
-        //
-        // [`get_many_mut`]: https://doc.rust-lang.org/stable/std/collections/hash_map/struct.HashMap.html#method.get_many_mut
-        /*
-        let s: &mut Box<dyn crate::InputStream> = ctx
-            .table_mut()
-            .get_input_stream_mut(src)
-            ?;
-        let d: &mut Box<dyn crate::OutputStream> = ctx
-            .table_mut()
-            .get_output_stream_mut(dst)
-            ?;
+        let mut pair = self.table_mut().get_stream_pair_mut(src, dst)?;
+        let (s, d) = pair.get_mut();
 
-        let bytes_spliced: u64 = s.splice(&mut **d, len).await?;
+        let len = write_len(&**d, len).await?;
+        let (bytes_spliced, end) = d.splice(&mut **s, len).await?;
 
-        Ok(bytes_spliced)
-        */
-        todo!()
+        Ok((bytes_spliced, end))
     }
 
     async fn blocking_splice(
@@ -163,37 +237,26 @@ impl<T: WasiView> streams::Host for T {
         dst: OutputStream,
         len: u64,
     ) -> Result<(u64, bool), streams::Error> {
-        // TODO: When this is really async make this block.
+        let (n, end) = self.splice(src, dst, len).await?;
+        if n != 0 || end || len == 0 {
+            return Ok((n, end));
+        }
+        // Nothing moved, either because there was nothing to read or
+        // because there was no room to write it, so wait for both.
+        wait_readable(self.table(), self.ctx(), src).await?;
+        wait_writable(self.table(), self.ctx(), dst).await?;
         self.splice(src, dst, len).await
     }
 
     async fn forward(
         &mut self,
-        _src: InputStream,
-        _dst: OutputStream,
+        src: InputStream,
+        dst: OutputStream,
     ) -> Result<u64, streams::Error> {
-        // TODO: We can't get two streams at the same time because they both
-        // carry the exclusive lifetime of `ctx`. When [`get_many_mut`] is
-        // stabilized, that could allow us to add a `get_many_stream_mut` or
-        // so which lets us do this.
-        //
-        // [`get_many_mut`]: https://doc.rust-lang.org/stable/std/collections/hash_map/struct.HashMap.html#method.get_many_mut
-        /*
-        let s: &mut Box<dyn crate::InputStream> = ctx
-            .table_mut()
-            .get_input_stream_mut(src)
-            ?;
-        let d: &mut Box<dyn crate::OutputStream> = ctx
-            .table_mut()
-            .get_output_stream_mut(dst)
-            ?;
+        let mut pair = self.table_mut().get_stream_pair_mut(src, dst)?;
+        let (s, d) = pair.get_mut();
 
-        let bytes_spliced: u64 = s.splice(&mut **d, len).await?;
-
-        Ok(bytes_spliced)
-        */
-
-        todo!()
+        Ok(forward_some(&mut **s, &mut **d).await?)
     }
 
     async fn subscribe_to_input_stream(&mut self, stream: InputStream) -> anyhow::Result<Pollable> {
@@ -211,3 +274,157 @@ impl<T: WasiView> streams::Host for T {
             .push(Box::new(PollableEntry::Write(stream)))?)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InputStream as _;
+    use std::any::Any;
+
+    /// A sink that takes at most `budget` more bytes.
+    struct Budgeted {
+        budget: u64,
+        written: Vec<u8>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::OutputStream for Budgeted {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+        async fn write(&mut self, buf: &[u8]) -> anyhow::Result<u64> {
+            assert!(buf.len() as u64 <= self.budget);
+            self.budget -= buf.len() as u64;
+            self.written.extend_from_slice(buf);
+            Ok(buf.len() as u64)
+        }
+        async fn write_budget(&self) -> anyhow::Result<u64> {
+            Ok(self.budget)
+        }
+        async fn writable(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct TestView {
+        table: Table,
+        ctx: WasiCtx,
+    }
+
+    impl TestView {
+        fn new() -> Self {
+            let mut table = Table::new();
+            let ctx = crate::WasiCtxBuilder::new().build(&mut table).unwrap();
+            TestView { table, ctx }
+        }
+    }
+
+    impl WasiView for TestView {
+        fn table(&self) -> &Table {
+            &self.table
+        }
+        fn table_mut(&mut self) -> &mut Table {
+            &mut self.table
+        }
+        fn ctx(&self) -> &WasiCtx {
+            &self.ctx
+        }
+        fn ctx_mut(&mut self) -> &mut WasiCtx {
+            &mut self.ctx
+        }
+    }
+
+    /// Give the other tasks of a single-threaded runtime some turns.
+    async fn yield_a_few_times() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn blocking_read_waits_for_a_write() {
+        let (reader, mut writer) = crate::pipe::channel(4);
+        let mut view = TestView::new();
+        let stream = view.table.push_input_stream(Box::new(reader)).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let read = streams::Host::blocking_read(&mut view, stream, 4);
+            let write = async {
+                yield_a_few_times().await;
+                crate::OutputStream::write(&mut writer, b"hi")
+                    .await
+                    .unwrap();
+            };
+            let (read, ()) = tokio::join!(read, write);
+            assert_eq!(read.unwrap(), (b"hi".to_vec(), false));
+        });
+    }
+
+    #[test]
+    fn blocking_write_waits_for_room() {
+        let (mut reader, writer) = crate::pipe::channel(4);
+        let mut view = TestView::new();
+        let stream = view.table.push_output_stream(Box::new(writer)).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let n = streams::Host::write(&mut view, stream, b"full".to_vec())
+                .await
+                .unwrap();
+            assert_eq!(n, 4);
+
+            let write = streams::Host::blocking_write(&mut view, stream, b"more".to_vec());
+            let read = async {
+                yield_a_few_times().await;
+                let mut buf = [0; 2];
+                reader.read(&mut buf).await.unwrap();
+                buf
+            };
+            let (written, read) = tokio::join!(write, read);
+            assert_eq!(written.unwrap(), 2);
+            assert_eq!(&read, b"fu");
+        });
+    }
+
+    #[test]
+    fn writes_are_cut_to_the_budget() {
+        let mut sink = Budgeted {
+            budget: 5,
+            written: Vec::new(),
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert_eq!(write_some(&mut sink, b"abc").await.unwrap(), 3);
+            assert_eq!(write_some(&mut sink, b"defg").await.unwrap(), 2);
+            assert_eq!(write_some(&mut sink, b"g").await.unwrap(), 0);
+        });
+        assert_eq!(sink.written, b"abcde");
+    }
+
+    #[test]
+    fn forward_stops_at_the_budget() {
+        let mut source = crate::pipe::ReadPipe::from(&b"abcdefgh"[..]);
+        let mut sink = Budgeted {
+            budget: 5,
+            written: Vec::new(),
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert_eq!(forward_some(&mut source, &mut sink).await.unwrap(), 5);
+            assert_eq!(forward_some(&mut source, &mut sink).await.unwrap(), 0);
+
+            // Nothing past the budget was taken from the source.
+            let mut rest = [0; 8];
+            let (n, _) = source.read(&mut rest).await.unwrap();
+            assert_eq!(&rest[..n as usize], b"fgh");
+        });
+        assert_eq!(sink.written, b"abcde");
+    }
+}
@@ -1,9 +1,11 @@
 use crate::{
+    http::TableHttpExt,
     stream::TableStreamExt,
     wasi,
     wasi::monotonic_clock::Instant,
     wasi::poll::Pollable,
     wasi::streams::{InputStream, OutputStream},
+    wasi::types::FutureIncomingResponse,
     WasiView,
 };
 
@@ -16,6 +18,8 @@ pub(crate) enum PollableEntry {
     Write(OutputStream),
     /// Poll for a monotonic-clock timer.
     MonotonicClock(Instant, bool),
+    /// Poll for the response to an outgoing HTTP request.
+    FutureIncomingResponse(FutureIncomingResponse),
     /* FIXME: need to rebuild the poll interface to let pollables be created in different crates.
     /// Poll for a tcp-socket.
     TcpSocket(TcpSocket),
@@ -62,6 +66,10 @@ impl<T: WasiView> wasi::poll::Host for T {
                         absolute,
                         userdata,
                     );
+                }
+                PollableEntry::FutureIncomingResponse(future) => {
+                    let response = self.table().get_future_incoming_response(future)?;
+                    poll.subscribe_read(response.pollable(), userdata);
                 } /*
                   PollableEntry::TcpSocket(tcp_socket) => {
                       let wasi_tcp_socket: &dyn crate::WasiTcpSocket =
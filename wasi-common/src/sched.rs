@@ -48,10 +48,11 @@ impl<'a> Poll<'a> {
         ud: Userdata,
     ) {
         let deadline = if absolute {
-            // Convert an absolute deadline to a relative one.
-            deadline.saturating_sub(clock.now())
-        } else {
             deadline
+        } else {
+            // Convert a relative deadline to an absolute one, so that it
+            // doesn't move while the poll waits.
+            clock.now().saturating_add(deadline)
         };
         self.subs.push((
             Subscription::MonotonicClock(MonotonicClockSubscription { clock, deadline }),
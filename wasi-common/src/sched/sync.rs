@@ -2,143 +2,281 @@ use crate::sched::{
     subscription::{RwEventFlags, RwStream},
     Poll, WasiSched,
 };
+use rustix::fd::{AsFd, OwnedFd};
 use rustix::io::{PollFd, PollFlags};
+use std::future::Future;
+use std::pin::Pin;
 use std::thread;
 use std::time::Duration;
 
 use anyhow::Error;
 
 pub async fn poll_oneoff<'a>(poll: &mut Poll<'a>) -> Result<(), Error> {
-    // Collect all stream I/O subscriptions. Clock subscriptions are handled
-    // separately below.
-    let mut ready = false;
-    let mut pollfds = Vec::new();
-    for rwsub in poll.rw_subscriptions() {
-        match rwsub.stream {
-            RwStream::Read(stream) => {
-                // Poll things that can be polled.
-                if let Some(fd) = stream.pollable_read() {
-                    #[cfg(unix)]
-                    {
-                        pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::IN));
-                        continue;
-                    }
-
-                    #[cfg(windows)]
-                    {
-                        if let Some(fd) = fd.as_socket() {
-                            pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::IN));
+    loop {
+        // Collect all stream I/O subscriptions. Streams with a host file
+        // descriptor are polled with a host `poll`, and the rest are asked
+        // whether they're ready, and if not, waited on by themselves. Clock
+        // subscriptions are handled separately below.
+        let mut ready = false;
+        let mut fds = Vec::new();
+        let mut polled = Vec::new();
+        let mut waiting = Vec::new();
+        for (index, rwsub) in poll.rw_subscriptions().enumerate() {
+            match rwsub.stream {
+                RwStream::Read(stream) => {
+                    // Poll things that can be polled. The host `poll` runs on
+                    // another thread, so it gets its own handles.
+                    if let Some(fd) = stream.pollable_read() {
+                        #[cfg(unix)]
+                        {
+                            fds.push((fd.try_clone_to_owned()?, PollFlags::IN));
+                            polled.push(index);
                             continue;
                         }
+
+                        #[cfg(windows)]
+                        {
+                            if let Some(fd) = fd.as_socket() {
+                                fds.push((fd.try_clone_to_owned()?, PollFlags::IN));
+                                polled.push(index);
+                                continue;
+                            }
+                        }
                     }
-                }
 
-                // Allow in-memory buffers or other immediately-available
-                // sources to complete successfully.
-                if let Ok(nbytes) = stream.num_ready_bytes().await {
-                    if nbytes != 0 {
+                    // Allow in-memory buffers, files, or other sources whose
+                    // reads never wait to complete successfully.
+                    if stream.num_ready_bytes().await.map_or(false, |n| n != 0) {
                         rwsub.complete(RwEventFlags::empty());
                         ready = true;
                         continue;
                     }
+                    match stream.read_ready().await {
+                        Ok(true) => rwsub.complete(RwEventFlags::empty()),
+                        Ok(false) => {
+                            waiting.push((index, RwStream::Read(stream)));
+                            continue;
+                        }
+                        Err(e) => rwsub.error(e),
+                    }
+                    ready = true;
                 }
 
-                return Err(anyhow::anyhow!("stream is not pollable for reading"));
-            }
-
-            RwStream::Write(stream) => {
-                let fd = stream
-                    .pollable_write()
-                    .ok_or_else(|| anyhow::anyhow!("stream is not pollable for writing"))?;
-
-                #[cfg(unix)]
-                {
-                    pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::OUT));
-                }
+                RwStream::Write(stream) => {
+                    if let Some(fd) = stream.pollable_write() {
+                        #[cfg(unix)]
+                        {
+                            fds.push((fd.try_clone_to_owned()?, PollFlags::OUT));
+                            polled.push(index);
+                            continue;
+                        }
 
-                #[cfg(windows)]
-                {
-                    if let Some(fd) = fd.as_socket() {
-                        pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::OUT));
-                    } else {
-                        return Err(anyhow::anyhow!(
-                            "unimplemented: polling for writing to non-OS resources"
-                        ));
+                        #[cfg(windows)]
+                        {
+                            if let Some(fd) = fd.as_socket() {
+                                fds.push((fd.try_clone_to_owned()?, PollFlags::OUT));
+                                polled.push(index);
+                                continue;
+                            }
+                            rwsub.error(anyhow::anyhow!(
+                                "unimplemented: polling for writing to non-OS resources"
+                            ));
+                            ready = true;
+                            continue;
+                        }
                     }
-                }
-            } /* FIXME redesign of sched to make it possible to define pollables out of crate
-              RwStream::TcpSocket(tcp_socket) => {
-                  let fd = tcp_socket.pollable();
-                  pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::IN | PollFlags::PRI));
-              }
-              */
-        }
-    }
 
-    // If we didn't have any streams that are immediately available, do an OS
-    // `poll` to wait for streams to become available.
-    if !ready {
-        loop {
-            let poll_timeout = if let Some(t) = poll.earliest_clock_deadline() {
-                // Convert the timeout to milliseconds for `poll`, rounding up.
-                //
-                // TODO: On Linux and FreeBSD, we could use `ppoll` instead
-                // which takes a `timespec.`
-                ((t.deadline + 999_999) / 1_000_000)
-                    .try_into()
-                    .map_err(|_| anyhow::anyhow!("overflow: poll timeout"))?
-            } else {
-                // A negative value requests an infinite timeout.
-                -1
-            };
-            tracing::debug!(
-                poll_timeout = tracing::field::debug(poll_timeout),
-                poll_fds = tracing::field::debug(&pollfds),
-                "poll"
-            );
-            match rustix::io::poll(&mut pollfds, poll_timeout) {
-                Ok(_num_ready) => {
+                    // Allow in-memory buffers or other sinks to complete
+                    // successfully once they have room.
+                    match stream.write_ready().await {
+                        Ok(true) => rwsub.complete(RwEventFlags::empty()),
+                        Ok(false) => {
+                            waiting.push((index, RwStream::Write(stream)));
+                            continue;
+                        }
+                        Err(e) => rwsub.error(e),
+                    }
                     ready = true;
-                    break;
-                }
-                Err(rustix::io::Errno::INTR) => continue,
-                Err(err) => return Err(std::io::Error::from(err).into()),
+                } /* FIXME redesign of sched to make it possible to define pollables out of crate
+                  RwStream::TcpSocket(tcp_socket) => {
+                      let fd = tcp_socket.pollable();
+                      pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::IN | PollFlags::PRI));
+                  }
+                  */
             }
         }
 
-        assert_eq!(poll.rw_subscriptions().count(), pollfds.len());
-
-        // If the OS `poll` returned events, record them.
+        // If a stream was immediately available, report it without waiting
+        // on the others.
         if ready {
-            // Iterate through the stream subscriptions, skipping those that
-            // were already completed due to being immediately available.
-            for (rwsub, pollfd) in poll.rw_subscriptions().zip(pollfds.into_iter()) {
-                let revents = pollfd.revents();
+            return Ok(());
+        }
+
+        // Wait for the host file descriptors until the earliest clock
+        // deadline.
+        let poll_timeout = if let Some(t) = poll.earliest_clock_deadline() {
+            // Convert the timeout to milliseconds for `poll`, rounding up.
+            //
+            // TODO: On Linux and FreeBSD, we could use `ppoll` instead
+            // which takes a `timespec.`
+            ((t.duration_until().unwrap_or(0) + 999_999) / 1_000_000)
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("overflow: poll timeout"))?
+        } else {
+            // A negative value requests an infinite timeout.
+            -1
+        };
+
+        // The host `poll` blocks, so it runs on the blocking pool, and the
+        // streams without a host file descriptor are waited on here at the
+        // same time. The first of them to be ready wakes the host `poll`.
+        let waker = if waiting.is_empty() {
+            None
+        } else {
+            let (waker, fd) = PollWaker::new()?;
+            fds.push((fd, PollFlags::IN));
+            Some(waker)
+        };
+
+        tracing::debug!(
+            poll_timeout = tracing::field::debug(poll_timeout),
+            poll_fds = tracing::field::debug(&fds),
+            "poll"
+        );
+        let polling = crate::filesystem::spawn_blocking(move || host_poll(fds, poll_timeout));
+        let revents = match waker {
+            None => polling.await?,
+            Some(waker) => {
+                tokio::pin!(polling);
+                tokio::select! {
+                    revents = &mut polling => revents?,
+                    (index, result) = first_ready(&waiting) => {
+                        waker.wake();
+                        if let Some(rwsub) = poll.rw_subscriptions().nth(index) {
+                            match result {
+                                Ok(()) => rwsub.complete(RwEventFlags::empty()),
+                                Err(e) => rwsub.error(e),
+                            }
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        // If the host `poll` returned events, record them.
+        if revents[..polled.len()].iter().any(|r| !r.is_empty()) {
+            let mut fds = polled.into_iter().zip(revents);
+            let mut next = fds.next();
+            for (index, rwsub) in poll.rw_subscriptions().enumerate() {
+                let revents = match next {
+                    Some((i, revents)) if i == index => revents,
+                    _ => continue,
+                };
+                next = fds.next();
                 if revents.contains(PollFlags::NVAL) {
                     rwsub.error(anyhow::anyhow!("rw subscription badf"));
                 } else if revents.contains(PollFlags::ERR) {
                     rwsub.error(anyhow::anyhow!("rw subscription io error"));
                 } else if revents.contains(PollFlags::HUP) {
                     rwsub.complete(RwEventFlags::HANGUP);
-                } else {
+                } else if !revents.is_empty() {
                     rwsub.complete(RwEventFlags::empty());
+                }
+            }
+            return Ok(());
+        }
+
+        // If the earliest deadline has passed, we timed out. The clock
+        // subscription reports that itself.
+        if let Some(t) = poll.earliest_clock_deadline() {
+            if t.result().is_some() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Poll `fds` for the given events, returning the events of each. An
+/// interrupted `poll` returns no events.
+fn host_poll(fds: Vec<(OwnedFd, PollFlags)>, timeout: i32) -> std::io::Result<Vec<PollFlags>> {
+    let mut pollfds = fds
+        .iter()
+        .map(|(fd, flags)| PollFd::from_borrowed_fd(fd.as_fd(), *flags))
+        .collect::<Vec<_>>();
+    match rustix::io::poll(&mut pollfds, timeout) {
+        Ok(_) => Ok(pollfds.iter().map(PollFd::revents).collect()),
+        Err(rustix::io::Errno::INTR) => Ok(vec![PollFlags::empty(); pollfds.len()]),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Wait until one of the `waiting` streams says it's ready, and return its
+/// subscription's index and the result.
+async fn first_ready(waiting: &[(usize, RwStream<'_>)]) -> (usize, Result<(), Error>) {
+    let mut waits = waiting
+        .iter()
+        .map(|(index, stream)| {
+            let index = *index;
+            let wait: Pin<Box<dyn Future<Output = (usize, Result<(), Error>)> + Send + '_>> =
+                match stream {
+                    RwStream::Read(stream) => {
+                        Box::pin(async move { (index, stream.readable().await) })
+                    }
+                    RwStream::Write(stream) => {
+                        Box::pin(async move { (index, stream.writable().await) })
+                    }
                 };
+            wait
+        })
+        .collect::<Vec<_>>();
+    std::future::poll_fn(|cx| {
+        for wait in waits.iter_mut() {
+            if let std::task::Poll::Ready(result) = wait.as_mut().poll(cx) {
+                return std::task::Poll::Ready(result);
             }
         }
-    };
-
-    // If we had no immediately-available events and no events becoming
-    // available in a `poll`, it means we timed out. Report that event.
-    if !ready {
-        poll.earliest_clock_deadline()
-            .expect("timed out")
-            .result()
-            .expect("timer deadline is past")
-            .unwrap()
+        std::task::Poll::Pending
+    })
+    .await
+}
+
+/// Wakes a host `poll` on the blocking pool that also waits on the file
+/// descriptor returned with it.
+struct PollWaker {
+    #[cfg(unix)]
+    pipe: OwnedFd,
+    #[cfg(windows)]
+    socket: std::net::UdpSocket,
+}
+
+impl PollWaker {
+    #[cfg(unix)]
+    fn new() -> std::io::Result<(Self, OwnedFd)> {
+        let (read, write) = rustix::io::pipe()?;
+        Ok((Self { pipe: write }, read))
     }
 
-    Ok(())
+    /// Windows can only poll sockets, so wake with a datagram that a socket
+    /// sends to itself.
+    #[cfg(windows)]
+    fn new() -> std::io::Result<(Self, OwnedFd)> {
+        let socket = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0))?;
+        socket.connect(socket.local_addr()?)?;
+        let fd = OwnedFd::from(socket.try_clone()?);
+        Ok((Self { socket }, fd))
+    }
+
+    fn wake(&self) {
+        // If this fails, the `poll` waits out its file descriptors or its
+        // timeout on the blocking pool, and nothing waits for it.
+        #[cfg(unix)]
+        let _ = rustix::io::write(&self.pipe, &[0]);
+        #[cfg(windows)]
+        let _ = self.socket.send(&[0]);
+    }
 }
+
 pub struct SyncSched;
 #[async_trait::async_trait]
 impl WasiSched for SyncSched {
@@ -1,3 +1,4 @@
+use crate::table::ManyMut;
 use crate::TableError;
 use anyhow::Error;
 use std::any::Any;
@@ -45,22 +46,33 @@ pub trait InputStream: Send + Sync {
         false
     }
 
+    /// If this stream is reading from a host file, return the file and the
+    /// stream's position in it, so that splices from it can be done by the
+    /// kernel without copying the bytes through the host. Whoever reads from
+    /// the file this way must then advance the stream with `skip`.
+    #[cfg(unix)]
+    fn file_source(&self) -> Option<(rustix::fd::BorrowedFd, u64)> {
+        None
+    }
+
     /// Read bytes from a stream and discard them.
     async fn skip(&mut self, nelem: u64) -> Result<(u64, bool), Error> {
+        let mut buf = vec![0; chunk_len(nelem)];
         let mut nread = 0;
-        let mut saw_end = false;
 
-        // TODO: Optimize by reading more than one byte at a time.
-        for _ in 0..nelem {
-            let (num, end) = self.read(&mut [0]).await?;
+        while nread < nelem {
+            let len = chunk_len(nelem - nread);
+            let (num, end) = self.read(&mut buf[..len]).await?;
             nread += num;
             if end {
-                saw_end = true;
+                return Ok((nread, true));
+            }
+            if num == 0 {
                 break;
             }
         }
 
-        Ok((nread, saw_end))
+        Ok((nread, false))
     }
 
     /// Return the number of bytes that may be read without blocking.
@@ -68,8 +80,19 @@ pub trait InputStream: Send + Sync {
         Ok(0)
     }
 
-    /// Test whether this stream is readable.
+    /// Test whether this stream is readable. The scheduler waits on this
+    /// for streams that have no host file descriptor and aren't
+    /// `read_ready`, so streams that can be not ready must wait here.
     async fn readable(&self) -> Result<(), Error>;
+
+    /// Test, without waiting, whether a read would return bytes or the end
+    /// of the stream right now. This is how the scheduler polls streams that
+    /// have no host file descriptor. Streams whose `readable` waits must
+    /// override this.
+    async fn read_ready(&self) -> Result<bool, Error> {
+        self.readable().await?;
+        Ok(true)
+    }
 }
 
 /// An output bytestream.
@@ -117,31 +140,24 @@ pub trait OutputStream: Send + Sync {
         src: &mut dyn InputStream,
         nelem: u64,
     ) -> Result<(u64, bool), Error> {
-        let mut nspliced = 0;
-        let mut saw_end = false;
-
-        // TODO: Optimize by splicing more than one byte at a time.
-        for _ in 0..nelem {
-            let mut buf = [0u8];
-            let (num, end) = src.read(&mut buf).await?;
-            self.write(&buf).await?;
-            nspliced += num;
-            if end {
-                saw_end = true;
-                break;
-            }
-        }
+        splice_buffered(self, src, nelem).await
+    }
 
-        Ok((nspliced, saw_end))
+    /// The number of bytes this stream can take right now, before a slow
+    /// consumer has to catch up. Writes of more bytes than this are cut
+    /// short. Streams that don't buffer what's written have no budget.
+    async fn write_budget(&self) -> Result<u64, Error> {
+        Ok(u64::MAX)
     }
 
     /// Repeatedly write a byte to a stream.
     async fn write_zeroes(&mut self, nelem: u64) -> Result<u64, Error> {
+        let zeroes = vec![0; chunk_len(nelem)];
         let mut nwritten = 0;
 
-        // TODO: Optimize by writing more than one byte at a time.
-        for _ in 0..nelem {
-            let num = self.write(&[0]).await?;
+        while nwritten < nelem {
+            let len = chunk_len(nelem - nwritten);
+            let num = self.write(&zeroes[..len]).await?;
             if num == 0 {
                 break;
             }
@@ -151,8 +167,66 @@ pub trait OutputStream: Send + Sync {
         Ok(nwritten)
     }
 
-    /// Test whether this stream is writeable.
+    /// Test whether this stream is writeable. Streams with a write budget
+    /// wait here until there's room in it; the scheduler waits on this for
+    /// streams that have no host file descriptor and aren't `write_ready`.
     async fn writable(&self) -> Result<(), Error>;
+
+    /// Test, without waiting, whether a write would take some bytes right
+    /// now. This is how the scheduler polls streams that have no host file
+    /// descriptor. Streams whose `writable` waits must override this.
+    async fn write_ready(&self) -> Result<bool, Error> {
+        self.writable().await?;
+        Ok(self.write_budget().await? != 0)
+    }
+}
+
+/// The most bytes the default `skip`, `splice`, and `write_zeroes` move at a
+/// time.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The length of the next chunk when `remaining` bytes are left to move.
+fn chunk_len(remaining: u64) -> usize {
+    // `CHUNK_SIZE` fits in a usize.
+    remaining.min(CHUNK_SIZE) as usize
+}
+
+/// Splice bytes from `src` to `dst` by reading them into a buffer and
+/// writing them out. This is the default `OutputStream::splice`, and streams
+/// with their own `splice` can fall back to it.
+pub async fn splice_buffered<O: OutputStream + ?Sized>(
+    dst: &mut O,
+    src: &mut dyn InputStream,
+    nelem: u64,
+) -> Result<(u64, bool), Error> {
+    let mut buf = vec![0; chunk_len(nelem)];
+    let mut nspliced = 0;
+
+    while nspliced < nelem {
+        let len = chunk_len(nelem - nspliced);
+        let (num, end) = src.read(&mut buf[..len]).await?;
+
+        // The bytes have been taken from `src`, so they must all be
+        // written.
+        let mut rest = &buf[..num as usize];
+        while !rest.is_empty() {
+            let written = dst.write(rest).await?;
+            if written == 0 {
+                anyhow::bail!("output stream stopped accepting bytes during a splice");
+            }
+            rest = &rest[written as usize..];
+        }
+
+        nspliced += num;
+        if end {
+            return Ok((nspliced, true));
+        }
+        if num == 0 {
+            break;
+        }
+    }
+
+    Ok((nspliced, false))
 }
 
 pub trait TableStreamExt {
@@ -163,7 +237,31 @@ pub trait TableStreamExt {
     fn push_output_stream(&mut self, ostream: Box<dyn OutputStream>) -> Result<u32, TableError>;
     fn get_output_stream(&self, fd: u32) -> Result<&dyn OutputStream, TableError>;
     fn get_output_stream_mut(&mut self, fd: u32) -> Result<&mut Box<dyn OutputStream>, TableError>;
+
+    /// Get an input stream and an output stream at once, to splice one into
+    /// the other.
+    fn get_stream_pair_mut(
+        &mut self,
+        input: u32,
+        output: u32,
+    ) -> Result<StreamPair<'_>, TableError>;
+}
+
+/// An input stream and an output stream taken out of a `Table` together by
+/// `TableStreamExt::get_stream_pair_mut`. They go back into the table when
+/// this is dropped.
+pub struct StreamPair<'a>(ManyMut<'a, 2>);
+
+impl StreamPair<'_> {
+    pub fn get_mut(&mut self) -> (&mut Box<dyn InputStream>, &mut Box<dyn OutputStream>) {
+        let [input, output] = self.0.get_mut();
+        (
+            input.downcast_mut().expect("checked when taken"),
+            output.downcast_mut().expect("checked when taken"),
+        )
+    }
 }
+
 impl TableStreamExt for crate::Table {
     fn push_input_stream(
         &mut self,
@@ -190,6 +288,18 @@ impl TableStreamExt for crate::Table {
     fn get_output_stream_mut(&mut self, fd: u32) -> Result<&mut Box<dyn OutputStream>, TableError> {
         self.get_mut::<Box<dyn OutputStream>>(fd)
     }
+    fn get_stream_pair_mut(
+        &mut self,
+        input: u32,
+        output: u32,
+    ) -> Result<StreamPair<'_>, TableError> {
+        let mut pair = self.get_many_mut([input, output])?;
+        let [input, output] = pair.get_mut();
+        if !input.is::<Box<dyn InputStream>>() || !output.is::<Box<dyn OutputStream>>() {
+            return Err(TableError::WrongType);
+        }
+        Ok(StreamPair(pair))
+    }
 }
 
 #[cfg(test)]
@@ -212,4 +322,35 @@ mod test {
         let _ = table.get_output_stream(ix).unwrap();
         let _ = table.get_output_stream_mut(ix).unwrap();
     }
+
+    #[test]
+    fn splice_streams_in_table() {
+        let contents = std::sync::Arc::new(std::sync::RwLock::new(Vec::new()));
+        let mut table = crate::Table::new();
+        let input = table
+            .push_input_stream(Box::new(crate::pipe::ReadPipe::from("hello")))
+            .unwrap();
+        let output = table
+            .push_output_stream(Box::new(crate::pipe::WritePipe::from_shared(
+                contents.clone(),
+            )))
+            .unwrap();
+
+        assert!(table.get_stream_pair_mut(output, input).is_err());
+        assert!(table.get_many_mut([input, input]).is_err());
+
+        let mut pair = table.get_stream_pair_mut(input, output).unwrap();
+        let (src, dst) = pair.get_mut();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (n, _) = rt.block_on(dst.splice(&mut **src, 3)).unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(*contents.read().unwrap(), b"hel");
+
+        // The streams are back in the table once the pair is dropped.
+        drop(pair);
+        assert!(table.get_input_stream(input).is_ok());
+        assert!(table.get_output_stream(output).is_ok());
+    }
 }
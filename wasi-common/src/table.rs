@@ -9,6 +9,8 @@ pub enum TableError {
     NotPresent,
     #[error("value is of another type")]
     WrongType,
+    #[error("value is already borrowed")]
+    AlreadyBorrowed,
 }
 
 /// The `Table` type is designed to map u32 handles to resources. The table is now part of the
@@ -86,6 +88,30 @@ impl Table {
         }
     }
 
+    /// Take the resources at several indices out of the table at once, so that one resource can
+    /// be used with another, as when splicing one stream into another. The resources may be of
+    /// any type, and go back into the table when the returned `ManyMut` is dropped. Asking for an
+    /// index more than once fails with `TableError::AlreadyBorrowed`.
+    pub fn get_many_mut<const N: usize>(
+        &mut self,
+        keys: [u32; N],
+    ) -> Result<ManyMut<'_, N>, TableError> {
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].contains(key) {
+                return Err(TableError::AlreadyBorrowed);
+            }
+        }
+        if !keys.iter().all(|key| self.map.contains_key(key)) {
+            return Err(TableError::NotPresent);
+        }
+        let values = keys.map(|key| self.map.remove(&key));
+        Ok(ManyMut {
+            table: self,
+            keys,
+            values,
+        })
+    }
+
     /// Remove a resource at a given index from the table.
     pub fn delete<T: Any + Sized>(&mut self, key: u32) -> Result<(), TableError> {
         if !self.contains_key(key) {
@@ -98,3 +124,36 @@ impl Table {
         Ok(())
     }
 }
+
+/// Resources taken out of a `Table` by `Table::get_many_mut`. They go back into the table when
+/// this is dropped.
+pub struct ManyMut<'a, const N: usize> {
+    table: &'a mut Table,
+    keys: [u32; N],
+    values: [Option<Box<dyn Any + Send + Sync>>; N],
+}
+
+impl<const N: usize> ManyMut<'_, N> {
+    /// Get mutable references to the resources, in the order of the keys they were taken with.
+    pub fn get_mut(&mut self) -> [&mut Box<dyn Any + Send + Sync>; N] {
+        let values: Vec<_> = self
+            .values
+            .iter_mut()
+            .map(|value| value.as_mut().expect("present until dropped"))
+            .collect();
+        match values.try_into() {
+            Ok(values) => values,
+            Err(_) => unreachable!("one value per key"),
+        }
+    }
+}
+
+impl<const N: usize> Drop for ManyMut<'_, N> {
+    fn drop(&mut self) {
+        for (key, value) in self.keys.iter().zip(&mut self.values) {
+            if let Some(value) = value.take() {
+                self.table.map.insert(*key, value);
+            }
+        }
+    }
+}
@@ -7,6 +7,7 @@ use wasi_common::Table;
 mod ip_name_lookup;
 mod network;
 mod network_impl;
+pub mod preview1;
 mod tcp;
 mod tcp_socket;
 mod udp;
@@ -24,6 +25,7 @@ pub struct WasiSocketsCtx {
     pool: Pool,
     network_creator: NetworkCreator,
     tcp_socket_creator: TcpSocketCreator,
+    preopened_listeners: Vec<Box<dyn WasiTcpSocket>>,
 }
 
 impl WasiSocketsCtx {
@@ -36,9 +38,18 @@ impl WasiSocketsCtx {
             pool,
             network_creator,
             tcp_socket_creator,
+            preopened_listeners: Vec::new(),
         }
     }
 
+    /// Add a listening socket for preview 1 programs, like wasmtime's
+    /// `--tcplisten`. Listeners get the file descriptors after stdio, in the
+    /// order they're added. Components get them from the preview 1 adapter's
+    /// `preopened-listeners` interface.
+    pub fn push_preopened_listener(&mut self, listener: Box<dyn WasiTcpSocket>) {
+        self.preopened_listeners.push(listener);
+    }
+
     /// Add network addresses to the pool.
     pub fn insert_addr<A: cap_std::net::ToSocketAddrs>(&mut self, addrs: A) -> std::io::Result<()> {
         self.pool.insert(addrs, ambient_authority())
@@ -95,5 +106,6 @@ pub fn add_to_linker<T: WasiSocketsView>(
     crate::wasi::ip_name_lookup::add_to_linker(l, |t| t)?;
     crate::wasi::instance_network::add_to_linker(l, |t| t)?;
     crate::wasi::network::add_to_linker(l, |t| t)?;
+    crate::preview1::preopened_listeners::add_to_linker(l, |t| t)?;
     Ok(())
 }
@@ -0,0 +1,85 @@
+//! Sockets for `wasi_common::preview1`.
+//!
+//! Preview 1 contexts with sockets implement
+//! `wasi_common::preview1::WasiPreview1Sockets` by forwarding to these
+//! functions, and return themselves from `WasiPreview1View::sockets`.
+
+use crate::{tcp_socket::TableTcpSocketExt, WasiSocketsView, WasiTcpSocket};
+use anyhow::Error;
+use cap_std::net::Shutdown;
+use wasi_common::wasi::streams::{InputStream, OutputStream};
+
+// The preview 1 adapter's own interface for the preopened listeners, which
+// it imports when it's built with its `sockets` feature.
+wasmtime::component::bindgen!({
+    path: "../crates/wasi-preview1-component-adapter/wit",
+    interfaces: "
+        import preopened-listeners: pkg.preopened-listeners
+    ",
+    tracing: true,
+    async: true,
+    with: {
+        "streams": wasi_common::wasi::streams,
+        "poll": wasi_common::wasi::poll,
+        "network": crate::wasi::network,
+        "tcp": crate::wasi::tcp,
+    }
+});
+
+#[async_trait::async_trait]
+impl<T: WasiSocketsView> preopened_listeners::Host for T {
+    async fn get_listeners(&mut self) -> anyhow::Result<Vec<crate::wasi::tcp::TcpSocket>> {
+        preopened_sockets(self)
+    }
+}
+
+/// Move the preopened listeners into the table, returning their handles.
+/// This also implements `preopened-listeners.get-listeners`.
+pub fn preopened_sockets(view: &mut impl WasiSocketsView) -> Result<Vec<u32>, Error> {
+    let listeners = std::mem::take(&mut view.ctx_mut().preopened_listeners);
+    listeners
+        .into_iter()
+        .map(|listener| Ok(view.table_mut().push(Box::new(listener))?))
+        .collect()
+}
+
+/// Accept a connection on `socket`. `nonblocking` is the mode of the
+/// connection.
+pub async fn accept(
+    view: &mut impl WasiSocketsView,
+    socket: u32,
+    nonblocking: bool,
+) -> Result<(u32, InputStream, OutputStream), Error> {
+    let table = view.table_mut();
+    let (connection, input_stream, output_stream, _addr) =
+        table.get_tcp_socket(socket)?.accept(nonblocking).await?;
+
+    let connection = table.push(Box::new(connection))?;
+    let input_stream = table.push(Box::new(input_stream))?;
+    let output_stream = table.push(Box::new(output_stream))?;
+
+    Ok((connection, input_stream, output_stream))
+}
+
+pub fn set_nonblocking(
+    view: &mut impl WasiSocketsView,
+    socket: u32,
+    nonblocking: bool,
+) -> Result<(), Error> {
+    view.table_mut()
+        .get_tcp_socket_mut(socket)?
+        .set_nonblocking(nonblocking)
+}
+
+pub async fn shutdown(
+    view: &impl WasiSocketsView,
+    socket: u32,
+    how: Shutdown,
+) -> Result<(), Error> {
+    view.table().get_tcp_socket(socket)?.shutdown(how).await
+}
+
+pub fn drop_socket(view: &mut impl WasiSocketsView, socket: u32) -> Result<(), Error> {
+    view.table_mut().delete::<Box<dyn WasiTcpSocket>>(socket)?;
+    Ok(())
+}
@@ -237,7 +237,12 @@ impl<T: WasiSocketsView> tcp::Host for T {
     }
 
     async fn non_blocking(&mut self, this: TcpSocket) -> anyhow::Result<Result<bool, Error>> {
-        todo!()
+        let table = self.table();
+        let socket = table.get_tcp_socket(this)?;
+
+        let value = socket.nonblocking()?;
+
+        Ok(Ok(value))
     }
 
     async fn set_non_blocking(
@@ -245,7 +250,12 @@ impl<T: WasiSocketsView> tcp::Host for T {
         this: TcpSocket,
         value: bool,
     ) -> anyhow::Result<Result<(), Error>> {
-        todo!()
+        let table = self.table_mut();
+        let socket = table.get_tcp_socket_mut(this)?;
+
+        socket.set_nonblocking(value)?;
+
+        Ok(Ok(()))
     }
 
     async fn subscribe(&mut self, this: TcpSocket) -> anyhow::Result<Pollable> {
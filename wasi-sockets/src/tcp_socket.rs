@@ -48,6 +48,7 @@ pub trait WasiTcpSocket: Send + Sync {
     fn v6_only(&self) -> Result<bool, Error>;
     fn set_v6_only(&self, value: bool) -> Result<(), Error>;
 
+    fn nonblocking(&self) -> Result<bool, Error>;
     fn set_nonblocking(&mut self, flag: bool) -> Result<(), Error>;
 
     async fn readable(&self) -> Result<(), Error>;
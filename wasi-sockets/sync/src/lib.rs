@@ -12,11 +12,15 @@ use wasmtime_wasi_sockets::{WasiNetwork, WasiSocketsCtx, WasiTcpSocket};
 
 pub struct WasiSocketsCtxBuilder {
     pool: Pool,
+    listeners: Vec<Box<dyn WasiTcpSocket>>,
 }
 
 impl WasiSocketsCtxBuilder {
     pub fn new() -> Self {
-        Self { pool: Pool::new() }
+        Self {
+            pool: Pool::new(),
+            listeners: Vec::new(),
+        }
     }
 
     pub fn inherit_network(mut self, ambient_authority: AmbientAuthority) -> Self {
@@ -31,23 +35,24 @@ impl WasiSocketsCtxBuilder {
         self
     }
 
-    /* FIXME: idk how to translate this idiom because i don't have any tests checked in showing its
-     * use. we cant allocate the fd until build().
-    pub fn preopened_listener(mut self, fd: u32, listener: impl Into<TcpSocket>) -> Self {
-        let listener: TcpSocket = listener.into();
-        let listener: Box<dyn WasiTcpSocket> = Box::new(TcpSocket::from(listener));
-
-        self.0.insert_listener(fd, listener);
+    /// Preopen a listening socket for preview 1 programs, run directly or
+    /// through the component adapter. Listeners get the file descriptors
+    /// after stdio, in the order they're added.
+    pub fn preopened_listener(mut self, listener: TcpListener) -> Self {
+        self.listeners.push(Box::new(TcpSocket::from(listener)));
         self
     }
-    */
 
     pub fn build(self) -> WasiSocketsCtx {
-        WasiSocketsCtx::new(
+        let mut ctx = WasiSocketsCtx::new(
             self.pool,
             Box::new(create_network),
             Box::new(create_tcp_socket),
-        )
+        );
+        for listener in self.listeners {
+            ctx.push_preopened_listener(listener);
+        }
+        ctx
     }
 }
 
@@ -1,5 +1,6 @@
 use cap_net_ext::{AddressFamily, Blocking, PoolExt, TcpListenerExt, UdpSocketExt};
 use cap_std::net::{Pool, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
 use io_extras::borrowed::BorrowedReadable;
 #[cfg(windows)]
 use io_extras::os::windows::{AsHandleOrSocket, BorrowedHandleOrSocket};
@@ -10,13 +11,22 @@ use rustix::fd::{AsFd, BorrowedFd, OwnedFd};
 use std::any::Any;
 use std::convert::TryInto;
 use std::io::{self, Read, Write};
+#[cfg(windows)]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use system_interface::io::{IoExt, IsReadWrite, ReadReady};
+use wasi_common::filesystem::spawn_blocking;
 use wasi_common::stream::{InputStream, OutputStream};
 use wasmtime_wasi_sockets::{RiFlags, RoFlags, WasiNetwork, WasiTcpSocket, WasiUdpSocket};
 
 pub struct Network(Pool);
-pub struct TcpSocket(Arc<TcpListener>);
+pub struct TcpSocket {
+    listener: Arc<TcpListener>,
+    /// Windows can't query whether a socket is non-blocking, so remember
+    /// what it was last set to. Clones share the socket, and so the flag.
+    #[cfg(windows)]
+    nonblocking: Arc<AtomicBool>,
+}
 pub struct UdpSocket(Arc<cap_std::net::UdpSocket>);
 
 impl Network {
@@ -26,16 +36,60 @@ impl Network {
 }
 
 impl TcpSocket {
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    fn with_blocking(listener: TcpListener, blocking: Blocking) -> Self {
+        Self {
+            listener: Arc::new(listener),
+            #[cfg(windows)]
+            nonblocking: Arc::new(AtomicBool::new(matches!(blocking, Blocking::No))),
+        }
+    }
+
     pub fn new(family: AddressFamily) -> io::Result<Self> {
-        Ok(Self(Arc::new(TcpListener::new(family, Blocking::Yes)?)))
+        Ok(Self::with_blocking(
+            TcpListener::new(family, Blocking::Yes)?,
+            Blocking::Yes,
+        ))
     }
 
     pub fn sock(fd: OwnedFd) -> Self {
-        Self(Arc::new(TcpListener::from(fd)))
+        Self::with_blocking(TcpListener::from(fd), Blocking::Yes)
     }
 
     pub fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
+        Self {
+            listener: Arc::clone(&self.listener),
+            #[cfg(windows)]
+            nonblocking: Arc::clone(&self.nonblocking),
+        }
+    }
+
+    /// Send up to `nelem` bytes of `file`, starting at `offset`, with
+    /// `sendfile`, so that they don't pass through the host. This blocks if
+    /// the socket is blocking. Returns `None` if the kernel can't `sendfile`
+    /// from `file`.
+    #[cfg(target_os = "linux")]
+    fn sendfile(
+        &self,
+        file: BorrowedFd<'_>,
+        mut offset: u64,
+        nelem: u64,
+    ) -> io::Result<Option<u64>> {
+        // Linux sends at most this many bytes in one call.
+        const MAX_SENDFILE: u64 = 0x7fff_f000;
+
+        let count = nelem.min(MAX_SENDFILE) as usize;
+        match rustix::fs::sendfile(self, file, Some(&mut offset), count) {
+            Ok(n) => Ok(Some(n as u64)),
+            Err(rustix::io::Errno::INVAL | rustix::io::Errno::NOSYS) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl From<TcpListener> for TcpSocket {
+    fn from(listener: TcpListener) -> Self {
+        Self::with_blocking(listener, Blocking::Yes)
     }
 }
 
@@ -73,7 +127,7 @@ impl WasiTcpSocket for TcpSocket {
     ) -> Result<(), anyhow::Error> {
         network
             .pool()
-            .bind_existing_tcp_listener(&self.0, local_address)?;
+            .bind_existing_tcp_listener(&self.listener, local_address)?;
         Ok(())
     }
 
@@ -81,7 +135,7 @@ impl WasiTcpSocket for TcpSocket {
         &self,
         _network: &dyn WasiNetwork, // FIXME: Can we remove this from the wit?
     ) -> Result<(), anyhow::Error> {
-        self.0.listen(None)?;
+        self.listener.listen(None)?;
         Ok(())
     }
 
@@ -101,8 +155,11 @@ impl WasiTcpSocket for TcpSocket {
             true => Blocking::No,
             false => Blocking::Yes,
         };
-        let (connection, addr) = self.0.accept_with(blocking)?;
-        let connection = TcpSocket::sock(connection.into());
+        // A blocking listener waits for a peer, so wait off the executor.
+        let listener = Arc::clone(&self.listener);
+        let (connection, addr) = spawn_blocking(move || listener.accept_with(blocking)).await?;
+        let connection =
+            TcpSocket::with_blocking(TcpListener::from(OwnedFd::from(connection)), blocking);
         let input_stream = connection.clone();
         let output_stream = connection.clone();
         Ok((
@@ -120,7 +177,7 @@ impl WasiTcpSocket for TcpSocket {
     ) -> Result<(Box<dyn InputStream>, Box<dyn OutputStream>), anyhow::Error> {
         network
             .pool()
-            .connect_existing_tcp_listener(&self.0, remote_address)?;
+            .connect_existing_tcp_listener(&self.listener, remote_address)?;
         let input_stream = self.clone();
         let output_stream = self.clone();
         Ok((Box::new(input_stream), Box::new(output_stream)))
@@ -159,14 +216,27 @@ impl WasiTcpSocket for TcpSocket {
         Ok(())
     }
 
+    #[cfg(unix)]
+    fn nonblocking(&self) -> Result<bool, anyhow::Error> {
+        let flags = rustix::fs::fcntl_getfl(self).map_err(io::Error::from)?;
+        Ok(flags.contains(rustix::fs::OFlags::NONBLOCK))
+    }
+
+    #[cfg(windows)]
+    fn nonblocking(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.nonblocking.load(Ordering::Relaxed))
+    }
+
     fn set_nonblocking(&mut self, flag: bool) -> Result<(), anyhow::Error> {
         self.as_socketlike_view::<TcpStream>()
             .set_nonblocking(flag)?;
+        #[cfg(windows)]
+        self.nonblocking.store(flag, Ordering::Relaxed);
         Ok(())
     }
 
     async fn readable(&self) -> Result<(), anyhow::Error> {
-        if is_read_write(&*self.0)?.0 {
+        if is_read_write(&*self.listener)?.0 {
             Ok(())
         } else {
             Err(anyhow::anyhow!("badf"))
@@ -174,7 +244,7 @@ impl WasiTcpSocket for TcpSocket {
     }
 
     async fn writable(&self) -> Result<(), anyhow::Error> {
-        if is_read_write(&*self.0)?.1 {
+        if is_read_write(&*self.listener)?.1 {
             Ok(())
         } else {
             Err(anyhow::anyhow!("badf"))
@@ -272,7 +342,15 @@ impl InputStream for TcpSocket {
         match Read::read(&mut &*self.as_socketlike_view::<TcpStream>(), buf) {
             Ok(0) => Ok((0, true)),
             Ok(n) => Ok((n as u64, false)),
-            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok((0, false)),
+            // A non-blocking socket with nothing to read yet.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                Ok((0, false))
+            }
             Err(err) => Err(err.into()),
         }
     }
@@ -283,7 +361,14 @@ impl InputStream for TcpSocket {
         match Read::read_vectored(&mut &*self.as_socketlike_view::<TcpStream>(), bufs) {
             Ok(0) => Ok((0, true)),
             Ok(n) => Ok((n as u64, false)),
-            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok((0, false)),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                Ok((0, false))
+            }
             Err(err) => Err(err.into()),
         }
     }
@@ -347,15 +432,35 @@ impl OutputStream for TcpSocket {
         src: &mut dyn InputStream,
         nelem: u64,
     ) -> Result<(u64, bool), anyhow::Error> {
+        // Both copies below block on the socket if it's blocking, so they
+        // run off the executor, on their own handles to the socket and `src`.
+        #[cfg(target_os = "linux")]
+        if let Some((file, offset)) = src.file_source() {
+            let file = file.try_clone_to_owned()?;
+            let socket = self.clone();
+            let sent = spawn_blocking(move || socket.sendfile(file.as_fd(), offset, nelem)).await?;
+            if let Some(n) = sent {
+                src.skip(n).await?;
+                return Ok((n, n == 0 && nelem != 0));
+            }
+        }
+
+        #[cfg(unix)]
         if let Some(readable) = src.pollable_read() {
-            let num = io::copy(
-                &mut io::Read::take(BorrowedReadable::borrow(readable), nelem),
-                &mut &*self.as_socketlike_view::<TcpStream>(),
-            )?;
-            Ok((num, num < nelem))
-        } else {
-            OutputStream::splice(self, src, nelem).await
+            let readable = readable.try_clone_to_owned()?;
+            let socket = self.clone();
+            let num = spawn_blocking(move || {
+                io::copy(
+                    &mut io::Read::take(BorrowedReadable::borrow(readable.as_fd()), nelem),
+                    &mut &*socket.as_socketlike_view::<TcpStream>(),
+                )
+            })
+            .await?;
+            return Ok((num, num < nelem));
         }
+
+        // Otherwise, copy through the streams' own reads and writes.
+        wasi_common::stream::splice_buffered(self, src, nelem).await
     }
     async fn write_zeroes(&mut self, nelem: u64) -> Result<u64, anyhow::Error> {
         let num = io::copy(
@@ -371,6 +476,20 @@ impl OutputStream for TcpSocket {
             Err(anyhow::anyhow!("badf"))
         }
     }
+    #[cfg(unix)]
+    async fn write_budget(&self) -> Result<u64, anyhow::Error> {
+        use rustix::io::{PollFd, PollFlags};
+
+        // A write takes at most what fits in the send buffer, and nothing if
+        // the buffer is full.
+        let mut fds = [PollFd::new(self, PollFlags::OUT)];
+        if rustix::io::poll(&mut fds, 0).map_err(io::Error::from)? == 0 {
+            return Ok(0);
+        }
+        let size =
+            rustix::net::sockopt::get_socket_send_buffer_size(self).map_err(io::Error::from)?;
+        Ok(size.try_into()?)
+    }
 }
 
 #[async_trait::async_trait]
@@ -387,7 +506,7 @@ impl WasiNetwork for Network {
 #[cfg(unix)]
 impl AsFd for TcpSocket {
     fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
+        self.listener.as_fd()
     }
 }
 
@@ -402,7 +521,7 @@ impl AsFd for UdpSocket {
 impl AsSocket for TcpSocket {
     /// Borrows the socket.
     fn as_socket(&self) -> BorrowedSocket<'_> {
-        self.0.as_socket()
+        self.listener.as_socket()
     }
 }
 
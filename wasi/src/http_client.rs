@@ -31,10 +31,13 @@ impl DefaultClient {
 
         let res = outgoing_handler::handle(req.id, self.options);
 
+        // The host sends the body until the stream is dropped, so drop it
+        // before waiting for the response.
+        streams::drop_output_stream(req.body);
+
         let response =
             http::Response::try_from(Response(res)).context("converting http response")?;
 
-        streams::drop_output_stream(req.body);
         http_types::drop_outgoing_request(req.id);
 
         Ok(response)